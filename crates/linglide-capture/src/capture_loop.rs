@@ -0,0 +1,192 @@
+//! Capture loops that feed frames into the encoding pipeline
+//!
+//! Each loop captures on a fixed-rate schedule, hands frames to the encoder
+//! through a latest-frame-wins handoff and exits when the encoder goes away
+//! or the shutdown signal fires.
+
+use crate::scheduler::CaptureScheduler;
+use crate::source::FrameSource;
+use crate::{FileSource, FrameDump, ScreenCapture, TestPatternSource, VirtualDisplay};
+use linglide_core::{unix_time_us, CaptureSource, Config, FrameSender};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Run the capture backend selected by the configuration
+///
+/// EVDI handles are not `Send`, so callers should run this on a dedicated
+/// thread with its own runtime.
+pub async fn run_capture(config: Config, frame_tx: FrameSender, shutdown: watch::Receiver<bool>) {
+    match config.source {
+        CaptureSource::TestPattern => run_test_pattern(config, frame_tx, shutdown).await,
+        CaptureSource::File(_) => run_file(config, frame_tx, shutdown).await,
+        CaptureSource::Display if config.mirror_mode => {
+            run_screen_capture(config, frame_tx, shutdown).await
+        }
+        CaptureSource::Display => run_virtual_display(config, frame_tx, shutdown).await,
+    }
+}
+
+/// Pull frames from a source at the configured frame rate
+///
+/// Returns when the receiver is dropped or shutdown is requested. Capture
+/// errors are logged and the loop keeps going. Frames are also written to
+/// `config.capture_dump` when set.
+pub async fn run_source<S: FrameSource>(
+    source: &mut S,
+    config: &Config,
+    frame_tx: &FrameSender,
+    shutdown: &mut watch::Receiver<bool>,
+) {
+    let mut dump = config
+        .capture_dump
+        .as_ref()
+        .and_then(|path| match FrameDump::create(path) {
+            Ok(dump) => Some(dump),
+            Err(e) => {
+                warn!("Failed to create capture dump {}: {}", path.display(), e);
+                None
+            }
+        });
+
+    let mut scheduler = CaptureScheduler::new(config.fps);
+    loop {
+        tokio::select! {
+            _ = scheduler.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+
+        let started_us = unix_time_us();
+        let result = tokio::select! {
+            result = source.next_frame() => result,
+            _ = shutdown.changed() => break,
+        };
+
+        match result {
+            Ok(mut frame) => {
+                frame.capture_started_us = started_us;
+                if let Some(ref mut dump) = dump {
+                    if let Err(e) = dump.write(&frame) {
+                        warn!("Capture dump error: {}", e);
+                    }
+                }
+                if frame_tx.send(frame).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                warn!("Capture error: {}", e);
+            }
+        }
+    }
+
+    if scheduler.missed() > 0 {
+        info!(
+            "Capture stopped ({} frame slots missed by slow captures)",
+            scheduler.missed()
+        );
+    }
+}
+
+/// Create an EVDI virtual display and capture it
+pub async fn run_virtual_display(
+    config: Config,
+    frame_tx: FrameSender,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!("Creating EVDI virtual display...");
+    let mut vd = match VirtualDisplay::new(config.clone()) {
+        Ok(vd) => vd,
+        Err(e) => {
+            warn!("Failed to create virtual display: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = vd.enable() {
+        warn!("Failed to enable virtual display: {}", e);
+        return;
+    }
+
+    // Initialize buffer (wait for mode from compositor)
+    info!("Waiting for display mode from compositor...");
+    let init = tokio::select! {
+        result = vd.init_buffer() => result,
+        _ = shutdown.changed() => Ok(()),
+    };
+    if let Err(e) = init {
+        warn!("Failed to initialize buffer: {}", e);
+    } else if !*shutdown.borrow() {
+        info!("EVDI virtual display ready, starting capture...");
+        run_source(&mut vd, &config, &frame_tx, &mut shutdown).await;
+    }
+
+    // Cleanup
+    if let Err(e) = vd.disable() {
+        warn!("Failed to disable virtual display: {}", e);
+    }
+}
+
+/// Capture the primary display (mirror mode)
+pub async fn run_screen_capture(
+    config: Config,
+    frame_tx: FrameSender,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!("Mirror mode: capturing primary display");
+    let mut capture = match ScreenCapture::new(config.width, config.height, 0, 0) {
+        Ok(capture) => capture,
+        Err(e) => {
+            warn!("Failed to create screen capture: {}", e);
+            return;
+        }
+    };
+
+    run_source(&mut capture, &config, &frame_tx, &mut shutdown).await;
+}
+
+/// Generate a synthetic test pattern
+pub async fn run_test_pattern(
+    config: Config,
+    frame_tx: FrameSender,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!(
+        "Test pattern source: {}x{} @ {} fps",
+        config.width, config.height, config.fps
+    );
+    let mut source = match TestPatternSource::new(config.width, config.height) {
+        Ok(source) => source,
+        Err(e) => {
+            warn!("Failed to create test pattern: {}", e);
+            return;
+        }
+    };
+
+    run_source(&mut source, &config, &frame_tx, &mut shutdown).await;
+}
+
+/// Replay recorded frames
+pub async fn run_file(config: Config, frame_tx: FrameSender, mut shutdown: watch::Receiver<bool>) {
+    let CaptureSource::File(ref path) = config.source else {
+        return;
+    };
+
+    info!("Replaying frames from {}", path.display());
+    let mut source = match FileSource::open(path, config.width, config.height) {
+        Ok(source) => source,
+        Err(e) => {
+            warn!("Failed to open {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let (width, height) = source.dimensions();
+    if (width, height) != (config.width, config.height) {
+        warn!(
+            "Recording is {}x{} but the display is configured as {}x{}",
+            width, height, config.width, config.height
+        );
+    }
+
+    run_source(&mut source, &config, &frame_tx, &mut shutdown).await;
+}
@@ -0,0 +1,89 @@
+//! Capture dumps for replaying sessions
+//!
+//! Frames are appended to a file as raw BGRA with no header, the format
+//! `FileSource` reads back when given the same width and height.
+
+use crate::Frame;
+use linglide_core::{Error, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Writer that records captured frames as raw BGRA
+pub struct FrameDump {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    dimensions: Option<(u32, u32)>,
+    frames: u64,
+}
+
+impl FrameDump {
+    /// Create (or truncate) a dump file
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::create(&path)?;
+        info!("Dumping captured frames to {}", path.display());
+
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            dimensions: None,
+            frames: 0,
+        })
+    }
+
+    /// Append a frame
+    ///
+    /// The first frame fixes the dump dimensions; frames of another size are
+    /// rejected since the raw format has no per-frame header.
+    pub fn write(&mut self, frame: &Frame) -> Result<()> {
+        if !frame.is_valid() {
+            return Err(Error::CaptureError("Cannot dump invalid frame".to_string()));
+        }
+
+        let dimensions = (frame.width, frame.height);
+        match self.dimensions {
+            None => {
+                info!(
+                    "Capture dump is {}x{} BGRA, replay with --width {} --height {} --source file:{}",
+                    frame.width,
+                    frame.height,
+                    frame.width,
+                    frame.height,
+                    self.path.display()
+                );
+                self.dimensions = Some(dimensions);
+            }
+            Some(expected) if expected != dimensions => {
+                return Err(Error::CaptureError(format!(
+                    "Frame size changed from {}x{} to {}x{}, not dumping",
+                    expected.0, expected.1, frame.width, frame.height
+                )));
+            }
+            Some(_) => {}
+        }
+
+        let len = frame.width as usize * frame.height as usize * 4;
+        self.writer.write_all(&frame.data()[..len])?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames written
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flush buffered frames to disk
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for FrameDump {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
@@ -0,0 +1,422 @@
+//! Frame source replaying recorded frames
+//!
+//! Supports three inputs, chosen by path:
+//! - a directory of PNG images, played in file name order
+//! - a `.y4m` file with 4:2:0 video
+//! - anything else as a raw BGRA dump (see `FrameDump`), sized by the
+//!   configured width and height
+//!
+//! Playback loops at the end of the input so a session can run indefinitely.
+
+use crate::source::FrameSource;
+use crate::Frame;
+use linglide_core::{Error, FramePool, PooledBuffer, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Source reading frames from disk
+pub struct FileSource {
+    reader: Reader,
+    width: u32,
+    height: u32,
+    sequence: u64,
+    pool: FramePool,
+}
+
+enum Reader {
+    Raw {
+        file: BufReader<File>,
+    },
+    Y4m {
+        file: BufReader<File>,
+        data_start: u64,
+        /// Scratch space for one planar frame
+        yuv: Vec<u8>,
+    },
+    Png {
+        paths: Vec<PathBuf>,
+        index: usize,
+    },
+}
+
+impl FileSource {
+    /// Open a recording
+    ///
+    /// `width` and `height` are only used for raw dumps; y4m and PNG inputs
+    /// carry their own dimensions.
+    pub fn open(path: impl AsRef<Path>, width: u32, height: u32) -> Result<Self> {
+        let path = path.as_ref();
+
+        if path.is_dir() {
+            Self::open_png_sequence(path)
+        } else if has_extension(path, "y4m") {
+            Self::open_y4m(path)
+        } else {
+            Self::open_raw(path, width, height)
+        }
+    }
+
+    fn open_raw(path: &Path, width: u32, height: u32) -> Result<Self> {
+        let frame_size = width as u64 * height as u64 * 4;
+        if frame_size == 0 {
+            return Err(Error::CaptureError(format!(
+                "Invalid raw frame size: {}x{}",
+                width, height
+            )));
+        }
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < frame_size {
+            return Err(Error::CaptureError(format!(
+                "{} is smaller than one {}x{} BGRA frame",
+                path.display(),
+                width,
+                height
+            )));
+        }
+        if len % frame_size != 0 {
+            tracing::warn!(
+                "{} is not a whole number of {}x{} frames, ignoring the trailing bytes",
+                path.display(),
+                width,
+                height
+            );
+        }
+
+        Ok(Self {
+            reader: Reader::Raw {
+                file: BufReader::new(file),
+            },
+            width,
+            height,
+            sequence: 0,
+            pool: FramePool::default(),
+        })
+    }
+
+    fn open_y4m(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut header = String::new();
+        file.read_line(&mut header)?;
+        let (width, height) = parse_y4m_header(&header)?;
+
+        Ok(Self {
+            reader: Reader::Y4m {
+                file,
+                data_start: header.len() as u64,
+                yuv: Vec::new(),
+            },
+            width,
+            height,
+            sequence: 0,
+            pool: FramePool::default(),
+        })
+    }
+
+    fn open_png_sequence(dir: &Path) -> Result<Self> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && has_extension(p, "png"))
+            .collect();
+        paths.sort();
+
+        let first = paths
+            .first()
+            .ok_or_else(|| Error::CaptureError(format!("No PNG files in {}", dir.display())))?;
+        let (width, height) = image::image_dimensions(first)
+            .map_err(|e| Error::CaptureError(format!("{}: {}", first.display(), e)))?;
+
+        Ok(Self {
+            reader: Reader::Png { paths, index: 0 },
+            width,
+            height,
+            sequence: 0,
+            pool: FramePool::default(),
+        })
+    }
+
+    /// Read the next frame's BGRA pixels, rewinding at the end of the input
+    fn read_frame(&mut self) -> Result<PooledBuffer> {
+        let (width, height) = (self.width, self.height);
+        let mut data = self.pool.acquire(width as usize * height as usize * 4);
+
+        match &mut self.reader {
+            Reader::Raw { file } => {
+                if !read_full(file, &mut data)? {
+                    file.seek(SeekFrom::Start(0))?;
+                    if !read_full(file, &mut data)? {
+                        return Err(Error::CaptureError("Raw dump is empty".to_string()));
+                    }
+                }
+                Ok(data)
+            }
+            Reader::Y4m {
+                file,
+                data_start,
+                yuv,
+            } => {
+                if !read_y4m_frame(file, width, height, yuv, &mut data)? {
+                    file.seek(SeekFrom::Start(*data_start))?;
+                    if !read_y4m_frame(file, width, height, yuv, &mut data)? {
+                        return Err(Error::CaptureError("y4m file has no frames".to_string()));
+                    }
+                }
+                Ok(data)
+            }
+            Reader::Png { paths, index } => {
+                let path = &paths[*index];
+                *index = (*index + 1) % paths.len();
+
+                let image = image::open(path)
+                    .map_err(|e| Error::CaptureError(format!("{}: {}", path.display(), e)))?
+                    .to_rgba8();
+                if image.dimensions() != (width, height) {
+                    return Err(Error::CaptureError(format!(
+                        "{} is {}x{}, expected {}x{}",
+                        path.display(),
+                        image.width(),
+                        image.height(),
+                        width,
+                        height
+                    )));
+                }
+
+                for (dst, src) in data.chunks_exact_mut(4).zip(image.pixels()) {
+                    dst.copy_from_slice(&[src[2], src[1], src[0], src[3]]);
+                }
+                Ok(data)
+            }
+        }
+    }
+}
+
+impl FrameSource for FileSource {
+    async fn next_frame(&mut self) -> Result<Frame> {
+        let data = self.read_frame()?;
+        let frame = Frame::from_buffer(data, self.width, self.height, self.sequence);
+        self.sequence += 1;
+        Ok(frame)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn on_resize(&mut self, _width: u32, _height: u32) -> Result<()> {
+        Err(Error::CaptureError(
+            "Recorded frames have a fixed size".to_string(),
+        ))
+    }
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case(ext))
+        .unwrap_or(false)
+}
+
+/// Fill `buf` completely, returning `false` at a clean or partial end of file
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse a `YUV4MPEG2` stream header, returning the frame dimensions
+fn parse_y4m_header(header: &str) -> Result<(u32, u32)> {
+    let mut params = header.trim_end().split(' ');
+    if params.next() != Some("YUV4MPEG2") {
+        return Err(Error::CaptureError("Not a y4m file".to_string()));
+    }
+
+    let (mut width, mut height) = (0, 0);
+    for param in params {
+        let (tag, value) = param.split_at(param.len().min(1));
+        match tag {
+            "W" => width = value.parse().unwrap_or(0),
+            "H" => height = value.parse().unwrap_or(0),
+            "C" if !value.starts_with("420") => {
+                return Err(Error::CaptureError(format!(
+                    "Unsupported y4m colorspace: C{} (only 4:2:0 is supported)",
+                    value
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    if width == 0 || height == 0 {
+        return Err(Error::CaptureError(
+            "y4m header is missing the frame size".to_string(),
+        ));
+    }
+    Ok((width, height))
+}
+
+/// Read one `FRAME` from a y4m stream and convert it into `bgra`
+///
+/// Returns `false` at the end of the stream.
+fn read_y4m_frame(
+    reader: &mut impl BufRead,
+    width: u32,
+    height: u32,
+    yuv: &mut Vec<u8>,
+    bgra: &mut [u8],
+) -> Result<bool> {
+    let mut marker = String::new();
+    if reader.read_line(&mut marker)? == 0 {
+        return Ok(false);
+    }
+    if !marker.starts_with("FRAME") {
+        return Err(Error::CaptureError("Corrupt y4m frame header".to_string()));
+    }
+
+    let (w, h) = (width as usize, height as usize);
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    yuv.resize(w * h + 2 * cw * ch, 0);
+    if !read_full(reader, yuv)? {
+        return Ok(false);
+    }
+
+    let (y_plane, chroma) = yuv.split_at(w * h);
+    let (u_plane, v_plane) = chroma.split_at(cw * ch);
+
+    for row in 0..h {
+        for col in 0..w {
+            let c = (row / 2) * cw + col / 2;
+            let pixel = yuv_to_bgra(y_plane[row * w + col], u_plane[c], v_plane[c]);
+            let i = (row * w + col) * 4;
+            bgra[i..i + 4].copy_from_slice(&pixel);
+        }
+    }
+    Ok(true)
+}
+
+/// BT.601 limited-range YUV to BGRA
+fn yuv_to_bgra(y: u8, u: u8, v: u8) -> [u8; 4] {
+    let c = 298 * (y as i32 - 16);
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+
+    let clamp = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+    [
+        clamp(c + 516 * d),
+        clamp(c - 100 * d - 208 * e),
+        clamp(c + 409 * e),
+        255,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::FrameDump;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn solid_frame(width: u32, height: u32, bgra: [u8; 4], sequence: u64) -> Frame {
+        let data = bgra.repeat((width * height) as usize);
+        Frame::new(data, width, height, sequence)
+    }
+
+    #[tokio::test]
+    async fn test_raw_dump_roundtrip_loops() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("session.bgra");
+
+        let mut dump = FrameDump::create(&path).unwrap();
+        dump.write(&solid_frame(4, 2, [1, 2, 3, 255], 0)).unwrap();
+        dump.write(&solid_frame(4, 2, [4, 5, 6, 255], 1)).unwrap();
+        assert!(dump.write(&solid_frame(2, 2, [0, 0, 0, 255], 2)).is_err());
+        assert_eq!(dump.frame_count(), 2);
+        drop(dump);
+
+        let mut source = FileSource::open(&path, 4, 2).unwrap();
+        assert_eq!(source.dimensions(), (4, 2));
+
+        let first = source.next_frame().await.unwrap();
+        let second = source.next_frame().await.unwrap();
+        let third = source.next_frame().await.unwrap();
+        assert_eq!(&first.data()[..4], &[1, 2, 3, 255]);
+        assert_eq!(&second.data()[..4], &[4, 5, 6, 255]);
+        assert_eq!(third.data(), first.data());
+        assert_eq!(third.sequence, 2);
+    }
+
+    #[test]
+    fn test_raw_too_small() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("short.bgra");
+        std::fs::write(&path, [0u8; 16]).unwrap();
+        assert!(FileSource::open(&path, 4, 2).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_y4m() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("clip.y4m");
+
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "YUV4MPEG2 W2 H2 F30:1 Ip A1:1 C420jpeg").unwrap();
+        // White frame, then black
+        file.write_all(b"FRAME\n").unwrap();
+        file.write_all(&[235, 235, 235, 235, 128, 128]).unwrap();
+        file.write_all(b"FRAME\n").unwrap();
+        file.write_all(&[16, 16, 16, 16, 128, 128]).unwrap();
+        drop(file);
+
+        let mut source = FileSource::open(&path, 0, 0).unwrap();
+        assert_eq!(source.dimensions(), (2, 2));
+
+        let white = source.next_frame().await.unwrap();
+        let black = source.next_frame().await.unwrap();
+        let again = source.next_frame().await.unwrap();
+        assert_eq!(&white.data()[..4], &[255, 255, 255, 255]);
+        assert_eq!(&black.data()[..4], &[0, 0, 0, 255]);
+        assert_eq!(again.data(), white.data());
+    }
+
+    #[test]
+    fn test_y4m_header() {
+        assert_eq!(
+            parse_y4m_header("YUV4MPEG2 W1920 H1080 F60:1\n").unwrap(),
+            (1920, 1080)
+        );
+        assert!(parse_y4m_header("YUV4MPEG2 W64 H64 C444\n").is_err());
+        assert!(parse_y4m_header("YUV4MPEG2 F30:1\n").is_err());
+        assert!(parse_y4m_header("P6 64 64\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_png_sequence() {
+        let dir = tempdir().unwrap();
+        for (name, rgba) in [("b.png", [0, 0, 255, 255]), ("a.png", [255, 0, 0, 255])] {
+            image::RgbaImage::from_pixel(3, 2, image::Rgba(rgba))
+                .save(dir.path().join(name))
+                .unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let mut source = FileSource::open(dir.path(), 0, 0).unwrap();
+        assert_eq!(source.dimensions(), (3, 2));
+
+        // Sorted by name and converted from RGBA to BGRA
+        let red = source.next_frame().await.unwrap();
+        let blue = source.next_frame().await.unwrap();
+        assert_eq!(&red.data()[..4], &[0, 0, 255, 255]);
+        assert_eq!(&blue.data()[..4], &[255, 0, 0, 255]);
+        assert!(source.on_resize(6, 4).is_err());
+    }
+
+    #[test]
+    fn test_empty_directory() {
+        let dir = tempdir().unwrap();
+        assert!(FileSource::open(dir.path(), 0, 0).is_err());
+    }
+}
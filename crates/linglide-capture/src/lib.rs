@@ -3,14 +3,28 @@
 //! This crate provides screen capture using:
 //! - X11 MIT-SHM extension (for X11 sessions)
 //! - PipeWire via GStreamer (for Wayland sessions)
+//! - A synthetic test pattern (for headless testing)
+//! - Recorded frames (for replaying captured sessions)
 
+pub mod capture_loop;
+pub mod dump;
+pub mod file_source;
 pub mod pipewire_capture;
+pub mod scheduler;
+pub mod source;
+pub mod test_pattern;
 pub mod virtual_display;
 pub mod x11_capture;
 
 // Re-export Frame from linglide-core for backwards compatibility
+pub use capture_loop::{run_capture, run_source};
+pub use dump::FrameDump;
+pub use file_source::FileSource;
 pub use linglide_core::Frame;
 pub use pipewire_capture::PipeWireCapture;
+pub use scheduler::CaptureScheduler;
+pub use source::FrameSource;
+pub use test_pattern::TestPatternSource;
 pub use virtual_display::VirtualDisplay;
 pub use x11_capture::X11Capture;
 
@@ -1,7 +1,7 @@
 //! Wayland screen capture using XDG Desktop Portal and PipeWire
 
 use crate::Frame;
-use linglide_core::{Error, Result};
+use linglide_core::{Error, FramePool, Result};
 use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
@@ -12,6 +12,7 @@ pub struct PipeWireCapture {
     width: u32,
     height: u32,
     frame_data: Arc<Mutex<Vec<u8>>>,
+    pool: FramePool,
     sequence: AtomicU64,
     running: Arc<AtomicBool>,
     _thread: Option<std::thread::JoinHandle<()>>,
@@ -41,6 +42,7 @@ impl PipeWireCapture {
             width,
             height,
             frame_data,
+            pool: FramePool::default(),
             sequence: AtomicU64::new(0),
             running,
             _thread: Some(thread),
@@ -48,14 +50,16 @@ impl PipeWireCapture {
     }
 
     pub fn capture(&mut self) -> Result<Frame> {
-        let data = self
+        let latest = self
             .frame_data
             .lock()
-            .map_err(|_| Error::CaptureError("Lock poisoned".into()))?
-            .clone();
+            .map_err(|_| Error::CaptureError("Lock poisoned".into()))?;
+        let mut data = self.pool.acquire(latest.len());
+        data.copy_from_slice(&latest);
+        drop(latest);
 
         let seq = self.sequence.fetch_add(1, Ordering::SeqCst);
-        Ok(Frame::new(data, self.width, self.height, seq))
+        Ok(Frame::from_buffer(data, self.width, self.height, seq))
     }
 
     pub fn dimensions(&self) -> (u32, u32) {
@@ -0,0 +1,90 @@
+//! Fixed-rate capture scheduling
+//!
+//! Sleeping for the rest of the frame interval after each capture drifts:
+//! every overrun pushes all later frames back. The scheduler lays capture
+//! slots on a fixed grid from the first tick instead, and skips slots missed
+//! while a capture overran rather than bursting to catch up, so frames are
+//! never captured faster than the configured rate.
+
+use std::time::Duration;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// Fixed-rate ticker that paces capture
+pub struct CaptureScheduler {
+    interval: Interval,
+    period: Duration,
+    missed: u64,
+}
+
+impl CaptureScheduler {
+    /// Create a scheduler for `fps` frames per second
+    pub fn new(fps: u32) -> Self {
+        let period = Duration::from_micros(1_000_000 / u64::from(fps.max(1)));
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        Self {
+            interval,
+            period,
+            missed: 0,
+        }
+    }
+
+    /// Time between capture slots
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Wait for the next capture slot and return its scheduled time
+    ///
+    /// The first slot is immediate. After an overrun the slot that was due
+    /// fires at once and the ones after it that have also passed are skipped.
+    pub async fn tick(&mut self) -> Instant {
+        let slot = self.interval.tick().await;
+        let late = Instant::now().saturating_duration_since(slot);
+        self.missed += (late.as_micros() / self.period.as_micros().max(1)) as u64;
+        slot
+    }
+
+    /// Slots skipped because a capture overran its interval
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fixed_rate_without_drift() {
+        let mut scheduler = CaptureScheduler::new(100);
+        let start = scheduler.tick().await;
+
+        // Work shorter than the period doesn't push later slots back
+        for i in 1..=5 {
+            tokio::time::sleep(Duration::from_millis(7)).await;
+            let slot = scheduler.tick().await;
+            assert_eq!(slot - start, Duration::from_millis(10 * i));
+        }
+        assert_eq!(scheduler.missed(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overrun_skips_slots() {
+        let mut scheduler = CaptureScheduler::new(100);
+        let start = scheduler.tick().await;
+
+        // A 25 ms capture: the 10 ms slot fires late, the 20 ms one is skipped
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        assert_eq!(scheduler.tick().await - start, Duration::from_millis(10));
+        assert_eq!(scheduler.missed(), 1);
+
+        // Back on the grid without a burst
+        assert_eq!(scheduler.tick().await - start, Duration::from_millis(30));
+        assert_eq!(
+            tokio::time::Instant::now() - start,
+            Duration::from_millis(30)
+        );
+        assert_eq!(scheduler.missed(), 1);
+    }
+}
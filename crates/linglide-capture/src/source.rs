@@ -0,0 +1,61 @@
+//! Frame source abstraction
+//!
+//! The capture loop pulls frames from any `FrameSource`, so display capture,
+//! synthetic patterns and recorded input share the same pacing and shutdown
+//! handling.
+
+use crate::{Frame, ScreenCapture, VirtualDisplay};
+use linglide_core::{Error, Result};
+use std::future::Future;
+
+/// Something that produces frames
+pub trait FrameSource {
+    /// Produce the next frame
+    ///
+    /// Sources may return immediately; the capture loop paces calls to the
+    /// configured frame rate.
+    fn next_frame(&mut self) -> impl Future<Output = Result<Frame>>;
+
+    /// Current frame dimensions
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Change the output resolution
+    ///
+    /// Sources that cannot be resized return an error and keep their size.
+    fn on_resize(&mut self, width: u32, height: u32) -> Result<()>;
+}
+
+impl FrameSource for VirtualDisplay {
+    async fn next_frame(&mut self) -> Result<Frame> {
+        self.capture_async().await
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        VirtualDisplay::dimensions(self)
+    }
+
+    fn on_resize(&mut self, _width: u32, _height: u32) -> Result<()> {
+        Err(Error::CaptureError(
+            "Virtual display mode is chosen by the compositor".to_string(),
+        ))
+    }
+}
+
+impl FrameSource for ScreenCapture {
+    async fn next_frame(&mut self) -> Result<Frame> {
+        self.capture()
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        ScreenCapture::dimensions(self)
+    }
+
+    fn on_resize(&mut self, width: u32, height: u32) -> Result<()> {
+        let (offset_x, offset_y) = match self {
+            Self::X11(cap) => cap.offset(),
+            Self::PipeWire(_) => (0, 0),
+        };
+        *self = ScreenCapture::new(width, height, offset_x, offset_y)?;
+        Ok(())
+    }
+}
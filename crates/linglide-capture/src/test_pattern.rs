@@ -0,0 +1,273 @@
+//! Synthetic test-pattern source
+//!
+//! Generates scrolling color bars with an elapsed-time clock and a frame
+//! counter, so the server can run end-to-end without a display server or the
+//! EVDI module. Moving content also keeps the encoder producing real P-frames.
+
+use crate::source::FrameSource;
+use crate::Frame;
+use linglide_core::{Error, FramePool, Result};
+use std::time::{Duration, Instant};
+
+/// Bar colors in BGRA: white, yellow, cyan, green, magenta, red, blue, black
+const BARS: [[u8; 4]; 8] = [
+    [255, 255, 255, 255],
+    [0, 255, 255, 255],
+    [255, 255, 0, 255],
+    [0, 255, 0, 255],
+    [255, 0, 255, 255],
+    [0, 0, 255, 255],
+    [255, 0, 0, 255],
+    [0, 0, 0, 255],
+];
+
+const TEXT_FG: [u8; 4] = [255, 255, 255, 255];
+const TEXT_BG: [u8; 4] = [0, 0, 0, 255];
+
+/// Glyph size in font pixels (3x5 digits plus one column of spacing)
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+const GLYPH_ADVANCE: u32 = GLYPH_WIDTH + 1;
+
+/// Source producing a moving test pattern
+pub struct TestPatternSource {
+    width: u32,
+    height: u32,
+    sequence: u64,
+    started: Instant,
+    pool: FramePool,
+}
+
+impl TestPatternSource {
+    /// Create a test pattern of the given size
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        check_dimensions(width, height)?;
+        Ok(Self {
+            width,
+            height,
+            sequence: 0,
+            started: Instant::now(),
+            pool: FramePool::default(),
+        })
+    }
+
+    /// Render the pattern for a frame number and elapsed time
+    pub fn render(&self, sequence: u64, elapsed: Duration) -> Frame {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let stride = width * 4;
+
+        // Bars scroll left by a few pixels per frame
+        let shift = (sequence as usize * 4) % width;
+        let mut row = vec![0u8; stride];
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let bar = ((x + shift) % width) * BARS.len() / width;
+            pixel.copy_from_slice(&BARS[bar]);
+        }
+
+        let mut data = self.pool.acquire(stride * height);
+        for line in data.chunks_exact_mut(stride) {
+            line.copy_from_slice(&row);
+        }
+
+        let scale = (self.height / 120).max(1);
+        let margin = 2 * scale;
+        let line_height = (GLYPH_HEIGHT + 2) * scale;
+        let mut canvas = Canvas {
+            data: &mut data[..],
+            width: self.width,
+            height: self.height,
+        };
+        canvas.text(margin, margin, scale, &format_clock(elapsed));
+        canvas.text(
+            margin,
+            margin + line_height,
+            scale,
+            &format!("{:08}", sequence),
+        );
+
+        Frame::from_buffer(data, self.width, self.height, sequence)
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    async fn next_frame(&mut self) -> Result<Frame> {
+        let frame = self.render(self.sequence, self.started.elapsed());
+        self.sequence += 1;
+        Ok(frame)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn on_resize(&mut self, width: u32, height: u32) -> Result<()> {
+        check_dimensions(width, height)?;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+}
+
+fn check_dimensions(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(Error::CaptureError(format!(
+            "Invalid test pattern size: {}x{}",
+            width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Format elapsed time as `HH:MM:SS.mmm`
+fn format_clock(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+/// 3x5 glyph rows, most significant of the low three bits is the left column
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => return None,
+    })
+}
+
+/// BGRA drawing target
+struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+}
+
+impl Canvas<'_> {
+    /// Fill a rectangle, clipped to the frame
+    fn fill(&mut self, x: u32, y: u32, w: u32, h: u32, color: [u8; 4]) {
+        let x_end = (x + w).min(self.width);
+        let y_end = (y + h).min(self.height);
+        for row in y.min(y_end)..y_end {
+            let start = (row * self.width + x.min(x_end)) as usize * 4;
+            let end = (row * self.width + x_end) as usize * 4;
+            for pixel in self.data[start..end].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draw text on a solid background
+    fn text(&mut self, x: u32, y: u32, scale: u32, text: &str) {
+        let columns = text.chars().count() as u32 * GLYPH_ADVANCE + 1;
+        self.fill(x, y, columns * scale, (GLYPH_HEIGHT + 2) * scale, TEXT_BG);
+
+        for (i, c) in text.chars().enumerate() {
+            let Some(rows) = glyph(c) else {
+                continue;
+            };
+            let gx = x + (1 + i as u32 * GLYPH_ADVANCE) * scale;
+            let gy = y + scale;
+            for (r, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        self.fill(
+                            gx + col * scale,
+                            gy + r as u32 * scale,
+                            scale,
+                            scale,
+                            TEXT_FG,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(frame: &Frame, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * frame.width + x) * 4) as usize;
+        frame.data()[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_frame_size() {
+        let source = TestPatternSource::new(320, 240).unwrap();
+        let frame = source.render(0, Duration::ZERO);
+        assert!(frame.is_valid());
+        assert_eq!(frame.size(), 320 * 240 * 4);
+        assert_eq!(frame.sequence, 0);
+    }
+
+    #[test]
+    fn test_bars_move() {
+        let source = TestPatternSource::new(320, 240).unwrap();
+        let first = source.render(0, Duration::ZERO);
+        let second = source.render(10, Duration::ZERO);
+
+        // Bottom row is clear of the text overlay
+        assert_eq!(pixel(&first, 0, 239), BARS[0]);
+        assert_ne!(pixel(&first, 39, 239), pixel(&second, 39, 239));
+    }
+
+    #[test]
+    fn test_counter_changes_overlay() {
+        let source = TestPatternSource::new(320, 240).unwrap();
+        let a = source.render(1, Duration::ZERO);
+        let b = source.render(2, Duration::ZERO);
+        let c = source.render(2, Duration::from_millis(1500));
+
+        // Text box at 2x scale: 4px margin, two 14px lines, 12 glyphs wide
+        let overlay = |f: &Frame| {
+            (4..32)
+                .flat_map(|y| (4..100).map(move |x| (x, y)))
+                .map(|(x, y)| pixel(f, x, y))
+                .collect::<Vec<_>>()
+        };
+        assert_ne!(overlay(&a), overlay(&b));
+        assert_ne!(overlay(&b), overlay(&c));
+        assert!(overlay(&a).contains(&TEXT_FG));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut source = TestPatternSource::new(320, 240).unwrap();
+        source.on_resize(640, 360).unwrap();
+        assert_eq!(source.dimensions(), (640, 360));
+        assert!(source.on_resize(0, 360).is_err());
+        assert_eq!(source.dimensions(), (640, 360));
+    }
+
+    #[test]
+    fn test_clock_format() {
+        assert_eq!(
+            format_clock(Duration::from_millis(3_723_045)),
+            "01:02:03.045"
+        );
+    }
+
+    #[test]
+    fn test_tiny_frame() {
+        // Overlay is clipped rather than panicking
+        let source = TestPatternSource::new(4, 2).unwrap();
+        assert!(source.render(7, Duration::from_secs(1)).is_valid());
+    }
+}
@@ -5,7 +5,7 @@
 
 use crate::Frame;
 use evdi::prelude::*;
-use linglide_core::{Config, Error, Result};
+use linglide_core::{Config, Error, FramePool, Rect, Result};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -22,6 +22,10 @@ pub struct VirtualDisplay {
     buffer_id: Option<BufferId>,
     /// Current mode
     mode: Option<Mode>,
+    /// Buffer version of the last captured frame
+    buffer_version: Option<u32>,
+    /// Recycled frame buffers
+    pool: FramePool,
     /// Frame sequence counter
     sequence: AtomicU64,
     /// Whether the display is active
@@ -58,6 +62,8 @@ impl VirtualDisplay {
             handle: None,
             buffer_id: None,
             mode: None,
+            buffer_version: None,
+            pool: FramePool::default(),
             sequence: AtomicU64::new(0),
             running: AtomicBool::new(false),
         })
@@ -158,6 +164,7 @@ impl VirtualDisplay {
 
         self.mode = Some(mode);
         self.buffer_id = Some(buffer_id);
+        self.buffer_version = None;
 
         Ok(())
     }
@@ -175,6 +182,7 @@ impl VirtualDisplay {
         self.handle = None;
         self.buffer_id = None;
         self.mode = None;
+        self.buffer_version = None;
 
         info!("Virtual display disabled");
         Ok(())
@@ -196,7 +204,10 @@ impl VirtualDisplay {
         // Request an update - timeout is OK, we'll use the last buffer content
         // EVDI only sends updates when there are actual changes on screen
         let timeout = Duration::from_millis(50);
-        let _ = handle_guard.request_update(buffer_id, timeout).await;
+        let updated = handle_guard
+            .request_update(buffer_id, timeout)
+            .await
+            .is_ok();
         // Ignore timeout errors - buffer still has valid data from last update
 
         // Get the buffer data (may be from a previous update if timeout)
@@ -213,11 +224,33 @@ impl VirtualDisplay {
         let width = mode.width;
         let height = mode.height;
 
-        // Copy the frame data
-        let data = bytes.to_vec();
+        // Damage: EVDI reports the rects grabbed by the last update. Without a
+        // new buffer version nothing changed; the first frame is sent whole.
+        let damage = match self.buffer_version {
+            Some(last) if !updated || buffer.version == Some(last) => Some(Vec::new()),
+            Some(_) => {
+                let rects: Vec<Rect> = buffer
+                    .rects()
+                    .iter()
+                    .filter_map(|r| Rect::from_corners(r.x1, r.y1, r.x2, r.y2))
+                    .collect();
+                (!rects.is_empty()).then_some(rects)
+            }
+            None => None,
+        };
+        let version = buffer.version;
+
+        // Copy the frame data into a recycled buffer
+        let mut data = self.pool.acquire(bytes.len());
+        data.copy_from_slice(bytes);
+        self.buffer_version = version.or(self.buffer_version);
 
         let seq = self.sequence.fetch_add(1, Ordering::SeqCst);
-        Ok(Frame::new(data, width, height, seq))
+        let frame = Frame::from_buffer(data, width, height, seq);
+        Ok(match damage {
+            Some(rects) => frame.with_damage(rects),
+            None => frame,
+        })
     }
 
     /// Get the display offset (for input coordinate mapping)
@@ -237,6 +270,14 @@ impl VirtualDisplay {
     pub fn output(&self) -> &str {
         "EVDI-1"
     }
+
+    /// Get the display dimensions (the compositor's mode once initialized)
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self.mode {
+            Some(ref mode) => (mode.width, mode.height),
+            None => (self.config.width, self.config.height),
+        }
+    }
 }
 
 impl Drop for VirtualDisplay {
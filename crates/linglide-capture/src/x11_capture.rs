@@ -1,9 +1,19 @@
 //! X11 screen capture using MIT-SHM extension
 
 use crate::Frame;
-use linglide_core::{Error, Result};
+use linglide_core::{Error, FramePool, Rect, Result};
 use std::ptr;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+use xcb::Xid;
+
+/// XDamage tracking of the root window
+struct DamageTracker {
+    damage: xcb::damage::Damage,
+    /// Scratch region receiving the damaged area on each capture
+    region: xcb::xfixes::Region,
+    /// Whether a full frame has been captured since tracking started
+    primed: bool,
+}
 
 /// X11 screen capture using MIT-SHM for zero-copy performance
 pub struct X11Capture {
@@ -17,6 +27,8 @@ pub struct X11Capture {
     offset_x: i32,
     offset_y: i32,
     sequence: u64,
+    damage: Option<DamageTracker>,
+    pool: FramePool,
 }
 
 // Safety: X11 connection and SHM are managed properly
@@ -73,6 +85,17 @@ impl X11Capture {
         conn.flush()
             .map_err(|e| Error::X11Connection(e.to_string()))?;
 
+        let damage = match create_damage_tracker(&conn, screen_num) {
+            Ok(tracker) => {
+                info!("XDamage extension available, tracking damaged regions");
+                Some(tracker)
+            }
+            Err(e) => {
+                warn!("XDamage unavailable, capturing full frames: {}", e);
+                None
+            }
+        };
+
         debug!(
             "X11 capture initialized: {}x{} at offset ({}, {})",
             width, height, offset_x, offset_y
@@ -89,6 +112,8 @@ impl X11Capture {
             offset_x,
             offset_y,
             sequence: 0,
+            damage,
+            pool: FramePool::default(),
         })
     }
 
@@ -102,6 +127,9 @@ impl X11Capture {
 
         let root = screen.root();
 
+        // Collect damage before grabbing so nothing drawn in between is lost
+        let damage = self.take_damage();
+
         // Request the image via SHM
         let cookie = self.conn.send_request(&xcb::shm::GetImage {
             drawable: xcb::x::Drawable::Window(root),
@@ -119,14 +147,67 @@ impl X11Capture {
             .wait_for_reply(cookie)
             .map_err(|e| Error::CaptureError(format!("GetImage failed: {:?}", e)))?;
 
-        // Copy data from shared memory
+        // Copy data from shared memory into a recycled buffer
         let buffer_size = (self.width * self.height * 4) as usize;
-        let data =
-            unsafe { std::slice::from_raw_parts(self.shm_addr as *const u8, buffer_size).to_vec() };
+        let mut data = self.pool.acquire(buffer_size);
+        data.copy_from_slice(unsafe {
+            std::slice::from_raw_parts(self.shm_addr as *const u8, buffer_size)
+        });
 
         self.sequence += 1;
 
-        Ok(Frame::new(data, self.width, self.height, self.sequence))
+        let frame = Frame::from_buffer(data, self.width, self.height, self.sequence);
+        Ok(match damage {
+            Some(rects) => frame.with_damage(rects),
+            None => frame,
+        })
+    }
+
+    /// Fetch and reset the damaged area of the captured region
+    ///
+    /// Returns `None` when damage is unknown: XDamage is unavailable, a
+    /// request failed, or this is the first frame.
+    fn take_damage(&mut self) -> Option<Vec<Rect>> {
+        let tracker = self.damage.as_mut()?;
+
+        // Drain DamageNotify events; the region query below is authoritative
+        while let Ok(Some(_)) = self.conn.poll_for_event() {}
+
+        // Move the accumulated damage into our region and clear it
+        self.conn.send_request(&xcb::damage::Subtract {
+            damage: tracker.damage,
+            repair: xcb::xfixes::Region::none(),
+            parts: tracker.region,
+        });
+        let cookie = self.conn.send_request(&xcb::xfixes::FetchRegion {
+            region: tracker.region,
+        });
+        let reply = match self.conn.wait_for_reply(cookie) {
+            Ok(reply) => reply,
+            Err(e) => {
+                debug!("XFixes FetchRegion failed: {:?}", e);
+                return None;
+            }
+        };
+
+        if !tracker.primed {
+            tracker.primed = true;
+            return None;
+        }
+
+        // Translate from root window to capture coordinates
+        let (ox, oy) = (self.offset_x, self.offset_y);
+        Some(
+            reply
+                .rectangles()
+                .iter()
+                .filter_map(|r| {
+                    let x = r.x as i32 - ox;
+                    let y = r.y as i32 - oy;
+                    Rect::from_corners(x, y, x + r.width as i32, y + r.height as i32)
+                })
+                .collect(),
+        )
     }
 
     /// Get the capture dimensions
@@ -140,8 +221,63 @@ impl X11Capture {
     }
 }
 
+/// Start XDamage tracking of the root window
+fn create_damage_tracker(conn: &xcb::Connection, screen_num: i32) -> Result<DamageTracker> {
+    // XFixes must be initialized before Damage, which depends on it
+    let cookie = conn.send_request(&xcb::xfixes::QueryVersion {
+        client_major_version: 5,
+        client_minor_version: 0,
+    });
+    conn.wait_for_reply(cookie)
+        .map_err(|_| Error::X11ExtensionMissing("XFIXES".to_string()))?;
+
+    let cookie = conn.send_request(&xcb::damage::QueryVersion {
+        client_major_version: 1,
+        client_minor_version: 1,
+    });
+    conn.wait_for_reply(cookie)
+        .map_err(|_| Error::X11ExtensionMissing("DAMAGE".to_string()))?;
+
+    let root = conn
+        .get_setup()
+        .roots()
+        .nth(screen_num as usize)
+        .ok_or_else(|| Error::X11Connection("Invalid screen".to_string()))?
+        .root();
+
+    let damage: xcb::damage::Damage = conn.generate_id();
+    conn.send_and_check_request(&xcb::damage::Create {
+        damage,
+        drawable: xcb::x::Drawable::Window(root),
+        level: xcb::damage::ReportLevel::NonEmpty,
+    })
+    .map_err(|e| Error::CaptureError(format!("DamageCreate failed: {:?}", e)))?;
+
+    let region: xcb::xfixes::Region = conn.generate_id();
+    conn.send_and_check_request(&xcb::xfixes::CreateRegion {
+        region,
+        rectangles: &[],
+    })
+    .map_err(|e| Error::CaptureError(format!("CreateRegion failed: {:?}", e)))?;
+
+    Ok(DamageTracker {
+        damage,
+        region,
+        primed: false,
+    })
+}
+
 impl Drop for X11Capture {
     fn drop(&mut self) {
+        if let Some(ref tracker) = self.damage {
+            self.conn.send_request(&xcb::damage::Destroy {
+                damage: tracker.damage,
+            });
+            self.conn.send_request(&xcb::xfixes::DestroyRegion {
+                region: tracker.region,
+            });
+        }
+
         // Detach from X server
         self.conn.send_request(&xcb::shm::Detach {
             shmseg: self.shm_seg,
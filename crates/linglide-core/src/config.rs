@@ -1,6 +1,7 @@
 //! Configuration types for LinGlide
 
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 /// Position of the virtual display relative to the primary display
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -41,6 +42,433 @@ impl std::str::FromStr for DisplayPosition {
     }
 }
 
+/// Where captured frames come from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureSource {
+    /// Virtual display, or the primary display in mirror mode
+    #[default]
+    Display,
+    /// Synthetic test pattern (no display server required)
+    TestPattern,
+    /// Recorded frames: a raw BGRA dump, a y4m file or a directory of PNGs
+    File(PathBuf),
+}
+
+impl std::str::FromStr for CaptureSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Paths are case-sensitive, so match the prefix before lowercasing
+        if let Some(path) = s.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err("Missing path in file:<path> source".to_string());
+            }
+            return Ok(CaptureSource::File(PathBuf::from(path)));
+        }
+
+        match s.to_lowercase().as_str() {
+            "display" => Ok(CaptureSource::Display),
+            "test-pattern" | "test" => Ok(CaptureSource::TestPattern),
+            _ => Err(format!(
+                "Invalid source: {}. Use: display, test-pattern, file:<path>",
+                s
+            )),
+        }
+    }
+}
+
+/// Compressed video format of the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    /// H.264 / AVC, decodable everywhere
+    #[default]
+    H264,
+    /// H.265 / HEVC
+    Hevc,
+    /// AV1
+    Av1,
+}
+
+impl VideoCodec {
+    /// Every codec, most efficient first
+    pub const ALL: [VideoCodec; 3] = [VideoCodec::Av1, VideoCodec::Hevc, VideoCodec::H264];
+
+    /// MP4 sample entry type, also the prefix of the WebCodecs codec string
+    pub fn sample_entry(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "avc1",
+            VideoCodec::Hevc => "hvc1",
+            VideoCodec::Av1 => "av01",
+        }
+    }
+
+    /// Codec of a WebCodecs codec string or sample entry type such as
+    /// `avc1.42c01f` or `hev1`
+    pub fn from_codec_string(codec: &str) -> Option<Self> {
+        match codec.split('.').next()? {
+            "avc1" | "avc3" => Some(VideoCodec::H264),
+            "hvc1" | "hev1" => Some(VideoCodec::Hevc),
+            "av01" => Some(VideoCodec::Av1),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoCodec::H264 => write!(f, "h264"),
+            VideoCodec::Hevc => write!(f, "hevc"),
+            VideoCodec::Av1 => write!(f, "av1"),
+        }
+    }
+}
+
+impl std::str::FromStr for VideoCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "h264" | "avc" => Ok(VideoCodec::H264),
+            "hevc" | "h265" => Ok(VideoCodec::Hevc),
+            "av1" => Ok(VideoCodec::Av1),
+            _ => Err(format!("Invalid codec: {}. Use: h264, hevc, av1", s)),
+        }
+    }
+}
+
+/// Video encoder implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderKind {
+    /// Cisco OpenH264 (prebuilt binary downloaded at build time)
+    #[default]
+    OpenH264,
+    /// libx264 (requires the `x264` feature)
+    X264,
+    /// libx265 HEVC (requires the `x265` feature)
+    X265,
+    /// SVT-AV1 (requires the `svt-av1` feature)
+    #[serde(rename = "svt-av1")]
+    SvtAv1,
+}
+
+impl EncoderKind {
+    /// Codec the encoder produces
+    pub fn codec(&self) -> VideoCodec {
+        match self {
+            EncoderKind::OpenH264 | EncoderKind::X264 => VideoCodec::H264,
+            EncoderKind::X265 => VideoCodec::Hevc,
+            EncoderKind::SvtAv1 => VideoCodec::Av1,
+        }
+    }
+}
+
+impl std::fmt::Display for EncoderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncoderKind::OpenH264 => write!(f, "openh264"),
+            EncoderKind::X264 => write!(f, "x264"),
+            EncoderKind::X265 => write!(f, "x265"),
+            EncoderKind::SvtAv1 => write!(f, "svt-av1"),
+        }
+    }
+}
+
+impl std::str::FromStr for EncoderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openh264" => Ok(EncoderKind::OpenH264),
+            "x264" => Ok(EncoderKind::X264),
+            "x265" => Ok(EncoderKind::X265),
+            "svt-av1" | "svtav1" => Ok(EncoderKind::SvtAv1),
+            _ => Err(format!(
+                "Invalid encoder: {}. Use: openh264, x264, x265, svt-av1",
+                s
+            )),
+        }
+    }
+}
+
+/// H.264 profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum H264Profile {
+    /// Constrained Baseline: CAVLC only, decodable by every hardware decoder
+    #[default]
+    ConstrainedBaseline,
+    /// High: CABAC and 8x8 transforms, better quality per bit
+    High,
+}
+
+impl H264Profile {
+    /// `profile_idc` written to the SPS
+    pub fn profile_idc(&self) -> u8 {
+        match self {
+            H264Profile::ConstrainedBaseline => 66,
+            H264Profile::High => 100,
+        }
+    }
+
+    /// SPS constraint flags byte (`constraint_set0_flag` and up)
+    pub fn constraint_flags(&self) -> u8 {
+        match self {
+            // constraint_set0 and constraint_set1 mark Constrained Baseline
+            H264Profile::ConstrainedBaseline => 0xC0,
+            H264Profile::High => 0x00,
+        }
+    }
+}
+
+impl std::fmt::Display for H264Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            H264Profile::ConstrainedBaseline => write!(f, "baseline"),
+            H264Profile::High => write!(f, "high"),
+        }
+    }
+}
+
+impl std::str::FromStr for H264Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "baseline" | "constrained-baseline" => Ok(H264Profile::ConstrainedBaseline),
+            "high" => Ok(H264Profile::High),
+            _ => Err(format!("Invalid profile: {}. Use: baseline, high", s)),
+        }
+    }
+}
+
+/// Highest quantizer H.264 allows for 8-bit video
+pub const MAX_QP: u8 = 51;
+
+/// Encoder tuning shared by all backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderSettings {
+    /// Frames between keyframes; 0 sends keyframes only at start and on request
+    pub gop: u32,
+    /// Refresh the picture with a moving intra column instead of IDR frames,
+    /// avoiding the bitrate spike of a full keyframe (x264 and x265)
+    pub intra_refresh: bool,
+    /// H.264 profile (ignored by HEVC and AV1 encoders)
+    pub profile: H264Profile,
+    /// Slices per frame (0 lets the encoder decide)
+    pub slices: u32,
+    /// Lowest quantizer (best quality) rate control may use
+    pub min_qp: u8,
+    /// Highest quantizer (worst quality) rate control may use
+    pub max_qp: u8,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            gop: 0,
+            intra_refresh: false,
+            profile: H264Profile::default(),
+            slices: 1,
+            min_qp: 0,
+            max_qp: MAX_QP,
+        }
+    }
+}
+
+impl EncoderSettings {
+    /// Check that the settings can be applied
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.min_qp > self.max_qp || self.max_qp > MAX_QP {
+            return Err(crate::Error::Config(format!(
+                "Invalid QP range {}-{} (must be within 0-{})",
+                self.min_qp, self.max_qp, MAX_QP
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// An additional encoding of the display, at reduced size or bitrate
+///
+/// Written as `SCALE:KBPS`, e.g. `2:1500` for half resolution at 1.5 Mbps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rendition {
+    /// Each dimension is divided by this (1 = full resolution)
+    pub scale: u32,
+    /// Target bitrate in kbps
+    pub bitrate: u32,
+}
+
+impl Rendition {
+    /// Create a rendition
+    pub fn new(scale: u32, bitrate: u32) -> Self {
+        Self { scale, bitrate }
+    }
+
+    /// Encoded size for a `width` x `height` stream, rounded down to even
+    /// dimensions as 4:2:0 encoders require
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = self.scale.max(1);
+        ((width / scale).max(2) & !1, (height / scale).max(2) & !1)
+    }
+}
+
+impl std::fmt::Display for Rendition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.scale, self.bitrate)
+    }
+}
+
+impl std::str::FromStr for Rendition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rendition: {}. Use SCALE:KBPS, e.g. 2:1500", s);
+        let (scale, bitrate) = s.split_once(':').ok_or_else(invalid)?;
+        let scale: u32 = scale.trim().parse().map_err(|_| invalid())?;
+        let bitrate: u32 = bitrate.trim().parse().map_err(|_| invalid())?;
+        if scale == 0 || bitrate == 0 {
+            return Err(invalid());
+        }
+        Ok(Rendition::new(scale, bitrate))
+    }
+}
+
+/// Resampling filter used when the stream size differs from the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleFilter {
+    /// Interpolate between the nearest pixels (fast, aliases below half size)
+    Bilinear,
+    /// Average the pixels each output pixel covers (sharpest text when shrinking)
+    #[default]
+    Area,
+}
+
+impl std::fmt::Display for ScaleFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScaleFilter::Bilinear => write!(f, "bilinear"),
+            ScaleFilter::Area => write!(f, "area"),
+        }
+    }
+}
+
+impl std::str::FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bilinear" => Ok(ScaleFilter::Bilinear),
+            "area" | "box" => Ok(ScaleFilter::Area),
+            _ => Err(format!("Invalid scale filter: {}. Use: area, bilinear", s)),
+        }
+    }
+}
+
+/// YUV matrix coefficients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMatrix {
+    /// ITU-R BT.601 (standard definition)
+    Bt601,
+    /// ITU-R BT.709 (high definition)
+    #[default]
+    Bt709,
+}
+
+/// Quantization range of the YUV samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorRange {
+    /// Luma 16-235, chroma 16-240 (broadcast/"TV" range)
+    #[default]
+    Limited,
+    /// All 0-255 values used ("PC" range)
+    Full,
+}
+
+/// Color space of the encoded video
+///
+/// Captured pixels are sRGB, so the primaries and transfer function are
+/// fixed; only the YUV matrix and range are configurable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct ColorSpace {
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+}
+
+impl ColorSpace {
+    /// Create a color space
+    pub fn new(matrix: ColorMatrix, range: ColorRange) -> Self {
+        Self { matrix, range }
+    }
+
+    /// Colour primaries code point (ITU-T H.273): BT.709/sRGB
+    pub fn primaries_code(&self) -> u8 {
+        1
+    }
+
+    /// Transfer characteristics code point (ITU-T H.273): sRGB
+    pub fn transfer_code(&self) -> u8 {
+        13
+    }
+
+    /// Matrix coefficients code point (ITU-T H.273)
+    pub fn matrix_code(&self) -> u8 {
+        match self.matrix {
+            ColorMatrix::Bt601 => 6,
+            ColorMatrix::Bt709 => 1,
+        }
+    }
+
+    /// Whether samples use the full 0-255 range
+    pub fn is_full_range(&self) -> bool {
+        self.range == ColorRange::Full
+    }
+}
+
+impl std::fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let matrix = match self.matrix {
+            ColorMatrix::Bt601 => "bt601",
+            ColorMatrix::Bt709 => "bt709",
+        };
+        match self.range {
+            ColorRange::Limited => write!(f, "{}", matrix),
+            ColorRange::Full => write!(f, "{}-full", matrix),
+        }
+    }
+}
+
+impl std::str::FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        let (matrix, range) = match s.strip_suffix("-full") {
+            Some(matrix) => (matrix, ColorRange::Full),
+            None => (s.as_str(), ColorRange::Limited),
+        };
+        let matrix = match matrix {
+            "bt601" | "601" => ColorMatrix::Bt601,
+            "bt709" | "709" => ColorMatrix::Bt709,
+            _ => {
+                return Err(format!(
+                    "Invalid color space: {}. Use: bt709, bt601, bt709-full, bt601-full",
+                    s
+                ))
+            }
+        };
+        Ok(ColorSpace::new(matrix, range))
+    }
+}
+
 /// Main configuration for LinGlide
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -48,6 +476,15 @@ pub struct Config {
     pub width: u32,
     /// Virtual display height in pixels
     pub height: u32,
+    /// Encoded stream width, if different from the display
+    #[serde(default)]
+    pub stream_width: Option<u32>,
+    /// Encoded stream height, if different from the display
+    #[serde(default)]
+    pub stream_height: Option<u32>,
+    /// Filter used to scale captured frames to the stream size
+    #[serde(default)]
+    pub scale_filter: ScaleFilter,
     /// Target frame rate
     pub fps: u32,
     /// Server port
@@ -62,6 +499,32 @@ pub struct Config {
     pub virtual_output: Option<String>,
     /// Mirror mode: capture primary display instead of creating virtual display
     pub mirror_mode: bool,
+    /// Frame source
+    #[serde(default)]
+    pub source: CaptureSource,
+    /// Write every captured frame to this file as raw BGRA
+    #[serde(default)]
+    pub capture_dump: Option<PathBuf>,
+    /// Color space used for conversion and signalled to clients
+    #[serde(default)]
+    pub color_space: ColorSpace,
+    /// Encoder implementation, which also selects the codec
+    #[serde(default)]
+    pub encoder: EncoderKind,
+    /// GOP, profile and rate control tuning
+    #[serde(default)]
+    pub encoder_settings: EncoderSettings,
+    /// Lower bitrate and frame rate while clients can't keep up
+    #[serde(default = "default_adaptive_bitrate")]
+    pub adaptive_bitrate: bool,
+    /// Extra renditions encoded next to the full-size stream, for clients
+    /// on slow links or small screens
+    #[serde(default)]
+    pub renditions: Vec<Rendition>,
+}
+
+fn default_adaptive_bitrate() -> bool {
+    true
 }
 
 impl Default for Config {
@@ -69,6 +532,9 @@ impl Default for Config {
         Self {
             width: 1920,
             height: 1080,
+            stream_width: None,
+            stream_height: None,
+            scale_filter: ScaleFilter::default(),
             fps: 60,
             port: 8443,
             position: DisplayPosition::RightOf,
@@ -76,6 +542,13 @@ impl Default for Config {
             primary_display: None,
             virtual_output: None,
             mirror_mode: false,
+            source: CaptureSource::Display,
+            capture_dump: None,
+            color_space: ColorSpace::default(),
+            encoder: EncoderKind::default(),
+            encoder_settings: EncoderSettings::default(),
+            adaptive_bitrate: default_adaptive_bitrate(),
+            renditions: Vec::new(),
         }
     }
 }
@@ -98,6 +571,28 @@ impl Config {
         self
     }
 
+    /// Builder pattern: set the encoded stream width
+    ///
+    /// Without a stream height, the height follows the display aspect ratio.
+    pub fn with_stream_width(mut self, width: u32) -> Self {
+        self.stream_width = Some(width);
+        self
+    }
+
+    /// Builder pattern: set the encoded stream height
+    ///
+    /// Without a stream width, the width follows the display aspect ratio.
+    pub fn with_stream_height(mut self, height: u32) -> Self {
+        self.stream_height = Some(height);
+        self
+    }
+
+    /// Builder pattern: set the filter used to scale frames to the stream size
+    pub fn with_scale_filter(mut self, filter: ScaleFilter) -> Self {
+        self.scale_filter = filter;
+        self
+    }
+
     /// Builder pattern: set frame rate
     pub fn with_fps(mut self, fps: u32) -> Self {
         self.fps = fps;
@@ -128,6 +623,93 @@ impl Config {
         self
     }
 
+    /// Builder pattern: set frame source
+    pub fn with_source(mut self, source: CaptureSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Builder pattern: set capture dump file
+    pub fn with_capture_dump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.capture_dump = Some(path.into());
+        self
+    }
+
+    /// Builder pattern: set color space
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Builder pattern: enable or disable adaptive bitrate
+    pub fn with_adaptive_bitrate(mut self, adaptive: bool) -> Self {
+        self.adaptive_bitrate = adaptive;
+        self
+    }
+
+    /// Builder pattern: set encoder implementation
+    pub fn with_encoder(mut self, encoder: EncoderKind) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Builder pattern: set encoder tuning
+    pub fn with_encoder_settings(mut self, settings: EncoderSettings) -> Self {
+        self.encoder_settings = settings;
+        self
+    }
+
+    /// Builder pattern: set the extra renditions
+    pub fn with_renditions(mut self, renditions: impl IntoIterator<Item = Rendition>) -> Self {
+        self.renditions = renditions.into_iter().collect();
+        self
+    }
+
+    /// Every rendition to encode: the full-size stream at `bitrate`, then the
+    /// extra ones
+    pub fn all_renditions(&self) -> Vec<Rendition> {
+        let mut renditions = vec![Rendition::new(1, self.bitrate)];
+        for rendition in &self.renditions {
+            if !renditions.contains(rendition) {
+                renditions.push(*rendition);
+            }
+        }
+        renditions
+    }
+
+    /// Size of the encoded stream
+    ///
+    /// The display size unless a stream size is set. A stream size given for
+    /// one dimension only keeps the display aspect ratio; sizes that differ
+    /// from the display are rounded down to even dimensions for 4:2:0.
+    pub fn stream_size(&self) -> (u32, u32) {
+        let scale = |value: u32, num: u32, den: u32| {
+            (u64::from(value) * u64::from(num) / u64::from(den.max(1))) as u32
+        };
+        let (width, height) = match (self.stream_width, self.stream_height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, scale(self.height, width, self.width)),
+            (None, Some(height)) => (scale(self.width, height, self.height), height),
+            (None, None) => return (self.width, self.height),
+        };
+        (width.max(2) & !1, height.max(2) & !1)
+    }
+
+    /// Configuration of the encoder for one rendition
+    ///
+    /// The display size is kept; renditions scale down the stream size.
+    pub fn for_rendition(&self, rendition: &Rendition) -> Config {
+        let (width, height) = self.stream_size();
+        let (stream_width, stream_height) = rendition.size(width, height);
+        Config {
+            stream_width: Some(stream_width),
+            stream_height: Some(stream_height),
+            bitrate: rendition.bitrate,
+            renditions: Vec::new(),
+            ..self.clone()
+        }
+    }
+
     /// Calculate bytes per frame for BGRA format
     pub fn frame_size_bytes(&self) -> usize {
         (self.width * self.height * 4) as usize
@@ -138,3 +720,206 @@ impl Config {
         format!("{}x{}_linglide", self.width, self.height)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_source_parse() {
+        assert_eq!(
+            "display".parse::<CaptureSource>(),
+            Ok(CaptureSource::Display)
+        );
+        assert_eq!(
+            "Test-Pattern".parse::<CaptureSource>(),
+            Ok(CaptureSource::TestPattern)
+        );
+        assert_eq!(
+            "test".parse::<CaptureSource>(),
+            Ok(CaptureSource::TestPattern)
+        );
+        assert!("screen".parse::<CaptureSource>().is_err());
+    }
+
+    #[test]
+    fn test_capture_source_file() {
+        // The path keeps its case even though keywords are case-insensitive
+        assert_eq!(
+            "file:/abs/Path".parse::<CaptureSource>(),
+            Ok(CaptureSource::File(PathBuf::from("/abs/Path")))
+        );
+        assert!("file:".parse::<CaptureSource>().is_err());
+    }
+
+    #[test]
+    fn test_color_space_parse() {
+        assert_eq!("bt709".parse::<ColorSpace>(), Ok(ColorSpace::default()));
+        assert_eq!(
+            "BT601-full".parse::<ColorSpace>(),
+            Ok(ColorSpace::new(ColorMatrix::Bt601, ColorRange::Full))
+        );
+        assert_eq!(
+            "709-full".parse::<ColorSpace>(),
+            Ok(ColorSpace::new(ColorMatrix::Bt709, ColorRange::Full))
+        );
+        assert!("bt2020".parse::<ColorSpace>().is_err());
+        assert!("full".parse::<ColorSpace>().is_err());
+        assert!("bt709-limited".parse::<ColorSpace>().is_err());
+    }
+
+    #[test]
+    fn test_color_space_codes() {
+        for name in ["bt709", "bt601", "bt709-full", "bt601-full"] {
+            let color_space: ColorSpace = name.parse().unwrap();
+            assert_eq!(color_space.to_string(), name);
+        }
+
+        let bt601 = ColorSpace::new(ColorMatrix::Bt601, ColorRange::Limited);
+        assert_eq!(bt601.matrix_code(), 6);
+        assert!(!bt601.is_full_range());
+        let bt709_full = ColorSpace::new(ColorMatrix::Bt709, ColorRange::Full);
+        assert_eq!(bt709_full.matrix_code(), 1);
+        assert!(bt709_full.is_full_range());
+    }
+
+    #[test]
+    fn test_encoder_kind_parse() {
+        assert_eq!("OpenH264".parse::<EncoderKind>(), Ok(EncoderKind::OpenH264));
+        assert_eq!("x264".parse::<EncoderKind>(), Ok(EncoderKind::X264));
+        assert!("nvenc".parse::<EncoderKind>().is_err());
+        assert!("".parse::<EncoderKind>().is_err());
+
+        for kind in [EncoderKind::OpenH264, EncoderKind::X264] {
+            assert_eq!(kind.to_string().parse::<EncoderKind>(), Ok(kind));
+        }
+    }
+
+    #[test]
+    fn test_h264_profile_parse() {
+        assert_eq!(
+            "baseline".parse::<H264Profile>(),
+            Ok(H264Profile::ConstrainedBaseline)
+        );
+        assert_eq!(
+            "constrained-baseline".parse::<H264Profile>(),
+            Ok(H264Profile::ConstrainedBaseline)
+        );
+        assert_eq!("High".parse::<H264Profile>(), Ok(H264Profile::High));
+        assert!("main".parse::<H264Profile>().is_err());
+
+        for profile in [H264Profile::ConstrainedBaseline, H264Profile::High] {
+            assert_eq!(profile.to_string().parse::<H264Profile>(), Ok(profile));
+        }
+    }
+
+    #[test]
+    fn test_encoder_settings_validate() {
+        assert!(EncoderSettings::default().validate().is_ok());
+
+        let fixed_qp = EncoderSettings {
+            min_qp: 26,
+            max_qp: 26,
+            ..Default::default()
+        };
+        assert!(fixed_qp.validate().is_ok());
+
+        let inverted = EncoderSettings {
+            min_qp: 30,
+            max_qp: 20,
+            ..Default::default()
+        };
+        assert!(inverted.validate().is_err());
+
+        let out_of_range = EncoderSettings {
+            max_qp: MAX_QP + 1,
+            ..Default::default()
+        };
+        assert!(out_of_range.validate().is_err());
+    }
+
+    #[test]
+    fn test_rendition_parse() {
+        assert_eq!("2:1500".parse::<Rendition>(), Ok(Rendition::new(2, 1500)));
+        assert_eq!(" 3 : 800 ".parse::<Rendition>(), Ok(Rendition::new(3, 800)));
+        assert_eq!(Rendition::new(2, 1500).to_string(), "2:1500");
+        for invalid in ["2", "2:", ":1500", "0:1500", "2:0", "a:1500", "2:1500:3"] {
+            assert!(invalid.parse::<Rendition>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_rendition_size() {
+        assert_eq!(Rendition::new(1, 8000).size(1920, 1080), (1920, 1080));
+        assert_eq!(Rendition::new(3, 1000).size(1920, 1080), (640, 360));
+        // 1366 / 4 = 341.5 and 768 / 4 = 192: rounded down to even
+        assert_eq!(Rendition::new(4, 500).size(1366, 768), (340, 192));
+        // Never smaller than the 2x2 a 4:2:0 frame needs
+        assert_eq!(Rendition::new(100, 100).size(64, 64), (2, 2));
+        // A scale of 0 is treated as full size
+        assert_eq!(Rendition::new(0, 100).size(1921, 1081), (1920, 1080));
+    }
+
+    #[test]
+    fn test_all_renditions() {
+        let config = Config::new().with_bitrate(8000).with_renditions([
+            Rendition::new(2, 1500),
+            Rendition::new(1, 8000),
+            Rendition::new(2, 1500),
+            Rendition::new(4, 500),
+        ]);
+        // The full-size stream comes first and duplicates are dropped
+        assert_eq!(
+            config.all_renditions(),
+            vec![
+                Rendition::new(1, 8000),
+                Rendition::new(2, 1500),
+                Rendition::new(4, 500),
+            ]
+        );
+        assert_eq!(
+            Config::new().all_renditions(),
+            vec![Rendition::new(1, 8000)]
+        );
+    }
+
+    #[test]
+    fn test_scale_filter_parse() {
+        assert_eq!("area".parse::<ScaleFilter>(), Ok(ScaleFilter::Area));
+        assert_eq!("box".parse::<ScaleFilter>(), Ok(ScaleFilter::Area));
+        assert_eq!("Bilinear".parse::<ScaleFilter>(), Ok(ScaleFilter::Bilinear));
+        assert!("lanczos".parse::<ScaleFilter>().is_err());
+        assert_eq!(ScaleFilter::default(), ScaleFilter::Area);
+    }
+
+    #[test]
+    fn test_stream_size() {
+        let display = Config::new().with_width(1920).with_height(1080);
+        assert_eq!(display.stream_size(), (1920, 1080));
+        // The display size is used as-is, even when odd
+        let odd = Config::new().with_width(1366).with_height(767);
+        assert_eq!(odd.stream_size(), (1366, 767));
+
+        // One dimension keeps the display aspect ratio
+        assert_eq!(
+            display.clone().with_stream_width(1280).stream_size(),
+            (1280, 720)
+        );
+        assert_eq!(
+            display.clone().with_stream_height(720).stream_size(),
+            (1280, 720)
+        );
+        // 1001 x 563.06 is rounded down to even
+        assert_eq!(
+            display.clone().with_stream_width(1001).stream_size(),
+            (1000, 562)
+        );
+
+        let both = display
+            .clone()
+            .with_stream_width(1281)
+            .with_stream_height(801);
+        assert_eq!(both.stream_size(), (1280, 800));
+        assert_eq!(display.with_stream_width(1).stream_size(), (2, 2));
+    }
+}
@@ -2,13 +2,163 @@
 //!
 //! This module provides the common Frame type used by both capture and encoder crates.
 
+use crate::pool::PooledBuffer;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Current time in microseconds since the Unix epoch, the clock frame and
+/// stage timestamps use
+pub fn unix_time_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// When a frame passed each stage on its way to a client
+///
+/// Timestamps are microseconds since the Unix epoch, 0 for stages not
+/// reached yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTimes {
+    /// Capture of the frame began
+    pub capture_started_us: u64,
+    /// Pixels were captured
+    pub captured_us: u64,
+    /// Encoder took the frame from the handoff
+    pub dequeued_us: u64,
+    /// Frame was scaled to the stream size and converted to YUV
+    pub converted_us: u64,
+    /// Encoded frame was muxed into a segment
+    pub encoded_us: u64,
+    /// Segment was written to the client's socket
+    pub sent_us: u64,
+}
+
+impl StageTimes {
+    /// Times for a frame captured at `captured_us`
+    pub fn captured_at(captured_us: u64) -> Self {
+        Self {
+            captured_us,
+            ..Self::default()
+        }
+    }
+
+    /// Time the capture backend took to produce the frame
+    pub fn capture_time(&self) -> Duration {
+        span(self.capture_started_us, self.captured_us)
+    }
+
+    /// Time the frame waited for the encoder
+    pub fn queue_time(&self) -> Duration {
+        span(self.captured_us, self.dequeued_us)
+    }
+
+    /// Time spent scaling the frame and converting it to YUV
+    pub fn convert_time(&self) -> Duration {
+        span(self.dequeued_us, self.converted_us)
+    }
+
+    /// Time spent encoding and muxing
+    pub fn encode_time(&self) -> Duration {
+        span(self.converted_us, self.encoded_us)
+    }
+
+    /// Time from encoding done to the segment being sent
+    pub fn send_time(&self) -> Duration {
+        span(self.encoded_us, self.sent_us)
+    }
+
+    /// Time from capture to the segment being sent
+    pub fn total_time(&self) -> Duration {
+        span(self.captured_us, self.sent_us)
+    }
+}
+
+/// Duration between two stage timestamps, zero if either is missing
+fn span(from_us: u64, to_us: u64) -> Duration {
+    if from_us == 0 || to_us == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_micros(to_us.saturating_sub(from_us))
+}
+
+/// Axis-aligned rectangle in frame pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Create a rectangle from its origin and size
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Create a rectangle from corner coordinates (exclusive `x2`/`y2`)
+    ///
+    /// Negative coordinates are clamped to zero. Returns `None` if the result
+    /// is empty.
+    pub fn from_corners(x1: i32, y1: i32, x2: i32, y2: i32) -> Option<Self> {
+        let (x1, y1) = (x1.max(0) as u32, y1.max(0) as u32);
+        let (x2, y2) = (x2.max(0) as u32, y2.max(0) as u32);
+        (x2 > x1 && y2 > y1).then(|| Self::new(x1, y1, x2 - x1, y2 - y1))
+    }
+
+    /// Exclusive right edge
+    pub fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    /// Exclusive bottom edge
+    pub fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Number of pixels covered
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// Check if the rectangle covers no pixels
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Clip to a `width` x `height` frame, returning `None` if nothing remains
+    pub fn clip(&self, width: u32, height: u32) -> Option<Self> {
+        let right = self.right().min(width);
+        let bottom = self.bottom().min(height);
+        (right > self.x && bottom > self.y)
+            .then(|| Self::new(self.x, self.y, right - self.x, bottom - self.y))
+    }
+
+    /// Smallest rectangle containing both
+    pub fn union(&self, other: &Rect) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+}
 
 /// Represents a captured frame from the screen
 #[derive(Clone)]
 pub struct Frame {
     /// Raw pixel data in BGRA format
-    data: Arc<Vec<u8>>,
+    data: Arc<PooledBuffer>,
     /// Frame width
     pub width: u32,
     /// Frame height
@@ -17,15 +167,26 @@ pub struct Frame {
     pub sequence: u64,
     /// Timestamp in microseconds
     pub timestamp_us: u64,
+    /// When the capture producing this frame began, 0 if unknown
+    pub capture_started_us: u64,
+    /// Regions that changed since the previous frame
+    ///
+    /// `None` means unknown (treat the whole frame as changed); an empty list
+    /// means nothing changed.
+    pub damage: Option<Vec<Rect>>,
 }
 
 impl Frame {
     /// Create a new frame from BGRA pixel data
     pub fn new(data: Vec<u8>, width: u32, height: u32, sequence: u64) -> Self {
-        let timestamp_us = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_micros() as u64)
-            .unwrap_or(0);
+        Self::from_buffer(PooledBuffer::unpooled(data), width, height, sequence)
+    }
+
+    /// Create a new frame from a pooled buffer
+    ///
+    /// The buffer returns to its pool once every clone of the frame is dropped.
+    pub fn from_buffer(data: PooledBuffer, width: u32, height: u32, sequence: u64) -> Self {
+        let timestamp_us = unix_time_us();
 
         Self {
             data: Arc::new(data),
@@ -33,6 +194,56 @@ impl Frame {
             height,
             sequence,
             timestamp_us,
+            capture_started_us: 0,
+            damage: None,
+        }
+    }
+
+    /// Attach damage information, clipping rectangles to the frame
+    pub fn with_damage(mut self, damage: impl IntoIterator<Item = Rect>) -> Self {
+        let (width, height) = (self.width, self.height);
+        self.damage = Some(
+            damage
+                .into_iter()
+                .filter_map(|r| r.clip(width, height))
+                .collect(),
+        );
+        self
+    }
+
+    /// Stage timestamps up to and including capture
+    pub fn stage_times(&self) -> StageTimes {
+        StageTimes {
+            capture_started_us: self.capture_started_us,
+            ..StageTimes::captured_at(self.timestamp_us)
+        }
+    }
+
+    /// Damaged regions, if the capture backend reported them
+    pub fn damage(&self) -> Option<&[Rect]> {
+        self.damage.as_deref()
+    }
+
+    /// Check if the capture backend reported no changes since the last frame
+    pub fn is_unchanged(&self) -> bool {
+        self.damage.as_ref().is_some_and(|d| d.is_empty())
+    }
+
+    /// Bounding box of all damage
+    ///
+    /// The whole frame when damage is unknown, `None` when nothing changed.
+    pub fn damage_bounds(&self) -> Option<Rect> {
+        match self.damage {
+            None => Some(Rect::new(0, 0, self.width, self.height)),
+            Some(ref rects) => rects.iter().copied().reduce(|a, b| a.union(&b)),
+        }
+    }
+
+    /// Number of damaged pixels (overlapping rectangles count twice)
+    pub fn damaged_area(&self) -> u64 {
+        match self.damage {
+            None => self.width as u64 * self.height as u64,
+            Some(ref rects) => rects.iter().map(Rect::area).sum(),
         }
     }
 
@@ -65,6 +276,94 @@ impl std::fmt::Debug for Frame {
             .field("height", &self.height)
             .field("sequence", &self.sequence)
             .field("size", &self.data.len())
+            .field("damage", &self.damage.as_ref().map(|d| d.len()))
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32) -> Frame {
+        Frame::new(vec![0; (width * height * 4) as usize], width, height, 0)
+    }
+
+    #[test]
+    fn test_rect_from_corners() {
+        assert_eq!(
+            Rect::from_corners(10, 20, 30, 25),
+            Some(Rect::new(10, 20, 20, 5))
+        );
+        assert_eq!(
+            Rect::from_corners(-5, -5, 5, 5),
+            Some(Rect::new(0, 0, 5, 5))
+        );
+        assert_eq!(Rect::from_corners(10, 10, 10, 20), None);
+        assert_eq!(Rect::from_corners(10, 10, 5, 20), None);
+    }
+
+    #[test]
+    fn test_rect_clip_and_union() {
+        let r = Rect::new(90, 50, 20, 20);
+        assert_eq!(r.clip(100, 60), Some(Rect::new(90, 50, 10, 10)));
+        assert_eq!(r.clip(80, 60), None);
+        assert_eq!(
+            Rect::new(0, 0, 10, 10).union(&Rect::new(20, 5, 5, 10)),
+            Rect::new(0, 0, 25, 15)
+        );
+    }
+
+    #[test]
+    fn test_unknown_damage_is_full_frame() {
+        let f = frame(64, 32);
+        assert!(f.damage().is_none());
+        assert!(!f.is_unchanged());
+        assert_eq!(f.damage_bounds(), Some(Rect::new(0, 0, 64, 32)));
+        assert_eq!(f.damaged_area(), 64 * 32);
+    }
+
+    #[test]
+    fn test_damage() {
+        let f = frame(64, 32).with_damage([
+            Rect::new(0, 0, 8, 8),
+            Rect::new(60, 30, 8, 8),
+            Rect::new(100, 100, 8, 8),
+        ]);
+        assert_eq!(
+            f.damage(),
+            Some(&[Rect::new(0, 0, 8, 8), Rect::new(60, 30, 4, 2)][..])
+        );
+        assert_eq!(f.damage_bounds(), Some(Rect::new(0, 0, 64, 32)));
+        assert_eq!(f.damaged_area(), 64 + 8);
+
+        let unchanged = frame(64, 32).with_damage([]);
+        assert!(unchanged.is_unchanged());
+        assert_eq!(unchanged.damage_bounds(), None);
+        assert_eq!(unchanged.damaged_area(), 0);
+    }
+
+    #[test]
+    fn test_stage_times() {
+        let mut times = StageTimes::captured_at(1_000);
+        times.dequeued_us = 1_500;
+        times.converted_us = 3_000;
+        assert_eq!(times.queue_time(), Duration::from_micros(500));
+        assert_eq!(times.convert_time(), Duration::from_micros(1_500));
+        // Stages not reached yet count as zero
+        assert_eq!(times.encode_time(), Duration::ZERO);
+        assert_eq!(times.total_time(), Duration::ZERO);
+
+        times.encoded_us = 8_000;
+        times.sent_us = 9_500;
+        assert_eq!(times.encode_time(), Duration::from_millis(5));
+        assert_eq!(times.send_time(), Duration::from_micros(1_500));
+        assert_eq!(times.total_time(), Duration::from_micros(8_500));
+
+        // Capture time is only known when the capture loop stamped its start
+        let mut f = frame(4, 4);
+        assert_eq!(f.stage_times().capture_time(), Duration::ZERO);
+        f.capture_started_us = f.timestamp_us - 4_000;
+        assert_eq!(f.stage_times().capture_time(), Duration::from_millis(4));
+    }
+}
@@ -0,0 +1,204 @@
+//! Latest-frame-wins handoff between pipeline stages
+//!
+//! A bounded channel between capture and encoder queues frames whenever the
+//! encoder falls behind, and every queued frame adds a frame interval of
+//! latency. The handoff holds a single frame instead: sending never waits,
+//! and a frame the receiver hasn't taken yet is replaced by the newer one.
+//! Damage of a replaced frame carries over, so changes it contained still
+//! get encoded.
+
+use crate::frame::{Frame, Rect};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+
+/// Damage rects carried over before they collapse into their bounding rect,
+/// so a receiver that keeps falling behind doesn't accumulate them unbounded
+const MAX_DAMAGE_RECTS: usize = 16;
+
+/// Create a connected sender and receiver
+pub fn frame_handoff() -> (FrameSender, FrameReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::default()),
+        ready: Notify::new(),
+    });
+    (
+        FrameSender {
+            shared: shared.clone(),
+        },
+        FrameReceiver { shared },
+    )
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a frame is stored or the sender goes away
+    ready: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    frame: Option<Frame>,
+    sender_closed: bool,
+    receiver_closed: bool,
+    /// Frames replaced before the receiver took them
+    replaced: u64,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Sending half of a frame handoff
+pub struct FrameSender {
+    shared: Arc<Shared>,
+}
+
+impl FrameSender {
+    /// Hand a frame over, replacing one the receiver hasn't taken yet
+    ///
+    /// Never waits. Returns the frame if the receiver has been dropped.
+    pub fn send(&self, mut frame: Frame) -> Result<(), Frame> {
+        let mut state = self.shared.state();
+        if state.receiver_closed {
+            return Err(frame);
+        }
+        if let Some(stale) = state.frame.take() {
+            frame.damage = merge_damage(stale.damage, frame.damage.take());
+            state.replaced += 1;
+        }
+        state.frame = Some(frame);
+        drop(state);
+
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+
+    /// Whether the receiver has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.shared.state().receiver_closed
+    }
+}
+
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        self.shared.state().sender_closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Receiving half of a frame handoff
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+
+impl FrameReceiver {
+    /// Wait for the newest frame
+    ///
+    /// Returns `None` once the sender is dropped and no frame is left.
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            {
+                let mut state = self.shared.state();
+                if let Some(frame) = state.frame.take() {
+                    return Some(frame);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            // A notification sent since the check is kept as a permit
+            self.shared.ready.notified().await;
+        }
+    }
+
+    /// Number of frames replaced by newer ones before they were received
+    pub fn replaced(&self) -> u64 {
+        self.shared.state().replaced
+    }
+}
+
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state();
+        state.receiver_closed = true;
+        state.frame = None;
+    }
+}
+
+/// Damage covering two consecutive frames (unknown if either is unknown)
+fn merge_damage(older: Option<Vec<Rect>>, newer: Option<Vec<Rect>>) -> Option<Vec<Rect>> {
+    let (mut older, newer) = (older?, newer?);
+    older.extend(newer);
+    if older.len() > MAX_DAMAGE_RECTS {
+        let bounds = older.iter().copied().reduce(|a, b| a.union(&b));
+        older = bounds.into_iter().collect();
+    }
+    Some(older)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(sequence: u64, damage: Option<Rect>) -> Frame {
+        let frame = Frame::new(vec![0; 4 * 4 * 4], 4, 4, sequence);
+        match damage {
+            Some(rect) => frame.with_damage([rect]),
+            None => frame,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_latest_frame_wins() {
+        let (tx, mut rx) = frame_handoff();
+        tx.send(frame(1, Some(Rect::new(0, 0, 1, 1)))).unwrap();
+        tx.send(frame(2, Some(Rect::new(2, 2, 1, 1)))).unwrap();
+        tx.send(frame(3, Some(Rect::new(3, 3, 1, 1)))).unwrap();
+
+        let latest = rx.recv().await.unwrap();
+        assert_eq!(latest.sequence, 3);
+        assert_eq!(rx.replaced(), 2);
+        // Changes in the skipped frames are still reported
+        assert_eq!(latest.damage().unwrap().len(), 3);
+
+        // Unknown damage stays unknown
+        tx.send(frame(4, None)).unwrap();
+        tx.send(frame(5, Some(Rect::new(0, 0, 1, 1)))).unwrap();
+        assert!(rx.recv().await.unwrap().damage().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_damage_collapses_past_cap() {
+        let (tx, mut rx) = frame_handoff();
+        let n = MAX_DAMAGE_RECTS as u32 + 1;
+        for i in 0..n {
+            let frame = Frame::new(vec![0; 32 * 32 * 4], 32, 32, i as u64);
+            tx.send(frame.with_damage([Rect::new(i, i, 1, 1)])).unwrap();
+        }
+        // Too many rects to keep apart: the bounding rect covers them all
+        let latest = rx.recv().await.unwrap();
+        assert_eq!(latest.damage().unwrap(), &[Rect::new(0, 0, n, n)]);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (tx, mut rx) = frame_handoff();
+        let waiter = tokio::spawn(async move {
+            let first = rx.recv().await.map(|f| f.sequence);
+            (first, rx.recv().await.is_none())
+        });
+        tx.send(frame(1, None)).unwrap();
+        tokio::task::yield_now().await;
+        drop(tx);
+        let (first, closed) = waiter.await.unwrap();
+        assert_eq!(first, Some(1));
+        assert!(closed);
+
+        let (tx, rx) = frame_handoff();
+        drop(rx);
+        assert!(tx.is_closed());
+        assert!(tx.send(frame(1, None)).is_err());
+    }
+}
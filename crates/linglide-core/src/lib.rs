@@ -5,9 +5,16 @@
 pub mod config;
 pub mod error;
 pub mod frame;
+pub mod handoff;
+pub mod pool;
 pub mod protocol;
 
-pub use config::{Config, DisplayPosition};
+pub use config::{
+    CaptureSource, ColorMatrix, ColorRange, ColorSpace, Config, DisplayPosition, EncoderKind,
+    EncoderSettings, H264Profile, Rendition, ScaleFilter, VideoCodec, MAX_QP,
+};
 pub use error::{Error, Result};
-pub use frame::Frame;
+pub use frame::{unix_time_us, Frame, Rect, StageTimes};
+pub use handoff::{frame_handoff, FrameReceiver, FrameSender};
+pub use pool::{FramePool, PooledBuffer};
 pub use protocol::InputEvent;
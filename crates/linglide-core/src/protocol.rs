@@ -1,5 +1,6 @@
 //! WebSocket protocol message types
 
+use crate::config::{ColorMatrix, ColorSpace};
 use serde::{Deserialize, Serialize};
 
 /// Pen/stylus button types
@@ -94,6 +95,11 @@ pub enum InputEvent {
         /// Modifier keys
         modifiers: Modifiers,
     },
+    /// Composed text from an on-screen keyboard or IME
+    TextInput {
+        /// UTF-8 text to type
+        text: String,
+    },
     /// Stylus/pen hovering (not touching surface)
     PenHover {
         /// X coordinate (normalized 0.0-1.0)
@@ -151,6 +157,26 @@ pub enum InputEvent {
     },
 }
 
+/// Identifier assigned to each input connection by the server
+pub type ClientId = u64;
+
+/// Message from the input WebSocket handlers to the input dispatcher
+#[derive(Debug, Clone)]
+pub enum InputCommand {
+    /// Input event from a client
+    Event {
+        /// Connection the event came from
+        client_id: ClientId,
+        /// The event itself
+        event: InputEvent,
+    },
+    /// Client disconnected; anything it still holds must be released
+    Disconnect {
+        /// Connection that went away
+        client_id: ClientId,
+    },
+}
+
 /// Keyboard modifier keys state
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Modifiers {
@@ -166,34 +192,125 @@ pub struct Modifiers {
 pub enum ServerMessage {
     /// Initial configuration sent to client
     Init {
+        /// Encoded stream size
         width: u32,
         height: u32,
+        /// Size of the desktop the stream shows, which can differ from the
+        /// stream; input coordinates stay normalized to it
+        display_width: u32,
+        display_height: u32,
         fps: u32,
-        /// WebCodecs codec string (e.g., "avc1.64002a")
+        /// WebCodecs codec string (e.g., "avc1.64002a", "hvc1.1.6.L123.B0", "av01.0.08M.08")
         #[serde(skip_serializing_if = "Option::is_none")]
         codec: Option<String>,
-        /// Base64-encoded avcC data for decoder configuration
+        /// Base64-encoded decoder configuration record (avcC, hvcC or av1C)
         #[serde(skip_serializing_if = "Option::is_none")]
         codec_data: Option<String>,
+        /// Color space of the stream, passed to the decoder as `colorSpace`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        color_space: Option<VideoColorSpace>,
+        /// Ticks per second of segment decode times (`tfdt`)
+        timescale: u32,
     },
     /// Error message
     Error { message: String },
+    /// Encoder settings after a `SetQuality` request
+    QualityChanged { bitrate: u32, fps: u32 },
     /// Server is ready to stream
     Ready,
     /// Ping for connection keepalive
     Ping { timestamp: u64 },
 }
 
+/// Color space in the form of a WebCodecs `VideoColorSpaceInit`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoColorSpace {
+    pub primaries: String,
+    pub transfer: String,
+    pub matrix: String,
+    pub full_range: bool,
+}
+
+impl From<ColorSpace> for VideoColorSpace {
+    fn from(color_space: ColorSpace) -> Self {
+        let matrix = match color_space.matrix {
+            ColorMatrix::Bt601 => "smpte170m",
+            ColorMatrix::Bt709 => "bt709",
+        };
+        Self {
+            primaries: "bt709".to_string(),
+            transfer: "iec61966-2-1".to_string(),
+            matrix: matrix.to_string(),
+            full_range: color_space.is_full_range(),
+        }
+    }
+}
+
 /// Client-to-server control messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
+    /// First message on the video socket; the server answers with `Init`
+    Hello(ClientHello),
     /// Client is ready to receive video
     Ready,
     /// Pong response to ping
     Pong { timestamp: u64 },
-    /// Request quality change
-    SetQuality { bitrate: u32 },
+    /// Request quality change (bitrate in kbps, optional lower frame rate)
+    SetQuality {
+        bitrate: u32,
+        #[serde(default)]
+        fps: Option<u32>,
+    },
+    /// Ask for a keyframe, e.g. after a decoder error
+    RequestKeyframe,
+    /// Playback statistics, sent periodically by the video client
+    Stats(ClientStats),
+}
+
+/// What a video client can decode and display
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientHello {
+    /// Decodable codecs, as WebCodecs codec strings or sample entry types
+    /// (`av01`, `hvc1`, `avc1`)
+    pub codecs: Vec<String>,
+    /// Largest picture the client can show, in CSS pixels (usually its screen)
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    /// Device pixels per CSS pixel
+    #[serde(default)]
+    pub device_pixel_ratio: Option<f64>,
+    /// Preferred frame rate
+    #[serde(default)]
+    pub fps: Option<u32>,
+}
+
+/// How playback is going on a video client
+///
+/// Frame counts cover the time since the client's previous report; the other
+/// fields are the client's current view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientStats {
+    /// Frames decoded and shown
+    #[serde(default)]
+    pub decoded_frames: u64,
+    /// Frames received but never shown (skipped while waiting for a
+    /// keyframe, failed to decode or discarded with a reset decoder)
+    #[serde(default)]
+    pub dropped_frames: u64,
+    /// Average time from handing a frame to the decoder to getting it back,
+    /// in milliseconds
+    #[serde(default)]
+    pub decode_latency_ms: Option<f64>,
+    /// Frames waiting in the decoder or for display
+    #[serde(default)]
+    pub jitter_buffer_depth: Option<u32>,
+    /// Display refresh rate in Hz
+    #[serde(default)]
+    pub refresh_rate: Option<f64>,
 }
 
 /// Frame metadata for video synchronization
@@ -206,3 +323,189 @@ pub struct FrameMetadata {
     /// Whether this is a keyframe
     pub is_keyframe: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ColorRange;
+    use serde_json::{json, Value};
+
+    fn client_message(value: Value) -> ClientMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_hello_from_viewer() {
+        let message = client_message(json!({
+            "type": "Hello",
+            "codecs": ["av01", "avc1.64002a"],
+            "max_width": 1920,
+            "max_height": 1080,
+            "device_pixel_ratio": 2.5,
+            "fps": 60
+        }));
+        let ClientMessage::Hello(hello) = message else {
+            panic!("expected Hello, got {:?}", message);
+        };
+        assert_eq!(hello.codecs, vec!["av01", "avc1.64002a"]);
+        assert_eq!(hello.max_width, Some(1920));
+        assert_eq!(hello.max_height, Some(1080));
+        assert_eq!(hello.device_pixel_ratio, Some(2.5));
+        assert_eq!(hello.fps, Some(60));
+    }
+
+    #[test]
+    fn test_hello_without_optional_fields() {
+        // The legacy viewer sends no frame rate; JSON.stringify drops undefined
+        let message = client_message(json!({ "type": "Hello", "codecs": ["avc1"] }));
+        let ClientMessage::Hello(hello) = message else {
+            panic!("expected Hello, got {:?}", message);
+        };
+        assert_eq!(
+            hello,
+            ClientHello {
+                codecs: vec!["avc1".to_string()],
+                ..Default::default()
+            }
+        );
+
+        let message = client_message(json!({ "type": "Hello", "codecs": [], "fps": null }));
+        assert!(matches!(
+            message,
+            ClientMessage::Hello(ClientHello { fps: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_stats_from_viewer() {
+        let message = client_message(json!({
+            "type": "Stats",
+            "decoded_frames": 58,
+            "dropped_frames": 2,
+            "decode_latency_ms": 3.25,
+            "jitter_buffer_depth": 1,
+            "refresh_rate": 59.94
+        }));
+        let ClientMessage::Stats(stats) = message else {
+            panic!("expected Stats, got {:?}", message);
+        };
+        assert_eq!(
+            stats,
+            ClientStats {
+                decoded_frames: 58,
+                dropped_frames: 2,
+                decode_latency_ms: Some(3.25),
+                jitter_buffer_depth: Some(1),
+                refresh_rate: Some(59.94),
+            }
+        );
+
+        // Nothing decoded yet and no refresh rate measured
+        let message = client_message(json!({
+            "type": "Stats",
+            "decoded_frames": 0,
+            "dropped_frames": 0,
+            "decode_latency_ms": null,
+            "jitter_buffer_depth": 0,
+            "refresh_rate": null
+        }));
+        let ClientMessage::Stats(stats) = message else {
+            panic!("expected Stats, got {:?}", message);
+        };
+        assert_eq!(stats.decode_latency_ms, None);
+        assert_eq!(stats.refresh_rate, None);
+    }
+
+    #[test]
+    fn test_control_requests_from_viewer() {
+        assert!(matches!(
+            client_message(json!({ "type": "RequestKeyframe" })),
+            ClientMessage::RequestKeyframe
+        ));
+        assert!(matches!(
+            client_message(json!({ "type": "SetQuality", "bitrate": 4000, "fps": 30 })),
+            ClientMessage::SetQuality {
+                bitrate: 4000,
+                fps: Some(30)
+            }
+        ));
+        assert!(matches!(
+            client_message(json!({ "type": "SetQuality", "bitrate": 2000 })),
+            ClientMessage::SetQuality {
+                bitrate: 2000,
+                fps: None
+            }
+        ));
+        assert!(matches!(
+            client_message(json!({ "type": "Pong", "timestamp": 1234 })),
+            ClientMessage::Pong { timestamp: 1234 }
+        ));
+    }
+
+    #[test]
+    fn test_init_field_names() {
+        let message = ServerMessage::Init {
+            width: 1280,
+            height: 720,
+            display_width: 2560,
+            display_height: 1440,
+            fps: 60,
+            codec: Some("avc1.64002a".to_string()),
+            codec_data: Some("AQIDBA==".to_string()),
+            color_space: Some(ColorSpace::new(ColorMatrix::Bt601, ColorRange::Full).into()),
+            timescale: 90_000,
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "type": "Init",
+                "width": 1280,
+                "height": 720,
+                "display_width": 2560,
+                "display_height": 1440,
+                "fps": 60,
+                "codec": "avc1.64002a",
+                "codec_data": "AQIDBA==",
+                "color_space": {
+                    "primaries": "bt709",
+                    "transfer": "iec61966-2-1",
+                    "matrix": "smpte170m",
+                    "fullRange": true
+                },
+                "timescale": 90000
+            })
+        );
+    }
+
+    #[test]
+    fn test_init_omits_unknown_codec() {
+        let message = ServerMessage::Init {
+            width: 1920,
+            height: 1080,
+            display_width: 1920,
+            display_height: 1080,
+            fps: 30,
+            codec: None,
+            codec_data: None,
+            color_space: None,
+            timescale: 90_000,
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        let fields = value.as_object().unwrap();
+        assert!(!fields.contains_key("codec"));
+        assert!(!fields.contains_key("codec_data"));
+        assert!(!fields.contains_key("color_space"));
+    }
+
+    #[test]
+    fn test_quality_changed_field_names() {
+        let message = ServerMessage::QualityChanged {
+            bitrate: 6000,
+            fps: 45,
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({ "type": "QualityChanged", "bitrate": 6000, "fps": 45 })
+        );
+    }
+}
@@ -101,6 +101,7 @@ impl LinGlideApp {
                 self.server_status.url = None;
                 self.server_status.pin = None;
                 self.server_status.connected_devices.clear();
+                self.server_status.metrics = None;
                 self.pairing_state = PairingState::default();
             }
             UiEvent::ServerError { message } => {
@@ -149,6 +150,9 @@ impl LinGlideApp {
                 self.server_status.usb_active = connected;
                 self.server_status.usb_device_count = device_count;
             }
+            UiEvent::Metrics { snapshot } => {
+                self.server_status.metrics = Some(snapshot);
+            }
         }
     }
 
@@ -4,6 +4,8 @@
 //! and the tokio async runtime running the server.
 
 use linglide_auth::device::Device;
+use linglide_core::EncoderSettings;
+use linglide_server::MetricsSnapshot;
 use std::sync::Arc;
 use tokio::sync::{broadcast, mpsc, RwLock};
 
@@ -45,6 +47,8 @@ pub enum UiEvent {
         connected: bool,
         device_count: usize,
     },
+    /// Periodic pipeline and client metrics
+    Metrics { snapshot: MetricsSnapshot },
 }
 
 /// Commands from the UI to the server/async side
@@ -65,6 +69,8 @@ pub enum UiCommand {
     SetMdns { enabled: bool },
     /// Enable/disable USB/ADB forwarding
     SetUsb { enabled: bool },
+    /// Change encoder tuning (applied on next server start)
+    SetEncoderSettings { settings: EncoderSettings },
     /// Refresh the persistent PIN
     RefreshPin,
     /// Shutdown the application
@@ -82,6 +88,8 @@ pub struct ServerStatus {
     pub mdns_active: bool,
     pub usb_active: bool,
     pub usb_device_count: usize,
+    /// Latest metrics while the server is running
+    pub metrics: Option<MetricsSnapshot>,
 }
 
 /// Current pairing session state
@@ -5,21 +5,20 @@
 use crate::bridge::{AsyncBridge, UiCommand, UiEvent};
 use anyhow::Result;
 use linglide_auth::{DeviceStorage, PairingManager};
-use linglide_capture::{Frame, ScreenCapture, VirtualDisplay};
-use linglide_core::{Config, DisplayPosition};
-use linglide_discovery::ServiceAdvertiser;
-use linglide_encoder::pipeline::StreamSegment;
-use linglide_encoder::EncodingPipeline;
-use linglide_input::{mouse::RelativeMouse, VirtualMouse, VirtualStylus, VirtualTouchscreen};
-use linglide_server::{
-    broadcast::AppState, create_router, create_rustls_config, CertificateManager,
-};
-use std::net::IpAddr;
+use linglide_capture::run_capture;
+use linglide_core::{Config, DisplayPosition, EncoderSettings};
+use linglide_input::InputDispatcher;
+use linglide_server::session::local_ip;
+use linglide_server::{CertificateManager, Session, SessionEvent, TlsMode};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot, RwLock};
 use tracing::{info, warn};
 
+/// How often pipeline metrics are pushed to the UI
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Server configuration
 #[derive(Clone)]
 #[allow(dead_code)]
@@ -29,6 +28,7 @@ pub struct ServerConfig {
     pub fps: u32,
     pub port: u16,
     pub bitrate: u32,
+    pub encoder_settings: EncoderSettings,
     pub mirror_mode: bool,
     pub position: DisplayPosition,
     pub enable_mdns: bool,
@@ -43,6 +43,7 @@ impl Default for ServerConfig {
             fps: 60,
             port: 8443,
             bitrate: 8000,
+            encoder_settings: EncoderSettings::default(),
             mirror_mode: false,
             position: DisplayPosition::RightOf,
             enable_mdns: true,
@@ -111,6 +112,9 @@ impl ServerController {
                 UiCommand::SetUsb { enabled: _ } => {
                     // Would need to restart server to change USB
                 }
+                UiCommand::SetEncoderSettings { settings } => {
+                    self.config.encoder_settings = settings;
+                }
                 UiCommand::RefreshPin => {
                     self.refresh_pin().await;
                 }
@@ -134,7 +138,7 @@ impl ServerController {
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
         // Get local IP and server URL
-        let local_ip = get_local_ip().unwrap_or_else(|| "localhost".to_string());
+        let local_ip = local_ip().unwrap_or_else(|| "localhost".to_string());
         let server_url = format!("https://{}:{}", local_ip, config.port);
 
         // Setup TLS and get certificate fingerprint
@@ -193,7 +197,6 @@ impl ServerController {
         // Spawn server task with pre-created resources
         let pm_clone = pairing_manager.clone();
         let ds_clone = device_storage.clone();
-        let fp_clone = fingerprint.clone();
         let devices_clone = paired_devices.clone();
         let persistent_pin = pairing_manager.get_persistent_pin().await;
         tokio::spawn(async move {
@@ -205,8 +208,6 @@ impl ServerController {
                 ds_clone,
                 cert_pem,
                 key_pem,
-                fp_clone,
-                local_ip,
                 devices_clone,
                 persistent_pin,
             )
@@ -268,16 +269,7 @@ impl ServerController {
     }
 }
 
-/// Get the local IP address
-fn get_local_ip() -> Option<String> {
-    use std::net::UdpSocket;
-    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
-    socket.connect("8.8.8.8:80").ok()?;
-    let addr = socket.local_addr().ok()?;
-    Some(addr.ip().to_string())
-}
-
-/// Run the actual server (based on main.rs logic)
+/// Run the server session until it stops or shutdown is requested
 #[allow(clippy::too_many_arguments)]
 async fn run_server(
     config: ServerConfig,
@@ -287,8 +279,6 @@ async fn run_server(
     _device_storage: Arc<DeviceStorage>,
     cert_pem: String,
     key_pem: String,
-    fingerprint: String,
-    local_ip: String,
     paired_devices: Vec<linglide_auth::device::Device>,
     persistent_pin: String,
 ) -> Result<()> {
@@ -299,313 +289,70 @@ async fn run_server(
         .with_port(config.port)
         .with_position(config.position)
         .with_bitrate(config.bitrate)
+        .with_encoder_settings(config.encoder_settings)
         .with_mirror_mode(config.mirror_mode);
 
-    let use_evdi = !config.mirror_mode;
     let (offset_x, offset_y) = (0_i32, 0_i32);
 
-    // Create channels
-    let (frame_tx, frame_rx) = mpsc::channel::<Frame>(2);
-    let (segment_tx, _segment_rx) = broadcast::channel::<StreamSegment>(16);
-    let (input_tx, mut input_rx) = mpsc::channel(64);
-
     // Create input devices
-    info!("Creating virtual input devices...");
-    let mut touchscreen = VirtualTouchscreen::new(config.width, config.height, offset_x, offset_y)?;
-    let mut mouse = VirtualMouse::new(config.width, config.height, offset_x, offset_y)?;
-    let mut scroll_mouse = RelativeMouse::new()?;
-    let mut stylus = VirtualStylus::new(config.width, config.height, offset_x, offset_y)?;
-
-    // Create TLS config from provided certs
-    let tls_config = create_rustls_config(&cert_pem, &key_pem)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to create TLS config: {}", e))?;
-
-    let server_url = format!("https://{}:{}", local_ip, config.port);
-
-    // Create app state
-    let state = Arc::new(AppState::new(
-        core_config.clone(),
-        segment_tx.clone(),
-        input_tx,
-        pairing_manager.clone(),
-        true, // auth_required
-        Some(fingerprint.clone()),
-    ));
-
-    // Create router
-    let router = create_router(state.clone());
-
-    // Check if port is available before proceeding
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], config.port));
-    match std::net::TcpListener::bind(addr) {
-        Ok(listener) => drop(listener), // Port is free, release it
-        Err(e) => {
-            return Err(anyhow::anyhow!(
-                "Port {} is already in use: {}",
-                config.port,
-                e
-            ));
-        }
-    }
-
-    // Send server started event (port is available)
-    let _ = event_tx.send(UiEvent::ServerStarted {
-        url: server_url.clone(),
-        fingerprint: fingerprint.clone(),
-        paired_devices,
-        pin: persistent_pin,
-    });
-
-    // Start mDNS if enabled
-    let mut mdns_advertiser: Option<ServiceAdvertiser> = None;
-    if config.enable_mdns {
-        match ServiceAdvertiser::new(config.port, None) {
-            Ok(mut advertiser) => {
-                let addresses: Vec<IpAddr> = get_local_ip()
-                    .and_then(|ip| ip.parse().ok())
-                    .into_iter()
-                    .collect();
-
-                if advertiser
-                    .start(
-                        env!("CARGO_PKG_VERSION"),
-                        Some(&fingerprint),
-                        Some(addresses),
-                    )
-                    .is_ok()
-                {
-                    info!("mDNS: Advertising as '{}'", advertiser.instance_name());
-                    let _ = event_tx.send(UiEvent::MdnsStatus { active: true });
-                    mdns_advertiser = Some(advertiser);
-                }
-            }
-            Err(e) => warn!("mDNS: Failed to create advertiser: {}", e),
-        }
-    }
-
-    // Spawn capture task
-    let frame_duration = Duration::from_micros(1_000_000 / config.fps as u64);
-    let capture_config = core_config.clone();
-
-    let capture_handle = if use_evdi {
-        let frame_tx = frame_tx.clone();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create capture runtime");
-
-            rt.block_on(async move {
-                info!("Creating EVDI virtual display...");
-                let mut vd = match VirtualDisplay::new(capture_config) {
-                    Ok(vd) => vd,
-                    Err(e) => {
-                        warn!("Failed to create virtual display: {}", e);
-                        return;
-                    }
-                };
-
-                if let Err(e) = vd.enable() {
-                    warn!("Failed to enable virtual display: {}", e);
-                    return;
-                }
-
-                if let Err(e) = vd.init_buffer().await {
-                    warn!("Failed to initialize buffer: {}", e);
-                    return;
-                }
-
-                info!("EVDI virtual display ready");
-
-                loop {
-                    let start = std::time::Instant::now();
-                    match vd.capture_async().await {
-                        Ok(frame) => {
-                            if frame_tx.send(frame).await.is_err() {
-                                break;
-                            }
-                        }
-                        Err(e) => warn!("EVDI capture error: {}", e),
-                    }
-                    let elapsed = start.elapsed();
-                    if elapsed < frame_duration {
-                        tokio::time::sleep(frame_duration - elapsed).await;
-                    }
-                }
-
-                let _ = vd.disable();
-            });
-        });
-
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(Duration::from_secs(3600)).await;
-            }
+    let dispatcher = InputDispatcher::new(config.width, config.height, offset_x, offset_y)?;
+
+    let mut session = Session::builder(core_config)
+        .capture(run_capture)
+        .input(dispatcher)
+        .tls(TlsMode::Pem {
+            cert: cert_pem,
+            key: key_pem,
         })
-    } else {
-        let frame_tx = frame_tx.clone();
-        let mut capture = ScreenCapture::new(capture_config.width, capture_config.height, 0, 0)?;
-
-        tokio::spawn(async move {
-            loop {
-                let start = std::time::Instant::now();
-                match capture.capture() {
-                    Ok(frame) => {
-                        if frame_tx.send(frame).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(e) => warn!("Capture error: {}", e),
+        .pairing_manager(pairing_manager)
+        .mdns(config.enable_mdns)
+        .usb(config.enable_usb)
+        .shutdown_timeout(Duration::from_secs(2))
+        .build();
+
+    let mut session_events = session.subscribe();
+    session.start().await?;
+
+    let stop_handle = session.stop_handle();
+    let mut paired_devices = Some(paired_devices);
+    let state = session.state().cloned();
+    let mut metrics_interval = tokio::time::interval(METRICS_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = session_events.recv() => match event {
+                Ok(SessionEvent::Started { url, fingerprint }) => {
+                    let _ = event_tx.send(UiEvent::ServerStarted {
+                        url,
+                        fingerprint: fingerprint.unwrap_or_default(),
+                        paired_devices: paired_devices.take().unwrap_or_default(),
+                        pin: persistent_pin.clone(),
+                    });
                 }
-                let elapsed = start.elapsed();
-                if elapsed < frame_duration {
-                    tokio::time::sleep(frame_duration - elapsed).await;
+                Ok(SessionEvent::MdnsStatus { active }) => {
+                    let _ = event_tx.send(UiEvent::MdnsStatus { active });
                 }
-            }
-        })
-    };
-
-    // Spawn encoding task
-    let segment_tx_clone = segment_tx.clone();
-    let enc_width = config.width;
-    let enc_height = config.height;
-    let enc_fps = config.fps;
-    let enc_bitrate = config.bitrate;
-
-    let (init_tx, init_rx) = std::sync::mpsc::channel::<(Vec<u8>, String, Vec<u8>)>();
-    let state_clone = state.clone();
-
-    std::thread::spawn(move || {
-        let pipeline = match EncodingPipeline::new(enc_width, enc_height, enc_fps, enc_bitrate) {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("Failed to create encoder: {}", e);
-                return;
-            }
-        };
-
-        if let Some(init_segment) = pipeline.get_init_segment() {
-            let codec_string = pipeline.get_codec_string();
-            let avcc_data = pipeline.get_avcc_data();
-            let _ = init_tx.send((init_segment, codec_string, avcc_data));
-        }
-
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .unwrap();
-
-        rt.block_on(pipeline.run(frame_rx, segment_tx_clone));
-    });
-
-    // Receive init segment
-    if let Ok((init_segment, codec_string, avcc_data)) =
-        init_rx.recv_timeout(std::time::Duration::from_secs(5))
-    {
-        info!(
-            "Received init segment: {} bytes, codec: {}",
-            init_segment.len(),
-            codec_string
-        );
-        state_clone.set_init_segment(init_segment);
-        state_clone.set_codec_config(codec_string, avcc_data);
-    }
-
-    // Keyframe capture task
-    let keyframe_state = state.clone();
-    let mut keyframe_rx = segment_tx.subscribe();
-    tokio::spawn(async move {
-        while let Ok(segment) = keyframe_rx.recv().await {
-            if segment.is_keyframe {
-                keyframe_state.set_keyframe_segment(segment.data);
-            }
-        }
-    });
-
-    // Input handling task
-    let input_handle = tokio::spawn(async move {
-        use linglide_core::protocol::InputEvent;
-
-        while let Some(event) = input_rx.recv().await {
-            let result = match event {
-                InputEvent::TouchStart { id, x, y } => touchscreen.touch_start(id, x, y),
-                InputEvent::TouchMove { id, x, y } => touchscreen.touch_move(id, x, y),
-                InputEvent::TouchEnd { id } => touchscreen.touch_end(id),
-                InputEvent::TouchCancel { id } => touchscreen.touch_cancel(id),
-                InputEvent::MouseDown { button, x, y } => mouse.mouse_down(button, x, y),
-                InputEvent::MouseUp { button, x, y } => mouse.mouse_up(button, x, y),
-                InputEvent::MouseMove { x, y } => mouse.mouse_move(x, y),
-                InputEvent::Scroll { dx, dy } => scroll_mouse.scroll(dx, dy),
-                InputEvent::KeyDown { .. } | InputEvent::KeyUp { .. } => Ok(()),
-                InputEvent::PenHover {
-                    x,
-                    y,
-                    pressure,
-                    tilt_x,
-                    tilt_y,
-                } => stylus.pen_hover(x, y, pressure, tilt_x, tilt_y),
-                InputEvent::PenDown {
-                    x,
-                    y,
-                    pressure,
-                    tilt_x,
-                    tilt_y,
-                    button,
-                } => stylus.pen_down(x, y, pressure, tilt_x, tilt_y, button),
-                InputEvent::PenMove {
-                    x,
-                    y,
-                    pressure,
-                    tilt_x,
-                    tilt_y,
-                } => stylus.pen_move(x, y, pressure, tilt_x, tilt_y),
-                InputEvent::PenUp { x, y } => stylus.pen_up(x, y),
-                InputEvent::PenButtonEvent { button, pressed } => {
-                    stylus.pen_button(button, pressed)
+                Ok(SessionEvent::Error { message }) => {
+                    let _ = event_tx.send(UiEvent::ServerError { message });
+                }
+                Ok(SessionEvent::Stopped) | Err(RecvError::Closed) => break,
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+            },
+            _ = metrics_interval.tick() => {
+                if let Some(state) = &state {
+                    let _ = event_tx.send(UiEvent::Metrics {
+                        snapshot: state.metrics.snapshot(),
+                    });
                 }
-            };
-
-            if let Err(e) = result {
-                warn!("Input error: {}", e);
             }
-        }
-    });
-
-    // Start HTTPS server
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], config.port));
-    let handle = axum_server::Handle::new();
-    let shutdown_handle = handle.clone();
-
-    // Spawn server
-    let server_future = axum_server::bind_rustls(addr, tls_config)
-        .handle(handle)
-        .serve(router.into_make_service());
-
-    info!("Server listening on https://{}:{}", local_ip, config.port);
-
-    // Wait for shutdown or server completion
-    tokio::select! {
-        result = server_future => {
-            if let Err(e) = result {
-                warn!("Server error: {}", e);
+            _ = &mut shutdown_rx => {
+                info!("Shutdown signal received");
+                stop_handle.stop();
+                break;
             }
         }
-        _ = &mut shutdown_rx => {
-            info!("Shutdown signal received");
-            shutdown_handle.graceful_shutdown(Some(Duration::from_secs(2)));
-        }
-    }
-
-    // Cleanup
-    capture_handle.abort();
-    input_handle.abort();
-
-    if let Some(mut advertiser) = mdns_advertiser {
-        let _ = advertiser.stop();
-        let _ = event_tx.send(UiEvent::MdnsStatus { active: false });
     }
 
-    info!("Server stopped");
+    session.wait().await?;
     Ok(())
 }
@@ -14,6 +14,8 @@ use crate::components::{
 use crate::theme::{colors, rounding, spacing, typography};
 use egui::{RichText, TextureHandle, Vec2};
 use linglide_auth::device::Device;
+use linglide_core::{EncoderSettings, H264Profile, MAX_QP};
+use linglide_server::MetricsSnapshot;
 use tokio::sync::mpsc;
 
 /// Tab selection for the main window
@@ -33,6 +35,7 @@ pub struct Settings {
     pub height: u32,
     pub fps: u32,
     pub bitrate: u32,
+    pub encoder: EncoderSettings,
     pub port: u16,
     pub mdns_enabled: bool,
     pub usb_enabled: bool,
@@ -45,6 +48,7 @@ impl Default for Settings {
             height: 1080,
             fps: 60,
             bitrate: 8000,
+            encoder: EncoderSettings::default(),
             port: 8443,
             mdns_enabled: true,
             usb_enabled: false,
@@ -397,6 +401,12 @@ impl MainWindow {
                         }
                     }
                 });
+
+                // Pipeline metrics (only when server running)
+                if let Some(metrics) = status.metrics.as_ref().filter(|_| status.running) {
+                    ui.add_space(spacing::CARD_MARGIN);
+                    card(ui, Some("Performance"), |ui| show_metrics(ui, metrics));
+                }
             });
     }
 
@@ -577,6 +587,90 @@ impl MainWindow {
 
                 ui.add_space(spacing::CARD_MARGIN);
 
+                // Encoder Settings Section
+                let previous = self.settings.encoder;
+                card(ui, Some("Encoder"), |ui| {
+                    let encoder = &mut self.settings.encoder;
+                    egui::Grid::new("encoder_grid")
+                        .num_columns(2)
+                        .spacing([20.0, 8.0])
+                        .show(ui, |ui| {
+                            // Profile
+                            ui.label(RichText::new("Profile").color(colors::TEXT_SECONDARY));
+                            egui::ComboBox::from_id_salt("encoder_profile")
+                                .selected_text(match encoder.profile {
+                                    H264Profile::ConstrainedBaseline => "Baseline",
+                                    H264Profile::High => "High",
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(
+                                        &mut encoder.profile,
+                                        H264Profile::ConstrainedBaseline,
+                                        "Baseline",
+                                    );
+                                    ui.selectable_value(
+                                        &mut encoder.profile,
+                                        H264Profile::High,
+                                        "High",
+                                    );
+                                });
+                            ui.end_row();
+
+                            // Keyframe interval
+                            ui.label(
+                                RichText::new("Keyframe Interval").color(colors::TEXT_SECONDARY),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut encoder.gop)
+                                    .range(0..=600)
+                                    .custom_formatter(|n, _| match n as u32 {
+                                        0 => "On demand".to_string(),
+                                        n => format!("{} frames", n),
+                                    }),
+                            )
+                            .on_hover_text("0 sends keyframes only when a client needs one");
+                            ui.end_row();
+
+                            // Slices
+                            ui.label(RichText::new("Slices").color(colors::TEXT_SECONDARY));
+                            ui.add(egui::DragValue::new(&mut encoder.slices).range(0..=16))
+                                .on_hover_text("0 lets the encoder decide");
+                            ui.end_row();
+
+                            // QP range
+                            ui.label(RichText::new("QP Range").color(colors::TEXT_SECONDARY));
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut encoder.min_qp)
+                                        .range(0..=encoder.max_qp),
+                                );
+                                ui.label(RichText::new("-").color(colors::TEXT_MUTED));
+                                ui.add(
+                                    egui::DragValue::new(&mut encoder.max_qp)
+                                        .range(encoder.min_qp..=MAX_QP),
+                                );
+                            });
+                            ui.end_row();
+                        });
+
+                    ui.add_space(4.0);
+
+                    ui.checkbox(
+                        &mut encoder.intra_refresh,
+                        RichText::new("Intra refresh").color(colors::TEXT_PRIMARY),
+                    )
+                    .on_hover_text(
+                        "Refresh the picture gradually instead of with large keyframes (x264 and x265)",
+                    );
+                });
+                if self.settings.encoder != previous {
+                    let _ = command_tx.try_send(UiCommand::SetEncoderSettings {
+                        settings: self.settings.encoder,
+                    });
+                }
+
+                ui.add_space(spacing::CARD_MARGIN);
+
                 // Network Settings Section
                 card(ui, Some("Network Settings"), |ui| {
                     egui::Grid::new("network_grid")
@@ -631,12 +725,105 @@ impl MainWindow {
                 // Info note
                 info_box(
                     ui,
-                    "Display and network settings require server restart to take effect",
+                    "Display, encoder and network settings require server restart to take effect",
                 );
             });
     }
 }
 
+/// Encoder timings per stream and link quality per connected client
+fn show_metrics(ui: &mut egui::Ui, metrics: &MetricsSnapshot) {
+    for rendition in &metrics.renditions {
+        ui.label(
+            RichText::new(&rendition.stream)
+                .font(typography::caption())
+                .color(colors::TEXT_MUTED),
+        );
+        egui::Grid::new(("metrics_rendition", rendition.index))
+            .num_columns(2)
+            .spacing([20.0, 4.0])
+            .show(ui, |ui| {
+                ui.label(RichText::new("Latency").color(colors::TEXT_SECONDARY));
+                ui.label(format!(
+                    "{:.0} ms median, {:.0} ms p95",
+                    rendition.latency_ms.p50, rendition.latency_ms.p95
+                ));
+                ui.end_row();
+
+                ui.label(RichText::new("Capture / convert / encode").color(colors::TEXT_SECONDARY))
+                    .on_hover_text(
+                        "Convert covers scaling and YUV conversion; queueing counts toward latency",
+                    );
+                ui.label(format!(
+                    "{:.1} / {:.1} / {:.1} ms",
+                    rendition.capture_ms.mean, rendition.convert_ms.mean, rendition.encode_ms.mean
+                ));
+                ui.end_row();
+
+                ui.label(RichText::new("Segments").color(colors::TEXT_SECONDARY));
+                ui.label(format!(
+                    "{} ({} keyframes), {:.1} KB average",
+                    rendition.segments,
+                    rendition.keyframes,
+                    rendition.segment_bytes.mean / 1024.0
+                ));
+                ui.end_row();
+            });
+        ui.add_space(8.0);
+    }
+
+    if metrics.clients.is_empty() {
+        ui.label(
+            RichText::new("No video clients")
+                .color(colors::TEXT_MUTED)
+                .italics(),
+        );
+        return;
+    }
+
+    egui::Grid::new("metrics_clients")
+        .num_columns(6)
+        .spacing([20.0, 4.0])
+        .show(ui, |ui| {
+            for heading in ["Client", "Stream", "RTT", "Queue", "Lag events", "Dropped"] {
+                ui.label(RichText::new(heading).color(colors::TEXT_SECONDARY));
+            }
+            ui.end_row();
+
+            for client in &metrics.clients {
+                ui.label(format!("#{}", client.id));
+                ui.label(client.rendition.to_string());
+                ui.label(match client.rtt_ms {
+                    Some(rtt) => format!("{} ms", rtt),
+                    None => "-".to_string(),
+                });
+                let queue = RichText::new(client.queue_depth.to_string());
+                ui.label(if client.queue_depth > 2 {
+                    queue.color(colors::WARNING)
+                } else {
+                    queue
+                });
+                let lag = RichText::new(client.lag_events.to_string());
+                ui.label(if client.lag_events > 0 {
+                    lag.color(colors::WARNING)
+                } else {
+                    lag
+                });
+                // Frames the client received but couldn't show
+                let received = client.decoded_frames.saturating_add(client.dropped_frames);
+                ui.label(if received == 0 {
+                    "-".to_string()
+                } else {
+                    format!(
+                        "{:.1}%",
+                        client.dropped_frames as f64 * 100.0 / received as f64
+                    )
+                });
+                ui.end_row();
+            }
+        });
+}
+
 /// Load the header logo from PNG file
 fn load_header_logo(ctx: &egui::Context) -> Option<TextureHandle> {
     let icon_paths = [
@@ -0,0 +1,59 @@
+//! Color conversion benchmarks
+//!
+//! Run with `cargo bench -p linglide-encoder --bench convert`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use linglide_encoder::convert::{i420_size, ColorMatrix, ColorRange, I420Converter};
+use std::hint::black_box;
+
+const RESOLUTIONS: [(u32, u32); 3] = [(1280, 720), (1920, 1080), (2560, 1600)];
+
+fn frame(width: u32, height: u32) -> Vec<u8> {
+    (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            [x as u8, y as u8, (x ^ y) as u8, 255]
+        })
+        .collect()
+}
+
+fn bench_convert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bgra_to_i420");
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let variants = [
+        ("scalar", I420Converter::default().with_simd(false)),
+        ("simd", I420Converter::default()),
+        (
+            "simd_threaded",
+            I420Converter::default().with_threads(threads),
+        ),
+        (
+            "simd_bt709_full",
+            I420Converter::new(ColorMatrix::Bt709, ColorRange::Full),
+        ),
+    ];
+
+    for (width, height) in RESOLUTIONS {
+        let bgra = frame(width, height);
+        let mut i420 = vec![0; i420_size(width, height)];
+        group.throughput(Throughput::Elements(u64::from(width * height)));
+
+        for (name, converter) in &variants {
+            group.bench_with_input(
+                BenchmarkId::new(*name, format!("{}x{}", width, height)),
+                &bgra,
+                |b, bgra| {
+                    b.iter(|| {
+                        converter
+                            .convert(black_box(bgra), width, height, &mut i420)
+                            .unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_convert);
+criterion_main!(benches);
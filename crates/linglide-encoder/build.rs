@@ -0,0 +1,26 @@
+fn main() {
+    #[cfg(feature = "x264")]
+    build_shim("x264", "x264", "0.148", "libx264-dev");
+    #[cfg(feature = "x265")]
+    build_shim("x265", "x265", "3.0", "libx265-dev");
+    #[cfg(feature = "svt-av1")]
+    build_shim("svt_av1", "SvtAv1Enc", "1.0", "libsvtav1enc-dev");
+}
+
+/// Compile `csrc/<name>_shim.c` against the system library found via pkg-config
+#[cfg(any(feature = "x264", feature = "x265", feature = "svt-av1"))]
+fn build_shim(name: &str, library: &str, min_version: &str, package: &str) {
+    let source = format!("csrc/{}_shim.c", name);
+    println!("cargo:rerun-if-changed={}", source);
+
+    let found = pkg_config::Config::new()
+        .atleast_version(min_version)
+        .probe(library)
+        .unwrap_or_else(|_| panic!("{} not found (install {})", library, package));
+
+    cc::Build::new()
+        .file(&source)
+        .includes(&found.include_paths)
+        .warnings(true)
+        .compile(&format!("linglide_{}_shim", name));
+}
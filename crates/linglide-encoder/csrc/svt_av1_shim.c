@@ -0,0 +1,198 @@
+/*
+ * Thin wrapper around SVT-AV1 for the `svt-av1` encoder backend.
+ *
+ * EbSvtAv1EncConfiguration changes layout between SVT-AV1 releases, so the
+ * Rust side only talks to this shim through an opaque handle and plain
+ * arguments. Compiled by build.rs when the `svt-av1` feature is enabled.
+ */
+
+#define _POSIX_C_SOURCE 200809L /* nanosleep */
+
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <EbSvtAv1Enc.h>
+
+/* Mirrors `OpenConfig` in svt_av1.rs */
+typedef struct {
+    int width;
+    int height;
+    int fps;
+    int bitrate_kbps;
+    int matrix;
+    int transfer;
+    int primaries;
+    int full_range;
+    int keyint;         /* 0: keyframes only on request */
+    int slices;         /* split into this many tile rows, rounded down to a power of two */
+    int qp_min;         /* H.264-style 0-51 scale */
+    int qp_max;
+} linglide_svt_av1_config;
+
+typedef struct {
+    EbComponentType *encoder;
+    EbSvtAv1EncConfiguration param;
+    int height;
+    int force_key;
+    uint8_t *out;
+    size_t out_capacity;
+} linglide_svt_av1;
+
+/* Longest wait for the packet of a submitted picture */
+#define PACKET_TIMEOUT_MS 1000
+
+/* Map an H.264-style quantizer (0-51) onto SVT-AV1's 1-63 range */
+static uint32_t av1_qp(int qp)
+{
+    uint32_t mapped = (uint32_t)((qp * 63 + 25) / 51);
+    return mapped < 1 ? 1 : mapped > 63 ? 63 : mapped;
+}
+
+static int copy_out(linglide_svt_av1 *h, const uint8_t *data, uint32_t len)
+{
+    if (len > h->out_capacity) {
+        uint8_t *out = realloc(h->out, len);
+        if (!out)
+            return -1;
+        h->out = out;
+        h->out_capacity = len;
+    }
+    memcpy(h->out, data, len);
+    return 0;
+}
+
+void linglide_svt_av1_close(linglide_svt_av1 *h);
+
+linglide_svt_av1 *linglide_svt_av1_open(const linglide_svt_av1_config *config)
+{
+    linglide_svt_av1 *h = calloc(1, sizeof(*h));
+    if (!h)
+        return NULL;
+
+    /* Fills `param` with the library defaults */
+#if SVT_AV1_CHECK_VERSION(3, 0, 0)
+    if (svt_av1_enc_init_handle(&h->encoder, &h->param) != EB_ErrorNone)
+#else
+    if (svt_av1_enc_init_handle(&h->encoder, NULL, &h->param) != EB_ErrorNone)
+#endif
+        goto fail;
+
+    EbSvtAv1EncConfiguration *param = &h->param;
+    param->enc_mode = 12; /* fastest real-time preset */
+    param->source_width = (uint32_t)config->width;
+    param->source_height = (uint32_t)config->height;
+    param->frame_rate_numerator = (uint32_t)config->fps;
+    param->frame_rate_denominator = 1;
+    param->encoder_bit_depth = 8;
+    param->encoder_color_format = EB_YUV420;
+    param->pred_structure = 1; /* low delay: no reordering, one packet per picture */
+    param->look_ahead_distance = 0;
+    param->rate_control_mode = 2; /* CBR */
+    param->target_bit_rate = (uint32_t)config->bitrate_kbps * 1000;
+    param->intra_period_length = config->keyint > 0 ? config->keyint - 1 : -1;
+    param->intra_refresh_type = 2; /* keyframes are full key frames */
+    param->force_key_frames = 1; /* honour key frames requested per picture */
+    param->min_qp_allowed = av1_qp(config->qp_min);
+    param->max_qp_allowed = av1_qp(config->qp_max);
+    for (int slices = config->slices; slices > 1; slices /= 2)
+        param->tile_rows++;
+
+    param->color_primaries = config->primaries;
+    param->transfer_characteristics = config->transfer;
+    param->matrix_coefficients = config->matrix;
+    param->color_range = config->full_range ? EB_CR_FULL_RANGE : EB_CR_STUDIO_RANGE;
+    h->height = config->height;
+
+    if (svt_av1_enc_set_parameter(h->encoder, param) != EB_ErrorNone)
+        goto fail;
+    if (svt_av1_enc_init(h->encoder) != EB_ErrorNone)
+        goto fail;
+    return h;
+
+fail:
+    linglide_svt_av1_close(h);
+    return NULL;
+}
+
+void linglide_svt_av1_close(linglide_svt_av1 *h)
+{
+    if (!h)
+        return;
+    if (h->encoder) {
+        svt_av1_enc_deinit(h->encoder);
+        svt_av1_enc_deinit_handle(h->encoder);
+    }
+    free(h->out);
+    free(h);
+}
+
+/*
+ * Encode one I420 picture and wait for its packet. The output is valid until
+ * the next call.
+ */
+int linglide_svt_av1_encode(linglide_svt_av1 *h, const uint8_t *y, const uint8_t *u,
+                            const uint8_t *v, int y_stride, int c_stride, int64_t pts,
+                            uint8_t **out, int *out_len, int *keyframe)
+{
+    EbSvtIOFormat planes;
+    EbBufferHeaderType picture;
+    EbBufferHeaderType *packet = NULL;
+
+    memset(&planes, 0, sizeof(planes));
+    planes.luma = (uint8_t *)y;
+    planes.cb = (uint8_t *)u;
+    planes.cr = (uint8_t *)v;
+    planes.y_stride = (uint32_t)y_stride;
+    planes.cb_stride = (uint32_t)c_stride;
+    planes.cr_stride = (uint32_t)c_stride;
+
+    memset(&picture, 0, sizeof(picture));
+    picture.size = sizeof(picture);
+    picture.p_buffer = (uint8_t *)&planes;
+    picture.n_filled_len = (uint32_t)(y_stride * h->height + c_stride * (h->height + 1));
+    picture.pts = pts;
+    picture.pic_type = h->force_key ? EB_AV1_KEY_PICTURE : EB_AV1_INVALID_PICTURE;
+    h->force_key = 0;
+
+    if (svt_av1_enc_send_picture(h->encoder, &picture) != EB_ErrorNone)
+        return -1;
+
+    /* In low-delay mode every picture comes back as exactly one packet */
+    const struct timespec poll = { 0, 500000 };
+    for (int waited = 0;; waited++) {
+        EbErrorType err = svt_av1_enc_get_packet(h->encoder, &packet, 0);
+        if (err == EB_ErrorNone)
+            break;
+        if (err != EB_NoErrorEmptyQueue || waited >= PACKET_TIMEOUT_MS * 2)
+            return -2;
+        nanosleep(&poll, NULL);
+    }
+
+    int ret = copy_out(h, packet->p_buffer, packet->n_filled_len);
+    *out = h->out;
+    *out_len = (int)packet->n_filled_len;
+    *keyframe = packet->pic_type == EB_AV1_KEY_PICTURE;
+    svt_av1_enc_release_out_buffer(&packet);
+    return ret;
+}
+
+void linglide_svt_av1_force_key(linglide_svt_av1 *h)
+{
+    h->force_key = 1;
+}
+
+/* Sequence header OBU. The output is valid until the next call. */
+int linglide_svt_av1_headers(linglide_svt_av1 *h, uint8_t **out, int *out_len)
+{
+    EbBufferHeaderType *header = NULL;
+
+    if (svt_av1_enc_stream_header(h->encoder, &header) != EB_ErrorNone || !header)
+        return -1;
+
+    int ret = copy_out(h, header->p_buffer, header->n_filled_len);
+    *out = h->out;
+    *out_len = (int)header->n_filled_len;
+    svt_av1_enc_stream_header_release(header);
+    return ret;
+}
@@ -0,0 +1,190 @@
+/*
+ * Thin wrapper around libx264 for the `x264` encoder backend.
+ *
+ * x264_param_t and x264_picture_t change layout between x264 builds, so the
+ * Rust side only talks to this shim through an opaque handle and plain
+ * arguments. Compiled by build.rs when the `x264` feature is enabled.
+ */
+
+#include <stdint.h>
+#include <stdlib.h>
+#include <x264.h>
+
+/* Mirrors `OpenConfig` in x264.rs */
+typedef struct {
+    int width;
+    int height;
+    int fps;
+    int bitrate_kbps;
+    int matrix;
+    int transfer;
+    int primaries;
+    int full_range;
+    int keyint;         /* 0: keyframes only on request */
+    int intra_refresh;
+    int high_profile;
+    int slices;         /* 0: let x264 decide */
+    int qp_min;
+    int qp_max;
+} linglide_x264_config;
+
+typedef struct {
+    x264_t *encoder;
+    x264_param_t param;
+    x264_picture_t picture;
+    int force_idr;
+    int bitrate_kbps;
+    int fps;
+} linglide_x264;
+
+/*
+ * With VFR input off, x264 budgets bits per frame from the configured frame
+ * rate, which can't be reconfigured. When fewer frames are fed, scale the
+ * target so the bitrate on the wire stays as requested.
+ */
+static void set_rate(linglide_x264 *h)
+{
+    x264_param_t *param = &h->param;
+    int64_t kbps = (int64_t)h->bitrate_kbps * param->i_fps_num / (h->fps > 0 ? h->fps : 1);
+
+    /* Constrain to about two frames of VBV so frames never queue up */
+    param->rc.i_rc_method = X264_RC_ABR;
+    param->rc.i_bitrate = (int)kbps;
+    param->rc.i_vbv_max_bitrate = (int)kbps;
+    param->rc.i_vbv_buffer_size = (int)(kbps / param->i_fps_num * 2);
+}
+
+linglide_x264 *linglide_x264_open(const linglide_x264_config *config)
+{
+    linglide_x264 *h = calloc(1, sizeof(*h));
+    if (!h)
+        return NULL;
+
+    if (x264_param_default_preset(&h->param, "superfast", "zerolatency") < 0)
+        goto fail;
+
+    h->param.i_csp = X264_CSP_I420;
+    h->param.i_width = config->width;
+    h->param.i_height = config->height;
+    h->param.i_fps_num = config->fps;
+    h->param.i_fps_den = 1;
+    h->param.b_repeat_headers = 1; /* SPS/PPS in front of every IDR */
+    h->param.b_annexb = 1;
+    h->param.i_log_level = X264_LOG_WARNING;
+    h->bitrate_kbps = config->bitrate_kbps;
+    h->fps = config->fps;
+    set_rate(h);
+
+    if (config->intra_refresh) {
+        /* The refresh wave sweeps the picture once per keyint */
+        h->param.b_intra_refresh = 1;
+        h->param.i_keyint_max = config->keyint > 0 ? config->keyint : config->fps;
+    } else {
+        h->param.i_keyint_max = config->keyint > 0 ? config->keyint : X264_KEYINT_MAX_INFINITE;
+    }
+    h->param.i_slice_count = config->slices;
+    h->param.rc.i_qp_min = config->qp_min;
+    h->param.rc.i_qp_max = config->qp_max;
+
+    h->param.vui.i_colorprim = config->primaries;
+    h->param.vui.i_transfer = config->transfer;
+    h->param.vui.i_colmatrix = config->matrix;
+    h->param.vui.b_fullrange = config->full_range;
+
+    if (x264_param_apply_profile(&h->param, config->high_profile ? "high" : "baseline") < 0)
+        goto fail;
+
+    h->encoder = x264_encoder_open(&h->param);
+    if (!h->encoder)
+        goto fail;
+
+    x264_picture_init(&h->picture);
+    h->picture.img.i_csp = X264_CSP_I420;
+    h->picture.img.i_plane = 3;
+    return h;
+
+fail:
+    free(h);
+    return NULL;
+}
+
+void linglide_x264_close(linglide_x264 *h)
+{
+    if (!h)
+        return;
+    if (h->encoder)
+        x264_encoder_close(h->encoder);
+    free(h);
+}
+
+/* Encode one I420 picture. The output is valid until the next call. */
+int linglide_x264_encode(linglide_x264 *h, const uint8_t *y, const uint8_t *u, const uint8_t *v,
+                         int y_stride, int c_stride, int64_t pts,
+                         uint8_t **out, int *out_len, int *keyframe)
+{
+    x264_nal_t *nals;
+    int nal_count;
+    x264_picture_t picture_out;
+
+    h->picture.img.plane[0] = (uint8_t *)y;
+    h->picture.img.plane[1] = (uint8_t *)u;
+    h->picture.img.plane[2] = (uint8_t *)v;
+    h->picture.img.i_stride[0] = y_stride;
+    h->picture.img.i_stride[1] = c_stride;
+    h->picture.img.i_stride[2] = c_stride;
+    h->picture.i_pts = pts;
+    h->picture.i_type = h->force_idr ? X264_TYPE_IDR : X264_TYPE_AUTO;
+    h->force_idr = 0;
+
+    int size = x264_encoder_encode(h->encoder, &nals, &nal_count, &h->picture, &picture_out);
+    if (size < 0)
+        return size;
+
+    /* NAL payloads are laid out back to back */
+    *out = size > 0 ? nals[0].p_payload : NULL;
+    *out_len = size;
+    /* b_keyframe is also set on intra refresh recovery points, which are P-slices */
+    *keyframe = size > 0 && picture_out.i_type == X264_TYPE_IDR;
+    return 0;
+}
+
+void linglide_x264_force_idr(linglide_x264 *h)
+{
+    h->force_idr = 1;
+}
+
+int linglide_x264_set_bitrate(linglide_x264 *h, int bitrate_kbps)
+{
+    x264_encoder_parameters(h->encoder, &h->param);
+    h->bitrate_kbps = bitrate_kbps;
+    set_rate(h);
+    return x264_encoder_reconfig(h->encoder, &h->param);
+}
+
+int linglide_x264_set_fps(linglide_x264 *h, int fps)
+{
+    x264_encoder_parameters(h->encoder, &h->param);
+    h->fps = fps;
+    set_rate(h);
+    return x264_encoder_reconfig(h->encoder, &h->param);
+}
+
+/* SPS and PPS. The output is valid until the next call into the encoder. */
+int linglide_x264_headers(linglide_x264 *h, uint8_t **out, int *out_len)
+{
+    x264_nal_t *nals;
+    int nal_count;
+
+    int size = x264_encoder_headers(h->encoder, &nals, &nal_count);
+    if (size < 0)
+        return size;
+
+    /* Keep SPS and PPS, drop the SEI carrying x264's version string */
+    *out = nals[0].p_payload;
+    *out_len = 0;
+    for (int i = 0; i < nal_count; i++) {
+        if (nals[i].i_type == NAL_SPS || nals[i].i_type == NAL_PPS)
+            *out_len = (int)(nals[i].p_payload + nals[i].i_payload - *out);
+    }
+    return 0;
+}
@@ -0,0 +1,207 @@
+/*
+ * Thin wrapper around libx265 for the `x265` encoder backend.
+ *
+ * x265_param and x265_picture change layout between x265 builds, so the
+ * Rust side only talks to this shim through an opaque handle and plain
+ * arguments. Compiled by build.rs when the `x265` feature is enabled.
+ */
+
+#include <stdint.h>
+#include <stdlib.h>
+#include <x265.h>
+
+/* Mirrors `OpenConfig` in x265.rs */
+typedef struct {
+    int width;
+    int height;
+    int fps;
+    int bitrate_kbps;
+    int matrix;
+    int transfer;
+    int primaries;
+    int full_range;
+    int keyint;         /* 0: keyframes only on request */
+    int intra_refresh;
+    int slices;         /* 0: let x265 decide */
+    int qp_min;
+    int qp_max;
+} linglide_x265_config;
+
+typedef struct {
+    x265_encoder *encoder;
+    x265_param *param;
+    x265_picture *picture;
+    int force_idr;
+    int bitrate_kbps;
+    int fps;
+} linglide_x265;
+
+/*
+ * x265 budgets bits per frame from the configured frame rate, which can't be
+ * reconfigured. When fewer frames are fed, scale the target so the bitrate on
+ * the wire stays as requested.
+ */
+static void set_rate(linglide_x265 *h)
+{
+    x265_param *param = h->param;
+    int kbps = (int)((int64_t)h->bitrate_kbps * param->fpsNum / (h->fps > 0 ? h->fps : 1));
+
+    /* Constrain to about two frames of VBV so frames never queue up */
+    param->rc.rateControlMode = X265_RC_ABR;
+    param->rc.bitrate = kbps;
+    param->rc.vbvMaxBitrate = kbps;
+    param->rc.vbvBufferSize = kbps / (int)param->fpsNum * 2;
+}
+
+void linglide_x265_close(linglide_x265 *h);
+
+linglide_x265 *linglide_x265_open(const linglide_x265_config *config)
+{
+    linglide_x265 *h = calloc(1, sizeof(*h));
+    if (!h)
+        return NULL;
+
+    h->param = x265_param_alloc();
+    if (!h->param || x265_param_default_preset(h->param, "ultrafast", "zerolatency") < 0)
+        goto fail;
+
+    h->param->internalCsp = X265_CSP_I420;
+    h->param->sourceWidth = config->width;
+    h->param->sourceHeight = config->height;
+    h->param->fpsNum = (uint32_t)config->fps;
+    h->param->fpsDenom = 1;
+    h->param->bRepeatHeaders = 1; /* VPS/SPS/PPS in front of every IDR */
+    h->param->bAnnexB = 1;
+    h->param->bOpenGOP = 0; /* keyframes are IDR, decodable on their own */
+    h->param->bEmitInfoSEI = 0;
+    h->param->logLevel = X265_LOG_WARNING;
+    h->bitrate_kbps = config->bitrate_kbps;
+    h->fps = config->fps;
+    set_rate(h);
+
+    if (config->intra_refresh) {
+        /* The refresh wave sweeps the picture once per keyint */
+        h->param->bIntraRefresh = 1;
+        h->param->keyframeMax = config->keyint > 0 ? config->keyint : config->fps;
+    } else {
+        h->param->keyframeMax = config->keyint > 0 ? config->keyint : -1; /* infinite */
+    }
+    if (config->slices > 0)
+        h->param->maxSlices = config->slices;
+    h->param->rc.qpMin = config->qp_min;
+    h->param->rc.qpMax = config->qp_max;
+
+    h->param->vui.bEnableVideoSignalTypePresentFlag = 1;
+    h->param->vui.bEnableColorDescriptionPresentFlag = 1;
+    h->param->vui.colorPrimaries = config->primaries;
+    h->param->vui.transferCharacteristics = config->transfer;
+    h->param->vui.matrixCoeffs = config->matrix;
+    h->param->vui.bEnableVideoFullRangeFlag = config->full_range;
+
+    if (x265_param_apply_profile(h->param, "main") < 0)
+        goto fail;
+
+    h->encoder = x265_encoder_open(h->param);
+    if (!h->encoder)
+        goto fail;
+
+    h->picture = x265_picture_alloc();
+    if (!h->picture)
+        goto fail;
+    x265_picture_init(h->param, h->picture);
+    h->picture->colorSpace = X265_CSP_I420;
+    h->picture->bitDepth = 8;
+    return h;
+
+fail:
+    linglide_x265_close(h);
+    return NULL;
+}
+
+void linglide_x265_close(linglide_x265 *h)
+{
+    if (!h)
+        return;
+    if (h->picture)
+        x265_picture_free(h->picture);
+    if (h->encoder)
+        x265_encoder_close(h->encoder);
+    if (h->param)
+        x265_param_free(h->param);
+    free(h);
+}
+
+/* Encode one I420 picture. The output is valid until the next call. */
+int linglide_x265_encode(linglide_x265 *h, const uint8_t *y, const uint8_t *u, const uint8_t *v,
+                         int y_stride, int c_stride, int64_t pts,
+                         uint8_t **out, int *out_len, int *keyframe)
+{
+    x265_nal *nals;
+    uint32_t nal_count = 0;
+    x265_picture picture_out;
+
+    h->picture->planes[0] = (void *)y;
+    h->picture->planes[1] = (void *)u;
+    h->picture->planes[2] = (void *)v;
+    h->picture->stride[0] = y_stride;
+    h->picture->stride[1] = c_stride;
+    h->picture->stride[2] = c_stride;
+    h->picture->pts = pts;
+    h->picture->sliceType = h->force_idr ? X265_TYPE_IDR : X265_TYPE_AUTO;
+    h->force_idr = 0;
+
+    x265_picture_init(h->param, &picture_out);
+    int ret = x265_encoder_encode(h->encoder, &nals, &nal_count, h->picture, &picture_out);
+    if (ret < 0)
+        return ret;
+
+    /* NAL payloads are laid out back to back */
+    *out = nal_count > 0 ? nals[0].payload : NULL;
+    *out_len = 0;
+    for (uint32_t i = 0; i < nal_count; i++)
+        *out_len += (int)nals[i].sizeBytes;
+    *keyframe = ret > 0 && picture_out.sliceType == X265_TYPE_IDR;
+    return 0;
+}
+
+void linglide_x265_force_idr(linglide_x265 *h)
+{
+    h->force_idr = 1;
+}
+
+int linglide_x265_set_bitrate(linglide_x265 *h, int bitrate_kbps)
+{
+    x265_encoder_parameters(h->encoder, h->param);
+    h->bitrate_kbps = bitrate_kbps;
+    set_rate(h);
+    return x265_encoder_reconfig(h->encoder, h->param);
+}
+
+int linglide_x265_set_fps(linglide_x265 *h, int fps)
+{
+    x265_encoder_parameters(h->encoder, h->param);
+    h->fps = fps;
+    set_rate(h);
+    return x265_encoder_reconfig(h->encoder, h->param);
+}
+
+/* VPS, SPS and PPS. The output is valid until the next call into the encoder. */
+int linglide_x265_headers(linglide_x265 *h, uint8_t **out, int *out_len)
+{
+    x265_nal *nals;
+    uint32_t nal_count = 0;
+
+    int ret = x265_encoder_headers(h->encoder, &nals, &nal_count);
+    if (ret < 0 || nal_count == 0)
+        return ret < 0 ? ret : -1;
+
+    /* Keep the parameter sets, drop any SEI that follows them */
+    *out = nals[0].payload;
+    *out_len = 0;
+    for (uint32_t i = 0; i < nal_count; i++) {
+        if (nals[i].type == NAL_UNIT_VPS || nals[i].type == NAL_UNIT_SPS ||
+            nals[i].type == NAL_UNIT_PPS)
+            *out_len = (int)(nals[i].payload + nals[i].sizeBytes - *out);
+    }
+    return 0;
+}
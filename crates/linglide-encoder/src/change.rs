@@ -0,0 +1,141 @@
+//! Static-frame detection
+//!
+//! Decides whether a captured frame is worth encoding. Damage reported by the
+//! capture backend is used when available; otherwise the pixels are compared
+//! with the previous frame. Unchanged frames are skipped, except for a
+//! periodic refresh so clients keep receiving data on a static desktop.
+
+use linglide_core::Frame;
+use std::time::{Duration, Instant};
+
+/// Default interval between refresh frames on a static screen
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks screen changes between frames
+pub struct ChangeDetector {
+    refresh_interval: Duration,
+    last_frame: Option<Frame>,
+    last_encoded: Option<Instant>,
+    skipped: u64,
+}
+
+impl Default for ChangeDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_REFRESH_INTERVAL)
+    }
+}
+
+impl ChangeDetector {
+    /// Create a detector that refreshes a static screen every `refresh_interval`
+    pub fn new(refresh_interval: Duration) -> Self {
+        Self {
+            refresh_interval,
+            last_frame: None,
+            last_encoded: None,
+            skipped: 0,
+        }
+    }
+
+    /// Check whether a frame should be encoded
+    pub fn should_encode(&mut self, frame: &Frame) -> bool {
+        self.should_encode_at(frame, Instant::now())
+    }
+
+    fn should_encode_at(&mut self, frame: &Frame, now: Instant) -> bool {
+        let changed = self.has_changed(frame);
+        // Frame data is shared, so keeping the previous frame is cheap
+        self.last_frame = Some(frame.clone());
+
+        let refresh_due = self
+            .last_encoded
+            .is_none_or(|t| now.duration_since(t) >= self.refresh_interval);
+
+        if changed || refresh_due {
+            self.last_encoded = Some(now);
+            true
+        } else {
+            self.skipped += 1;
+            false
+        }
+    }
+
+    fn has_changed(&self, frame: &Frame) -> bool {
+        if let Some(damage) = frame.damage() {
+            return !damage.is_empty();
+        }
+
+        match self.last_frame {
+            Some(ref last) => {
+                last.width != frame.width
+                    || last.height != frame.height
+                    || last.data() != frame.data()
+            }
+            None => true,
+        }
+    }
+
+    /// Force the next frame to be encoded
+    pub fn reset(&mut self) {
+        self.last_frame = None;
+        self.last_encoded = None;
+    }
+
+    /// Number of frames skipped so far
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linglide_core::Rect;
+
+    fn frame(fill: u8) -> Frame {
+        Frame::new(vec![fill; 16 * 16 * 4], 16, 16, 0)
+    }
+
+    #[test]
+    fn test_pixel_comparison() {
+        let mut detector = ChangeDetector::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(detector.should_encode_at(&frame(0), now));
+        assert!(!detector.should_encode_at(&frame(0), now));
+        assert!(detector.should_encode_at(&frame(1), now));
+        assert!(!detector.should_encode_at(&frame(1), now));
+        assert_eq!(detector.skipped_frames(), 2);
+    }
+
+    #[test]
+    fn test_damage_overrides_comparison() {
+        let mut detector = ChangeDetector::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(detector.should_encode_at(&frame(0), now));
+        // Reported damage wins even if the pixels happen to match
+        assert!(detector.should_encode_at(&frame(0).with_damage([Rect::new(0, 0, 1, 1)]), now));
+        assert!(!detector.should_encode_at(&frame(9).with_damage([]), now));
+    }
+
+    #[test]
+    fn test_periodic_refresh() {
+        let mut detector = ChangeDetector::new(Duration::from_secs(1));
+        let start = Instant::now();
+
+        assert!(detector.should_encode_at(&frame(0), start));
+        assert!(!detector.should_encode_at(&frame(0), start + Duration::from_millis(500)));
+        assert!(detector.should_encode_at(&frame(0), start + Duration::from_millis(1000)));
+        assert!(!detector.should_encode_at(&frame(0), start + Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut detector = ChangeDetector::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(detector.should_encode_at(&frame(0), now));
+        detector.reset();
+        assert!(detector.should_encode_at(&frame(0), now));
+    }
+}
@@ -0,0 +1,542 @@
+//! Stream description from codec parameter sets
+//!
+//! The fMP4 sample entry and the WebCodecs codec string both describe the
+//! stream with a few fields from the encoder's headers: profile and level from
+//! the H.264 SPS, profile, tier and level from the HEVC SPS, and profile,
+//! level and color format from the AV1 sequence header. Without headers the
+//! lowest level that fits the resolution and frame rate is assumed.
+
+/// AV1 OBU type of a sequence header
+pub const OBU_SEQUENCE_HEADER: u8 = 1;
+/// AV1 OBU type of a temporal delimiter
+pub const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+/// Split an Annex B byte stream into NAL units, without start codes
+pub fn annexb_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&data[start..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        units.push(&data[start..]);
+    }
+
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+/// A NAL unit never ends in a zero byte, so trailing zeros belong to the
+/// next four-byte start code
+fn trim_trailing_zeros(data: &[u8]) -> &[u8] {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+/// Remove emulation prevention bytes (`00 00 03`) from a NAL unit
+pub fn unescape_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+/// One AV1 OBU in low-overhead bitstream format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Obu<'a> {
+    pub obu_type: u8,
+    /// The whole OBU, header included
+    pub data: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+/// Split low-overhead AV1 data into OBUs
+///
+/// Stops at the first OBU without a size field, since its end can't be found.
+pub fn av1_obus(data: &[u8]) -> Vec<Obu<'_>> {
+    let mut obus = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let header = data[pos];
+        let has_extension = header & 0x04 != 0;
+        let has_size = header & 0x02 != 0;
+        let mut offset = pos + 1 + usize::from(has_extension);
+        if !has_size {
+            break;
+        }
+        let Some((size, len)) = read_leb128(data.get(offset..).unwrap_or_default()) else {
+            break;
+        };
+        offset += len;
+        let Some(end) = offset.checked_add(size).filter(|&end| end <= data.len()) else {
+            break;
+        };
+
+        obus.push(Obu {
+            obu_type: (header >> 3) & 0x0F,
+            data: &data[pos..end],
+            payload: &data[offset..end],
+        });
+        pos = end;
+    }
+
+    obus
+}
+
+/// Decode an unsigned LEB128 value, returning it and its length in bytes
+fn read_leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, &byte) in data.iter().take(8).enumerate() {
+        value |= usize::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// MSB-first bit reader
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit != 0)
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0u32, |value, _| {
+            Some((value << 1) | u32::from(self.flag()?))
+        })
+    }
+
+    /// AV1 `uvlc()` variable length code
+    fn uvlc(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while !self.flag()? {
+            leading_zeros += 1;
+            if leading_zeros >= 32 {
+                return Some(u32::MAX);
+            }
+        }
+        Some(self.bits(leading_zeros)? + ((1u32 << leading_zeros) - 1))
+    }
+}
+
+/// Lowest H.264 level (`level_idc`) whose frame size and macroblock rate
+/// limits fit the stream, from Table A-1 of the spec
+pub fn h264_level(width: u32, height: u32, fps: u32) -> u8 {
+    // (level_idc, max macroblocks per second, max frame size in macroblocks)
+    const LEVELS: [(u8, u64, u64); 9] = [
+        (30, 40_500, 1_620),
+        (31, 108_000, 3_600),
+        (32, 216_000, 5_120),
+        (40, 245_760, 8_192),
+        (42, 522_240, 8_704),
+        (50, 589_824, 22_080),
+        (51, 983_040, 36_864),
+        (52, 2_073_600, 36_864),
+        (60, 4_177_920, 139_264),
+    ];
+
+    let frame_mbs = u64::from(width.div_ceil(16)) * u64::from(height.div_ceil(16));
+    let mbs_per_second = frame_mbs * u64::from(fps);
+    LEVELS
+        .iter()
+        .find(|&&(_, max_mbps, max_fs)| frame_mbs <= max_fs && mbs_per_second <= max_mbps)
+        .map_or(60, |&(level, _, _)| level)
+}
+
+/// Lowest entry of a (level, max picture size, max sample rate) table that
+/// fits the stream
+fn lowest_level(levels: &[(u8, u64, u64)], width: u32, height: u32, fps: u32) -> u8 {
+    let picture_size = u64::from(width) * u64::from(height);
+    let sample_rate = picture_size * u64::from(fps);
+    levels
+        .iter()
+        .find(|&&(_, max_size, max_rate)| picture_size <= max_size && sample_rate <= max_rate)
+        .or(levels.last())
+        .map_or(0, |&(level, _, _)| level)
+}
+
+/// General profile, tier and level of an HEVC stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HevcProfileTierLevel {
+    pub profile_space: u8,
+    pub high_tier: bool,
+    pub profile_idc: u8,
+    pub compatibility_flags: u32,
+    /// Progressive, interlaced, frame-only and profile constraint flags
+    pub constraint_flags: [u8; 6],
+    /// `level_idc`: 30 times the level number
+    pub level_idc: u8,
+    /// Temporal sub-layers in the stream
+    pub sub_layers: u8,
+    pub temporal_id_nested: bool,
+}
+
+impl HevcProfileTierLevel {
+    /// Main profile at the lowest level that fits the stream (Table A.8)
+    pub fn main(width: u32, height: u32, fps: u32) -> Self {
+        // (level_idc, max luma picture size, max luma sample rate)
+        const LEVELS: [(u8, u64, u64); 13] = [
+            (30, 36_864, 552_960),
+            (60, 122_880, 3_686_400),
+            (63, 245_760, 7_372_800),
+            (90, 552_960, 16_588_800),
+            (93, 983_040, 33_177_600),
+            (120, 2_228_224, 66_846_720),
+            (123, 2_228_224, 133_693_440),
+            (150, 8_912_896, 267_386_880),
+            (153, 8_912_896, 534_773_760),
+            (156, 8_912_896, 1_069_547_520),
+            (180, 35_651_584, 1_069_547_520),
+            (183, 35_651_584, 2_139_095_040),
+            (186, 35_651_584, 4_278_190_080),
+        ];
+
+        Self {
+            profile_space: 0,
+            high_tier: false,
+            profile_idc: 1,
+            // Main also conforms to Main 10
+            compatibility_flags: 0x6000_0000,
+            // Progressive, non-packed, frame-only
+            constraint_flags: [0xB0, 0, 0, 0, 0, 0],
+            level_idc: lowest_level(&LEVELS, width, height, fps),
+            sub_layers: 1,
+            temporal_id_nested: true,
+        }
+    }
+
+    /// Read the general `profile_tier_level()` from an SPS NAL unit
+    pub fn from_sps(sps: &[u8]) -> Option<Self> {
+        let rbsp = unescape_rbsp(sps);
+        // Two-byte NAL header, then the VPS id, sub-layer count and nesting flag
+        let fields = rbsp.get(2..15)?;
+        Some(Self {
+            profile_space: fields[1] >> 6,
+            high_tier: fields[1] & 0x20 != 0,
+            profile_idc: fields[1] & 0x1F,
+            compatibility_flags: u32::from_be_bytes(fields[2..6].try_into().ok()?),
+            constraint_flags: fields[6..12].try_into().ok()?,
+            level_idc: fields[12],
+            sub_layers: ((fields[0] >> 1) & 0x07) + 1,
+            temporal_id_nested: fields[0] & 0x01 != 0,
+        })
+    }
+
+    /// Codec string as defined by ISO/IEC 14496-15, e.g. `hvc1.1.6.L93.B0`
+    pub fn codec_string(&self) -> String {
+        let space = ["", "A", "B", "C"][usize::from(self.profile_space & 3)];
+        let mut codec = format!(
+            "hvc1.{}{}.{:X}.{}{}",
+            space,
+            self.profile_idc,
+            self.compatibility_flags.reverse_bits(),
+            if self.high_tier { 'H' } else { 'L' },
+            self.level_idc
+        );
+
+        // Constraint bytes, with trailing zero bytes omitted
+        if let Some(last) = self.constraint_flags.iter().rposition(|&b| b != 0) {
+            for byte in &self.constraint_flags[..=last] {
+                codec.push_str(&format!(".{:X}", byte));
+            }
+        }
+        codec
+    }
+
+    /// Profile, tier and level fields of an `HEVCDecoderConfigurationRecord`
+    pub fn write_record_fields(&self, buf: &mut Vec<u8>) {
+        buf.push((self.profile_space << 6) | (u8::from(self.high_tier) << 5) | self.profile_idc);
+        buf.extend_from_slice(&self.compatibility_flags.to_be_bytes());
+        buf.extend_from_slice(&self.constraint_flags);
+        buf.push(self.level_idc);
+    }
+}
+
+/// Stream properties from an AV1 sequence header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Av1SequenceInfo {
+    pub profile: u8,
+    /// `seq_level_idx` of the first operating point
+    pub level: u8,
+    pub high_tier: bool,
+    pub bit_depth: u8,
+    pub monochrome: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub chroma_sample_position: u8,
+}
+
+impl Av1SequenceInfo {
+    /// 8-bit 4:2:0 Main profile at the lowest level that fits the stream
+    /// (Annex A.3)
+    pub fn main(width: u32, height: u32, fps: u32) -> Self {
+        // (seq_level_idx, max picture size, max display rate)
+        const LEVELS: [(u8, u64, u64); 13] = [
+            (0, 147_456, 4_423_680),
+            (1, 278_784, 8_363_520),
+            (4, 665_856, 19_975_680),
+            (5, 1_065_024, 31_950_720),
+            (8, 2_359_296, 70_778_880),
+            (9, 2_359_296, 141_557_760),
+            (12, 8_912_896, 267_386_880),
+            (13, 8_912_896, 534_773_760),
+            (14, 8_912_896, 1_069_547_520),
+            (15, 8_912_896, 1_069_547_520),
+            (16, 35_651_584, 1_069_547_520),
+            (17, 35_651_584, 2_139_095_040),
+            (18, 35_651_584, 4_278_190_080),
+        ];
+
+        Self {
+            profile: 0,
+            level: lowest_level(&LEVELS, width, height, fps),
+            high_tier: false,
+            bit_depth: 8,
+            monochrome: false,
+            subsampling_x: true,
+            subsampling_y: true,
+            chroma_sample_position: 0,
+        }
+    }
+
+    /// Parse a `sequence_header_obu()` payload
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let mut r = BitReader::new(payload);
+        let profile = r.bits(3)? as u8;
+        let _still_picture = r.flag()?;
+        let reduced_still_picture_header = r.flag()?;
+
+        let (level, high_tier);
+        if reduced_still_picture_header {
+            level = r.bits(5)? as u8;
+            high_tier = false;
+        } else {
+            let mut decoder_model_info_present = false;
+            let mut buffer_delay_length = 0;
+            if r.flag()? {
+                // timing_info()
+                r.bits(32)?;
+                r.bits(32)?;
+                if r.flag()? {
+                    r.uvlc()?;
+                }
+                decoder_model_info_present = r.flag()?;
+                if decoder_model_info_present {
+                    buffer_delay_length = r.bits(5)? + 1;
+                    r.bits(32)?;
+                    r.bits(10)?;
+                }
+            }
+            let initial_display_delay_present = r.flag()?;
+            let operating_points = r.bits(5)? + 1;
+
+            let mut first = None;
+            for _ in 0..operating_points {
+                r.bits(12)?;
+                let seq_level_idx = r.bits(5)? as u8;
+                let seq_tier = seq_level_idx > 7 && r.flag()?;
+                if decoder_model_info_present && r.flag()? {
+                    r.bits(buffer_delay_length)?;
+                    r.bits(buffer_delay_length)?;
+                    r.flag()?;
+                }
+                if initial_display_delay_present && r.flag()? {
+                    r.bits(4)?;
+                }
+                first.get_or_insert((seq_level_idx, seq_tier));
+            }
+            (level, high_tier) = first?;
+        }
+
+        // Frame size and coding tools, up to color_config()
+        let width_bits = r.bits(4)? + 1;
+        let height_bits = r.bits(4)? + 1;
+        r.bits(width_bits)?;
+        r.bits(height_bits)?;
+        if !reduced_still_picture_header && r.flag()? {
+            r.bits(4 + 3)?;
+        }
+        r.bits(3)?;
+        if !reduced_still_picture_header {
+            r.bits(4)?;
+            let enable_order_hint = r.flag()?;
+            if enable_order_hint {
+                r.bits(2)?;
+            }
+            let force_screen_content_tools = if r.flag()? { 2 } else { r.bits(1)? };
+            if force_screen_content_tools > 0 && !r.flag()? {
+                r.bits(1)?;
+            }
+            if enable_order_hint {
+                r.bits(3)?;
+            }
+        }
+        r.bits(3)?;
+
+        // color_config()
+        let high_bitdepth = r.flag()?;
+        let bit_depth = match (profile, high_bitdepth) {
+            (2, true) if r.flag()? => 12,
+            (_, true) => 10,
+            (_, false) => 8,
+        };
+        let monochrome = profile != 1 && r.flag()?;
+        let (mut primaries, mut transfer, mut matrix) = (2, 2, 2);
+        if r.flag()? {
+            primaries = r.bits(8)?;
+            transfer = r.bits(8)?;
+            matrix = r.bits(8)?;
+        }
+
+        let (subsampling_x, subsampling_y);
+        if monochrome {
+            (subsampling_x, subsampling_y) = (true, true);
+        } else if primaries == 1 && transfer == 13 && matrix == 0 {
+            // sRGB: 4:4:4 full range
+            (subsampling_x, subsampling_y) = (false, false);
+        } else {
+            r.flag()?;
+            (subsampling_x, subsampling_y) = match profile {
+                0 => (true, true),
+                1 => (false, false),
+                _ if bit_depth == 12 => {
+                    let x = r.flag()?;
+                    (x, x && r.flag()?)
+                }
+                _ => (true, false),
+            };
+        }
+        let chroma_sample_position = if subsampling_x && subsampling_y && !monochrome {
+            r.bits(2)? as u8
+        } else {
+            0
+        };
+
+        Some(Self {
+            profile,
+            level,
+            high_tier,
+            bit_depth,
+            monochrome,
+            subsampling_x,
+            subsampling_y,
+            chroma_sample_position,
+        })
+    }
+
+    /// Codec string as defined by the AV1 ISOBMFF binding, e.g. `av01.0.08M.08`
+    pub fn codec_string(&self) -> String {
+        format!(
+            "av01.{}.{:02}{}.{:02}",
+            self.profile,
+            self.level,
+            if self.high_tier { 'H' } else { 'M' },
+            self.bit_depth
+        )
+    }
+
+    /// Fixed fields of an `AV1CodecConfigurationRecord`, before the config OBUs
+    pub fn record_header(&self) -> [u8; 4] {
+        [
+            0x81, // marker and version 1
+            (self.profile << 5) | self.level,
+            (u8::from(self.high_tier) << 7)
+                | (u8::from(self.bit_depth > 8) << 6)
+                | (u8::from(self.bit_depth == 12) << 5)
+                | (u8::from(self.monochrome) << 4)
+                | (u8::from(self.subsampling_x) << 3)
+                | (u8::from(self.subsampling_y) << 2)
+                | self.chroma_sample_position,
+            0, // no initial presentation delay
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annexb_nal_units() {
+        let data = [
+            0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x68, 0xCE, 0, 0, 0, 1, 0x65, 0x88,
+        ];
+        assert_eq!(
+            annexb_nal_units(&data),
+            vec![&[0x67, 0x42][..], &[0x68, 0xCE], &[0x65, 0x88]]
+        );
+        assert_eq!(
+            unescape_rbsp(&[1, 0, 0, 3, 0, 0, 0, 3, 1]),
+            [1, 0, 0, 0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_hevc_profile_tier_level() {
+        // x265 Main profile SPS for 1920x1080 at level 4.1; the compatibility
+        // flags contain an emulation prevention byte
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0xB0, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x7B, 0xA0,
+        ];
+        let ptl = HevcProfileTierLevel::from_sps(&sps).unwrap();
+        assert_eq!(ptl, HevcProfileTierLevel::main(1920, 1080, 60));
+        assert_eq!(ptl.codec_string(), "hvc1.1.6.L123.B0");
+
+        assert_eq!(HevcProfileTierLevel::main(1280, 720, 30).level_idc, 93);
+        let mut record = Vec::new();
+        ptl.write_record_fields(&mut record);
+        assert_eq!(record, [0x01, 0x60, 0, 0, 0, 0xB0, 0, 0, 0, 0, 0, 123]);
+    }
+
+    #[test]
+    fn test_av1_sequence_header() {
+        // 1920x1080, profile 0, level 4.0, 8-bit 4:2:0 BT.709 limited range
+        let obu = [
+            0x0A, 0x0E, 0x00, 0x00, 0x00, 0x42, 0xAB, 0xBF, 0xC3, 0x73, 0x09, 0xE6, 0x40, 0x40,
+            0x40, 0x41,
+        ];
+        let obus = av1_obus(&obu);
+        assert_eq!(obus.len(), 1);
+        assert_eq!(obus[0].obu_type, OBU_SEQUENCE_HEADER);
+
+        let info = Av1SequenceInfo::parse(obus[0].payload).unwrap();
+        assert_eq!(info, Av1SequenceInfo::main(1920, 1080, 30));
+        assert_eq!(info.codec_string(), "av01.0.08M.08");
+
+        let main = Av1SequenceInfo::main(1920, 1080, 60);
+        assert_eq!(main.codec_string(), "av01.0.09M.08");
+        assert_eq!(main.record_header(), [0x81, 0x09, 0x0C, 0x00]);
+    }
+}
@@ -0,0 +1,533 @@
+//! BGRA to I420 color conversion
+//!
+//! Converts captured BGRA frames into the planar YUV 4:2:0 layout expected by
+//! the encoder. Chroma is taken from the average of each 2x2 block. The inner
+//! loops use SSE2 on x86_64 and fall back to portable scalar code elsewhere;
+//! both paths use the same fixed-point arithmetic and produce identical output.
+//! Large frames can be split into row bands converted on separate threads.
+
+pub use linglide_core::{ColorMatrix, ColorRange, ColorSpace};
+
+use linglide_core::{Error, Result};
+
+/// Fractional bits of the fixed-point coefficients
+const SHIFT: u32 = 14;
+const ROUND: i32 = 1 << (SHIFT - 1);
+
+/// Luma weights of red and blue (Kr, Kb)
+fn weights(matrix: ColorMatrix) -> (f64, f64) {
+    match matrix {
+        ColorMatrix::Bt601 => (0.299, 0.114),
+        ColorMatrix::Bt709 => (0.2126, 0.0722),
+    }
+}
+
+/// Size in bytes of an I420 image
+pub fn i420_size(width: u32, height: u32) -> usize {
+    let (width, height) = (width as usize, height as usize);
+    width * height + 2 * width.div_ceil(2) * height.div_ceil(2)
+}
+
+/// Fixed-point conversion coefficients, in B, G, R order to match the pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Coefficients {
+    y: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+    y_offset: i32,
+}
+
+impl Coefficients {
+    fn new(matrix: ColorMatrix, range: ColorRange) -> Self {
+        let (kr, kb) = weights(matrix);
+        let (y_scale, c_scale, y_offset) = match range {
+            ColorRange::Limited => (219.0 / 255.0, 224.0 / 255.0, 16),
+            ColorRange::Full => (1.0, 1.0, 0),
+        };
+        let fixed = |v: f64| (v * f64::from(1 << SHIFT)).round() as i32;
+
+        // Round each row so it keeps its exact sum: grey must map to neutral
+        // chroma and white to the top of the luma range
+        let yr = fixed(kr * y_scale);
+        let yb = fixed(kb * y_scale);
+        let yg = fixed(y_scale) - yr - yb;
+
+        let ub = fixed(0.5 * c_scale);
+        let ur = fixed(-kr / (2.0 * (1.0 - kb)) * c_scale);
+        let ug = -ub - ur;
+
+        let vr = fixed(0.5 * c_scale);
+        let vb = fixed(-kb / (2.0 * (1.0 - kr)) * c_scale);
+        let vg = -vr - vb;
+
+        Self {
+            y: [yb, yg, yr],
+            u: [ub, ug, ur],
+            v: [vb, vg, vr],
+            y_offset,
+        }
+    }
+
+    #[inline]
+    fn luma(&self, b: i32, g: i32, r: i32) -> u8 {
+        let y = ((self.y[0] * b + self.y[1] * g + self.y[2] * r + ROUND) >> SHIFT) + self.y_offset;
+        y.clamp(0, 255) as u8
+    }
+
+    #[inline]
+    fn chroma(&self, b: i32, g: i32, r: i32) -> (u8, u8) {
+        let u = ((self.u[0] * b + self.u[1] * g + self.u[2] * r + ROUND) >> SHIFT) + 128;
+        let v = ((self.v[0] * b + self.v[1] * g + self.v[2] * r + ROUND) >> SHIFT) + 128;
+        (u.clamp(0, 255) as u8, v.clamp(0, 255) as u8)
+    }
+}
+
+/// BGRA to I420 converter
+#[derive(Debug, Clone)]
+pub struct I420Converter {
+    color_space: ColorSpace,
+    coefficients: Coefficients,
+    threads: usize,
+    simd: bool,
+}
+
+impl Default for I420Converter {
+    fn default() -> Self {
+        Self::from_color_space(ColorSpace::default())
+    }
+}
+
+impl I420Converter {
+    /// Create a single-threaded converter
+    pub fn new(matrix: ColorMatrix, range: ColorRange) -> Self {
+        Self {
+            color_space: ColorSpace::new(matrix, range),
+            coefficients: Coefficients::new(matrix, range),
+            threads: 1,
+            simd: true,
+        }
+    }
+
+    /// Create a single-threaded converter for a color space
+    pub fn from_color_space(color_space: ColorSpace) -> Self {
+        Self::new(color_space.matrix, color_space.range)
+    }
+
+    /// Builder pattern: convert row bands on up to `threads` threads
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Builder pattern: enable or disable the SIMD code path
+    pub fn with_simd(mut self, simd: bool) -> Self {
+        self.simd = simd;
+        self
+    }
+
+    /// Get the color space produced by this converter
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Convert a tightly packed BGRA image into `i420` (Y, then U, then V plane)
+    ///
+    /// Odd dimensions are supported; the last chroma row/column then covers a
+    /// single pixel row/column.
+    pub fn convert(&self, bgra: &[u8], width: u32, height: u32, i420: &mut [u8]) -> Result<()> {
+        let w = width as usize;
+        let h = height as usize;
+        if bgra.len() < w * h * 4 {
+            return Err(Error::EncoderError(format!(
+                "BGRA buffer too small for {}x{}: {} bytes",
+                width,
+                height,
+                bgra.len()
+            )));
+        }
+        let size = i420_size(width, height);
+        if i420.len() < size {
+            return Err(Error::EncoderError(format!(
+                "I420 buffer too small for {}x{}: {} < {} bytes",
+                width,
+                height,
+                i420.len(),
+                size
+            )));
+        }
+        if w == 0 || h == 0 {
+            return Ok(());
+        }
+
+        let cw = w.div_ceil(2);
+        let ch = h.div_ceil(2);
+        let (y_plane, chroma) = i420[..size].split_at_mut(w * h);
+        let (u_plane, v_plane) = chroma.split_at_mut(cw * ch);
+        let bgra = &bgra[..w * h * 4];
+
+        // Bands always hold an even number of rows so chroma rows don't straddle them
+        let bands = self.threads.min(ch);
+        if bands <= 1 {
+            self.convert_band(bgra, w, y_plane, u_plane, v_plane);
+            return Ok(());
+        }
+
+        let chroma_rows = ch.div_ceil(bands);
+        let rows = chroma_rows * 2;
+        std::thread::scope(|scope| {
+            let mut parts = bgra
+                .chunks(rows * w * 4)
+                .zip(y_plane.chunks_mut(rows * w))
+                .zip(u_plane.chunks_mut(chroma_rows * cw))
+                .zip(v_plane.chunks_mut(chroma_rows * cw));
+            // Keep the first band for the calling thread
+            let first = parts.next();
+            for (((src, y), u), v) in parts {
+                scope.spawn(move || self.convert_band(src, w, y, u, v));
+            }
+            if let Some((((src, y), u), v)) = first {
+                self.convert_band(src, w, y, u, v);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Convert a band of whole rows starting on an even row
+    fn convert_band(&self, bgra: &[u8], w: usize, y_plane: &mut [u8], u: &mut [u8], v: &mut [u8]) {
+        let cw = w.div_ceil(2);
+        let mut rows = bgra.chunks(w * 4);
+        let mut y_rows = y_plane.chunks_mut(w);
+
+        for (u_row, v_row) in u.chunks_mut(cw).zip(v.chunks_mut(cw)) {
+            let (Some(top), Some(y_top)) = (rows.next(), y_rows.next()) else {
+                break;
+            };
+            self.luma_row(top, y_top);
+
+            // A trailing odd row is averaged with itself
+            let bottom = match (rows.next(), y_rows.next()) {
+                (Some(bottom), Some(y_bottom)) => {
+                    self.luma_row(bottom, y_bottom);
+                    bottom
+                }
+                _ => top,
+            };
+            self.chroma_row(top, bottom, u_row, v_row);
+        }
+    }
+
+    fn luma_row(&self, bgra: &[u8], y: &mut [u8]) {
+        let mut done = 0;
+        #[cfg(target_arch = "x86_64")]
+        if self.simd {
+            // SAFETY: SSE2 is part of the x86_64 baseline
+            done = unsafe { sse2::luma_row(bgra, y, &self.coefficients) };
+        }
+        luma_row_scalar(&bgra[done * 4..], &mut y[done..], &self.coefficients);
+    }
+
+    fn chroma_row(&self, top: &[u8], bottom: &[u8], u: &mut [u8], v: &mut [u8]) {
+        let mut done = 0;
+        #[cfg(target_arch = "x86_64")]
+        if self.simd {
+            // SAFETY: SSE2 is part of the x86_64 baseline
+            done = unsafe { sse2::chroma_row(top, bottom, u, v, &self.coefficients) };
+        }
+        chroma_row_scalar(
+            &top[done * 8..],
+            &bottom[done * 8..],
+            &mut u[done..],
+            &mut v[done..],
+            &self.coefficients,
+        );
+    }
+}
+
+fn luma_row_scalar(bgra: &[u8], y: &mut [u8], c: &Coefficients) {
+    for (px, out) in bgra.chunks_exact(4).zip(y.iter_mut()) {
+        *out = c.luma(i32::from(px[0]), i32::from(px[1]), i32::from(px[2]));
+    }
+}
+
+fn chroma_row_scalar(top: &[u8], bottom: &[u8], u: &mut [u8], v: &mut [u8], c: &Coefficients) {
+    let blocks = top.chunks(8).zip(bottom.chunks(8));
+    for ((a, b), (u, v)) in blocks.zip(u.iter_mut().zip(v.iter_mut())) {
+        // A trailing odd column is averaged with itself
+        let (a1, b1) = if a.len() >= 8 {
+            (&a[4..8], &b[4..8])
+        } else {
+            (&a[..4], &b[..4])
+        };
+        let avg = |i: usize| {
+            (i32::from(a[i]) + i32::from(a1[i]) + i32::from(b[i]) + i32::from(b1[i]) + 2) >> 2
+        };
+        (*u, *v) = c.chroma(avg(0), avg(1), avg(2));
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    //! SSE2 kernels. Each returns how many output samples it wrote; the caller
+    //! finishes the row with the scalar code.
+
+    use super::{Coefficients, ROUND, SHIFT};
+    use std::arch::x86_64::*;
+
+    #[inline(always)]
+    unsafe fn weights(c: [i32; 3]) -> __m128i {
+        let [b, g, r] = c.map(|v| v as i16);
+        _mm_setr_epi16(b, g, r, 0, b, g, r, 0)
+    }
+
+    /// Add the B+G and R partial sums produced by `_mm_madd_epi16` for 4 pixels
+    #[inline(always)]
+    unsafe fn pair_sum(lo: __m128i, hi: __m128i) -> __m128i {
+        let lo = _mm_castsi128_ps(lo);
+        let hi = _mm_castsi128_ps(hi);
+        let even = _mm_shuffle_ps::<0b10_00_10_00>(lo, hi);
+        let odd = _mm_shuffle_ps::<0b11_01_11_01>(lo, hi);
+        _mm_add_epi32(_mm_castps_si128(even), _mm_castps_si128(odd))
+    }
+
+    #[inline(always)]
+    unsafe fn scale(sum: __m128i, offset: __m128i) -> __m128i {
+        let rounded = _mm_add_epi32(sum, _mm_set1_epi32(ROUND));
+        _mm_add_epi32(_mm_srai_epi32::<{ SHIFT as i32 }>(rounded), offset)
+    }
+
+    /// Luma of 4 BGRA pixels as 32-bit lanes
+    #[inline(always)]
+    unsafe fn luma4(px: __m128i, w: __m128i, offset: __m128i) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let lo = _mm_madd_epi16(_mm_unpacklo_epi8(px, zero), w);
+        let hi = _mm_madd_epi16(_mm_unpackhi_epi8(px, zero), w);
+        scale(pair_sum(lo, hi), offset)
+    }
+
+    /// Averaged B, G, R, A of the two 2x2 blocks covered by 4 columns of two rows
+    #[inline(always)]
+    unsafe fn block_average(top: __m128i, bottom: __m128i) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let lo = _mm_add_epi16(
+            _mm_unpacklo_epi8(top, zero),
+            _mm_unpacklo_epi8(bottom, zero),
+        );
+        let hi = _mm_add_epi16(
+            _mm_unpackhi_epi8(top, zero),
+            _mm_unpackhi_epi8(bottom, zero),
+        );
+        let first = _mm_add_epi16(lo, _mm_srli_si128::<8>(lo));
+        let second = _mm_add_epi16(hi, _mm_srli_si128::<8>(hi));
+        let sums = _mm_unpacklo_epi64(first, second);
+        _mm_srli_epi16::<2>(_mm_add_epi16(sums, _mm_set1_epi16(2)))
+    }
+
+    pub(super) unsafe fn luma_row(bgra: &[u8], y: &mut [u8], c: &Coefficients) -> usize {
+        let w = weights(c.y);
+        let offset = _mm_set1_epi32(c.y_offset);
+        let count = y.len().min(bgra.len() / 4) / 8 * 8;
+
+        for i in (0..count).step_by(8) {
+            let src = bgra.as_ptr().add(i * 4) as *const __m128i;
+            let a = luma4(_mm_loadu_si128(src), w, offset);
+            let b = luma4(_mm_loadu_si128(src.add(1)), w, offset);
+            let packed = _mm_packus_epi16(_mm_packs_epi32(a, b), _mm_setzero_si128());
+            _mm_storel_epi64(y.as_mut_ptr().add(i) as *mut __m128i, packed);
+        }
+        count
+    }
+
+    pub(super) unsafe fn chroma_row(
+        top: &[u8],
+        bottom: &[u8],
+        u: &mut [u8],
+        v: &mut [u8],
+        c: &Coefficients,
+    ) -> usize {
+        let uw = weights(c.u);
+        let vw = weights(c.v);
+        let offset = _mm_set1_epi32(128);
+        let count = u.len().min(v.len()).min(top.len().min(bottom.len()) / 8) / 4 * 4;
+
+        for i in (0..count).step_by(4) {
+            let t = top.as_ptr().add(i * 8) as *const __m128i;
+            let b = bottom.as_ptr().add(i * 8) as *const __m128i;
+            let first = block_average(_mm_loadu_si128(t), _mm_loadu_si128(b));
+            let second = block_average(_mm_loadu_si128(t.add(1)), _mm_loadu_si128(b.add(1)));
+
+            let u4 = scale(
+                pair_sum(_mm_madd_epi16(first, uw), _mm_madd_epi16(second, uw)),
+                offset,
+            );
+            let v4 = scale(
+                pair_sum(_mm_madd_epi16(first, vw), _mm_madd_epi16(second, vw)),
+                offset,
+            );
+            let packed = _mm_packus_epi16(_mm_packs_epi32(u4, v4), _mm_setzero_si128());
+            let bytes = _mm_cvtsi128_si64(packed) as u64;
+            u[i..i + 4].copy_from_slice(&(bytes as u32).to_le_bytes());
+            v[i..i + 4].copy_from_slice(&((bytes >> 32) as u32).to_le_bytes());
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic test image with gradients, noise and hard edges
+    fn pattern(width: u32, height: u32) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let noise = state.to_le_bytes();
+                let pixel = match (x / 8 + y / 8) % 3 {
+                    0 => [(x * 7) as u8, (y * 5) as u8, ((x + y) * 3) as u8, 255],
+                    1 => [noise[0], noise[1], noise[2], noise[3]],
+                    _ => [255 * (x % 2) as u8, 255 * (y % 2) as u8, 0, 255],
+                };
+                data.extend_from_slice(&pixel);
+            }
+        }
+        data
+    }
+
+    fn convert(converter: &I420Converter, bgra: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let mut out = vec![0; i420_size(width, height)];
+        converter.convert(bgra, width, height, &mut out).unwrap();
+        out
+    }
+
+    /// Floating-point conversion of a single color
+    fn reference(matrix: ColorMatrix, range: ColorRange, [b, g, r]: [f64; 3]) -> [f64; 3] {
+        let (kr, kb) = weights(matrix);
+        let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+        let u = (b - y) / (2.0 * (1.0 - kb));
+        let v = (r - y) / (2.0 * (1.0 - kr));
+        match range {
+            ColorRange::Limited => [
+                16.0 + y * 219.0 / 255.0,
+                128.0 + u * 224.0 / 255.0,
+                128.0 + v * 224.0 / 255.0,
+            ],
+            ColorRange::Full => [y, 128.0 + u, 128.0 + v],
+        }
+    }
+
+    #[test]
+    fn test_solid_colors() {
+        let colors = [
+            [0, 0, 0],
+            [255, 255, 255],
+            [128, 128, 128],
+            [0, 0, 255],
+            [0, 255, 0],
+            [255, 0, 0],
+            [30, 200, 90],
+        ];
+        for matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709] {
+            for range in [ColorRange::Limited, ColorRange::Full] {
+                let converter = I420Converter::new(matrix, range);
+                for [b, g, r] in colors {
+                    let bgra: Vec<u8> = [b, g, r, 255].repeat(4);
+                    let out = convert(&converter, &bgra, 2, 2);
+                    let expected = reference(matrix, range, [b, g, r].map(f64::from));
+                    for (got, want) in [out[0], out[4], out[5]].iter().zip(expected) {
+                        let want = want.round().clamp(0.0, 255.0);
+                        assert!(
+                            (f64::from(*got) - want).abs() <= 1.0,
+                            "{matrix:?} {range:?} {:?}: got {got}, want {want}",
+                            [b, g, r]
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_extremes() {
+        let limited = I420Converter::new(ColorMatrix::Bt709, ColorRange::Limited);
+        let full = I420Converter::new(ColorMatrix::Bt709, ColorRange::Full);
+        let black = [0, 0, 0, 255].repeat(4);
+        let white = [255, 255, 255, 255].repeat(4);
+
+        assert_eq!(convert(&limited, &black, 2, 2), [16, 16, 16, 16, 128, 128]);
+        assert_eq!(
+            convert(&limited, &white, 2, 2),
+            [235, 235, 235, 235, 128, 128]
+        );
+        assert_eq!(convert(&full, &black, 2, 2), [0, 0, 0, 0, 128, 128]);
+        assert_eq!(convert(&full, &white, 2, 2), [255, 255, 255, 255, 128, 128]);
+    }
+
+    #[test]
+    fn test_chroma_averages_block() {
+        let converter = I420Converter::new(ColorMatrix::Bt601, ColorRange::Full);
+        // Blue and yellow pixels average to grey
+        let blue = [255, 0, 0, 255];
+        let yellow = [0, 255, 255, 255];
+        let bgra = [blue, yellow, yellow, blue].concat();
+        let out = convert(&converter, &bgra, 2, 2);
+        assert_eq!(&out[4..], &[128, 128]);
+
+        // Only the top-left pixel is red; the chroma is a quarter of red's
+        let red = [0, 0, 255, 255];
+        let black = [0, 0, 0, 255];
+        let bgra = [red, black, black, black].concat();
+        let out = convert(&converter, &bgra, 2, 2);
+        let expected = reference(ColorMatrix::Bt601, ColorRange::Full, [0.0, 0.0, 64.0]);
+        assert!((f64::from(out[4]) - expected[1]).abs() <= 1.0);
+        assert!((f64::from(out[5]) - expected[2]).abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        for (width, height) in [(64, 16), (37, 9), (1, 1), (8, 3), (1920, 4), (15, 2)] {
+            let bgra = pattern(width, height);
+            for matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709] {
+                for range in [ColorRange::Limited, ColorRange::Full] {
+                    let scalar = I420Converter::new(matrix, range).with_simd(false);
+                    let simd = I420Converter::new(matrix, range);
+                    assert_eq!(
+                        convert(&simd, &bgra, width, height),
+                        convert(&scalar, &bgra, width, height),
+                        "{width}x{height} {matrix:?} {range:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_threads_match_single_thread() {
+        for (width, height) in [(64, 64), (33, 17), (16, 2), (10, 7)] {
+            let bgra = pattern(width, height);
+            let single = convert(&I420Converter::default(), &bgra, width, height);
+            for threads in [2, 3, 8, 64] {
+                let converter = I420Converter::default().with_threads(threads);
+                assert_eq!(
+                    convert(&converter, &bgra, width, height),
+                    single,
+                    "{width}x{height} on {threads} threads"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_buffer_size_checks() {
+        let converter = I420Converter::default();
+        let mut out = vec![0; i420_size(4, 4)];
+        assert!(converter.convert(&[0; 60], 4, 4, &mut out).is_err());
+        assert!(converter.convert(&[0; 64], 4, 4, &mut out[..20]).is_err());
+        assert!(converter.convert(&[0; 64], 4, 4, &mut out).is_ok());
+        assert_eq!(i420_size(3, 3), 9 + 2 * 4);
+    }
+}
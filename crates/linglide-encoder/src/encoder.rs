@@ -3,37 +3,45 @@
 //! OpenH264 is Cisco's open-source H.264 codec that automatically downloads
 //! prebuilt binaries, making it easy to use without system dependencies.
 
-use linglide_core::{Error, Result};
+use crate::convert::{i420_size, I420Converter};
+use crate::video_encoder::VideoEncoder;
+use linglide_core::{
+    unix_time_us, ColorSpace, EncoderSettings, Error, H264Profile, Result, VideoCodec,
+};
 use openh264::encoder::{Encoder, EncoderConfig};
-use openh264::formats::YUVBuffer;
+use openh264::formats::{YUVBuffer, YUVSlices};
 use openh264::OpenH264API;
-use tracing::{debug, info};
+use openh264_sys2::{
+    SBitrateInfo, SEncParamExt, ENCODER_OPTION_BITRATE, ENCODER_OPTION_FRAME_RATE,
+    ENCODER_OPTION_SVC_ENCODE_PARAM_EXT, PRO_BASELINE, PRO_HIGH, SM_FIXEDSLCNUM_SLICE,
+    SM_SINGLE_SLICE, SPATIAL_LAYER_ALL,
+};
+use std::os::raw::c_int;
+use std::ptr::addr_of_mut;
+use tracing::{debug, info, warn};
 
 /// H.264 encoder wrapper with low-latency settings
 pub struct H264Encoder {
     encoder: Encoder,
     width: u32,
     height: u32,
+    fps: u32,
+    bitrate: u32,
+    initialized: bool,
+    settings: EncoderSettings,
     frame_count: i64,
+    converter: I420Converter,
     yuv_buffer: Vec<u8>,
 }
 
 impl H264Encoder {
     /// Create a new H.264 encoder
     pub fn new(width: u32, height: u32, fps: u32, bitrate: u32) -> Result<Self> {
-        let config = EncoderConfig::new()
-            .max_frame_rate(fps as f32)
-            .rate_control_mode(openh264::encoder::RateControlMode::Bitrate)
-            .set_bitrate_bps(bitrate * 1000) // Convert kbps to bps
-            .enable_skip_frame(false);
-
-        let api = OpenH264API::from_source();
-        let encoder = Encoder::with_api_config(api, config)
-            .map_err(|e| Error::EncoderError(format!("Failed to create encoder: {}", e)))?;
+        let encoder = create_encoder(fps, bitrate)?;
 
         // Pre-allocate YUV buffer (I420 format: Y + U/4 + V/4)
-        let yuv_size = (width * height * 3 / 2) as usize;
-        let yuv_buffer = vec![0u8; yuv_size];
+        let yuv_buffer = vec![0u8; i420_size(width, height)];
+        let converter = I420Converter::default().with_threads(conversion_threads(width, height));
 
         info!(
             "H.264 encoder initialized: {}x{} @ {} fps, {} kbps",
@@ -44,59 +52,159 @@ impl H264Encoder {
             encoder,
             width,
             height,
+            fps,
+            bitrate,
+            initialized: false,
+            settings: EncoderSettings::default(),
             frame_count: 0,
+            converter,
             yuv_buffer,
         })
     }
 
-    /// Convert BGRA to YUV420 (I420) format
-    fn bgra_to_yuv420(&mut self, bgra: &[u8]) {
-        let width = self.width as usize;
-        let height = self.height as usize;
-        let y_size = width * height;
-        let uv_size = y_size / 4;
-
-        // Split buffer into planes safely
-        let (y_plane, uv_planes) = self.yuv_buffer.split_at_mut(y_size);
-        let (u_plane, v_plane) = uv_planes.split_at_mut(uv_size);
-
-        // Convert each pixel
-        for y in 0..height {
-            for x in 0..width {
-                let bgra_idx = (y * width + x) * 4;
-                if bgra_idx + 2 >= bgra.len() {
-                    continue;
-                }
-                let b = bgra[bgra_idx] as i32;
-                let g = bgra[bgra_idx + 1] as i32;
-                let r = bgra[bgra_idx + 2] as i32;
-
-                // BT.601 conversion
-                let y_val = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
-                y_plane[y * width + x] = y_val.clamp(0, 255) as u8;
-
-                // Subsample U and V (2x2 blocks)
-                if y % 2 == 0 && x % 2 == 0 {
-                    let uv_idx = (y / 2) * (width / 2) + (x / 2);
-                    let u_val = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
-                    let v_val = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
-                    u_plane[uv_idx] = u_val.clamp(0, 255) as u8;
-                    v_plane[uv_idx] = v_val.clamp(0, 255) as u8;
-                }
-            }
+    /// Builder pattern: set the color space frames are converted to
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.converter = I420Converter::from_color_space(color_space)
+            .with_threads(conversion_threads(self.width, self.height));
+        self
+    }
+
+    /// Builder pattern: set GOP, profile, slice and QP settings
+    ///
+    /// Applied when the encoder initializes on the first frame.
+    pub fn with_settings(mut self, settings: EncoderSettings) -> Self {
+        if settings.intra_refresh {
+            warn!("OpenH264 does not support intra refresh, sending keyframes instead");
+        }
+        self.settings = settings;
+        self
+    }
+
+    /// Get the color space frames are converted to
+    pub fn color_space(&self) -> ColorSpace {
+        self.converter.color_space()
+    }
+
+    /// Initialize OpenH264 and apply the encoder settings
+    ///
+    /// The wrapper configures OpenH264 lazily on the first frame, using its
+    /// own defaults. A grey frame is encoded to trigger that, then the full
+    /// parameter set is adjusted on the live encoder. Changes that affect the
+    /// SPS reset the encoder, so the next frame is an IDR with new headers.
+    fn initialize(&mut self) -> Result<()> {
+        if self.initialized {
+            return Ok(());
+        }
+
+        let grey = vec![128u8; i420_size(self.width, self.height)];
+        let yuv = YUVBuffer::from_vec(grey, self.width as usize, self.height as usize);
+        self.encoder
+            .encode(&yuv)
+            .map_err(|e| Error::EncoderError(format!("Failed to initialize encoder: {}", e)))?;
+        self.initialized = true;
+
+        self.apply_settings()?;
+        self.encoder.force_intra_frame();
+        Ok(())
+    }
+
+    fn apply_settings(&mut self) -> Result<()> {
+        let settings = self.settings;
+        let color_space = self.converter.color_space();
+
+        let mut params = SEncParamExt::default();
+        // SAFETY: OpenH264 copies its current parameters into `params`
+        let ret = unsafe {
+            self.encoder.raw_api().get_option(
+                ENCODER_OPTION_SVC_ENCODE_PARAM_EXT,
+                addr_of_mut!(params).cast(),
+            )
+        };
+        if ret != 0 {
+            return Err(Error::EncoderError(format!(
+                "Failed to read encoder parameters (error {})",
+                ret
+            )));
         }
+
+        params.uiIntraPeriod = settings.gop;
+        params.iMinQp = c_int::from(settings.min_qp);
+        params.iMaxQp = c_int::from(settings.max_qp);
+        params.iEntropyCodingModeFlag = c_int::from(settings.profile == H264Profile::High);
+
+        let layer = &mut params.sSpatialLayers[0];
+        layer.uiProfileIdc = match settings.profile {
+            H264Profile::ConstrainedBaseline => PRO_BASELINE,
+            H264Profile::High => PRO_HIGH,
+        };
+        if settings.slices == 1 {
+            layer.sSliceArgument.uiSliceMode = SM_SINGLE_SLICE;
+        } else {
+            // A slice count of 0 lets OpenH264 pick one per core
+            layer.sSliceArgument.uiSliceMode = SM_FIXEDSLCNUM_SLICE;
+            layer.sSliceArgument.uiSliceNum = settings.slices;
+        }
+
+        // Signal the conversion matrix and range in the SPS VUI
+        layer.bVideoSignalTypePresent = true;
+        layer.uiVideoFormat = 5; // unspecified
+        layer.bFullRange = color_space.is_full_range();
+        layer.bColorDescriptionPresent = true;
+        layer.uiColorPrimaries = color_space.primaries_code();
+        layer.uiTransferCharacteristics = color_space.transfer_code();
+        layer.uiColorMatrix = color_space.matrix_code();
+
+        // SAFETY: `params` is a complete parameter set read back above and
+        // outlives the call
+        let ret = unsafe {
+            self.encoder.raw_api().set_option(
+                ENCODER_OPTION_SVC_ENCODE_PARAM_EXT,
+                addr_of_mut!(params).cast(),
+            )
+        };
+        if ret != 0 {
+            return Err(Error::EncoderError(format!(
+                "Failed to apply encoder settings (error {})",
+                ret
+            )));
+        }
+
+        debug!("OpenH264 settings applied: {:?}", settings);
+        Ok(())
     }
 
-    /// Encode a frame from BGRA data
-    pub fn encode(&mut self, bgra: &[u8]) -> Result<EncodedFrame> {
+    /// Get frame count
+    pub fn frame_count(&self) -> i64 {
+        self.frame_count
+    }
+}
+
+impl VideoEncoder for H264Encoder {
+    fn name(&self) -> &'static str {
+        "openh264"
+    }
+
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::H264
+    }
+
+    fn encode(&mut self, bgra: &[u8]) -> Result<EncodedFrame> {
+        self.initialize()?;
+
         // Convert BGRA to YUV420
-        self.bgra_to_yuv420(bgra);
+        self.converter
+            .convert(bgra, self.width, self.height, &mut self.yuv_buffer)?;
+        let converted_us = unix_time_us();
 
-        // Create YUV buffer for openh264 from our converted data
-        let yuv = YUVBuffer::from_vec(
-            self.yuv_buffer.clone(),
-            self.width as usize,
-            self.height as usize,
+        // Borrow the converted planes rather than copying them
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let (y_plane, uv_planes) = self.yuv_buffer.split_at(width * height);
+        let (u_plane, v_plane) = uv_planes.split_at(width * height / 4);
+        let yuv = YUVSlices::new(
+            (y_plane, u_plane, v_plane),
+            (width, height),
+            (width, width / 2, width / 2),
         );
 
         // Encode the frame
@@ -106,7 +214,7 @@ impl H264Encoder {
             .map_err(|e| Error::EncoderError(format!("Encoding failed: {}", e)))?;
 
         let bytes = bitstream.to_vec();
-        let is_keyframe = self.check_keyframe(&bytes);
+        let is_keyframe = check_keyframe(&bytes);
 
         // Debug first frame to understand NAL format
         if self.frame_count == 0 {
@@ -130,6 +238,7 @@ impl H264Encoder {
             data: bytes,
             pts,
             is_keyframe,
+            converted_us,
         };
 
         self.frame_count += 1;
@@ -137,55 +246,81 @@ impl H264Encoder {
         Ok(frame)
     }
 
-    /// Check if NAL data contains a keyframe
-    fn check_keyframe(&self, bytes: &[u8]) -> bool {
-        let mut has_idr = false;
-        let mut has_sps = false;
-
-        // Look for NAL units with 4-byte start code
-        for i in 0..bytes.len().saturating_sub(4) {
-            if bytes[i] == 0
-                && bytes[i + 1] == 0
-                && bytes[i + 2] == 0
-                && bytes[i + 3] == 1
-                && i + 4 < bytes.len()
-            {
-                let nal_type = bytes[i + 4] & 0x1F;
-                if nal_type == 5 {
-                    has_idr = true;
-                }
-                if nal_type == 7 {
-                    has_sps = true;
-                }
-            }
+    fn force_keyframe(&mut self) {
+        self.encoder.force_intra_frame();
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        if self.bitrate == bitrate {
+            return Ok(());
         }
+        self.bitrate = bitrate;
 
-        // Also check 3-byte start codes
-        for i in 0..bytes.len().saturating_sub(3) {
-            if bytes[i] == 0
-                && bytes[i + 1] == 0
-                && bytes[i + 2] == 1
-                && (i == 0 || bytes[i - 1] != 0)
-                && i + 3 < bytes.len()
-            {
-                let nal_type = bytes[i + 3] & 0x1F;
-                if nal_type == 5 {
-                    has_idr = true;
-                }
-                if nal_type == 7 {
-                    has_sps = true;
-                }
-            }
+        // OpenH264 initializes lazily on the first frame; until then the
+        // option can't be set, so start over with the new configuration
+        if !self.initialized {
+            self.encoder = create_encoder(self.fps, bitrate)?;
+            return Ok(());
         }
 
-        // Frame is a keyframe if it has SPS or IDR
-        has_idr || has_sps
+        let mut info = SBitrateInfo {
+            iLayer: SPATIAL_LAYER_ALL,
+            iBitrate: (bitrate * 1000) as c_int,
+        };
+        // SAFETY: the target bitrate is a runtime option that doesn't affect
+        // any state we hold; `info` outlives the call
+        let ret = unsafe {
+            self.encoder
+                .raw_api()
+                .set_option(ENCODER_OPTION_BITRATE, addr_of_mut!(info).cast())
+        };
+        if ret != 0 {
+            return Err(Error::EncoderError(format!(
+                "Failed to set bitrate to {} kbps (error {})",
+                bitrate, ret
+            )));
+        }
+
+        debug!("OpenH264 bitrate set to {} kbps", bitrate);
+        Ok(())
     }
 
-    /// Get encoder headers (SPS/PPS)
-    pub fn get_headers(&mut self) -> Result<Vec<u8>> {
-        // OpenH264 includes SPS/PPS in the first keyframe
-        // We'll generate a dummy frame to get the headers
+    fn set_frame_rate(&mut self, fps: u32) -> Result<()> {
+        if self.fps == fps {
+            return Ok(());
+        }
+        self.fps = fps;
+
+        if !self.initialized {
+            self.encoder = create_encoder(fps, self.bitrate)?;
+            return Ok(());
+        }
+
+        let mut rate = fps as f32;
+        // SAFETY: the frame rate is a runtime option that doesn't affect any
+        // state we hold; `rate` outlives the call
+        let ret = unsafe {
+            self.encoder
+                .raw_api()
+                .set_option(ENCODER_OPTION_FRAME_RATE, addr_of_mut!(rate).cast())
+        };
+        if ret != 0 {
+            return Err(Error::EncoderError(format!(
+                "Failed to set frame rate to {} fps (error {})",
+                fps, ret
+            )));
+        }
+
+        debug!("OpenH264 frame rate set to {} fps", fps);
+        Ok(())
+    }
+
+    fn parameter_sets(&mut self) -> Result<Vec<u8>> {
+        self.initialize()?;
+
+        // OpenH264 includes SPS/PPS in keyframes
+        // We'll generate a dummy keyframe to get the headers
+        self.encoder.force_intra_frame();
         let dummy_yuv = vec![128u8; (self.width * self.height * 3 / 2) as usize];
 
         let yuv = YUVBuffer::from_vec(dummy_yuv, self.width as usize, self.height as usize);
@@ -233,22 +368,91 @@ impl H264Encoder {
             Ok(headers)
         }
     }
+}
 
-    /// Get frame count
-    pub fn frame_count(&self) -> i64 {
-        self.frame_count
+/// Create an OpenH264 encoder with low-latency rate control
+fn create_encoder(fps: u32, bitrate: u32) -> Result<Encoder> {
+    let config = EncoderConfig::new()
+        .max_frame_rate(fps as f32)
+        .rate_control_mode(openh264::encoder::RateControlMode::Bitrate)
+        .set_bitrate_bps(bitrate * 1000) // Convert kbps to bps
+        .enable_skip_frame(false);
+
+    let api = OpenH264API::from_source();
+    Encoder::with_api_config(api, config)
+        .map_err(|e| Error::EncoderError(format!("Failed to create encoder: {}", e)))
+}
+
+/// Check if NAL data contains a keyframe
+fn check_keyframe(bytes: &[u8]) -> bool {
+    let mut has_idr = false;
+    let mut has_sps = false;
+
+    // Look for NAL units with 4-byte start code
+    for i in 0..bytes.len().saturating_sub(4) {
+        if bytes[i] == 0
+            && bytes[i + 1] == 0
+            && bytes[i + 2] == 0
+            && bytes[i + 3] == 1
+            && i + 4 < bytes.len()
+        {
+            let nal_type = bytes[i + 4] & 0x1F;
+            if nal_type == 5 {
+                has_idr = true;
+            }
+            if nal_type == 7 {
+                has_sps = true;
+            }
+        }
+    }
+
+    // Also check 3-byte start codes
+    for i in 0..bytes.len().saturating_sub(3) {
+        if bytes[i] == 0
+            && bytes[i + 1] == 0
+            && bytes[i + 2] == 1
+            && (i == 0 || bytes[i - 1] != 0)
+            && i + 3 < bytes.len()
+        {
+            let nal_type = bytes[i + 3] & 0x1F;
+            if nal_type == 5 {
+                has_idr = true;
+            }
+            if nal_type == 7 {
+                has_sps = true;
+            }
+        }
+    }
+
+    // Frame is a keyframe if it has SPS or IDR
+    has_idr || has_sps
+}
+
+/// Number of threads used for color conversion at this resolution
+///
+/// Splitting only pays off once a frame is large enough to outweigh the cost
+/// of spawning the helper threads.
+fn conversion_threads(width: u32, height: u32) -> usize {
+    if width * height < 1280 * 720 {
+        return 1;
     }
+    std::thread::available_parallelism()
+        .map(|n| n.get().min(4))
+        .unwrap_or(1)
 }
 
 /// Represents an encoded video frame
 #[derive(Clone)]
 pub struct EncodedFrame {
-    /// Encoded NAL data
+    /// Encoded access unit: Annex B NAL units, or OBUs for AV1
     pub data: Vec<u8>,
     /// Presentation timestamp
     pub pts: i64,
     /// Whether this is a keyframe
     pub is_keyframe: bool,
+    /// When the input finished converting to YUV, in microseconds since the
+    /// Unix epoch
+    pub converted_us: u64,
 }
 
 impl std::fmt::Debug for EncodedFrame {
@@ -257,6 +461,7 @@ impl std::fmt::Debug for EncodedFrame {
             .field("size", &self.data.len())
             .field("pts", &self.pts)
             .field("is_keyframe", &self.is_keyframe)
+            .field("converted_us", &self.converted_us)
             .finish()
     }
 }
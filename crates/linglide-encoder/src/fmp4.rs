@@ -1,16 +1,47 @@
 //! Fragmented MP4 muxer for browser-compatible streaming
 
+use crate::codec_config::{
+    annexb_nal_units, av1_obus, h264_level, Av1SequenceInfo, HevcProfileTierLevel,
+    OBU_SEQUENCE_HEADER,
+};
 use crate::encoder::EncodedFrame;
 use bytes::{BufMut, BytesMut};
+use linglide_core::{ColorSpace, H264Profile, VideoCodec};
 
-/// Fragmented MP4 muxer for H.264 streams
+/// Ticks per second of the media timeline
+pub const TIMESCALE: u32 = 90_000;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// `prft` flag: the wall-clock time is when the frame was captured
+const PRFT_CAPTURED: u32 = 24;
+
+/// Fragmented MP4 muxer for H.264, HEVC and AV1 streams
+///
+/// Decode times come from the capture timestamps passed with each frame, so
+/// the timeline follows the real frame rate, including frames skipped on a
+/// static screen. Each segment also carries a `prft` box mapping its decode
+/// time to the capture wall-clock time for latency measurement.
 pub struct Fmp4Muxer {
     width: u32,
     height: u32,
-    timescale: u32,
+    fps: u32,
+    /// Nominal sample duration at the current frame rate, in timescale ticks
+    frame_duration: u32,
+    /// Capture time of the first sample, the origin of the timeline
+    base_timestamp_us: Option<u64>,
+    last_decode_time: Option<u64>,
     sequence_number: u32,
+    codec: VideoCodec,
+    /// HEVC video parameter set
+    vps: Vec<u8>,
     sps: Vec<u8>,
     pps: Vec<u8>,
+    /// AV1 sequence header OBU
+    sequence_header: Vec<u8>,
+    color_space: ColorSpace,
+    profile: H264Profile,
 }
 
 impl Fmp4Muxer {
@@ -19,87 +50,178 @@ impl Fmp4Muxer {
         Self {
             width,
             height,
-            timescale: fps * 1000, // Higher timescale for precision
+            fps,
+            frame_duration: TIMESCALE / fps.max(1),
+            base_timestamp_us: None,
+            last_decode_time: None,
             sequence_number: 1,
+            codec: VideoCodec::default(),
+            vps: Vec::new(),
             sps: Vec::new(),
             pps: Vec::new(),
+            sequence_header: Vec::new(),
+            color_space: ColorSpace::default(),
+            profile: H264Profile::default(),
         }
     }
 
-    /// Get the codec string for WebCodecs (avc1.PPCCLL format)
+    /// Builder pattern: set the color space signalled in the sample entry
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Get the timescale (ticks per second) of the media timeline
+    pub fn timescale(&self) -> u32 {
+        TIMESCALE
+    }
+
+    /// Update the frame rate used to bound sample durations
+    pub fn set_frame_rate(&mut self, fps: u32) {
+        self.frame_duration = TIMESCALE / fps.max(1);
+    }
+
+    /// Builder pattern: set the codec of the stream
+    pub fn with_codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Builder pattern: set the H.264 profile the encoder was configured with
+    ///
+    /// Only used to describe the stream before the SPS has been seen.
+    pub fn with_profile(mut self, profile: H264Profile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Get the codec of the stream
+    pub fn codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    /// H.264 profile, constraint flags and level of the stream
+    ///
+    /// Read from the SPS; without one, derived from the configured profile
+    /// and the lowest level that fits the resolution and frame rate.
+    fn profile_level(&self) -> [u8; 3] {
+        match self.sps.get(1..4) {
+            Some(bytes) => [bytes[0], bytes[1], bytes[2]],
+            None => [
+                self.profile.profile_idc(),
+                self.profile.constraint_flags(),
+                h264_level(self.width, self.height, self.fps),
+            ],
+        }
+    }
+
+    /// HEVC profile, tier and level, from the SPS when available
+    fn hevc_profile_tier_level(&self) -> HevcProfileTierLevel {
+        HevcProfileTierLevel::from_sps(&self.sps)
+            .unwrap_or_else(|| HevcProfileTierLevel::main(self.width, self.height, self.fps))
+    }
+
+    /// AV1 stream properties, from the sequence header when available
+    fn av1_sequence_info(&self) -> Av1SequenceInfo {
+        av1_obus(&self.sequence_header)
+            .first()
+            .and_then(|obu| Av1SequenceInfo::parse(obu.payload))
+            .unwrap_or_else(|| Av1SequenceInfo::main(self.width, self.height, self.fps))
+    }
+
+    /// Get the codec string for WebCodecs
+    ///
+    /// `avc1.PPCCLL` for H.264, `hvc1.P.C.TL.B` for HEVC and `av01.P.LLT.DD`
+    /// for AV1.
     pub fn get_codec_string(&self) -> String {
-        if self.sps.len() >= 4 {
-            format!(
-                "avc1.{:02x}{:02x}{:02x}",
-                self.sps[1], self.sps[2], self.sps[3]
-            )
-        } else {
-            // Fallback: High profile, level 4.2
-            "avc1.64002a".to_string()
+        match self.codec {
+            VideoCodec::H264 => {
+                let [profile, constraints, level] = self.profile_level();
+                format!("avc1.{:02x}{:02x}{:02x}", profile, constraints, level)
+            }
+            VideoCodec::Hevc => self.hevc_profile_tier_level().codec_string(),
+            VideoCodec::Av1 => self.av1_sequence_info().codec_string(),
         }
     }
 
-    /// Get the avcC box data for WebCodecs description
-    pub fn get_avcc_data(&self) -> Vec<u8> {
-        let mut buf = BytesMut::new();
-        buf.put_u8(1); // version
-        buf.put_u8(if self.sps.len() > 1 {
-            self.sps[1]
-        } else {
-            0x64
-        }); // profile
-        buf.put_u8(if self.sps.len() > 2 {
-            self.sps[2]
-        } else {
-            0x00
-        }); // profile compat
-        buf.put_u8(if self.sps.len() > 3 {
-            self.sps[3]
-        } else {
-            0x2a
-        }); // level
-        buf.put_u8(0xFF); // length size minus one (3 = 4 bytes)
-        buf.put_u8(0xE1); // num SPS (1)
-        buf.put_u16(self.sps.len() as u16);
-        buf.put_slice(&self.sps);
-        buf.put_u8(1); // num PPS
-        buf.put_u16(self.pps.len() as u16);
-        buf.put_slice(&self.pps);
-        buf.to_vec()
+    /// Get the decoder configuration record (avcC, hvcC or av1C box content)
+    /// for the WebCodecs description
+    pub fn get_codec_description(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self.codec {
+            VideoCodec::H264 => {
+                buf.push(1); // version
+                buf.extend_from_slice(&self.profile_level()); // profile, profile compat, level
+                buf.push(0xFF); // length size minus one (3 = 4 bytes)
+                buf.push(0xE1); // num SPS (1)
+                buf.extend_from_slice(&(self.sps.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&self.sps);
+                buf.push(1); // num PPS
+                buf.extend_from_slice(&(self.pps.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&self.pps);
+            }
+            VideoCodec::Hevc => {
+                let ptl = self.hevc_profile_tier_level();
+                buf.push(1); // version
+                ptl.write_record_fields(&mut buf);
+                buf.extend_from_slice(&0xF000u16.to_be_bytes()); // no min spatial segmentation
+                buf.push(0xFC); // parallelism unknown
+                buf.push(0xFD); // chroma format 4:2:0
+                buf.push(0xF8); // luma bit depth 8
+                buf.push(0xF8); // chroma bit depth 8
+                buf.extend_from_slice(&0u16.to_be_bytes()); // average frame rate unknown
+                                                            // temporal layers, temporal id nesting, length size minus one (3 = 4 bytes)
+                buf.push((ptl.sub_layers << 3) | (u8::from(ptl.temporal_id_nested) << 2) | 3);
+
+                let arrays = [(32, &self.vps), (33, &self.sps), (34, &self.pps)];
+                buf.push(arrays.iter().filter(|(_, nal)| !nal.is_empty()).count() as u8);
+                for (nal_type, nal) in arrays.iter().filter(|(_, nal)| !nal.is_empty()) {
+                    buf.push(0x80 | nal_type); // complete array of this NAL type
+                    buf.extend_from_slice(&1u16.to_be_bytes());
+                    buf.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+                    buf.extend_from_slice(nal);
+                }
+            }
+            VideoCodec::Av1 => {
+                buf.extend_from_slice(&self.av1_sequence_info().record_header());
+                buf.extend_from_slice(&self.sequence_header);
+            }
+        }
+        buf
     }
 
-    /// Parse SPS and PPS from H.264 headers
+    /// Store the parameter sets from the encoder's headers
+    ///
+    /// H.264 and HEVC headers are Annex B NAL units; AV1 headers are OBUs
+    /// containing the sequence header.
     pub fn set_headers(&mut self, headers: &[u8]) {
-        let mut i = 0;
-        while i + 4 < headers.len() {
-            // Look for start codes
-            if headers[i] == 0 && headers[i + 1] == 0 && headers[i + 2] == 0 && headers[i + 3] == 1
-            {
-                let start = i + 4;
-                // Find next start code or end
-                let mut end = headers.len();
-                for j in start..headers.len().saturating_sub(3) {
-                    if headers[j] == 0
-                        && headers[j + 1] == 0
-                        && headers[j + 2] == 0
-                        && headers[j + 3] == 1
-                    {
-                        end = j;
-                        break;
+        match self.codec {
+            VideoCodec::H264 => {
+                for nal in annexb_nal_units(headers) {
+                    match nal[0] & 0x1F {
+                        7 => self.sps = nal.to_vec(), // SPS
+                        8 => self.pps = nal.to_vec(), // PPS
+                        _ => {}
                     }
                 }
-
-                if start < end {
-                    let nal_type = headers[start] & 0x1F;
-                    match nal_type {
-                        7 => self.sps = headers[start..end].to_vec(), // SPS
-                        8 => self.pps = headers[start..end].to_vec(), // PPS
+            }
+            VideoCodec::Hevc => {
+                for nal in annexb_nal_units(headers) {
+                    match (nal[0] >> 1) & 0x3F {
+                        32 => self.vps = nal.to_vec(), // VPS
+                        33 => self.sps = nal.to_vec(), // SPS
+                        34 => self.pps = nal.to_vec(), // PPS
                         _ => {}
                     }
                 }
-                i = end;
-            } else {
-                i += 1;
+            }
+            VideoCodec::Av1 => {
+                if let Some(obu) = av1_obus(headers)
+                    .into_iter()
+                    .find(|obu| obu.obu_type == OBU_SEQUENCE_HEADER)
+                {
+                    self.sequence_header = obu.data.to_vec();
+                }
             }
         }
     }
@@ -117,12 +239,17 @@ impl Fmp4Muxer {
         buf.to_vec()
     }
 
-    /// Create a media segment for the given frame
-    pub fn create_media_segment(&mut self, frame: &EncodedFrame, duration: u32) -> Vec<u8> {
+    /// Create a media segment for a frame captured at `timestamp_us`
+    /// (microseconds since the Unix epoch)
+    pub fn create_media_segment(&mut self, frame: &EncodedFrame, timestamp_us: u64) -> Vec<u8> {
+        let (decode_time, duration) = self.sample_timing(timestamp_us);
         let mut buf = BytesMut::new();
 
+        // prft box
+        self.write_prft(&mut buf, timestamp_us, decode_time);
+
         // moof box
-        self.write_moof(&mut buf, frame, duration);
+        self.write_moof(&mut buf, frame, decode_time, duration);
 
         // mdat box
         self.write_mdat(&mut buf, &frame.data);
@@ -132,6 +259,28 @@ impl Fmp4Muxer {
         buf.to_vec()
     }
 
+    /// Decode time and duration of the next sample, in timescale ticks
+    ///
+    /// The duration is the interval since the previous sample, capped at the
+    /// nominal frame duration so that a frame following a pause in encoding
+    /// doesn't claim the whole gap; the gap stays visible in the decode time.
+    fn sample_timing(&mut self, timestamp_us: u64) -> (u64, u32) {
+        let base = *self.base_timestamp_us.get_or_insert(timestamp_us);
+        let mut decode_time = timestamp_us.saturating_sub(base) * u64::from(TIMESCALE) / 1_000_000;
+
+        let duration = match self.last_decode_time {
+            Some(last) => {
+                // Capture clocks may repeat or step back; keep decode times increasing
+                decode_time = decode_time.max(last + 1);
+                (decode_time - last).min(u64::from(self.frame_duration)) as u32
+            }
+            None => self.frame_duration,
+        };
+        self.last_decode_time = Some(decode_time);
+
+        (decode_time, duration)
+    }
+
     fn write_box(buf: &mut BytesMut, box_type: &[u8; 4], content: &[u8]) {
         let size = 8 + content.len() as u32;
         buf.put_u32(size);
@@ -143,7 +292,8 @@ impl Fmp4Muxer {
         let mut content = BytesMut::new();
         content.put_slice(b"isom"); // major brand
         content.put_u32(0x200); // minor version
-        content.put_slice(b"isomiso2avc1mp41"); // compatible brands
+        content.put_slice(b"isomiso2mp41"); // compatible brands
+        content.put_slice(box_types(self.codec).0);
         Self::write_box(buf, b"ftyp", &content);
     }
 
@@ -161,7 +311,7 @@ impl Fmp4Muxer {
         content.put_slice(&[0, 0, 0]); // flags
         content.put_u32(0); // creation time
         content.put_u32(0); // modification time
-        content.put_u32(self.timescale); // timescale
+        content.put_u32(TIMESCALE); // timescale
         content.put_u32(0); // duration
         content.put_u32(0x00010000); // rate (1.0)
         content.put_u16(0x0100); // volume (1.0)
@@ -235,7 +385,7 @@ impl Fmp4Muxer {
         content.put_slice(&[0, 0, 0]); // flags
         content.put_u32(0); // creation time
         content.put_u32(0); // modification time
-        content.put_u32(self.timescale);
+        content.put_u32(TIMESCALE);
         content.put_u32(0); // duration
         content.put_u16(0x55C4); // language (und)
         content.put_u16(0); // pre-defined
@@ -311,12 +461,12 @@ impl Fmp4Muxer {
         content.put_slice(&[0, 0, 0]); // flags
         content.put_u32(1); // entry count
 
-        self.write_avc1(&mut content);
+        self.write_sample_entry(&mut content);
 
         Self::write_box(buf, b"stsd", &content);
     }
 
-    fn write_avc1(&self, buf: &mut BytesMut) {
+    fn write_sample_entry(&self, buf: &mut BytesMut) {
         let mut content = BytesMut::new();
         content.put_slice(&[0; 6]); // reserved
         content.put_u16(1); // data reference index
@@ -335,37 +485,25 @@ impl Fmp4Muxer {
         content.put_u16(0x0018); // depth (24-bit color)
         content.put_i16(-1); // pre-defined
 
-        self.write_avcc(&mut content);
+        let (sample_entry, config_box) = box_types(self.codec);
+        Self::write_box(&mut content, config_box, &self.get_codec_description());
+        self.write_colr(&mut content);
 
-        Self::write_box(buf, b"avc1", &content);
+        Self::write_box(buf, sample_entry, &content);
     }
 
-    fn write_avcc(&self, buf: &mut BytesMut) {
+    fn write_colr(&self, buf: &mut BytesMut) {
         let mut content = BytesMut::new();
-        content.put_u8(1); // version
-        content.put_u8(if self.sps.len() > 1 {
-            self.sps[1]
-        } else {
-            0x64
-        }); // profile
-        content.put_u8(if self.sps.len() > 2 {
-            self.sps[2]
+        content.put_slice(b"nclx"); // colour type: on-screen colours
+        content.put_u16(self.color_space.primaries_code() as u16);
+        content.put_u16(self.color_space.transfer_code() as u16);
+        content.put_u16(self.color_space.matrix_code() as u16);
+        content.put_u8(if self.color_space.is_full_range() {
+            0x80
         } else {
-            0x00
-        }); // profile compat
-        content.put_u8(if self.sps.len() > 3 {
-            self.sps[3]
-        } else {
-            0x1F
-        }); // level
-        content.put_u8(0xFF); // length size minus one (3 = 4 bytes)
-        content.put_u8(0xE1); // num SPS (1)
-        content.put_u16(self.sps.len() as u16);
-        content.put_slice(&self.sps);
-        content.put_u8(1); // num PPS
-        content.put_u16(self.pps.len() as u16);
-        content.put_slice(&self.pps);
-        Self::write_box(buf, b"avcC", &content);
+            0
+        }); // full range flag + reserved
+        Self::write_box(buf, b"colr", &content);
     }
 
     fn write_stts(&self, buf: &mut BytesMut) {
@@ -419,10 +557,29 @@ impl Fmp4Muxer {
         Self::write_box(buf, b"trex", &content);
     }
 
-    fn write_moof(&self, buf: &mut BytesMut, frame: &EncodedFrame, duration: u32) {
+    fn write_prft(&self, buf: &mut BytesMut, timestamp_us: u64, decode_time: u64) {
+        let seconds = timestamp_us / 1_000_000 + NTP_UNIX_OFFSET;
+        let fraction = ((timestamp_us % 1_000_000) << 32) / 1_000_000;
+
+        let mut content = BytesMut::new();
+        content.put_u8(1); // version 1 for 64-bit media time
+        content.put_uint(u64::from(PRFT_CAPTURED), 3);
+        content.put_u32(1); // reference track ID
+        content.put_u64((seconds << 32) | fraction); // NTP timestamp
+        content.put_u64(decode_time); // media time
+        Self::write_box(buf, b"prft", &content);
+    }
+
+    fn write_moof(
+        &self,
+        buf: &mut BytesMut,
+        frame: &EncodedFrame,
+        decode_time: u64,
+        duration: u32,
+    ) {
         let mut content = BytesMut::new();
         self.write_mfhd(&mut content);
-        self.write_traf(&mut content, frame, duration);
+        self.write_traf(&mut content, frame, decode_time, duration);
         Self::write_box(buf, b"moof", &content);
     }
 
@@ -434,10 +591,16 @@ impl Fmp4Muxer {
         Self::write_box(buf, b"mfhd", &content);
     }
 
-    fn write_traf(&self, buf: &mut BytesMut, frame: &EncodedFrame, duration: u32) {
+    fn write_traf(
+        &self,
+        buf: &mut BytesMut,
+        frame: &EncodedFrame,
+        decode_time: u64,
+        duration: u32,
+    ) {
         let mut content = BytesMut::new();
         self.write_tfhd(&mut content);
-        self.write_tfdt(&mut content, frame.pts as u64 * duration as u64);
+        self.write_tfdt(&mut content, decode_time);
         self.write_trun(&mut content, frame, duration);
         Self::write_box(buf, b"traf", &content);
     }
@@ -496,3 +659,177 @@ impl Fmp4Muxer {
         buf.put_slice(data);
     }
 }
+
+/// Sample entry and decoder configuration box types of a codec
+fn box_types(codec: VideoCodec) -> (&'static [u8; 4], &'static [u8; 4]) {
+    match codec {
+        VideoCodec::H264 => (b"avc1", b"avcC"),
+        VideoCodec::Hevc => (b"hvc1", b"hvcC"),
+        VideoCodec::Av1 => (b"av01", b"av1C"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linglide_core::{ColorMatrix, ColorRange};
+
+    /// Content of the first box of the given type, searching nested boxes
+    fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> &'a [u8] {
+        let pos = data
+            .windows(4)
+            .position(|w| w == box_type)
+            .expect("box present");
+        let size = u32::from_be_bytes(data[pos - 4..pos].try_into().unwrap()) as usize;
+        &data[pos + 4..pos - 4 + size]
+    }
+
+    /// Decode time and sample duration of a media segment
+    fn timing(segment: &[u8]) -> (u64, u32) {
+        let tfdt = find_box(segment, b"tfdt");
+        let trun = find_box(segment, b"trun");
+        (
+            u64::from_be_bytes(tfdt[4..12].try_into().unwrap()),
+            u32::from_be_bytes(trun[12..16].try_into().unwrap()),
+        )
+    }
+
+    fn encoded(pts: i64) -> EncodedFrame {
+        EncodedFrame {
+            data: vec![0, 0, 0, 1, 0x65],
+            pts,
+            is_keyframe: pts == 0,
+            converted_us: 0,
+        }
+    }
+
+    #[test]
+    fn test_segment_timing_follows_capture_time() {
+        let start = 1_700_000_000_000_000;
+        let mut muxer = Fmp4Muxer::new(64, 64, 30);
+
+        // First sample starts the timeline with the nominal duration
+        let first = muxer.create_media_segment(&encoded(0), start);
+        assert_eq!(timing(&first), (0, 3000));
+
+        // A faster frame gets its real interval
+        let second = muxer.create_media_segment(&encoded(1), start + 16_667);
+        assert_eq!(timing(&second), (1500, 1500));
+
+        // Frames skipped on a static screen leave a gap without stretching the sample
+        let third = muxer.create_media_segment(&encoded(2), start + 1_000_000);
+        assert_eq!(timing(&third), (90_000, 3000));
+
+        // A clock stepping back never reorders samples
+        let fourth = muxer.create_media_segment(&encoded(3), start + 500_000);
+        assert_eq!(timing(&fourth), (90_001, 1));
+
+        muxer.set_frame_rate(15);
+        let fifth = muxer.create_media_segment(&encoded(4), start + 2_000_000);
+        assert_eq!(timing(&fifth), (180_000, 6000));
+    }
+
+    #[test]
+    fn test_prft_box() {
+        let mut muxer = Fmp4Muxer::new(64, 64, 30);
+        muxer.create_media_segment(&encoded(0), 1_000_000);
+        let segment = muxer.create_media_segment(&encoded(1), 2_500_000);
+
+        // prft precedes the moof it describes
+        assert_eq!(&segment[4..8], b"prft");
+        let prft = find_box(&segment, b"prft");
+        assert_eq!(&prft[..4], &[1, 0, 0, PRFT_CAPTURED as u8]);
+        assert_eq!(u32::from_be_bytes(prft[4..8].try_into().unwrap()), 1);
+        let ntp = u64::from_be_bytes(prft[8..16].try_into().unwrap());
+        assert_eq!(ntp >> 32, 2 + NTP_UNIX_OFFSET);
+        assert_eq!(ntp & 0xFFFF_FFFF, 1 << 31);
+        assert_eq!(
+            u64::from_be_bytes(prft[16..24].try_into().unwrap()),
+            135_000
+        );
+    }
+
+    #[test]
+    fn test_colr_box() {
+        let muxer = Fmp4Muxer::new(64, 64, 30);
+        let init = muxer.create_init_segment();
+        assert_eq!(
+            find_box(&init, b"colr"),
+            b"nclx\x00\x01\x00\x0d\x00\x01\x00"
+        );
+
+        let muxer = Fmp4Muxer::new(64, 64, 30)
+            .with_color_space(ColorSpace::new(ColorMatrix::Bt601, ColorRange::Full));
+        let init = muxer.create_init_segment();
+        assert_eq!(
+            find_box(&init, b"colr"),
+            b"nclx\x00\x01\x00\x0d\x00\x06\x80"
+        );
+    }
+
+    #[test]
+    fn test_codec_string() {
+        // Before headers are known: configured profile, level from the frame size
+        let muxer = Fmp4Muxer::new(1920, 1080, 60);
+        assert_eq!(muxer.get_codec_string(), "avc1.42c02a");
+        let muxer = Fmp4Muxer::new(1280, 720, 30).with_profile(H264Profile::High);
+        assert_eq!(muxer.get_codec_string(), "avc1.64001f");
+
+        // The SPS wins once parsed
+        let mut muxer = Fmp4Muxer::new(1920, 1080, 60);
+        muxer.set_headers(&[
+            0, 0, 0, 1, 0x67, 0x64, 0x00, 0x28, 0xac, 0, 0, 0, 1, 0x68, 0xee,
+        ]);
+        assert_eq!(muxer.get_codec_string(), "avc1.640028");
+        assert_eq!(&muxer.get_codec_description()[1..4], &[0x64, 0x00, 0x28]);
+    }
+
+    #[test]
+    fn test_hevc_init_segment() {
+        let mut muxer = Fmp4Muxer::new(1920, 1080, 60).with_codec(VideoCodec::Hevc);
+        assert_eq!(muxer.get_codec_string(), "hvc1.1.6.L123.B0");
+
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0xB0, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x5D, 0xA0,
+        ];
+        let mut headers = vec![0, 0, 0, 1, 0x40, 0x01, 0x0C, 0, 0, 0, 1];
+        headers.extend_from_slice(&sps);
+        headers.extend_from_slice(&[0, 0, 0, 1, 0x44, 0x01, 0xC1]);
+        muxer.set_headers(&headers);
+
+        // The SPS wins once parsed (level 3.1 here)
+        assert_eq!(muxer.get_codec_string(), "hvc1.1.6.L93.B0");
+        let description = muxer.get_codec_description();
+        assert_eq!(&description[..2], &[1, 0x01]);
+        assert_eq!(description[12], 93);
+        assert_eq!(description[22], 3); // VPS, SPS and PPS arrays
+
+        let init = muxer.create_init_segment();
+        assert_eq!(&find_box(&init, b"ftyp")[8..], b"isomiso2mp41hvc1");
+        assert_eq!(find_box(&init, b"hvcC"), description);
+    }
+
+    #[test]
+    fn test_av1_init_segment() {
+        let mut muxer = Fmp4Muxer::new(1920, 1080, 30).with_codec(VideoCodec::Av1);
+        assert_eq!(muxer.get_codec_string(), "av01.0.08M.08");
+
+        let sequence_header = [
+            0x0A, 0x0E, 0x00, 0x00, 0x00, 0x42, 0xAB, 0xBF, 0xC3, 0x73, 0x09, 0xE6, 0x40, 0x40,
+            0x40, 0x41,
+        ];
+        // A leading temporal delimiter is skipped
+        let mut headers = vec![0x12, 0x00];
+        headers.extend_from_slice(&sequence_header);
+        muxer.set_headers(&headers);
+
+        let description = muxer.get_codec_description();
+        assert_eq!(&description[..4], &[0x81, 0x08, 0x0C, 0]);
+        assert_eq!(&description[4..], &sequence_header);
+
+        let init = muxer.create_init_segment();
+        assert_eq!(&find_box(&init, b"ftyp")[8..], b"isomiso2mp41av01");
+        assert_eq!(find_box(&init, b"av1C"), description);
+    }
+}
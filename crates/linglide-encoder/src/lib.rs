@@ -1,11 +1,34 @@
-//! LinGlide Encoder - H.264 video encoding
+//! LinGlide Encoder - low-latency video encoding
 //!
-//! This crate provides low-latency H.264 encoding using x264.
+//! This crate provides H.264 encoding using OpenH264 or, with the `x264`
+//! feature, libx264. HEVC (`x265` feature) and AV1 (`svt-av1` feature) are
+//! available when built against the system encoder libraries.
 
+pub mod change;
+pub mod codec_config;
+pub mod convert;
 pub mod encoder;
 pub mod fmp4;
 pub mod pipeline;
+pub mod scale;
+#[cfg(feature = "svt-av1")]
+pub mod svt_av1;
+pub mod video_encoder;
+#[cfg(feature = "x264")]
+pub mod x264;
+#[cfg(feature = "x265")]
+pub mod x265;
 
+pub use change::ChangeDetector;
+pub use convert::{ColorMatrix, ColorRange, I420Converter};
 pub use encoder::H264Encoder;
 pub use fmp4::Fmp4Muxer;
-pub use pipeline::EncodingPipeline;
+pub use pipeline::{EncodingPipeline, KeyframeRequest, PipelineCommand, QualitySettings};
+pub use scale::Scaler;
+#[cfg(feature = "svt-av1")]
+pub use svt_av1::SvtAv1Encoder;
+pub use video_encoder::{create_encoder, VideoEncoder};
+#[cfg(feature = "x264")]
+pub use x264::X264Encoder;
+#[cfg(feature = "x265")]
+pub use x265::X265Encoder;
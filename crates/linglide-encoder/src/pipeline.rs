@@ -1,8 +1,16 @@
 //! Async encoding pipeline
 
-use crate::{Fmp4Muxer, H264Encoder};
-use linglide_core::{Frame, Result};
-use tokio::sync::{broadcast, mpsc};
+use crate::change::ChangeDetector;
+use crate::scale::Scaler;
+use crate::video_encoder::{create_encoder, VideoEncoder};
+use crate::Fmp4Muxer;
+use linglide_core::{
+    unix_time_us, Config, Error, Frame, FrameReceiver, Result, ScaleFilter, StageTimes, VideoCodec,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tracing::{debug, info, warn};
 
 /// Encoded segment ready for streaming
@@ -16,42 +24,167 @@ pub struct StreamSegment {
     pub is_keyframe: bool,
     /// Sequence number
     pub sequence: u64,
+    /// When the frame was captured, scaled and encoded
+    pub times: StageTimes,
+}
+
+/// Lowest bitrate accepted at runtime, in kbps
+pub const MIN_BITRATE: u32 = 100;
+/// Highest bitrate accepted at runtime, in kbps
+pub const MAX_BITRATE: u32 = 100_000;
+
+/// Minimum time between keyframes forced on request
+///
+/// Joining and lagging clients all ask for a keyframe; requests within this
+/// window are served by the same IDR.
+pub const KEYFRAME_MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Bitrate and frame rate the pipeline is encoding at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualitySettings {
+    /// Target bitrate in kbps
+    pub bitrate: u32,
+    /// Encoded frames per second
+    pub fps: u32,
+}
+
+/// Runtime request to a running pipeline
+#[derive(Debug)]
+pub enum PipelineCommand {
+    /// Change the bitrate, and optionally lower the frame rate
+    SetQuality {
+        bitrate: u32,
+        fps: Option<u32>,
+        /// Receives the settings actually applied
+        reply: oneshot::Sender<Result<QualitySettings>>,
+    },
+}
+
+/// Shared flag that asks a running pipeline for a keyframe
+///
+/// Unlike a queued command a request can't be dropped when the pipeline is
+/// busy; any number of requests before the next frame become one.
+#[derive(Debug, Clone, Default)]
+pub struct KeyframeRequest(Arc<AtomicBool>);
+
+impl KeyframeRequest {
+    /// Ask for the next encoded frame to be a keyframe
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Whether a keyframe was requested since the last call
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::AcqRel)
+    }
 }
 
 /// Async encoding pipeline that processes frames and produces stream segments
 pub struct EncodingPipeline {
-    encoder: H264Encoder,
+    encoder: Box<dyn VideoEncoder>,
     muxer: Fmp4Muxer,
-    frame_duration: u32,
+    /// Encoded size; frames of another size are scaled to it
+    width: u32,
+    height: u32,
+    scale_filter: ScaleFilter,
+    scaler: Option<Scaler>,
+    scaled: Vec<u8>,
     init_segment: Option<Vec<u8>>,
+    change_detector: ChangeDetector,
+    /// Capture frame rate; the encoded rate can only be lowered from here
+    max_fps: u32,
+    quality: QualitySettings,
+    /// A frame dropped by the frame rate cap contained changes
+    pending_change: bool,
+    last_encoded_us: Option<u64>,
+    /// A client asked for a keyframe that hasn't been encoded yet
+    keyframe_requested: bool,
+    /// Keyframe requests from outside the pipeline task
+    keyframe_request: KeyframeRequest,
+    keyframe_interval: Duration,
+    last_keyframe: Option<Instant>,
 }
 
 impl EncodingPipeline {
-    /// Create a new encoding pipeline
+    /// Create a new encoding pipeline with the default encoder
     pub fn new(width: u32, height: u32, fps: u32, bitrate: u32) -> Result<Self> {
-        let mut encoder = H264Encoder::new(width, height, fps, bitrate)?;
-        let mut muxer = Fmp4Muxer::new(width, height, fps);
+        let config = Config::new()
+            .with_width(width)
+            .with_height(height)
+            .with_fps(fps)
+            .with_bitrate(bitrate);
+        Self::from_config(&config)
+    }
+
+    /// Create a pipeline with the encoder and color space selected in `config`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Self::with_encoder(create_encoder(config)?, config)
+    }
+
+    /// Create a pipeline around an existing encoder
+    pub fn with_encoder(mut encoder: Box<dyn VideoEncoder>, config: &Config) -> Result<Self> {
+        let (width, height) = config.stream_size();
+        let mut muxer = Fmp4Muxer::new(width, height, config.fps)
+            .with_codec(encoder.codec())
+            .with_color_space(config.color_space)
+            .with_profile(config.encoder_settings.profile);
 
         // Get and parse headers
-        let headers = encoder.get_headers()?;
+        let headers = encoder.parameter_sets()?;
         muxer.set_headers(&headers);
 
         // Pre-generate init segment
         let init_segment = muxer.create_init_segment();
 
-        // Frame duration in timescale units
-        let frame_duration = (fps * 1000) / fps; // timescale / fps
-
-        info!("Encoding pipeline initialized");
+        info!("Encoding pipeline initialized ({})", encoder.name());
 
         Ok(Self {
             encoder,
             muxer,
-            frame_duration,
+            width,
+            height,
+            scale_filter: config.scale_filter,
+            scaler: None,
+            scaled: Vec::new(),
             init_segment: Some(init_segment),
+            change_detector: ChangeDetector::default(),
+            max_fps: config.fps,
+            quality: QualitySettings {
+                bitrate: config.bitrate,
+                fps: config.fps,
+            },
+            pending_change: false,
+            last_encoded_us: None,
+            keyframe_requested: false,
+            keyframe_request: KeyframeRequest::default(),
+            keyframe_interval: KEYFRAME_MIN_INTERVAL,
+            last_keyframe: None,
         })
     }
 
+    /// Builder pattern: set how often a static screen is re-encoded
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.change_detector = ChangeDetector::new(interval);
+        self
+    }
+
+    /// Builder pattern: set the minimum time between requested keyframes
+    pub fn with_keyframe_interval(mut self, interval: Duration) -> Self {
+        self.keyframe_interval = interval;
+        self
+    }
+
+    /// Builder pattern: share the flag clients set to request keyframes
+    pub fn with_keyframe_request(mut self, request: KeyframeRequest) -> Self {
+        self.keyframe_request = request;
+        self
+    }
+
+    /// Handle for requesting keyframes once the pipeline is running
+    pub fn keyframe_request(&self) -> KeyframeRequest {
+        self.keyframe_request.clone()
+    }
+
     /// Get the initialization segment (call once per client)
     pub fn get_init_segment(&self) -> Option<Vec<u8>> {
         self.init_segment.clone()
@@ -62,31 +195,168 @@ impl EncodingPipeline {
         self.muxer.get_codec_string()
     }
 
-    /// Get the avcC data for WebCodecs description
-    pub fn get_avcc_data(&self) -> Vec<u8> {
-        self.muxer.get_avcc_data()
+    /// Get the decoder configuration record for the WebCodecs description
+    pub fn get_codec_description(&self) -> Vec<u8> {
+        self.muxer.get_codec_description()
+    }
+
+    /// Get the encoded width and height
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Get the codec of the stream
+    pub fn codec(&self) -> VideoCodec {
+        self.encoder.codec()
+    }
+
+    /// Get the current bitrate and frame rate
+    pub fn quality(&self) -> QualitySettings {
+        self.quality
+    }
+
+    /// Change the bitrate (kbps) and optionally the frame rate without
+    /// restarting the stream
+    ///
+    /// The bitrate is clamped to `MIN_BITRATE..=MAX_BITRATE` and the frame
+    /// rate to the capture rate; the applied settings are returned.
+    pub fn set_quality(&mut self, bitrate: u32, fps: Option<u32>) -> Result<QualitySettings> {
+        let bitrate = bitrate.clamp(MIN_BITRATE, MAX_BITRATE);
+        let fps = fps.unwrap_or(self.quality.fps).clamp(1, self.max_fps);
+
+        if bitrate != self.quality.bitrate {
+            self.encoder.set_bitrate(bitrate)?;
+            self.quality.bitrate = bitrate;
+        }
+        if fps != self.quality.fps {
+            self.encoder.set_frame_rate(fps)?;
+            self.muxer.set_frame_rate(fps);
+            self.quality.fps = fps;
+        }
+
+        info!(
+            "Encoder quality set to {} kbps @ {} fps",
+            self.quality.bitrate, self.quality.fps
+        );
+        Ok(self.quality)
+    }
+
+    /// Ask for the next encoded frame to be a keyframe
+    ///
+    /// Requests are coalesced: at most one keyframe is forced per keyframe
+    /// interval, however many clients ask.
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    /// Whether a requested keyframe may be forced now
+    fn keyframe_due(&mut self) -> bool {
+        self.keyframe_requested |= self.keyframe_request.take();
+        self.keyframe_requested
+            && self
+                .last_keyframe
+                .is_none_or(|last| last.elapsed() >= self.keyframe_interval)
+    }
+
+    /// Decide whether a captured frame is encoded
+    fn should_encode(&mut self, frame: &Frame) -> bool {
+        // Idle the encoder while the screen is static, but remember changes
+        // in frames dropped by the frame rate cap
+        self.pending_change |= self.change_detector.should_encode(frame);
+        // A due keyframe is encoded even if nothing changed
+        self.pending_change |= self.keyframe_due();
+        if !self.pending_change {
+            return false;
+        }
+
+        if self.quality.fps < self.max_fps {
+            // Allow some capture jitter before dropping a frame
+            let interval_us = 900_000 / u64::from(self.quality.fps);
+            let too_soon = self
+                .last_encoded_us
+                .is_some_and(|last| frame.timestamp_us.saturating_sub(last) < interval_us);
+            if too_soon {
+                return false;
+            }
+        }
+
+        self.pending_change = false;
+        self.last_encoded_us = Some(frame.timestamp_us);
+        true
+    }
+
+    fn handle_command(&mut self, command: PipelineCommand) {
+        match command {
+            PipelineCommand::SetQuality {
+                bitrate,
+                fps,
+                reply,
+            } => {
+                let result = self.set_quality(bitrate, fps);
+                if let Err(ref e) = result {
+                    warn!("Failed to change encoder quality: {}", e);
+                }
+                let _ = reply.send(result);
+            }
+        }
     }
 
     /// Encode a frame and return the media segment
     pub fn encode_frame(&mut self, frame: &Frame) -> Result<StreamSegment> {
-        let encoded = self.encoder.encode(frame.data())?;
+        let mut times = frame.stage_times();
+        times.dequeued_us = unix_time_us();
+        if self.keyframe_due() {
+            debug!("Forcing keyframe");
+            self.encoder.force_keyframe();
+        }
+
+        let data = if (frame.width, frame.height) == (self.width, self.height) {
+            frame.data()
+        } else {
+            let src_size = (frame.width, frame.height);
+            if !matches!(&self.scaler, Some(scaler) if scaler.src_size() == src_size) {
+                debug!(
+                    "Scaling {}x{} frames to {}x{}",
+                    frame.width, frame.height, self.width, self.height
+                );
+                self.scaler = Some(
+                    Scaler::new(frame.width, frame.height, self.width, self.height)
+                        .with_filter(self.scale_filter),
+                );
+                self.scaled
+                    .resize(self.width as usize * self.height as usize * 4, 0);
+            }
+            if let Some(scaler) = &self.scaler {
+                scaler.scale(frame.data(), &mut self.scaled)?;
+            }
+            &self.scaled
+        };
+        let encoded = self.encoder.encode(data)?;
+        times.converted_us = encoded.converted_us;
         let is_keyframe = encoded.is_keyframe;
+        if is_keyframe {
+            self.keyframe_requested = false;
+            self.last_keyframe = Some(Instant::now());
+        }
         let segment_data = self
             .muxer
-            .create_media_segment(&encoded, self.frame_duration);
+            .create_media_segment(&encoded, frame.timestamp_us);
+        times.encoded_us = unix_time_us();
 
         Ok(StreamSegment {
             data: segment_data,
             is_init: false,
             is_keyframe,
             sequence: frame.sequence,
+            times,
         })
     }
 
     /// Run the pipeline as an async task
     pub async fn run(
         mut self,
-        mut frame_rx: mpsc::Receiver<Frame>,
+        mut frame_rx: FrameReceiver,
+        mut control_rx: mpsc::Receiver<PipelineCommand>,
         segment_tx: broadcast::Sender<StreamSegment>,
     ) {
         info!("Encoding pipeline started");
@@ -94,7 +364,22 @@ impl EncodingPipeline {
         // Note: init segment should be retrieved via get_init_segment() and sent to clients separately
         // We no longer broadcast it here since clients may not be connected yet
 
-        while let Some(frame) = frame_rx.recv().await {
+        loop {
+            let frame = tokio::select! {
+                frame = frame_rx.recv() => match frame {
+                    Some(frame) => frame,
+                    None => break,
+                },
+                Some(command) = control_rx.recv() => {
+                    self.handle_command(command);
+                    continue;
+                }
+            };
+
+            if !self.should_encode(&frame) {
+                continue;
+            }
+
             match self.encode_frame(&frame) {
                 Ok(segment) => {
                     debug!("Encoded segment: {} bytes", segment.data.len());
@@ -108,6 +393,223 @@ impl EncodingPipeline {
             }
         }
 
-        info!("Encoding pipeline stopped");
+        info!(
+            "Encoding pipeline stopped ({} unchanged frames skipped, {} replaced by newer captures)",
+            self.change_detector.skipped_frames(),
+            frame_rx.replaced()
+        );
+    }
+}
+
+/// Ask a running pipeline to change its bitrate and frame rate
+pub async fn request_quality(
+    control_tx: &mpsc::Sender<PipelineCommand>,
+    bitrate: u32,
+    fps: Option<u32>,
+) -> Result<QualitySettings> {
+    let (reply, reply_rx) = oneshot::channel();
+    let command = PipelineCommand::SetQuality {
+        bitrate,
+        fps,
+        reply,
+    };
+    control_tx
+        .send(command)
+        .await
+        .map_err(|_| Error::EncoderError("Encoding pipeline is not running".to_string()))?;
+    reply_rx
+        .await
+        .map_err(|_| Error::EncoderError("Encoding pipeline stopped".to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(fps: u32) -> EncodingPipeline {
+        EncodingPipeline::new(64, 64, fps, 2000).unwrap()
+    }
+
+    fn frame(fill: u8, timestamp_us: u64) -> Frame {
+        let mut frame = Frame::new(vec![fill; 64 * 64 * 4], 64, 64, 0);
+        frame.timestamp_us = timestamp_us;
+        frame
+    }
+
+    #[test]
+    fn test_set_quality_clamps() {
+        let mut pipeline = pipeline(30);
+        let quality = pipeline.set_quality(1_000_000, Some(120)).unwrap();
+        assert_eq!(
+            quality,
+            QualitySettings {
+                bitrate: MAX_BITRATE,
+                fps: 30
+            }
+        );
+
+        let quality = pipeline.set_quality(0, Some(0)).unwrap();
+        assert_eq!(
+            quality,
+            QualitySettings {
+                bitrate: MIN_BITRATE,
+                fps: 1
+            }
+        );
+
+        // Frame rate is kept unless given
+        let quality = pipeline.set_quality(4000, None).unwrap();
+        assert_eq!(
+            quality,
+            QualitySettings {
+                bitrate: 4000,
+                fps: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_frame_rate_cap_keeps_changes() {
+        let mut pipeline = pipeline(60);
+        pipeline.set_quality(2000, Some(30)).unwrap();
+
+        assert!(pipeline.should_encode(&frame(0, 0)));
+        // Changed, but only 1/60 s later
+        assert!(!pipeline.should_encode(&frame(1, 16_667)));
+        // Unchanged itself, yet carries the dropped change
+        assert!(pipeline.should_encode(&frame(1, 33_333)));
+        assert!(!pipeline.should_encode(&frame(1, 50_000)));
+        assert!(!pipeline.should_encode(&frame(1, 66_667)));
+    }
+
+    #[test]
+    fn test_requested_keyframe() {
+        let mut pipeline = pipeline(30);
+        assert!(pipeline.should_encode(&frame(0, 0)));
+        assert!(!pipeline.encode_frame(&frame(0, 0)).unwrap().is_keyframe);
+
+        // Encoded on request even though the screen is static
+        pipeline.request_keyframe();
+        assert!(pipeline.should_encode(&frame(0, 33_333)));
+        assert!(
+            pipeline
+                .encode_frame(&frame(0, 33_333))
+                .unwrap()
+                .is_keyframe
+        );
+        assert!(!pipeline.should_encode(&frame(0, 66_667)));
+    }
+
+    #[test]
+    fn test_shared_keyframe_request() {
+        let mut pipeline = pipeline(30);
+        let request = pipeline.keyframe_request();
+        assert!(pipeline.should_encode(&frame(0, 0)));
+        assert!(!pipeline.encode_frame(&frame(0, 0)).unwrap().is_keyframe);
+
+        // Repeated requests before the next frame yield a single keyframe
+        request.request();
+        request.request();
+        assert!(pipeline.should_encode(&frame(0, 33_333)));
+        assert!(
+            pipeline
+                .encode_frame(&frame(0, 33_333))
+                .unwrap()
+                .is_keyframe
+        );
+        assert!(!pipeline.should_encode(&frame(0, 66_667)));
+    }
+
+    #[test]
+    fn test_keyframe_requests_are_rate_limited() {
+        let mut pipeline = pipeline(30).with_keyframe_interval(Duration::from_secs(60));
+        pipeline.request_keyframe();
+        assert!(pipeline.should_encode(&frame(0, 0)));
+        assert!(pipeline.encode_frame(&frame(0, 0)).unwrap().is_keyframe);
+
+        // Served by the previous keyframe until the interval has passed
+        pipeline.request_keyframe();
+        assert!(!pipeline.should_encode(&frame(0, 33_333)));
+        assert!(pipeline.should_encode(&frame(1, 66_667)));
+        assert!(
+            !pipeline
+                .encode_frame(&frame(1, 66_667))
+                .unwrap()
+                .is_keyframe
+        );
+
+        pipeline.keyframe_interval = Duration::ZERO;
+        assert!(pipeline.should_encode(&frame(1, 100_000)));
+        assert!(
+            pipeline
+                .encode_frame(&frame(1, 100_000))
+                .unwrap()
+                .is_keyframe
+        );
+    }
+
+    #[test]
+    fn test_scales_frames_to_encoded_size() {
+        let mut pipeline = pipeline(30);
+        let mut large = Frame::new(vec![0; 128 * 128 * 4], 128, 128, 0);
+        large.timestamp_us = 0;
+        assert!(!pipeline.encode_frame(&large).unwrap().data.is_empty());
+        assert_eq!(pipeline.size(), (64, 64));
+        assert_eq!(pipeline.scaler.as_ref().unwrap().src_size(), (128, 128));
+
+        // Frames already at the encoded size bypass the scaler
+        assert!(pipeline.encode_frame(&frame(1, 33_333)).is_ok());
+    }
+
+    #[test]
+    fn test_segment_stage_times() {
+        let mut pipeline = pipeline(30);
+        let mut frame = Frame::new(vec![0; 64 * 64 * 4], 64, 64, 0);
+        frame.capture_started_us = frame.timestamp_us - 2_000;
+        let times = pipeline.encode_frame(&frame).unwrap().times;
+        assert_eq!(times.capture_time(), Duration::from_millis(2));
+        assert_eq!(times.captured_us, frame.timestamp_us);
+        assert!(times.dequeued_us >= times.captured_us);
+        assert!(times.converted_us >= times.dequeued_us);
+        assert!(times.encoded_us >= times.converted_us);
+        assert_eq!(times.sent_us, 0);
+    }
+
+    #[test]
+    fn test_stream_size_independent_of_display() {
+        let config = Config::new()
+            .with_width(128)
+            .with_height(96)
+            .with_stream_width(64)
+            .with_scale_filter(ScaleFilter::Bilinear);
+        let mut pipeline = EncodingPipeline::from_config(&config).unwrap();
+        assert_eq!(pipeline.size(), (64, 48));
+
+        let display = Frame::new(vec![0; 128 * 96 * 4], 128, 96, 0);
+        assert!(pipeline.encode_frame(&display).is_ok());
+        let scaler = pipeline.scaler.as_ref().unwrap();
+        assert_eq!(scaler.dst_size(), (64, 48));
+        assert_eq!(scaler.filter(), ScaleFilter::Bilinear);
+    }
+
+    #[tokio::test]
+    async fn test_control_channel() {
+        let (frame_tx, frame_rx) = linglide_core::frame_handoff();
+        let (control_tx, control_rx) = mpsc::channel(4);
+        let (segment_tx, _) = broadcast::channel(4);
+        let task = tokio::spawn(pipeline(30).run(frame_rx, control_rx, segment_tx));
+
+        let quality = request_quality(&control_tx, 1500, Some(10)).await.unwrap();
+        assert_eq!(
+            quality,
+            QualitySettings {
+                bitrate: 1500,
+                fps: 10
+            }
+        );
+
+        drop(frame_tx);
+        task.await.unwrap();
+        assert!(request_quality(&control_tx, 1500, None).await.is_err());
     }
 }
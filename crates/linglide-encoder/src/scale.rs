@@ -0,0 +1,352 @@
+//! Frame scaling
+//!
+//! Resizes captured frames when the stream size differs from the display,
+//! either as BGRA before conversion or as I420 after it. Two filters are
+//! available:
+//!
+//! - Area: each output pixel is the average of the source pixels it covers,
+//!   which keeps text readable at half size where point sampling would drop
+//!   whole strokes.
+//! - Bilinear: each output pixel blends the four nearest source pixels. It
+//!   is cheaper at large ratios and upscales smoothly, but aliases below
+//!   half size.
+
+use crate::convert::i420_size;
+use linglide_core::{Error, Result, ScaleFilter};
+
+/// Fractional bits of bilinear weights
+const WEIGHT_BITS: u32 = 8;
+const WEIGHT_ONE: u32 = 1 << WEIGHT_BITS;
+
+/// Scales BGRA or I420 images of one size to another
+#[derive(Debug, Clone)]
+pub struct Scaler {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    filter: ScaleFilter,
+    /// Sampling of full-resolution planes (BGRA and luma)
+    luma: Kernel,
+    /// Sampling of the half-resolution chroma planes
+    chroma: Kernel,
+}
+
+impl Scaler {
+    /// Create an area scaler from `src_width` x `src_height` to `dst_width` x `dst_height`
+    pub fn new(src_width: u32, src_height: u32, dst_width: u32, dst_height: u32) -> Self {
+        let filter = ScaleFilter::Area;
+        Self {
+            src_width,
+            src_height,
+            dst_width,
+            dst_height,
+            filter,
+            luma: Kernel::new(filter, src_width, src_height, dst_width, dst_height),
+            chroma: Kernel::new(
+                filter,
+                src_width.div_ceil(2),
+                src_height.div_ceil(2),
+                dst_width.div_ceil(2),
+                dst_height.div_ceil(2),
+            ),
+        }
+    }
+
+    /// Builder pattern: set the filter
+    pub fn with_filter(self, filter: ScaleFilter) -> Self {
+        if filter == self.filter {
+            return self;
+        }
+        let (src_width, src_height, dst_width, dst_height) = (
+            self.src_width,
+            self.src_height,
+            self.dst_width,
+            self.dst_height,
+        );
+        Self {
+            filter,
+            luma: Kernel::new(filter, src_width, src_height, dst_width, dst_height),
+            chroma: Kernel::new(
+                filter,
+                src_width.div_ceil(2),
+                src_height.div_ceil(2),
+                dst_width.div_ceil(2),
+                dst_height.div_ceil(2),
+            ),
+            ..self
+        }
+    }
+
+    /// Size of the images this scaler accepts
+    pub fn src_size(&self) -> (u32, u32) {
+        (self.src_width, self.src_height)
+    }
+
+    /// Size of the images this scaler produces
+    pub fn dst_size(&self) -> (u32, u32) {
+        (self.dst_width, self.dst_height)
+    }
+
+    /// Filter used for resampling
+    pub fn filter(&self) -> ScaleFilter {
+        self.filter
+    }
+
+    /// Scale a tightly packed BGRA image into `out`
+    pub fn scale(&self, bgra: &[u8], out: &mut [u8]) -> Result<()> {
+        let src_size = self.src_width as usize * self.src_height as usize * 4;
+        let dst_size = self.dst_width as usize * self.dst_height as usize * 4;
+        self.check(bgra.len(), src_size, out.len(), dst_size)?;
+
+        self.luma.apply(
+            &bgra[..src_size],
+            self.src_width as usize * 4,
+            4,
+            &mut out[..dst_size],
+        );
+        Ok(())
+    }
+
+    /// Scale a tightly packed I420 image (Y, U then V planes) into `out`
+    pub fn scale_i420(&self, i420: &[u8], out: &mut [u8]) -> Result<()> {
+        let src_size = i420_size(self.src_width, self.src_height);
+        let dst_size = i420_size(self.dst_width, self.dst_height);
+        self.check(i420.len(), src_size, out.len(), dst_size)?;
+
+        let src_luma = self.src_width as usize * self.src_height as usize;
+        let dst_luma = self.dst_width as usize * self.dst_height as usize;
+        let (src_y, src_chroma) = i420[..src_size].split_at(src_luma);
+        let (dst_y, dst_chroma) = out[..dst_size].split_at_mut(dst_luma);
+        self.luma.apply(src_y, self.src_width as usize, 1, dst_y);
+
+        let src_stride = self.src_width.div_ceil(2) as usize;
+        let (src_u, src_v) = src_chroma.split_at(src_chroma.len() / 2);
+        let (dst_u, dst_v) = dst_chroma.split_at_mut(dst_chroma.len() / 2);
+        self.chroma.apply(src_u, src_stride, 1, dst_u);
+        self.chroma.apply(src_v, src_stride, 1, dst_v);
+        Ok(())
+    }
+
+    fn check(
+        &self,
+        src_len: usize,
+        src_size: usize,
+        out_len: usize,
+        dst_size: usize,
+    ) -> Result<()> {
+        if src_len < src_size || out_len < dst_size {
+            return Err(Error::EncoderError(format!(
+                "Buffers too small to scale {}x{} to {}x{}: {} and {} bytes",
+                self.src_width, self.src_height, self.dst_width, self.dst_height, src_len, out_len
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Source samples feeding each output sample of one plane
+#[derive(Debug, Clone)]
+enum Kernel {
+    /// Source ranges averaged into each output column and row
+    Area {
+        columns: Vec<(usize, usize)>,
+        rows: Vec<(usize, usize)>,
+    },
+    /// Source pairs blended into each output column and row
+    Bilinear { columns: Vec<Lerp>, rows: Vec<Lerp> },
+}
+
+/// Blend of two neighbouring source samples
+#[derive(Debug, Clone, Copy)]
+struct Lerp {
+    first: usize,
+    second: usize,
+    /// Weight of `second`, in 1/256ths
+    weight: u32,
+}
+
+impl Kernel {
+    fn new(
+        filter: ScaleFilter,
+        src_width: u32,
+        src_height: u32,
+        dst_width: u32,
+        dst_height: u32,
+    ) -> Self {
+        match filter {
+            ScaleFilter::Area => Kernel::Area {
+                columns: spans(src_width, dst_width),
+                rows: spans(src_height, dst_height),
+            },
+            ScaleFilter::Bilinear => Kernel::Bilinear {
+                columns: lerps(src_width, dst_width),
+                rows: lerps(src_height, dst_height),
+            },
+        }
+    }
+
+    /// Resample a plane of `channels` interleaved samples per pixel
+    fn apply(&self, src: &[u8], src_stride: usize, channels: usize, out: &mut [u8]) {
+        let mut pixels = out.chunks_exact_mut(channels);
+        match self {
+            Kernel::Area { columns, rows } => {
+                for &(y0, y1) in rows {
+                    for &(x0, x1) in columns {
+                        let mut sum = [0u32; 4];
+                        for row in src[y0 * src_stride..y1 * src_stride].chunks_exact(src_stride) {
+                            for pixel in row[x0 * channels..x1 * channels].chunks_exact(channels) {
+                                for (total, &value) in sum.iter_mut().zip(pixel) {
+                                    *total += u32::from(value);
+                                }
+                            }
+                        }
+
+                        let count = ((y1 - y0) * (x1 - x0)) as u32;
+                        let pixel = pixels.next().expect("output sized by caller");
+                        for (value, total) in pixel.iter_mut().zip(sum) {
+                            *value = ((total + count / 2) / count) as u8;
+                        }
+                    }
+                }
+            }
+            Kernel::Bilinear { columns, rows } => {
+                let round = 1 << (2 * WEIGHT_BITS - 1);
+                for row in rows {
+                    let top = &src[row.first * src_stride..][..src_stride];
+                    let bottom = &src[row.second * src_stride..][..src_stride];
+                    for column in columns {
+                        let (left, right) = (column.first * channels, column.second * channels);
+                        let pixel = pixels.next().expect("output sized by caller");
+                        for (c, value) in pixel.iter_mut().enumerate() {
+                            let blend = |line: &[u8]| {
+                                u32::from(line[left + c]) * (WEIGHT_ONE - column.weight)
+                                    + u32::from(line[right + c]) * column.weight
+                            };
+                            let total =
+                                blend(top) * (WEIGHT_ONE - row.weight) + blend(bottom) * row.weight;
+                            *value = ((total + round) >> (2 * WEIGHT_BITS)) as u8;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Source range covered by each of `dst` output pixels along one axis
+fn spans(src: u32, dst: u32) -> Vec<(usize, usize)> {
+    let (src, dst) = (u64::from(src), u64::from(dst));
+    (0..dst)
+        .map(|i| {
+            let start = i * src / dst;
+            let end = ((i + 1) * src / dst).max(start + 1).min(src);
+            (start as usize, end as usize)
+        })
+        .collect()
+}
+
+/// Source pair nearest the centre of each of `dst` output pixels along one axis
+fn lerps(src: u32, dst: u32) -> Vec<Lerp> {
+    let (src, dst) = (u64::from(src), u64::from(dst));
+    let last = src.saturating_sub(1);
+    let one = u64::from(WEIGHT_ONE);
+    (0..dst)
+        .map(|i| {
+            // Pixel centres line up: output i sits at (i + 0.5) * src / dst - 0.5
+            let position = ((2 * i + 1) * src * one / (2 * dst)).saturating_sub(one / 2);
+            let first = (position / one).min(last);
+            Lerp {
+                first: first as usize,
+                second: (first + 1).min(last) as usize,
+                weight: (position % one) as u32,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(x, y))
+            .collect()
+    }
+
+    #[test]
+    fn test_halving_averages_blocks() {
+        // Alternating black and white columns become mid grey
+        let src = image(4, 2, |x, _| if x % 2 == 0 { [0; 4] } else { [255; 4] });
+        let mut out = vec![0u8; 2 * 4];
+        Scaler::new(4, 2, 2, 1).scale(&src, &mut out).unwrap();
+        assert_eq!(out, [128; 8]);
+
+        let src = image(4, 4, |x, y| [x as u8 * 10, y as u8 * 10, 0, 255]);
+        let mut out = vec![0u8; 2 * 2 * 4];
+        Scaler::new(4, 4, 2, 2).scale(&src, &mut out).unwrap();
+        assert_eq!(
+            out,
+            [5, 5, 0, 255, 25, 5, 0, 255, 5, 25, 0, 255, 25, 25, 0, 255]
+        );
+    }
+
+    #[test]
+    fn test_uneven_ratio_covers_whole_source() {
+        // 5 -> 2: columns 0-1 and 2-4, so the last column isn't lost
+        let src = image(5, 1, |x, _| if x == 4 { [250; 4] } else { [0; 4] });
+        let mut out = vec![0u8; 2 * 4];
+        Scaler::new(5, 1, 2, 1).scale(&src, &mut out).unwrap();
+        assert_eq!(&out[..4], &[0; 4]);
+        assert_eq!(&out[4..], &[83; 4]);
+    }
+
+    #[test]
+    fn test_bilinear() {
+        // Halving samples between each pair, like the area filter
+        let src = image(4, 4, |x, y| [x as u8 * 10, y as u8 * 10, 0, 255]);
+        let mut out = vec![0u8; 2 * 2 * 4];
+        let scaler = Scaler::new(4, 4, 2, 2).with_filter(ScaleFilter::Bilinear);
+        assert_eq!(scaler.filter(), ScaleFilter::Bilinear);
+        scaler.scale(&src, &mut out).unwrap();
+        assert_eq!(
+            out,
+            [5, 5, 0, 255, 25, 5, 0, 255, 5, 25, 0, 255, 25, 25, 0, 255]
+        );
+
+        // Doubling interpolates, clamping at the edges
+        let src = image(2, 1, |x, _| [x as u8 * 255; 4]);
+        let mut out = vec![0u8; 4 * 4];
+        Scaler::new(2, 1, 4, 1)
+            .with_filter(ScaleFilter::Bilinear)
+            .scale(&src, &mut out)
+            .unwrap();
+        let firsts: Vec<u8> = out.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(firsts, [0, 64, 191, 255]);
+    }
+
+    #[test]
+    fn test_i420_scales_planes_separately() {
+        // 6x6 -> 3x3: luma 36 -> 9 samples, chroma 3x3 -> 2x2
+        let src: Vec<u8> = [vec![100; 36], vec![50; 9], vec![200; 9]].concat();
+        let mut out = vec![0u8; i420_size(3, 3)];
+        for filter in [ScaleFilter::Area, ScaleFilter::Bilinear] {
+            Scaler::new(6, 6, 3, 3)
+                .with_filter(filter)
+                .scale_i420(&src, &mut out)
+                .unwrap();
+            assert_eq!(out, [vec![100; 9], vec![50; 4], vec![200; 4]].concat());
+        }
+    }
+
+    #[test]
+    fn test_rejects_short_buffers() {
+        let scaler = Scaler::new(4, 4, 2, 2);
+        let mut out = vec![0u8; 16];
+        assert!(scaler.scale(&[0u8; 63], &mut out).is_err());
+        assert!(scaler.scale(&[0u8; 64], &mut out[..15]).is_err());
+        assert!(scaler.scale_i420(&[0u8; 23], &mut out).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! AV1 encoder using SVT-AV1
+//!
+//! AV1 compresses better than HEVC and is royalty free, but real-time
+//! encoding is CPU hungry and SVT-AV1 needs the system library
+//! (`libsvtav1enc-dev`), so it is only built with the `svt-av1` feature. The
+//! encoder is driven through a small C shim (`csrc/svt_av1_shim.c`) that hides
+//! SVT-AV1's version-dependent configuration struct.
+
+use crate::codec_config::{av1_obus, OBU_TEMPORAL_DELIMITER};
+use crate::convert::{i420_size, I420Converter};
+use crate::encoder::EncodedFrame;
+use crate::video_encoder::VideoEncoder;
+use linglide_core::{unix_time_us, ColorSpace, EncoderSettings, Error, Result, VideoCodec};
+use std::os::raw::c_int;
+use std::ptr::{self, NonNull};
+use tracing::{debug, info, warn};
+
+/// Opaque encoder handle owned by the shim
+#[repr(C)]
+struct RawEncoder {
+    _private: [u8; 0],
+}
+
+/// Encoder parameters, mirrored by `linglide_svt_av1_config` in the shim
+#[repr(C)]
+struct OpenConfig {
+    width: c_int,
+    height: c_int,
+    fps: c_int,
+    bitrate_kbps: c_int,
+    matrix: c_int,
+    transfer: c_int,
+    primaries: c_int,
+    full_range: c_int,
+    keyint: c_int,
+    slices: c_int,
+    qp_min: c_int,
+    qp_max: c_int,
+}
+
+extern "C" {
+    fn linglide_svt_av1_open(config: *const OpenConfig) -> *mut RawEncoder;
+    fn linglide_svt_av1_close(encoder: *mut RawEncoder);
+    #[allow(clippy::too_many_arguments)]
+    fn linglide_svt_av1_encode(
+        encoder: *mut RawEncoder,
+        y: *const u8,
+        u: *const u8,
+        v: *const u8,
+        y_stride: c_int,
+        c_stride: c_int,
+        pts: i64,
+        out: *mut *mut u8,
+        out_len: *mut c_int,
+        keyframe: *mut c_int,
+    ) -> c_int;
+    fn linglide_svt_av1_force_key(encoder: *mut RawEncoder);
+    fn linglide_svt_av1_headers(
+        encoder: *mut RawEncoder,
+        out: *mut *mut u8,
+        out_len: *mut c_int,
+    ) -> c_int;
+}
+
+/// SVT-AV1 encoder with the fastest low-delay preset
+pub struct SvtAv1Encoder {
+    raw: NonNull<RawEncoder>,
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate: u32,
+    settings: EncoderSettings,
+    frame_count: i64,
+    converter: I420Converter,
+    yuv_buffer: Vec<u8>,
+}
+
+// SAFETY: the SVT-AV1 handle is only used through `&mut self` and SVT-AV1
+// encoders may be moved between threads
+unsafe impl Send for SvtAv1Encoder {}
+
+impl SvtAv1Encoder {
+    /// Create a new SVT-AV1 encoder
+    pub fn new(width: u32, height: u32, fps: u32, bitrate: u32) -> Result<Self> {
+        let settings = EncoderSettings::default();
+        let raw = open(
+            width,
+            height,
+            fps,
+            bitrate,
+            ColorSpace::default(),
+            &settings,
+        )?;
+
+        info!(
+            "SVT-AV1 encoder initialized: {}x{} @ {} fps, {} kbps",
+            width, height, fps, bitrate
+        );
+
+        Ok(Self {
+            raw,
+            width,
+            height,
+            fps,
+            bitrate,
+            settings,
+            frame_count: 0,
+            converter: I420Converter::default(),
+            yuv_buffer: vec![0u8; i420_size(width, height)],
+        })
+    }
+
+    /// Builder pattern: set the color space frames are converted to
+    ///
+    /// SVT-AV1 writes the color space into the sequence header, so the encoder
+    /// is reopened with the new settings.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Result<Self> {
+        self.converter = I420Converter::from_color_space(color_space);
+        self.reopen()?;
+        Ok(self)
+    }
+
+    /// Builder pattern: set GOP, slice and QP settings
+    ///
+    /// Slices map to tile rows. Intra refresh isn't supported.
+    pub fn with_settings(mut self, settings: EncoderSettings) -> Result<Self> {
+        if settings.intra_refresh {
+            warn!("SVT-AV1 does not support intra refresh, sending keyframes instead");
+        }
+        self.settings = settings;
+        self.reopen()?;
+        Ok(self)
+    }
+
+    /// Replace the encoder with one using the current settings
+    ///
+    /// The new encoder starts with a keyframe carrying a fresh sequence header.
+    fn reopen(&mut self) -> Result<()> {
+        let raw = open(
+            self.width,
+            self.height,
+            self.fps,
+            self.bitrate,
+            self.converter.color_space(),
+            &self.settings,
+        )?;
+        // SAFETY: the old handle came from `linglide_svt_av1_open` and is replaced
+        unsafe { linglide_svt_av1_close(self.raw.as_ptr()) };
+        self.raw = raw;
+        Ok(())
+    }
+
+    /// Get frame count
+    pub fn frame_count(&self) -> i64 {
+        self.frame_count
+    }
+}
+
+impl VideoEncoder for SvtAv1Encoder {
+    fn name(&self) -> &'static str {
+        "svt-av1"
+    }
+
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::Av1
+    }
+
+    fn encode(&mut self, bgra: &[u8]) -> Result<EncodedFrame> {
+        self.converter
+            .convert(bgra, self.width, self.height, &mut self.yuv_buffer)?;
+        let converted_us = unix_time_us();
+
+        let y_size = (self.width * self.height) as usize;
+        let c_size = (self.width.div_ceil(2) * self.height.div_ceil(2)) as usize;
+        let planes = self.yuv_buffer.as_ptr();
+
+        let mut out = ptr::null_mut();
+        let mut out_len: c_int = 0;
+        let mut keyframe: c_int = 0;
+        // SAFETY: the planes point into `yuv_buffer`, which holds a full I420
+        // image; SVT-AV1 copies them before the call returns
+        let ret = unsafe {
+            linglide_svt_av1_encode(
+                self.raw.as_ptr(),
+                planes,
+                planes.add(y_size),
+                planes.add(y_size + c_size),
+                self.width as c_int,
+                self.width.div_ceil(2) as c_int,
+                self.frame_count,
+                &mut out,
+                &mut out_len,
+                &mut keyframe,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::EncoderError(format!(
+                "SVT-AV1 encoding failed (error {})",
+                ret
+            )));
+        }
+
+        // SAFETY: the shim returns a buffer it owns that stays valid until the
+        // next call into the encoder
+        let packet = match out_len {
+            0 => &[][..],
+            len => unsafe { std::slice::from_raw_parts(out, len as usize) },
+        };
+        // MP4 samples must not carry temporal delimiters
+        let data = av1_obus(packet)
+            .iter()
+            .filter(|obu| obu.obu_type != OBU_TEMPORAL_DELIMITER)
+            .flat_map(|obu| obu.data)
+            .copied()
+            .collect::<Vec<u8>>();
+
+        debug!(
+            "Encoded frame {}: {} bytes, keyframe={}",
+            self.frame_count,
+            data.len(),
+            keyframe != 0
+        );
+
+        let frame = EncodedFrame {
+            data,
+            pts: self.frame_count,
+            is_keyframe: keyframe != 0,
+            converted_us,
+        };
+        self.frame_count += 1;
+
+        Ok(frame)
+    }
+
+    fn force_keyframe(&mut self) {
+        // SAFETY: `raw` is a live handle
+        unsafe { linglide_svt_av1_force_key(self.raw.as_ptr()) };
+    }
+
+    /// SVT-AV1 can't change rate control on a running encoder, so this
+    /// reopens it and the next frame is a keyframe
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        let previous = std::mem::replace(&mut self.bitrate, bitrate);
+        if let Err(e) = self.reopen() {
+            self.bitrate = previous;
+            return Err(e);
+        }
+
+        debug!("SVT-AV1 bitrate set to {} kbps", bitrate);
+        Ok(())
+    }
+
+    /// Reopens the encoder like [`set_bitrate`](Self::set_bitrate)
+    fn set_frame_rate(&mut self, fps: u32) -> Result<()> {
+        let previous = std::mem::replace(&mut self.fps, fps);
+        if let Err(e) = self.reopen() {
+            self.fps = previous;
+            return Err(e);
+        }
+
+        debug!("SVT-AV1 frame rate set to {} fps", fps);
+        Ok(())
+    }
+
+    fn parameter_sets(&mut self) -> Result<Vec<u8>> {
+        let mut out = ptr::null_mut();
+        let mut out_len: c_int = 0;
+        // SAFETY: `raw` is a live handle; the output is copied before the next call
+        let ret = unsafe { linglide_svt_av1_headers(self.raw.as_ptr(), &mut out, &mut out_len) };
+        if ret < 0 || out.is_null() {
+            return Err(Error::EncoderError(format!(
+                "Failed to get SVT-AV1 sequence header (error {})",
+                ret
+            )));
+        }
+
+        Ok(unsafe { std::slice::from_raw_parts(out, out_len as usize) }.to_vec())
+    }
+}
+
+impl Drop for SvtAv1Encoder {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from `linglide_svt_av1_open` and is not used again
+        unsafe { linglide_svt_av1_close(self.raw.as_ptr()) };
+    }
+}
+
+fn open(
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate: u32,
+    color_space: ColorSpace,
+    settings: &EncoderSettings,
+) -> Result<NonNull<RawEncoder>> {
+    let config = OpenConfig {
+        width: width as c_int,
+        height: height as c_int,
+        fps: fps as c_int,
+        bitrate_kbps: bitrate as c_int,
+        matrix: c_int::from(color_space.matrix_code()),
+        transfer: c_int::from(color_space.transfer_code()),
+        primaries: c_int::from(color_space.primaries_code()),
+        full_range: c_int::from(color_space.is_full_range()),
+        keyint: settings.gop as c_int,
+        slices: settings.slices as c_int,
+        qp_min: c_int::from(settings.min_qp),
+        qp_max: c_int::from(settings.max_qp),
+    };
+    // SAFETY: `config` outlives the call; the shim returns null on failure
+    let raw = unsafe { linglide_svt_av1_open(&config) };
+    NonNull::new(raw).ok_or_else(|| {
+        Error::EncoderError(format!(
+            "Failed to open SVT-AV1 encoder for {}x{} @ {} fps",
+            width, height, fps
+        ))
+    })
+}
@@ -0,0 +1,185 @@
+//! Pluggable video encoder backends
+//!
+//! The pipeline drives encoders through the `VideoEncoder` trait, so the
+//! implementation can be picked at runtime from `Config::encoder`. OpenH264 is
+//! always available; x264, x265 (HEVC) and SVT-AV1 are built with the `x264`,
+//! `x265` and `svt-av1` features.
+
+use crate::encoder::{EncodedFrame, H264Encoder};
+use linglide_core::{Config, EncoderKind, Error, Result, VideoCodec};
+
+/// Video encoder fed with BGRA frames
+pub trait VideoEncoder: Send {
+    /// Short name of the implementation, for logs and diagnostics
+    fn name(&self) -> &'static str;
+
+    /// Codec of the encoded stream
+    fn codec(&self) -> VideoCodec;
+
+    /// Encode a BGRA frame into Annex B NAL units (H.264, HEVC) or OBUs (AV1)
+    fn encode(&mut self, bgra: &[u8]) -> Result<EncodedFrame>;
+
+    /// Make the next encoded frame a keyframe
+    fn force_keyframe(&mut self);
+
+    /// Change the target bitrate in kbps
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<()>;
+
+    /// Change the frame rate used for rate control
+    fn set_frame_rate(&mut self, fps: u32) -> Result<()>;
+
+    /// Get the stream headers: SPS and PPS (plus VPS for HEVC) as Annex B
+    /// NAL units, or the AV1 sequence header OBU
+    fn parameter_sets(&mut self) -> Result<Vec<u8>>;
+}
+
+/// Create the encoder selected in `config`
+pub fn create_encoder(config: &Config) -> Result<Box<dyn VideoEncoder>> {
+    let (width, height) = config.stream_size();
+    config.encoder_settings.validate()?;
+    match config.encoder {
+        EncoderKind::OpenH264 => Ok(Box::new(
+            H264Encoder::new(width, height, config.fps, config.bitrate)?
+                .with_color_space(config.color_space)
+                .with_settings(config.encoder_settings),
+        )),
+        #[cfg(feature = "x264")]
+        EncoderKind::X264 => Ok(Box::new(
+            crate::x264::X264Encoder::new(width, height, config.fps, config.bitrate)?
+                .with_color_space(config.color_space)?
+                .with_settings(config.encoder_settings)?,
+        )),
+        #[cfg(feature = "x265")]
+        EncoderKind::X265 => Ok(Box::new(
+            crate::x265::X265Encoder::new(width, height, config.fps, config.bitrate)?
+                .with_color_space(config.color_space)?
+                .with_settings(config.encoder_settings)?,
+        )),
+        #[cfg(feature = "svt-av1")]
+        EncoderKind::SvtAv1 => Ok(Box::new(
+            crate::svt_av1::SvtAv1Encoder::new(width, height, config.fps, config.bitrate)?
+                .with_color_space(config.color_space)?
+                .with_settings(config.encoder_settings)?,
+        )),
+        #[allow(unreachable_patterns)]
+        kind => Err(Error::EncoderError(format!(
+            "{} support not compiled in (rebuild with --features {})",
+            kind, kind
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linglide_core::{EncoderSettings, H264Profile};
+
+    #[test]
+    fn test_openh264_backend() {
+        let config = Config::new().with_width(64).with_height(64).with_fps(30);
+        let mut encoder = create_encoder(&config).unwrap();
+        assert_eq!(encoder.name(), "openh264");
+
+        let headers = encoder.parameter_sets().unwrap();
+        // Starts with an SPS
+        assert_eq!(&headers[..5], &[0, 0, 0, 1, 0x67]);
+
+        // The header probe already produced the IDR frame
+        let frame = vec![0x40; 64 * 64 * 4];
+        let encoded = encoder.encode(&frame).unwrap();
+        assert!(!encoded.is_keyframe);
+        assert!(encoded.converted_us > 0);
+        encoder.set_bitrate(500).unwrap();
+        encoder.set_frame_rate(15).unwrap();
+        assert!(!encoder.encode(&frame).unwrap().is_keyframe);
+        encoder.force_keyframe();
+        assert!(encoder.encode(&frame).unwrap().is_keyframe);
+    }
+
+    #[test]
+    fn test_openh264_settings() {
+        let settings = EncoderSettings {
+            gop: 4,
+            profile: H264Profile::High,
+            ..Default::default()
+        };
+        let config = Config::new()
+            .with_width(64)
+            .with_height(64)
+            .with_fps(30)
+            .with_encoder_settings(settings);
+        let mut encoder = create_encoder(&config).unwrap();
+
+        // SPS profile_idc follows the requested profile
+        let headers = encoder.parameter_sets().unwrap();
+        assert_eq!(headers[5], H264Profile::High.profile_idc());
+
+        let keyframes: Vec<bool> = (0..8u8)
+            .map(|i| {
+                let frame = vec![i * 16; 64 * 64 * 4];
+                encoder.encode(&frame).unwrap().is_keyframe
+            })
+            .collect();
+        assert_eq!(keyframes.iter().filter(|&&k| k).count(), 2);
+    }
+
+    #[cfg(feature = "x264")]
+    #[test]
+    fn test_x264_intra_refresh_keyframes() {
+        let settings = EncoderSettings {
+            gop: 4,
+            intra_refresh: true,
+            ..Default::default()
+        };
+        let config = Config::new()
+            .with_width(64)
+            .with_height(64)
+            .with_fps(30)
+            .with_encoder(EncoderKind::X264)
+            .with_encoder_settings(settings);
+        let mut encoder = create_encoder(&config).unwrap();
+
+        // Recovery points of the refresh wave are P-slices, not keyframes
+        let keyframes: Vec<bool> = (0..12u8)
+            .map(|i| {
+                let frame = vec![i * 16; 64 * 64 * 4];
+                encoder.encode(&frame).unwrap().is_keyframe
+            })
+            .collect();
+        assert!(keyframes[0]);
+        assert_eq!(keyframes.iter().filter(|&&k| k).count(), 1);
+
+        encoder.force_keyframe();
+        let frame = vec![0x40; 64 * 64 * 4];
+        assert!(encoder.encode(&frame).unwrap().is_keyframe);
+        assert!(!encoder.encode(&frame).unwrap().is_keyframe);
+    }
+
+    #[test]
+    fn test_invalid_qp_range() {
+        let settings = EncoderSettings {
+            min_qp: 40,
+            max_qp: 30,
+            ..Default::default()
+        };
+        let config = Config::new().with_encoder_settings(settings);
+        assert!(create_encoder(&config).is_err());
+    }
+
+    #[cfg(not(feature = "x264"))]
+    #[test]
+    fn test_x264_requires_feature() {
+        let config = Config::new().with_encoder(EncoderKind::X264);
+        assert!(create_encoder(&config).is_err());
+    }
+
+    #[cfg(not(any(feature = "x265", feature = "svt-av1")))]
+    #[test]
+    fn test_hevc_and_av1_require_features() {
+        for kind in [EncoderKind::X265, EncoderKind::SvtAv1] {
+            let config = Config::new().with_encoder(kind);
+            let err = create_encoder(&config).err().unwrap().to_string();
+            assert!(err.contains(&format!("--features {}", kind)), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! H.264 encoder using libx264
+//!
+//! x264 gives better quality per bit and lower latency than OpenH264 but is
+//! GPL licensed and needs the system library (`libx264-dev`), so it is only
+//! built with the `x264` feature. The encoder is driven through a small C
+//! shim (`csrc/x264_shim.c`) that hides x264's version-dependent structs.
+
+use crate::convert::{i420_size, I420Converter};
+use crate::encoder::EncodedFrame;
+use crate::video_encoder::VideoEncoder;
+use linglide_core::{
+    unix_time_us, ColorSpace, EncoderSettings, Error, H264Profile, Result, VideoCodec,
+};
+use std::os::raw::c_int;
+use std::ptr::{self, NonNull};
+use tracing::{debug, info};
+
+/// Opaque encoder handle owned by the shim
+#[repr(C)]
+struct RawEncoder {
+    _private: [u8; 0],
+}
+
+/// Encoder parameters, mirrored by `linglide_x264_config` in the shim
+#[repr(C)]
+struct OpenConfig {
+    width: c_int,
+    height: c_int,
+    fps: c_int,
+    bitrate_kbps: c_int,
+    matrix: c_int,
+    transfer: c_int,
+    primaries: c_int,
+    full_range: c_int,
+    keyint: c_int,
+    intra_refresh: c_int,
+    high_profile: c_int,
+    slices: c_int,
+    qp_min: c_int,
+    qp_max: c_int,
+}
+
+extern "C" {
+    fn linglide_x264_open(config: *const OpenConfig) -> *mut RawEncoder;
+    fn linglide_x264_close(encoder: *mut RawEncoder);
+    #[allow(clippy::too_many_arguments)]
+    fn linglide_x264_encode(
+        encoder: *mut RawEncoder,
+        y: *const u8,
+        u: *const u8,
+        v: *const u8,
+        y_stride: c_int,
+        c_stride: c_int,
+        pts: i64,
+        out: *mut *mut u8,
+        out_len: *mut c_int,
+        keyframe: *mut c_int,
+    ) -> c_int;
+    fn linglide_x264_force_idr(encoder: *mut RawEncoder);
+    fn linglide_x264_set_bitrate(encoder: *mut RawEncoder, bitrate_kbps: c_int) -> c_int;
+    fn linglide_x264_set_fps(encoder: *mut RawEncoder, fps: c_int) -> c_int;
+    fn linglide_x264_headers(
+        encoder: *mut RawEncoder,
+        out: *mut *mut u8,
+        out_len: *mut c_int,
+    ) -> c_int;
+}
+
+/// libx264 encoder with zero-latency settings
+pub struct X264Encoder {
+    raw: NonNull<RawEncoder>,
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate: u32,
+    settings: EncoderSettings,
+    frame_count: i64,
+    converter: I420Converter,
+    yuv_buffer: Vec<u8>,
+}
+
+// SAFETY: the x264 handle is only used through `&mut self` and x264 encoders
+// may be moved between threads
+unsafe impl Send for X264Encoder {}
+
+impl X264Encoder {
+    /// Create a new x264 encoder
+    pub fn new(width: u32, height: u32, fps: u32, bitrate: u32) -> Result<Self> {
+        let settings = EncoderSettings::default();
+        let raw = open(
+            width,
+            height,
+            fps,
+            bitrate,
+            ColorSpace::default(),
+            &settings,
+        )?;
+
+        info!(
+            "x264 encoder initialized: {}x{} @ {} fps, {} kbps",
+            width, height, fps, bitrate
+        );
+
+        Ok(Self {
+            raw,
+            width,
+            height,
+            fps,
+            bitrate,
+            settings,
+            frame_count: 0,
+            converter: I420Converter::default(),
+            yuv_buffer: vec![0u8; i420_size(width, height)],
+        })
+    }
+
+    /// Builder pattern: set the color space frames are converted to
+    ///
+    /// x264 writes the color space into the SPS VUI, so the encoder is
+    /// reopened with the new settings.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Result<Self> {
+        self.converter = I420Converter::from_color_space(color_space);
+        self.reopen()?;
+        Ok(self)
+    }
+
+    /// Builder pattern: set GOP, intra refresh, profile, slice and QP settings
+    pub fn with_settings(mut self, settings: EncoderSettings) -> Result<Self> {
+        self.settings = settings;
+        self.reopen()?;
+        Ok(self)
+    }
+
+    /// Replace the encoder with one using the current settings
+    fn reopen(&mut self) -> Result<()> {
+        let raw = open(
+            self.width,
+            self.height,
+            self.fps,
+            self.bitrate,
+            self.converter.color_space(),
+            &self.settings,
+        )?;
+        // SAFETY: the old handle came from `linglide_x264_open` and is replaced
+        unsafe { linglide_x264_close(self.raw.as_ptr()) };
+        self.raw = raw;
+        Ok(())
+    }
+
+    /// Get frame count
+    pub fn frame_count(&self) -> i64 {
+        self.frame_count
+    }
+}
+
+impl VideoEncoder for X264Encoder {
+    fn name(&self) -> &'static str {
+        "x264"
+    }
+
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::H264
+    }
+
+    fn encode(&mut self, bgra: &[u8]) -> Result<EncodedFrame> {
+        self.converter
+            .convert(bgra, self.width, self.height, &mut self.yuv_buffer)?;
+        let converted_us = unix_time_us();
+
+        let y_size = (self.width * self.height) as usize;
+        let c_size = (self.width.div_ceil(2) * self.height.div_ceil(2)) as usize;
+        let planes = self.yuv_buffer.as_ptr();
+
+        let mut out = ptr::null_mut();
+        let mut out_len: c_int = 0;
+        let mut keyframe: c_int = 0;
+        // SAFETY: the planes point into `yuv_buffer`, which holds a full I420
+        // image; x264 only reads them during the call
+        let ret = unsafe {
+            linglide_x264_encode(
+                self.raw.as_ptr(),
+                planes,
+                planes.add(y_size),
+                planes.add(y_size + c_size),
+                self.width as c_int,
+                self.width.div_ceil(2) as c_int,
+                self.frame_count,
+                &mut out,
+                &mut out_len,
+                &mut keyframe,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::EncoderError(format!(
+                "x264 encoding failed (error {})",
+                ret
+            )));
+        }
+
+        // SAFETY: the shim returns a buffer owned by x264 that stays valid
+        // until the next call into the encoder
+        let data = match out_len {
+            0 => Vec::new(),
+            len => unsafe { std::slice::from_raw_parts(out, len as usize) }.to_vec(),
+        };
+
+        debug!(
+            "Encoded frame {}: {} bytes, keyframe={}",
+            self.frame_count,
+            data.len(),
+            keyframe != 0
+        );
+
+        let frame = EncodedFrame {
+            data,
+            pts: self.frame_count,
+            is_keyframe: keyframe != 0,
+            converted_us,
+        };
+        self.frame_count += 1;
+
+        Ok(frame)
+    }
+
+    fn force_keyframe(&mut self) {
+        // SAFETY: `raw` is a live handle
+        unsafe { linglide_x264_force_idr(self.raw.as_ptr()) };
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        // SAFETY: `raw` is a live handle
+        let ret = unsafe { linglide_x264_set_bitrate(self.raw.as_ptr(), bitrate as c_int) };
+        if ret < 0 {
+            return Err(Error::EncoderError(format!(
+                "Failed to set bitrate to {} kbps (error {})",
+                bitrate, ret
+            )));
+        }
+
+        self.bitrate = bitrate;
+        debug!("x264 bitrate set to {} kbps", bitrate);
+        Ok(())
+    }
+
+    fn set_frame_rate(&mut self, fps: u32) -> Result<()> {
+        // SAFETY: `raw` is a live handle
+        let ret = unsafe { linglide_x264_set_fps(self.raw.as_ptr(), fps as c_int) };
+        if ret < 0 {
+            return Err(Error::EncoderError(format!(
+                "Failed to set frame rate to {} fps (error {})",
+                fps, ret
+            )));
+        }
+
+        self.fps = fps;
+        debug!("x264 frame rate set to {} fps", fps);
+        Ok(())
+    }
+
+    fn parameter_sets(&mut self) -> Result<Vec<u8>> {
+        let mut out = ptr::null_mut();
+        let mut out_len: c_int = 0;
+        // SAFETY: `raw` is a live handle; the output is copied before the next call
+        let ret = unsafe { linglide_x264_headers(self.raw.as_ptr(), &mut out, &mut out_len) };
+        if ret < 0 || out.is_null() {
+            return Err(Error::EncoderError(format!(
+                "Failed to get x264 headers (error {})",
+                ret
+            )));
+        }
+
+        Ok(unsafe { std::slice::from_raw_parts(out, out_len as usize) }.to_vec())
+    }
+}
+
+impl Drop for X264Encoder {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from `linglide_x264_open` and is not used again
+        unsafe { linglide_x264_close(self.raw.as_ptr()) };
+    }
+}
+
+fn open(
+    width: u32,
+    height: u32,
+    fps: u32,
+    bitrate: u32,
+    color_space: ColorSpace,
+    settings: &EncoderSettings,
+) -> Result<NonNull<RawEncoder>> {
+    let config = OpenConfig {
+        width: width as c_int,
+        height: height as c_int,
+        fps: fps as c_int,
+        bitrate_kbps: bitrate as c_int,
+        matrix: c_int::from(color_space.matrix_code()),
+        transfer: c_int::from(color_space.transfer_code()),
+        primaries: c_int::from(color_space.primaries_code()),
+        full_range: c_int::from(color_space.is_full_range()),
+        keyint: settings.gop as c_int,
+        intra_refresh: c_int::from(settings.intra_refresh),
+        high_profile: c_int::from(settings.profile == H264Profile::High),
+        slices: settings.slices as c_int,
+        qp_min: c_int::from(settings.min_qp),
+        qp_max: c_int::from(settings.max_qp),
+    };
+    // SAFETY: `config` outlives the call; the shim returns null on failure
+    let raw = unsafe { linglide_x264_open(&config) };
+    NonNull::new(raw).ok_or_else(|| {
+        Error::EncoderError(format!(
+            "Failed to open x264 encoder for {}x{} @ {} fps",
+            width, height, fps
+        ))
+    })
+}
@@ -0,0 +1,442 @@
+//! Input event routing shared by every server frontend
+//!
+//! The dispatcher owns all virtual devices and routes client events to them.
+//! It keeps per-client state so that touches from different clients never
+//! collide and so anything a client is holding is released when it goes away.
+
+use crate::mouse::RelativeMouse;
+use crate::{
+    InputSink, VirtualDevice, VirtualKeyboard, VirtualMouse, VirtualStylus, VirtualTouchscreen,
+};
+use linglide_core::protocol::{ClientId, InputCommand, InputEvent, Modifiers};
+use linglide_core::Result;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Input state held by a single client
+#[derive(Debug, Default)]
+struct ClientState {
+    /// Client touch id -> dispatcher touch id
+    touches: HashMap<u32, u32>,
+    /// Mouse buttons currently held
+    buttons: Vec<u8>,
+    /// Last known pointer position (normalized)
+    last_position: (f64, f64),
+    /// Keys currently held (web `KeyboardEvent.code`)
+    keys: Vec<String>,
+    /// Whether this client last brought the pen into range
+    pen_active: bool,
+}
+
+/// Routes input events from all clients to the virtual devices
+pub struct InputDispatcher<S: InputSink = VirtualDevice> {
+    touchscreen: VirtualTouchscreen<S>,
+    mouse: VirtualMouse<S>,
+    scroll_mouse: RelativeMouse<S>,
+    stylus: VirtualStylus<S>,
+    keyboard: VirtualKeyboard<S>,
+    clients: HashMap<ClientId, ClientState>,
+    /// Next touch id handed to the touchscreen
+    next_touch_id: u32,
+}
+
+impl InputDispatcher {
+    /// Create the virtual devices for a display region
+    pub fn new(width: u32, height: u32, offset_x: i32, offset_y: i32) -> Result<Self> {
+        info!("Creating virtual input devices...");
+
+        Ok(Self::from_devices(
+            VirtualTouchscreen::new(width, height, offset_x, offset_y)?,
+            VirtualMouse::new(width, height, offset_x, offset_y)?,
+            RelativeMouse::new()?,
+            VirtualStylus::new(width, height, offset_x, offset_y)?,
+            VirtualKeyboard::new()?,
+        ))
+    }
+}
+
+impl<S: InputSink> InputDispatcher<S> {
+    /// Create a dispatcher from existing devices
+    pub fn from_devices(
+        touchscreen: VirtualTouchscreen<S>,
+        mouse: VirtualMouse<S>,
+        scroll_mouse: RelativeMouse<S>,
+        stylus: VirtualStylus<S>,
+        keyboard: VirtualKeyboard<S>,
+    ) -> Self {
+        Self {
+            touchscreen,
+            mouse,
+            scroll_mouse,
+            stylus,
+            keyboard,
+            clients: HashMap::new(),
+            next_touch_id: 0,
+        }
+    }
+
+    /// Handle a command from the input channel
+    pub fn handle(&mut self, command: InputCommand) -> Result<()> {
+        match command {
+            InputCommand::Event { client_id, event } => self.dispatch(client_id, event),
+            InputCommand::Disconnect { client_id } => self.disconnect(client_id),
+        }
+    }
+
+    /// Route a single event from a client to the matching device
+    pub fn dispatch(&mut self, client_id: ClientId, event: InputEvent) -> Result<()> {
+        let client = self.clients.entry(client_id).or_default();
+
+        match event {
+            InputEvent::TouchStart { id, x, y } => {
+                // A client reusing an id without TouchEnd would otherwise
+                // strand the old touch's slot
+                if let Some(stale) = client.touches.remove(&id) {
+                    self.touchscreen.touch_end(stale)?;
+                }
+                let touch_id = self.next_touch_id;
+                self.next_touch_id = self.next_touch_id.wrapping_add(1);
+                self.touchscreen.touch_start(touch_id, x, y)?;
+                client.touches.insert(id, touch_id);
+                Ok(())
+            }
+            InputEvent::TouchMove { id, x, y } => match client.touches.get(&id) {
+                Some(&touch_id) => self.touchscreen.touch_move(touch_id, x, y),
+                None => Ok(()),
+            },
+            InputEvent::TouchEnd { id } => match client.touches.remove(&id) {
+                Some(touch_id) => self.touchscreen.touch_end(touch_id),
+                None => Ok(()),
+            },
+            InputEvent::TouchCancel { id } => match client.touches.remove(&id) {
+                Some(touch_id) => self.touchscreen.touch_cancel(touch_id),
+                None => Ok(()),
+            },
+            InputEvent::MouseDown { button, x, y } => {
+                client.last_position = (x, y);
+                self.mouse.mouse_down(button, x, y)?;
+                if !client.buttons.contains(&button) {
+                    client.buttons.push(button);
+                }
+                Ok(())
+            }
+            InputEvent::MouseUp { button, x, y } => {
+                client.last_position = (x, y);
+                client.buttons.retain(|b| *b != button);
+                self.mouse.mouse_up(button, x, y)
+            }
+            InputEvent::MouseMove { x, y } => {
+                client.last_position = (x, y);
+                self.mouse.mouse_move(x, y)
+            }
+            InputEvent::Scroll { dx, dy } => self.scroll_mouse.scroll(dx, dy),
+            InputEvent::KeyDown { key, modifiers } => {
+                self.keyboard.key_down(&key, &modifiers)?;
+                if !client.keys.contains(&key) {
+                    client.keys.push(key);
+                }
+                Ok(())
+            }
+            InputEvent::KeyUp { key, modifiers } => {
+                client.keys.retain(|k| *k != key);
+                self.keyboard.key_up(&key, &modifiers)
+            }
+            InputEvent::TextInput { text } => self.keyboard.type_text(&text),
+            InputEvent::PenHover {
+                x,
+                y,
+                pressure,
+                tilt_x,
+                tilt_y,
+            } => {
+                self.mark_pen_owner(client_id);
+                self.stylus.pen_hover(x, y, pressure, tilt_x, tilt_y)
+            }
+            InputEvent::PenDown {
+                x,
+                y,
+                pressure,
+                tilt_x,
+                tilt_y,
+                button,
+            } => {
+                self.mark_pen_owner(client_id);
+                self.stylus.pen_down(x, y, pressure, tilt_x, tilt_y, button)
+            }
+            InputEvent::PenMove {
+                x,
+                y,
+                pressure,
+                tilt_x,
+                tilt_y,
+            } => self.stylus.pen_move(x, y, pressure, tilt_x, tilt_y),
+            InputEvent::PenUp { x, y } => self.stylus.pen_up(x, y),
+            InputEvent::PenButtonEvent { button, pressed } => {
+                self.stylus.pen_button(button, pressed)
+            }
+        }
+    }
+
+    /// Record which client is driving the shared stylus
+    fn mark_pen_owner(&mut self, client_id: ClientId) {
+        for (id, client) in self.clients.iter_mut() {
+            client.pen_active = *id == client_id;
+        }
+    }
+
+    /// Release everything a client still holds and forget its state
+    ///
+    /// All releases are attempted even if one of them fails; the first error
+    /// is returned.
+    pub fn disconnect(&mut self, client_id: ClientId) -> Result<()> {
+        let Some(client) = self.clients.remove(&client_id) else {
+            return Ok(());
+        };
+
+        debug!(
+            "Releasing input for client {}: {} touches, {} buttons, {} keys",
+            client_id,
+            client.touches.len(),
+            client.buttons.len(),
+            client.keys.len()
+        );
+
+        let mut results = Vec::new();
+
+        for touch_id in client.touches.into_values() {
+            results.push(self.touchscreen.touch_cancel(touch_id));
+        }
+
+        let (x, y) = client.last_position;
+        for button in client.buttons {
+            results.push(self.mouse.mouse_up(button, x, y));
+        }
+
+        for key in client.keys.into_iter().rev() {
+            results.push(self.keyboard.key_up(&key, &Modifiers::default()));
+        }
+
+        if client.pen_active {
+            results.push(self.stylus.pen_leave());
+        }
+
+        results.into_iter().collect()
+    }
+
+    /// Release input held by every client, e.g. on shutdown
+    pub fn release_all(&mut self) -> Result<()> {
+        let client_ids: Vec<ClientId> = self.clients.keys().copied().collect();
+        let mut results: Vec<Result<()>> = client_ids
+            .into_iter()
+            .map(|client_id| self.disconnect(client_id))
+            .collect();
+        results.push(self.keyboard.release_all());
+        results.into_iter().collect()
+    }
+
+    /// Number of clients with tracked input state
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Run the dispatcher until the input channel closes
+    pub async fn run(mut self, mut input_rx: mpsc::Receiver<InputCommand>) {
+        while let Some(command) = input_rx.recv().await {
+            if let Err(e) = self.handle(command) {
+                warn!("Input error: {}", e);
+            }
+        }
+
+        if let Err(e) = self.release_all() {
+            warn!("Failed to release input: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::touch::MAX_SLOTS;
+    use crate::RecordingSink;
+    use evdev::{AbsoluteAxisCode, EventType, KeyCode};
+
+    fn dispatcher() -> (InputDispatcher<RecordingSink>, RecordingSink) {
+        let sink = RecordingSink::new();
+        let dispatcher = InputDispatcher::from_devices(
+            VirtualTouchscreen::with_sink(sink.clone(), 1000, 1000, 0, 0),
+            VirtualMouse::with_sink(sink.clone(), 1000, 1000, 0, 0),
+            RelativeMouse::with_sink(sink.clone()),
+            VirtualStylus::with_sink(sink.clone(), 1000, 1000, 0, 0),
+            VirtualKeyboard::with_sink(sink.clone()),
+        );
+        (dispatcher, sink)
+    }
+
+    fn slots(events: &[(u16, u16, i32)]) -> Vec<i32> {
+        events
+            .iter()
+            .filter(|(ty, code, _)| {
+                *ty == EventType::ABSOLUTE.0 && *code == AbsoluteAxisCode::ABS_MT_SLOT.0
+            })
+            .map(|(_, _, value)| *value)
+            .collect()
+    }
+
+    #[test]
+    fn test_touch_ids_are_per_client() {
+        let (mut dispatcher, sink) = dispatcher();
+
+        dispatcher
+            .dispatch(
+                1,
+                InputEvent::TouchStart {
+                    id: 0,
+                    x: 0.1,
+                    y: 0.1,
+                },
+            )
+            .unwrap();
+        dispatcher
+            .dispatch(
+                2,
+                InputEvent::TouchStart {
+                    id: 0,
+                    x: 0.9,
+                    y: 0.9,
+                },
+            )
+            .unwrap();
+        assert_eq!(slots(&sink.take_triples()), vec![0, 1]);
+
+        dispatcher
+            .dispatch(2, InputEvent::TouchEnd { id: 0 })
+            .unwrap();
+        assert_eq!(slots(&sink.take_triples()), vec![1]);
+        assert_eq!(dispatcher.client_count(), 2);
+    }
+
+    #[test]
+    fn test_reused_touch_id_releases_slot() {
+        let (mut dispatcher, sink) = dispatcher();
+        let start = InputEvent::TouchStart {
+            id: 0,
+            x: 0.5,
+            y: 0.5,
+        };
+
+        // More restarts than there are slots, none of them ended
+        for _ in 0..=MAX_SLOTS {
+            dispatcher.dispatch(1, start.clone()).unwrap();
+        }
+        assert_eq!(dispatcher.touchscreen.active_touch_count(), 1);
+
+        // Each restart lifts the old touch before reusing its slot
+        let events = sink.take_triples();
+        assert!(slots(&events).iter().all(|slot| *slot == 0));
+        let lifted = events
+            .iter()
+            .filter(|e| {
+                **e == (
+                    EventType::ABSOLUTE.0,
+                    AbsoluteAxisCode::ABS_MT_TRACKING_ID.0,
+                    -1,
+                )
+            })
+            .count();
+        assert_eq!(lifted, MAX_SLOTS as usize);
+
+        dispatcher
+            .dispatch(1, InputEvent::TouchEnd { id: 0 })
+            .unwrap();
+        assert_eq!(dispatcher.touchscreen.active_touch_count(), 0);
+    }
+
+    #[test]
+    fn test_disconnect_releases_held_input() {
+        let (mut dispatcher, sink) = dispatcher();
+
+        dispatcher
+            .dispatch(
+                1,
+                InputEvent::TouchStart {
+                    id: 3,
+                    x: 0.5,
+                    y: 0.5,
+                },
+            )
+            .unwrap();
+        dispatcher
+            .dispatch(
+                1,
+                InputEvent::MouseDown {
+                    button: 2,
+                    x: 0.5,
+                    y: 0.5,
+                },
+            )
+            .unwrap();
+        dispatcher
+            .dispatch(
+                1,
+                InputEvent::KeyDown {
+                    key: "KeyA".to_string(),
+                    modifiers: Modifiers::default(),
+                },
+            )
+            .unwrap();
+        sink.take();
+
+        dispatcher
+            .handle(InputCommand::Disconnect { client_id: 1 })
+            .unwrap();
+        let events = sink.take_triples();
+
+        assert!(events.contains(&(
+            EventType::ABSOLUTE.0,
+            AbsoluteAxisCode::ABS_MT_TRACKING_ID.0,
+            -1
+        )));
+        assert!(events.contains(&(EventType::KEY.0, KeyCode::BTN_TOUCH.0, 0)));
+        assert!(events.contains(&(EventType::KEY.0, KeyCode::BTN_RIGHT.0, 0)));
+        assert!(events.contains(&(EventType::KEY.0, KeyCode::KEY_A.0, 0)));
+        assert_eq!(dispatcher.client_count(), 0);
+
+        // Nothing left to release
+        dispatcher.disconnect(1).unwrap();
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_disconnect_only_affects_own_client() {
+        let (mut dispatcher, sink) = dispatcher();
+
+        dispatcher
+            .dispatch(
+                1,
+                InputEvent::TouchStart {
+                    id: 0,
+                    x: 0.1,
+                    y: 0.1,
+                },
+            )
+            .unwrap();
+        dispatcher
+            .dispatch(
+                2,
+                InputEvent::TouchStart {
+                    id: 0,
+                    x: 0.9,
+                    y: 0.9,
+                },
+            )
+            .unwrap();
+        sink.take();
+
+        dispatcher.disconnect(2).unwrap();
+        let events = sink.take_triples();
+        assert_eq!(slots(&events), vec![1]);
+        // Client 1 still touching, so BTN_TOUCH stays down
+        assert!(!events.contains(&(EventType::KEY.0, KeyCode::BTN_TOUCH.0, 0)));
+    }
+}
@@ -0,0 +1,594 @@
+//! Virtual keyboard for hardware keyboards attached to the client
+//!
+//! Keys arrive as web `KeyboardEvent.code` strings, which name physical key
+//! positions independent of layout, so they map directly onto evdev key codes
+//! and the desktop's own keymap does the rest.
+
+use crate::text::{self, KeyboardLayout, Keystroke};
+use crate::{InputSink, VirtualDevice};
+use evdev::{EventType, InputEvent, KeyCode};
+use linglide_core::protocol::Modifiers;
+use linglide_core::{Error, Result};
+use tracing::debug;
+
+/// Map a web `KeyboardEvent.code` value to an evdev key code
+pub fn key_code(code: &str) -> Option<KeyCode> {
+    let key = match code {
+        // Letters
+        "KeyA" => KeyCode::KEY_A,
+        "KeyB" => KeyCode::KEY_B,
+        "KeyC" => KeyCode::KEY_C,
+        "KeyD" => KeyCode::KEY_D,
+        "KeyE" => KeyCode::KEY_E,
+        "KeyF" => KeyCode::KEY_F,
+        "KeyG" => KeyCode::KEY_G,
+        "KeyH" => KeyCode::KEY_H,
+        "KeyI" => KeyCode::KEY_I,
+        "KeyJ" => KeyCode::KEY_J,
+        "KeyK" => KeyCode::KEY_K,
+        "KeyL" => KeyCode::KEY_L,
+        "KeyM" => KeyCode::KEY_M,
+        "KeyN" => KeyCode::KEY_N,
+        "KeyO" => KeyCode::KEY_O,
+        "KeyP" => KeyCode::KEY_P,
+        "KeyQ" => KeyCode::KEY_Q,
+        "KeyR" => KeyCode::KEY_R,
+        "KeyS" => KeyCode::KEY_S,
+        "KeyT" => KeyCode::KEY_T,
+        "KeyU" => KeyCode::KEY_U,
+        "KeyV" => KeyCode::KEY_V,
+        "KeyW" => KeyCode::KEY_W,
+        "KeyX" => KeyCode::KEY_X,
+        "KeyY" => KeyCode::KEY_Y,
+        "KeyZ" => KeyCode::KEY_Z,
+
+        // Digit row
+        "Digit1" => KeyCode::KEY_1,
+        "Digit2" => KeyCode::KEY_2,
+        "Digit3" => KeyCode::KEY_3,
+        "Digit4" => KeyCode::KEY_4,
+        "Digit5" => KeyCode::KEY_5,
+        "Digit6" => KeyCode::KEY_6,
+        "Digit7" => KeyCode::KEY_7,
+        "Digit8" => KeyCode::KEY_8,
+        "Digit9" => KeyCode::KEY_9,
+        "Digit0" => KeyCode::KEY_0,
+
+        // Punctuation
+        "Minus" => KeyCode::KEY_MINUS,
+        "Equal" => KeyCode::KEY_EQUAL,
+        "BracketLeft" => KeyCode::KEY_LEFTBRACE,
+        "BracketRight" => KeyCode::KEY_RIGHTBRACE,
+        "Backslash" => KeyCode::KEY_BACKSLASH,
+        "Semicolon" => KeyCode::KEY_SEMICOLON,
+        "Quote" => KeyCode::KEY_APOSTROPHE,
+        "Backquote" => KeyCode::KEY_GRAVE,
+        "Comma" => KeyCode::KEY_COMMA,
+        "Period" => KeyCode::KEY_DOT,
+        "Slash" => KeyCode::KEY_SLASH,
+        "IntlBackslash" => KeyCode::KEY_102ND,
+        "IntlRo" => KeyCode::KEY_RO,
+        "IntlYen" => KeyCode::KEY_YEN,
+
+        // Whitespace and editing
+        "Enter" => KeyCode::KEY_ENTER,
+        "Escape" => KeyCode::KEY_ESC,
+        "Backspace" => KeyCode::KEY_BACKSPACE,
+        "Tab" => KeyCode::KEY_TAB,
+        "Space" => KeyCode::KEY_SPACE,
+        "Insert" => KeyCode::KEY_INSERT,
+        "Delete" => KeyCode::KEY_DELETE,
+        "Home" => KeyCode::KEY_HOME,
+        "End" => KeyCode::KEY_END,
+        "PageUp" => KeyCode::KEY_PAGEUP,
+        "PageDown" => KeyCode::KEY_PAGEDOWN,
+
+        // Arrows
+        "ArrowUp" => KeyCode::KEY_UP,
+        "ArrowDown" => KeyCode::KEY_DOWN,
+        "ArrowLeft" => KeyCode::KEY_LEFT,
+        "ArrowRight" => KeyCode::KEY_RIGHT,
+
+        // Modifiers and locks
+        "ShiftLeft" => KeyCode::KEY_LEFTSHIFT,
+        "ShiftRight" => KeyCode::KEY_RIGHTSHIFT,
+        "ControlLeft" => KeyCode::KEY_LEFTCTRL,
+        "ControlRight" => KeyCode::KEY_RIGHTCTRL,
+        "AltLeft" => KeyCode::KEY_LEFTALT,
+        "AltRight" => KeyCode::KEY_RIGHTALT,
+        "MetaLeft" | "OSLeft" => KeyCode::KEY_LEFTMETA,
+        "MetaRight" | "OSRight" => KeyCode::KEY_RIGHTMETA,
+        "CapsLock" => KeyCode::KEY_CAPSLOCK,
+        "NumLock" => KeyCode::KEY_NUMLOCK,
+        "ScrollLock" => KeyCode::KEY_SCROLLLOCK,
+        "ContextMenu" => KeyCode::KEY_COMPOSE,
+
+        // System keys
+        "PrintScreen" => KeyCode::KEY_SYSRQ,
+        "Pause" => KeyCode::KEY_PAUSE,
+
+        // Function keys
+        "F1" => KeyCode::KEY_F1,
+        "F2" => KeyCode::KEY_F2,
+        "F3" => KeyCode::KEY_F3,
+        "F4" => KeyCode::KEY_F4,
+        "F5" => KeyCode::KEY_F5,
+        "F6" => KeyCode::KEY_F6,
+        "F7" => KeyCode::KEY_F7,
+        "F8" => KeyCode::KEY_F8,
+        "F9" => KeyCode::KEY_F9,
+        "F10" => KeyCode::KEY_F10,
+        "F11" => KeyCode::KEY_F11,
+        "F12" => KeyCode::KEY_F12,
+        "F13" => KeyCode::KEY_F13,
+        "F14" => KeyCode::KEY_F14,
+        "F15" => KeyCode::KEY_F15,
+        "F16" => KeyCode::KEY_F16,
+        "F17" => KeyCode::KEY_F17,
+        "F18" => KeyCode::KEY_F18,
+        "F19" => KeyCode::KEY_F19,
+        "F20" => KeyCode::KEY_F20,
+        "F21" => KeyCode::KEY_F21,
+        "F22" => KeyCode::KEY_F22,
+        "F23" => KeyCode::KEY_F23,
+        "F24" => KeyCode::KEY_F24,
+
+        // Numpad
+        "Numpad0" => KeyCode::KEY_KP0,
+        "Numpad1" => KeyCode::KEY_KP1,
+        "Numpad2" => KeyCode::KEY_KP2,
+        "Numpad3" => KeyCode::KEY_KP3,
+        "Numpad4" => KeyCode::KEY_KP4,
+        "Numpad5" => KeyCode::KEY_KP5,
+        "Numpad6" => KeyCode::KEY_KP6,
+        "Numpad7" => KeyCode::KEY_KP7,
+        "Numpad8" => KeyCode::KEY_KP8,
+        "Numpad9" => KeyCode::KEY_KP9,
+        "NumpadAdd" => KeyCode::KEY_KPPLUS,
+        "NumpadSubtract" => KeyCode::KEY_KPMINUS,
+        "NumpadMultiply" => KeyCode::KEY_KPASTERISK,
+        "NumpadDivide" => KeyCode::KEY_KPSLASH,
+        "NumpadDecimal" => KeyCode::KEY_KPDOT,
+        "NumpadEnter" => KeyCode::KEY_KPENTER,
+        "NumpadEqual" => KeyCode::KEY_KPEQUAL,
+        "NumpadComma" => KeyCode::KEY_KPCOMMA,
+
+        // Media keys
+        "AudioVolumeMute" | "VolumeMute" => KeyCode::KEY_MUTE,
+        "AudioVolumeDown" | "VolumeDown" => KeyCode::KEY_VOLUMEDOWN,
+        "AudioVolumeUp" | "VolumeUp" => KeyCode::KEY_VOLUMEUP,
+        "MediaPlayPause" => KeyCode::KEY_PLAYPAUSE,
+        "MediaStop" => KeyCode::KEY_STOPCD,
+        "MediaTrackNext" => KeyCode::KEY_NEXTSONG,
+        "MediaTrackPrevious" => KeyCode::KEY_PREVIOUSSONG,
+
+        // IME keys
+        "Lang1" => KeyCode::KEY_HANGEUL,
+        "Lang2" => KeyCode::KEY_HANJA,
+
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Left-hand modifier keys in the order they are pressed
+const MODIFIER_KEYS: [KeyCode; 4] = [
+    KeyCode::KEY_LEFTCTRL,
+    KeyCode::KEY_LEFTALT,
+    KeyCode::KEY_LEFTSHIFT,
+    KeyCode::KEY_LEFTMETA,
+];
+
+/// Check whether a key is one of the modifier keys
+fn is_modifier(key: KeyCode) -> bool {
+    matches!(
+        key,
+        KeyCode::KEY_LEFTCTRL
+            | KeyCode::KEY_RIGHTCTRL
+            | KeyCode::KEY_LEFTALT
+            | KeyCode::KEY_RIGHTALT
+            | KeyCode::KEY_LEFTSHIFT
+            | KeyCode::KEY_RIGHTSHIFT
+            | KeyCode::KEY_LEFTMETA
+            | KeyCode::KEY_RIGHTMETA
+    )
+}
+
+/// Whether the modifier state requests the given left-hand modifier
+fn modifier_requested(modifiers: &Modifiers, key: KeyCode) -> bool {
+    match key {
+        KeyCode::KEY_LEFTCTRL => modifiers.ctrl,
+        KeyCode::KEY_LEFTALT => modifiers.alt,
+        KeyCode::KEY_LEFTSHIFT => modifiers.shift,
+        KeyCode::KEY_LEFTMETA => modifiers.meta,
+        _ => false,
+    }
+}
+
+/// Both sides of a modifier, used to check whether it is already held
+fn modifier_pair(key: KeyCode) -> [KeyCode; 2] {
+    match key {
+        KeyCode::KEY_LEFTCTRL => [KeyCode::KEY_LEFTCTRL, KeyCode::KEY_RIGHTCTRL],
+        KeyCode::KEY_LEFTALT => [KeyCode::KEY_LEFTALT, KeyCode::KEY_RIGHTALT],
+        KeyCode::KEY_LEFTSHIFT => [KeyCode::KEY_LEFTSHIFT, KeyCode::KEY_RIGHTSHIFT],
+        _ => [KeyCode::KEY_LEFTMETA, KeyCode::KEY_RIGHTMETA],
+    }
+}
+
+/// Virtual keyboard driven by web key events
+pub struct VirtualKeyboard<S: InputSink = VirtualDevice> {
+    device: S,
+    /// Keys pressed by the client, in press order
+    pressed: Vec<KeyCode>,
+    /// Modifiers pressed on the client's behalf from the modifier state
+    synthesized: Vec<KeyCode>,
+    /// Desktop layout used to type text
+    layout: KeyboardLayout,
+}
+
+impl VirtualKeyboard {
+    /// Create a new virtual keyboard
+    pub fn new() -> Result<Self> {
+        let device = VirtualDevice::new_keyboard("LinGlide Keyboard")?;
+        Ok(Self::with_sink(device).with_layout(KeyboardLayout::detect()))
+    }
+}
+
+impl<S: InputSink> VirtualKeyboard<S> {
+    /// Create a keyboard that writes to the given sink
+    pub fn with_sink(device: S) -> Self {
+        Self {
+            device,
+            pressed: Vec::new(),
+            synthesized: Vec::new(),
+            layout: KeyboardLayout::default(),
+        }
+    }
+
+    /// Builder pattern: set the desktop layout used to type text
+    pub fn with_layout(mut self, layout: KeyboardLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    fn key_event(key: KeyCode, value: i32) -> [InputEvent; 2] {
+        [
+            InputEvent::new(EventType::KEY.0, key.0, value),
+            InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0),
+        ]
+    }
+
+    /// Press and release a key, holding Shift around it if needed
+    fn tap(key: KeyCode, shift: bool, events: &mut Vec<InputEvent>) {
+        if shift {
+            events.extend(Self::key_event(KeyCode::KEY_LEFTSHIFT, 1));
+        }
+        events.extend(Self::key_event(key, 1));
+        events.extend(Self::key_event(key, 0));
+        if shift {
+            events.extend(Self::key_event(KeyCode::KEY_LEFTSHIFT, 0));
+        }
+    }
+
+    fn is_held(&self, key: KeyCode) -> bool {
+        self.pressed.contains(&key) || self.synthesized.contains(&key)
+    }
+
+    /// Handle key down event
+    ///
+    /// Modifiers reported in `modifiers` but not currently held are pressed
+    /// first, so shortcuts still work when the modifier's own key event was
+    /// swallowed by the client (e.g. an on-screen modifier toggle).
+    pub fn key_down(&mut self, code: &str, modifiers: &Modifiers) -> Result<()> {
+        let key =
+            key_code(code).ok_or_else(|| Error::InputError(format!("Unknown key: {}", code)))?;
+
+        // Browser auto-repeat; the compositor generates its own repeats
+        if self.pressed.contains(&key) {
+            return Ok(());
+        }
+
+        debug!("Key down: code={}, key={:?}", code, key);
+
+        let mut events = Vec::new();
+
+        if !is_modifier(key) {
+            for modifier in MODIFIER_KEYS {
+                let requested = modifier_requested(modifiers, modifier);
+                let held = modifier_pair(modifier).iter().any(|k| self.is_held(*k));
+
+                if requested && !held {
+                    events.extend(Self::key_event(modifier, 1));
+                    self.synthesized.push(modifier);
+                } else if !requested && self.synthesized.contains(&modifier) {
+                    // Client no longer reports this modifier
+                    events.extend(Self::key_event(modifier, 0));
+                    self.synthesized.retain(|k| *k != modifier);
+                }
+            }
+        }
+
+        events.extend(Self::key_event(key, 1));
+        self.pressed.push(key);
+
+        self.device.emit(&events)
+    }
+
+    /// Handle key up event
+    ///
+    /// Synthesized modifiers are released, in reverse order, once no other
+    /// non-modifier key is held.
+    pub fn key_up(&mut self, code: &str, _modifiers: &Modifiers) -> Result<()> {
+        let key =
+            key_code(code).ok_or_else(|| Error::InputError(format!("Unknown key: {}", code)))?;
+
+        debug!("Key up: code={}, key={:?}", code, key);
+
+        let mut events = Vec::new();
+
+        if let Some(pos) = self.pressed.iter().position(|k| *k == key) {
+            self.pressed.remove(pos);
+            events.extend(Self::key_event(key, 0));
+        }
+
+        if !self.pressed.iter().any(|k| !is_modifier(*k)) {
+            while let Some(modifier) = self.synthesized.pop() {
+                events.extend(Self::key_event(modifier, 0));
+            }
+        }
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        self.device.emit(&events)
+    }
+
+    /// Type a Unicode string
+    ///
+    /// Each character is emitted as its own batch so the compose sequence of
+    /// one character is fully processed before the next one starts.
+    pub fn type_text(&mut self, text: &str) -> Result<()> {
+        debug!("Typing text: {} chars", text.chars().count());
+
+        for c in text.chars() {
+            let mut events = Vec::new();
+
+            match text::keystroke(c, self.layout) {
+                Some(Keystroke::Key { key, shift }) => Self::tap(key, shift, &mut events),
+                Some(Keystroke::Unicode(codepoint)) => {
+                    // Ctrl+Shift+U, hex digits, Space to commit
+                    events.extend(Self::key_event(KeyCode::KEY_LEFTCTRL, 1));
+                    events.extend(Self::key_event(KeyCode::KEY_LEFTSHIFT, 1));
+                    events.extend(Self::key_event(KeyCode::KEY_U, 1));
+                    events.extend(Self::key_event(KeyCode::KEY_U, 0));
+                    events.extend(Self::key_event(KeyCode::KEY_LEFTSHIFT, 0));
+                    events.extend(Self::key_event(KeyCode::KEY_LEFTCTRL, 0));
+                    for digit in format!("{:x}", codepoint).chars() {
+                        if let Some((key, shift)) = text::hex_digit_key(digit, self.layout) {
+                            Self::tap(key, shift, &mut events);
+                        }
+                    }
+                    events.extend(Self::key_event(KeyCode::KEY_SPACE, 1));
+                    events.extend(Self::key_event(KeyCode::KEY_SPACE, 0));
+                }
+                None => continue,
+            }
+
+            self.device.emit(&events)?;
+        }
+
+        Ok(())
+    }
+
+    /// Release every held key, e.g. when the client disconnects
+    pub fn release_all(&mut self) -> Result<()> {
+        let mut events = Vec::new();
+
+        while let Some(key) = self.pressed.pop() {
+            events.extend(Self::key_event(key, 0));
+        }
+        while let Some(modifier) = self.synthesized.pop() {
+            events.extend(Self::key_event(modifier, 0));
+        }
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Releasing {} held keys", events.len() / 2);
+        self.device.emit(&events)
+    }
+
+    /// Get the number of keys currently held down
+    pub fn pressed_key_count(&self) -> usize {
+        self.pressed.len() + self.synthesized.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingSink;
+
+    #[test]
+    fn test_key_code_mapping() {
+        assert_eq!(key_code("KeyA"), Some(KeyCode::KEY_A));
+        assert_eq!(key_code("Digit0"), Some(KeyCode::KEY_0));
+        assert_eq!(key_code("ShiftRight"), Some(KeyCode::KEY_RIGHTSHIFT));
+        assert_eq!(key_code("NumpadEnter"), Some(KeyCode::KEY_KPENTER));
+        assert_eq!(key_code("Unidentified"), None);
+    }
+
+    const KEY: u16 = EventType::KEY.0;
+    const SYN: (u16, u16, i32) = (EventType::SYNCHRONIZATION.0, 0, 0);
+
+    fn keyboard() -> (VirtualKeyboard<RecordingSink>, RecordingSink) {
+        let sink = RecordingSink::new();
+        (VirtualKeyboard::with_sink(sink.clone()), sink)
+    }
+
+    #[test]
+    fn test_synthesized_modifier_ordering() {
+        let (mut keyboard, sink) = keyboard();
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Default::default()
+        };
+
+        keyboard.key_down("KeyC", &ctrl).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::KEY_LEFTCTRL.0, 1),
+                SYN,
+                (KEY, KeyCode::KEY_C.0, 1),
+                SYN,
+            ]
+        );
+
+        // Browser auto-repeat is ignored
+        keyboard.key_down("KeyC", &ctrl).unwrap();
+        assert!(sink.take().is_empty());
+
+        keyboard.key_up("KeyC", &Modifiers::default()).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::KEY_C.0, 0),
+                SYN,
+                (KEY, KeyCode::KEY_LEFTCTRL.0, 0),
+                SYN,
+            ]
+        );
+        assert_eq!(keyboard.pressed_key_count(), 0);
+    }
+
+    #[test]
+    fn test_physical_modifier_not_duplicated() {
+        let (mut keyboard, sink) = keyboard();
+        let shift = Modifiers {
+            shift: true,
+            ..Default::default()
+        };
+
+        keyboard.key_down("ShiftRight", &shift).unwrap();
+        keyboard.key_down("KeyA", &shift).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::KEY_RIGHTSHIFT.0, 1),
+                SYN,
+                (KEY, KeyCode::KEY_A.0, 1),
+                SYN,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_release_all() {
+        let (mut keyboard, sink) = keyboard();
+        let alt = Modifiers {
+            alt: true,
+            ..Default::default()
+        };
+
+        keyboard.key_down("Tab", &alt).unwrap();
+        sink.take();
+
+        keyboard.release_all().unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::KEY_TAB.0, 0),
+                SYN,
+                (KEY, KeyCode::KEY_LEFTALT.0, 0),
+                SYN,
+            ]
+        );
+
+        keyboard.release_all().unwrap();
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_type_text_compose_fallback() {
+        let (mut keyboard, sink) = keyboard();
+
+        keyboard.type_text("é").unwrap();
+        let keys: Vec<(u16, i32)> = sink
+            .take_triples()
+            .into_iter()
+            .filter(|(ty, _, _)| *ty == KEY)
+            .map(|(_, code, value)| (code, value))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (KeyCode::KEY_LEFTCTRL.0, 1),
+                (KeyCode::KEY_LEFTSHIFT.0, 1),
+                (KeyCode::KEY_U.0, 1),
+                (KeyCode::KEY_U.0, 0),
+                (KeyCode::KEY_LEFTSHIFT.0, 0),
+                (KeyCode::KEY_LEFTCTRL.0, 0),
+                (KeyCode::KEY_E.0, 1),
+                (KeyCode::KEY_E.0, 0),
+                (KeyCode::KEY_9.0, 1),
+                (KeyCode::KEY_9.0, 0),
+                (KeyCode::KEY_SPACE.0, 1),
+                (KeyCode::KEY_SPACE.0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_text_follows_layout() {
+        let (keyboard, sink) = keyboard();
+        let mut keyboard = keyboard.with_layout(KeyboardLayout::Us);
+        keyboard.type_text("1").unwrap();
+        assert_eq!(
+            sink.take_triples()
+                .into_iter()
+                .filter(|(ty, _, _)| *ty == KEY)
+                .map(|(_, code, value)| (code, value))
+                .collect::<Vec<_>>(),
+            vec![(KeyCode::KEY_1.0, 1), (KeyCode::KEY_1.0, 0)]
+        );
+
+        // On AZERTY the top row needs Shift for digits, so "1" (U+0031) is
+        // composed and its hex digits are shifted
+        let mut keyboard = keyboard.with_layout(KeyboardLayout::Azerty);
+        keyboard.type_text("1").unwrap();
+        let keys: Vec<(u16, i32)> = sink
+            .take_triples()
+            .into_iter()
+            .filter(|(ty, _, _)| *ty == KEY)
+            .map(|(_, code, value)| (code, value))
+            .skip(6)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (KeyCode::KEY_LEFTSHIFT.0, 1),
+                (KeyCode::KEY_3.0, 1),
+                (KeyCode::KEY_3.0, 0),
+                (KeyCode::KEY_LEFTSHIFT.0, 0),
+                (KeyCode::KEY_LEFTSHIFT.0, 1),
+                (KeyCode::KEY_1.0, 1),
+                (KeyCode::KEY_1.0, 0),
+                (KeyCode::KEY_LEFTSHIFT.0, 0),
+                (KeyCode::KEY_SPACE.0, 1),
+                (KeyCode::KEY_SPACE.0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mapped_keys_are_keyboard_keys() {
+        // new_keyboard only advertises KEY_ESC..=KEY_MICMUTE
+        for code in ["KeyQ", "F24", "MediaPlayPause", "Lang1", "Pause"] {
+            let key = key_code(code).unwrap();
+            assert!((1..=248).contains(&key.0), "{} out of range", code);
+        }
+    }
+}
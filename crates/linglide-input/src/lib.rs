@@ -2,12 +2,19 @@
 //!
 //! This crate provides virtual input device creation and event injection.
 
+pub mod dispatcher;
+pub mod keyboard;
 pub mod mouse;
+pub mod sink;
 pub mod stylus;
+pub mod text;
 pub mod touch;
 pub mod uinput;
 
+pub use dispatcher::InputDispatcher;
+pub use keyboard::VirtualKeyboard;
 pub use mouse::VirtualMouse;
+pub use sink::{InputSink, RecordingSink};
 pub use stylus::VirtualStylus;
 pub use touch::VirtualTouchscreen;
 pub use uinput::VirtualDevice;
@@ -1,13 +1,13 @@
 //! Virtual mouse emulation
 
-use crate::VirtualDevice;
+use crate::{InputSink, VirtualDevice};
 use evdev::{AbsoluteAxisCode, EventType, InputEvent, KeyCode, RelativeAxisCode};
 use linglide_core::{Error, Result};
 use tracing::debug;
 
 /// Virtual mouse for desktop control
-pub struct VirtualMouse {
-    device: VirtualDevice,
+pub struct VirtualMouse<S: InputSink = VirtualDevice> {
+    device: S,
     width: u32,
     height: u32,
     offset_x: i32,
@@ -27,14 +27,21 @@ impl VirtualMouse {
             offset_y,
         )?;
 
-        Ok(Self {
+        Ok(Self::with_sink(device, width, height, offset_x, offset_y))
+    }
+}
+
+impl<S: InputSink> VirtualMouse<S> {
+    /// Create a mouse that writes to the given sink
+    pub fn with_sink(device: S, width: u32, height: u32, offset_x: i32, offset_y: i32) -> Self {
+        Self {
             device,
             width,
             height,
             offset_x,
             offset_y,
             button_states: [false; 3],
-        })
+        }
     }
 
     /// Convert normalized coordinates to absolute coordinates
@@ -139,15 +146,22 @@ impl VirtualMouse {
 }
 
 /// Relative mouse for scroll support
-pub struct RelativeMouse {
-    device: VirtualDevice,
+pub struct RelativeMouse<S: InputSink = VirtualDevice> {
+    device: S,
 }
 
 impl RelativeMouse {
     /// Create a new relative mouse (for scroll events)
     pub fn new() -> Result<Self> {
         let device = VirtualDevice::new_mouse("LinGlide Scroll")?;
-        Ok(Self { device })
+        Ok(Self::with_sink(device))
+    }
+}
+
+impl<S: InputSink> RelativeMouse<S> {
+    /// Create a relative mouse that writes to the given sink
+    pub fn with_sink(device: S) -> Self {
+        Self { device }
     }
 
     /// Emit scroll event
@@ -184,3 +198,74 @@ impl RelativeMouse {
         self.device.emit(&events)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingSink;
+
+    const SYN: (u16, u16, i32) = (EventType::SYNCHRONIZATION.0, 0, 0);
+
+    #[test]
+    fn test_mouse_click_with_offset() {
+        let sink = RecordingSink::new();
+        let mut mouse = VirtualMouse::with_sink(sink.clone(), 800, 600, 1920, 0);
+
+        mouse.mouse_down(0, 0.25, 0.5).unwrap();
+        assert!(mouse.is_button_pressed(0));
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (EventType::ABSOLUTE.0, AbsoluteAxisCode::ABS_X.0, 2120),
+                (EventType::ABSOLUTE.0, AbsoluteAxisCode::ABS_Y.0, 300),
+                (EventType::KEY.0, KeyCode::BTN_LEFT.0, 1),
+                SYN,
+            ]
+        );
+
+        mouse.mouse_up(0, 0.25, 0.5).unwrap();
+        assert!(!mouse.is_button_pressed(0));
+        assert_eq!(
+            sink.take_triples()[2],
+            (EventType::KEY.0, KeyCode::BTN_LEFT.0, 0)
+        );
+    }
+
+    #[test]
+    fn test_mouse_invalid_button() {
+        let sink = RecordingSink::new();
+        let mut mouse = VirtualMouse::with_sink(sink.clone(), 800, 600, 0, 0);
+
+        assert!(mouse.mouse_down(5, 0.5, 0.5).is_err());
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_scroll() {
+        let sink = RecordingSink::new();
+        let mut mouse = RelativeMouse::with_sink(sink.clone());
+
+        mouse.scroll(0.0, 30.0).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (EventType::RELATIVE.0, RelativeAxisCode::REL_WHEEL.0, -2),
+                SYN,
+            ]
+        );
+
+        mouse.scroll(45.0, -15.0).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (EventType::RELATIVE.0, RelativeAxisCode::REL_WHEEL.0, 1),
+                (EventType::RELATIVE.0, RelativeAxisCode::REL_HWHEEL.0, -3),
+                SYN,
+            ]
+        );
+
+        // Deltas below one notch are dropped
+        mouse.scroll(5.0, 5.0).unwrap();
+        assert!(sink.take().is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+//! Destinations for generated evdev events
+//!
+//! Devices write through `InputSink` so their event sequences can be checked
+//! without access to `/dev/uinput`.
+
+use crate::VirtualDevice;
+use evdev::InputEvent;
+use linglide_core::Result;
+use std::sync::{Arc, Mutex};
+
+/// Something that accepts batches of evdev events
+pub trait InputSink {
+    /// Emit a batch of events
+    fn emit(&mut self, events: &[InputEvent]) -> Result<()>;
+}
+
+impl InputSink for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> Result<()> {
+        VirtualDevice::emit(self, events)
+    }
+}
+
+/// In-memory sink that records every emitted event
+///
+/// Clones share the same buffer, so a test can keep one handle while the
+/// device under test owns another.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingSink {
+    events: Arc<Mutex<Vec<InputEvent>>>,
+}
+
+impl RecordingSink {
+    /// Create an empty recording sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a copy of all recorded events
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.events.lock().map(|e| e.clone()).unwrap_or_default()
+    }
+
+    /// Take all recorded events, leaving the buffer empty
+    pub fn take(&self) -> Vec<InputEvent> {
+        self.events
+            .lock()
+            .map(|mut e| std::mem::take(&mut *e))
+            .unwrap_or_default()
+    }
+
+    /// Recorded events as `(type, code, value)` tuples, convenient for assertions
+    pub fn take_triples(&self) -> Vec<(u16, u16, i32)> {
+        self.take()
+            .iter()
+            .map(|e| (e.event_type().0, e.code(), e.value()))
+            .collect()
+    }
+}
+
+impl InputSink for RecordingSink {
+    fn emit(&mut self, events: &[InputEvent]) -> Result<()> {
+        if let Ok(mut recorded) = self.events.lock() {
+            recorded.extend_from_slice(events);
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 //! Virtual stylus/pen handling with pressure and tilt support
 
-use crate::VirtualDevice;
+use crate::{InputSink, VirtualDevice};
 use evdev::{AbsoluteAxisCode, EventType, InputEvent, KeyCode};
 use linglide_core::protocol::PenButton;
 use linglide_core::Result;
@@ -14,8 +14,8 @@ const RESOLUTION: i32 = 10;
 
 /// Virtual stylus with pressure, tilt, and button support
 /// Compatible with Wacom tablet protocol for drawing applications
-pub struct VirtualStylus {
-    device: VirtualDevice,
+pub struct VirtualStylus<S: InputSink = VirtualDevice> {
+    device: S,
     width: u32,
     height: u32,
     /// Offset for coordinate translation (virtual display position)
@@ -41,7 +41,14 @@ impl VirtualStylus {
             offset_y,
         )?;
 
-        Ok(Self {
+        Ok(Self::with_sink(device, width, height, offset_x, offset_y))
+    }
+}
+
+impl<S: InputSink> VirtualStylus<S> {
+    /// Create a stylus that writes to the given sink
+    pub fn with_sink(device: S, width: u32, height: u32, offset_x: i32, offset_y: i32) -> Self {
+        Self {
             device,
             width,
             height,
@@ -52,7 +59,7 @@ impl VirtualStylus {
             eraser_mode: false,
             stylus_button1: false,
             stylus_button2: false,
-        })
+        }
     }
 
     /// Convert normalized coordinates (0.0-1.0) to absolute device coordinates
@@ -424,3 +431,97 @@ impl VirtualStylus {
         self.tip_down
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingSink;
+
+    const ABS: u16 = EventType::ABSOLUTE.0;
+    const KEY: u16 = EventType::KEY.0;
+    const SYN: (u16, u16, i32) = (EventType::SYNCHRONIZATION.0, 0, 0);
+
+    fn stylus() -> (VirtualStylus<RecordingSink>, RecordingSink) {
+        let sink = RecordingSink::new();
+        let stylus = VirtualStylus::with_sink(sink.clone(), 100, 100, 0, 0);
+        (stylus, sink)
+    }
+
+    #[test]
+    fn test_pen_hover_down_up() {
+        let (mut stylus, sink) = stylus();
+
+        stylus.pen_hover(0.5, 0.5, 0.0, 10.0, -10.0).unwrap();
+        assert!(stylus.is_in_range());
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::BTN_TOOL_PEN.0, 1),
+                (ABS, AbsoluteAxisCode::ABS_X.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_Y.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_PRESSURE.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_TILT_X.0, 10),
+                (ABS, AbsoluteAxisCode::ABS_TILT_Y.0, -10),
+                (ABS, AbsoluteAxisCode::ABS_DISTANCE.0, 50),
+                SYN,
+            ]
+        );
+
+        stylus
+            .pen_down(0.5, 0.5, 1.0, 0.0, 0.0, PenButton::Primary)
+            .unwrap();
+        assert!(stylus.is_tip_down());
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::BTN_TOOL_PEN.0, 1),
+                (ABS, AbsoluteAxisCode::ABS_X.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_Y.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_PRESSURE.0, MAX_PRESSURE),
+                (ABS, AbsoluteAxisCode::ABS_TILT_X.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_TILT_Y.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_DISTANCE.0, 0),
+                (KEY, KeyCode::BTN_TOUCH.0, 1),
+                SYN,
+            ]
+        );
+
+        stylus.pen_up(0.5, 0.5).unwrap();
+        assert!(!stylus.is_tip_down());
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (ABS, AbsoluteAxisCode::ABS_X.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_Y.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_PRESSURE.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_DISTANCE.0, 50),
+                (KEY, KeyCode::BTN_TOUCH.0, 0),
+                SYN,
+            ]
+        );
+
+        // A second pen up is a no-op
+        stylus.pen_up(0.5, 0.5).unwrap();
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_pen_leave_releases_buttons() {
+        let (mut stylus, sink) = stylus();
+
+        stylus.pen_hover(0.1, 0.1, 0.0, 0.0, 0.0).unwrap();
+        stylus.pen_button(PenButton::Secondary, true).unwrap();
+        sink.take();
+
+        stylus.pen_leave().unwrap();
+        assert!(!stylus.is_in_range());
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (KEY, KeyCode::BTN_STYLUS.0, 0),
+                (KEY, KeyCode::BTN_TOOL_PEN.0, 0),
+                SYN,
+            ]
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Unicode text typing for on-screen keyboards
+//!
+//! When the desktop uses the US layout, printable ASCII is typed as plain key
+//! presses (with Shift where needed). Everything else, including ASCII on any
+//! other layout, uses the Ctrl+Shift+U hex entry sequence understood by GTK
+//! and IBus, which covers accented letters, CJK and emoji without remapping
+//! the desktop keymap.
+
+use evdev::KeyCode;
+use std::process::Command;
+use tracing::debug;
+
+/// Active desktop keyboard layout, as far as typing text is concerned
+///
+/// uinput sends key positions, so which character a key produces depends on
+/// the layout. Only the layouts listed here have known positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyboardLayout {
+    /// US QWERTY: every printable ASCII character has a known key
+    Us,
+    /// French/Belgian AZERTY: digits need Shift and `a` is on the US Q key
+    Azerty,
+    /// Anything else, or a layout that couldn't be detected
+    ///
+    /// Hex digits are assumed to be on their US keys, as on QWERTY and
+    /// QWERTZ layouts.
+    #[default]
+    Unknown,
+}
+
+impl KeyboardLayout {
+    /// Detect the active layout from the XKB configuration
+    ///
+    /// Asks `setxkbmap` on X11 and falls back to `XKB_DEFAULT_LAYOUT` and
+    /// `XKB_DEFAULT_VARIANT`, which Wayland compositors read.
+    pub fn detect() -> Self {
+        let query = Command::new("setxkbmap")
+            .arg("-query")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+
+        let layout = match query {
+            Some(query) => Self::from_setxkbmap_query(&query),
+            None => match std::env::var("XKB_DEFAULT_LAYOUT") {
+                Ok(layout) => {
+                    let variant = std::env::var("XKB_DEFAULT_VARIANT").unwrap_or_default();
+                    Self::from_xkb(&layout, &variant)
+                }
+                Err(_) => KeyboardLayout::Unknown,
+            },
+        };
+        debug!("Detected keyboard layout: {:?}", layout);
+        layout
+    }
+
+    /// Layout from the output of `setxkbmap -query`
+    pub fn from_setxkbmap_query(query: &str) -> Self {
+        let field = |name: &str| {
+            query.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == name).then(|| value.trim())
+            })
+        };
+        match field("layout") {
+            Some(layout) => Self::from_xkb(layout, field("variant").unwrap_or("")),
+            None => KeyboardLayout::Unknown,
+        }
+    }
+
+    /// Layout from XKB layout and variant names
+    ///
+    /// With several layouts configured the active group isn't known, so only
+    /// a single layout without a variant is recognised.
+    pub fn from_xkb(layout: &str, variant: &str) -> Self {
+        if !variant.trim().is_empty() {
+            return KeyboardLayout::Unknown;
+        }
+        match layout.trim() {
+            "us" => KeyboardLayout::Us,
+            "fr" | "be" => KeyboardLayout::Azerty,
+            _ => KeyboardLayout::Unknown,
+        }
+    }
+}
+
+/// How a single character is typed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keystroke {
+    /// A key that produces the character on the active layout, optionally
+    /// shifted
+    Key { key: KeyCode, shift: bool },
+    /// Unicode code point entered via the Ctrl+Shift+U compose sequence
+    Unicode(u32),
+}
+
+/// Work out how to type a character on the given layout
+///
+/// Returns `None` for control characters that have no sensible keystroke.
+pub fn keystroke(c: char, layout: KeyboardLayout) -> Option<Keystroke> {
+    let key = match layout {
+        KeyboardLayout::Us => us_layout_key(c),
+        KeyboardLayout::Azerty | KeyboardLayout::Unknown => layout_independent_key(c),
+    };
+    if let Some((key, shift)) = key {
+        return Some(Keystroke::Key { key, shift });
+    }
+
+    if c.is_control() {
+        return None;
+    }
+
+    Some(Keystroke::Unicode(c as u32))
+}
+
+/// Key, and whether Shift is needed, for a hexadecimal digit in the compose
+/// sequence
+pub fn hex_digit_key(digit: char, layout: KeyboardLayout) -> Option<(KeyCode, bool)> {
+    let digit = digit.to_ascii_lowercase();
+    if !digit.is_ascii_hexdigit() {
+        return None;
+    }
+    match (layout, digit) {
+        (KeyboardLayout::Azerty, 'a') => Some((KeyCode::KEY_Q, false)),
+        (KeyboardLayout::Azerty, '0'..='9') => us_layout_key(digit).map(|(key, _)| (key, true)),
+        _ => us_layout_key(digit),
+    }
+}
+
+/// Keys that produce the same character on every layout
+fn layout_independent_key(c: char) -> Option<(KeyCode, bool)> {
+    match c {
+        ' ' | '\n' | '\t' | '\u{8}' => us_layout_key(c),
+        _ => None,
+    }
+}
+
+/// Map a character to its key on the US layout
+fn us_layout_key(c: char) -> Option<(KeyCode, bool)> {
+    let unshifted = |key| Some((key, false));
+    let shifted = |key| Some((key, true));
+
+    match c {
+        'a' => unshifted(KeyCode::KEY_A),
+        'b' => unshifted(KeyCode::KEY_B),
+        'c' => unshifted(KeyCode::KEY_C),
+        'd' => unshifted(KeyCode::KEY_D),
+        'e' => unshifted(KeyCode::KEY_E),
+        'f' => unshifted(KeyCode::KEY_F),
+        'g' => unshifted(KeyCode::KEY_G),
+        'h' => unshifted(KeyCode::KEY_H),
+        'i' => unshifted(KeyCode::KEY_I),
+        'j' => unshifted(KeyCode::KEY_J),
+        'k' => unshifted(KeyCode::KEY_K),
+        'l' => unshifted(KeyCode::KEY_L),
+        'm' => unshifted(KeyCode::KEY_M),
+        'n' => unshifted(KeyCode::KEY_N),
+        'o' => unshifted(KeyCode::KEY_O),
+        'p' => unshifted(KeyCode::KEY_P),
+        'q' => unshifted(KeyCode::KEY_Q),
+        'r' => unshifted(KeyCode::KEY_R),
+        's' => unshifted(KeyCode::KEY_S),
+        't' => unshifted(KeyCode::KEY_T),
+        'u' => unshifted(KeyCode::KEY_U),
+        'v' => unshifted(KeyCode::KEY_V),
+        'w' => unshifted(KeyCode::KEY_W),
+        'x' => unshifted(KeyCode::KEY_X),
+        'y' => unshifted(KeyCode::KEY_Y),
+        'z' => unshifted(KeyCode::KEY_Z),
+        'A'..='Z' => us_layout_key(c.to_ascii_lowercase()).map(|(key, _)| (key, true)),
+
+        '1' => unshifted(KeyCode::KEY_1),
+        '2' => unshifted(KeyCode::KEY_2),
+        '3' => unshifted(KeyCode::KEY_3),
+        '4' => unshifted(KeyCode::KEY_4),
+        '5' => unshifted(KeyCode::KEY_5),
+        '6' => unshifted(KeyCode::KEY_6),
+        '7' => unshifted(KeyCode::KEY_7),
+        '8' => unshifted(KeyCode::KEY_8),
+        '9' => unshifted(KeyCode::KEY_9),
+        '0' => unshifted(KeyCode::KEY_0),
+        '!' => shifted(KeyCode::KEY_1),
+        '@' => shifted(KeyCode::KEY_2),
+        '#' => shifted(KeyCode::KEY_3),
+        '$' => shifted(KeyCode::KEY_4),
+        '%' => shifted(KeyCode::KEY_5),
+        '^' => shifted(KeyCode::KEY_6),
+        '&' => shifted(KeyCode::KEY_7),
+        '*' => shifted(KeyCode::KEY_8),
+        '(' => shifted(KeyCode::KEY_9),
+        ')' => shifted(KeyCode::KEY_0),
+
+        '-' => unshifted(KeyCode::KEY_MINUS),
+        '_' => shifted(KeyCode::KEY_MINUS),
+        '=' => unshifted(KeyCode::KEY_EQUAL),
+        '+' => shifted(KeyCode::KEY_EQUAL),
+        '[' => unshifted(KeyCode::KEY_LEFTBRACE),
+        '{' => shifted(KeyCode::KEY_LEFTBRACE),
+        ']' => unshifted(KeyCode::KEY_RIGHTBRACE),
+        '}' => shifted(KeyCode::KEY_RIGHTBRACE),
+        '\\' => unshifted(KeyCode::KEY_BACKSLASH),
+        '|' => shifted(KeyCode::KEY_BACKSLASH),
+        ';' => unshifted(KeyCode::KEY_SEMICOLON),
+        ':' => shifted(KeyCode::KEY_SEMICOLON),
+        '\'' => unshifted(KeyCode::KEY_APOSTROPHE),
+        '"' => shifted(KeyCode::KEY_APOSTROPHE),
+        '`' => unshifted(KeyCode::KEY_GRAVE),
+        '~' => shifted(KeyCode::KEY_GRAVE),
+        ',' => unshifted(KeyCode::KEY_COMMA),
+        '<' => shifted(KeyCode::KEY_COMMA),
+        '.' => unshifted(KeyCode::KEY_DOT),
+        '>' => shifted(KeyCode::KEY_DOT),
+        '/' => unshifted(KeyCode::KEY_SLASH),
+        '?' => shifted(KeyCode::KEY_SLASH),
+
+        ' ' => unshifted(KeyCode::KEY_SPACE),
+        '\n' => unshifted(KeyCode::KEY_ENTER),
+        '\t' => unshifted(KeyCode::KEY_TAB),
+        '\u{8}' => unshifted(KeyCode::KEY_BACKSPACE),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_keystrokes() {
+        assert_eq!(
+            keystroke('a', KeyboardLayout::Us),
+            Some(Keystroke::Key {
+                key: KeyCode::KEY_A,
+                shift: false
+            })
+        );
+        assert_eq!(
+            keystroke('Q', KeyboardLayout::Us),
+            Some(Keystroke::Key {
+                key: KeyCode::KEY_Q,
+                shift: true
+            })
+        );
+        assert_eq!(
+            keystroke('?', KeyboardLayout::Us),
+            Some(Keystroke::Key {
+                key: KeyCode::KEY_SLASH,
+                shift: true
+            })
+        );
+        assert_eq!(
+            keystroke('\n', KeyboardLayout::Us),
+            Some(Keystroke::Key {
+                key: KeyCode::KEY_ENTER,
+                shift: false
+            })
+        );
+    }
+
+    #[test]
+    fn test_unicode_fallback() {
+        assert_eq!(
+            keystroke('é', KeyboardLayout::Us),
+            Some(Keystroke::Unicode(0xe9))
+        );
+        assert_eq!(
+            keystroke('😀', KeyboardLayout::Us),
+            Some(Keystroke::Unicode(0x1f600))
+        );
+        assert_eq!(keystroke('\r', KeyboardLayout::Us), None);
+    }
+
+    #[test]
+    fn test_other_layouts_use_unicode() {
+        for layout in [KeyboardLayout::Azerty, KeyboardLayout::Unknown] {
+            // Characters that move between layouts go through compose
+            assert_eq!(keystroke('a', layout), Some(Keystroke::Unicode(0x61)));
+            assert_eq!(keystroke('1', layout), Some(Keystroke::Unicode(0x31)));
+            assert_eq!(keystroke('@', layout), Some(Keystroke::Unicode(0x40)));
+            assert_eq!(
+                keystroke(' ', layout),
+                Some(Keystroke::Key {
+                    key: KeyCode::KEY_SPACE,
+                    shift: false
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_hex_digit_keys() {
+        let us = KeyboardLayout::Us;
+        assert_eq!(hex_digit_key('0', us), Some((KeyCode::KEY_0, false)));
+        assert_eq!(hex_digit_key('f', us), Some((KeyCode::KEY_F, false)));
+        assert_eq!(hex_digit_key('F', us), Some((KeyCode::KEY_F, false)));
+        assert_eq!(hex_digit_key('g', us), None);
+
+        let azerty = KeyboardLayout::Azerty;
+        assert_eq!(hex_digit_key('1', azerty), Some((KeyCode::KEY_1, true)));
+        assert_eq!(hex_digit_key('a', azerty), Some((KeyCode::KEY_Q, false)));
+        assert_eq!(hex_digit_key('e', azerty), Some((KeyCode::KEY_E, false)));
+    }
+
+    #[test]
+    fn test_layout_detection() {
+        let query = "rules:      evdev\nmodel:      pc105\nlayout:     us\n";
+        assert_eq!(
+            KeyboardLayout::from_setxkbmap_query(query),
+            KeyboardLayout::Us
+        );
+        let query = "rules:      evdev\nlayout:     fr\n";
+        assert_eq!(
+            KeyboardLayout::from_setxkbmap_query(query),
+            KeyboardLayout::Azerty
+        );
+        let query = "layout:     us\nvariant:    dvorak\n";
+        assert_eq!(
+            KeyboardLayout::from_setxkbmap_query(query),
+            KeyboardLayout::Unknown
+        );
+        assert_eq!(
+            KeyboardLayout::from_setxkbmap_query("rules: evdev\n"),
+            KeyboardLayout::Unknown
+        );
+
+        assert_eq!(KeyboardLayout::from_xkb("de", ""), KeyboardLayout::Unknown);
+        // The active group of a multi-layout setup isn't known
+        assert_eq!(
+            KeyboardLayout::from_xkb("us,de", ""),
+            KeyboardLayout::Unknown
+        );
+    }
+}
@@ -1,14 +1,17 @@
 //! Virtual touchscreen handling with multitouch support
 
-use crate::VirtualDevice;
+use crate::{InputSink, VirtualDevice};
 use evdev::{AbsoluteAxisCode, EventType, InputEvent, KeyCode};
 use linglide_core::{Error, Result};
 use std::collections::HashMap;
 use tracing::{debug, info};
 
+/// Number of multitouch slots
+pub(crate) const MAX_SLOTS: u32 = 10;
+
 /// Virtual touchscreen with multitouch protocol type B support
-pub struct VirtualTouchscreen {
-    device: VirtualDevice,
+pub struct VirtualTouchscreen<S: InputSink = VirtualDevice> {
+    device: S,
     width: u32,
     height: u32,
     /// Active touch points (id -> slot)
@@ -25,26 +28,32 @@ pub struct VirtualTouchscreen {
 impl VirtualTouchscreen {
     /// Create a new virtual touchscreen
     pub fn new(width: u32, height: u32, offset_x: i32, offset_y: i32) -> Result<Self> {
-        let max_slots = 10;
         let device = VirtualDevice::new_multitouch_with_offset(
             "LinGlide Touchscreen",
             width,
             height,
             offset_x,
             offset_y,
-            max_slots,
+            MAX_SLOTS,
         )?;
 
-        Ok(Self {
+        Ok(Self::with_sink(device, width, height, offset_x, offset_y))
+    }
+}
+
+impl<S: InputSink> VirtualTouchscreen<S> {
+    /// Create a touchscreen that writes to the given sink
+    pub fn with_sink(device: S, width: u32, height: u32, offset_x: i32, offset_y: i32) -> Self {
+        Self {
             device,
             width,
             height,
             active_touches: HashMap::new(),
-            max_slots,
+            max_slots: MAX_SLOTS,
             next_tracking_id: 0,
             offset_x,
             offset_y,
-        })
+        }
     }
 
     /// Find an available slot for a new touch
@@ -114,6 +123,8 @@ impl VirtualTouchscreen {
         let result = self.device.emit(&events);
         if let Err(ref e) = result {
             info!("Touch emit error: {:?}", e);
+            // The touch never reached the device, so don't hold its slot
+            self.active_touches.remove(&id);
         }
         result
     }
@@ -204,3 +215,131 @@ impl VirtualTouchscreen {
         self.active_touches.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordingSink;
+
+    const ABS: u16 = EventType::ABSOLUTE.0;
+    const KEY: u16 = EventType::KEY.0;
+    const SYN: (u16, u16, i32) = (EventType::SYNCHRONIZATION.0, 0, 0);
+
+    fn touchscreen() -> (VirtualTouchscreen<RecordingSink>, RecordingSink) {
+        let sink = RecordingSink::new();
+        let touchscreen = VirtualTouchscreen::with_sink(sink.clone(), 1000, 500, 0, 0);
+        (touchscreen, sink)
+    }
+
+    #[test]
+    fn test_touch_start_and_end() {
+        let (mut touchscreen, sink) = touchscreen();
+
+        touchscreen.touch_start(7, 0.5, 0.5).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (ABS, AbsoluteAxisCode::ABS_MT_SLOT.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_MT_TRACKING_ID.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_MT_POSITION_X.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_MT_POSITION_Y.0, 250),
+                (ABS, AbsoluteAxisCode::ABS_X.0, 500),
+                (ABS, AbsoluteAxisCode::ABS_Y.0, 250),
+                (KEY, KeyCode::BTN_TOUCH.0, 1),
+                SYN,
+            ]
+        );
+
+        touchscreen.touch_move(7, 0.1, 0.2).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (ABS, AbsoluteAxisCode::ABS_MT_SLOT.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_MT_POSITION_X.0, 100),
+                (ABS, AbsoluteAxisCode::ABS_MT_POSITION_Y.0, 100),
+                (ABS, AbsoluteAxisCode::ABS_X.0, 100),
+                (ABS, AbsoluteAxisCode::ABS_Y.0, 100),
+                SYN,
+            ]
+        );
+
+        touchscreen.touch_end(7).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (ABS, AbsoluteAxisCode::ABS_MT_SLOT.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_MT_TRACKING_ID.0, -1),
+                (KEY, KeyCode::BTN_TOUCH.0, 0),
+                SYN,
+            ]
+        );
+        assert_eq!(touchscreen.active_touch_count(), 0);
+    }
+
+    #[test]
+    fn test_multitouch_slot_reuse() {
+        let (mut touchscreen, sink) = touchscreen();
+
+        touchscreen.touch_start(1, 0.1, 0.1).unwrap();
+        touchscreen.touch_start(2, 0.9, 0.9).unwrap();
+        sink.take();
+
+        // Lifting one finger keeps BTN_TOUCH down
+        touchscreen.touch_end(1).unwrap();
+        assert_eq!(
+            sink.take_triples(),
+            vec![
+                (ABS, AbsoluteAxisCode::ABS_MT_SLOT.0, 0),
+                (ABS, AbsoluteAxisCode::ABS_MT_TRACKING_ID.0, -1),
+                SYN,
+            ]
+        );
+
+        // The freed slot is reused with a fresh tracking id
+        touchscreen.touch_start(3, 0.5, 0.5).unwrap();
+        let events = sink.take_triples();
+        assert_eq!(events[0], (ABS, AbsoluteAxisCode::ABS_MT_SLOT.0, 0));
+        assert_eq!(events[1], (ABS, AbsoluteAxisCode::ABS_MT_TRACKING_ID.0, 2));
+        assert_eq!(touchscreen.active_touch_count(), 2);
+    }
+
+    /// Sink whose device has gone away
+    struct FailingSink;
+
+    impl InputSink for FailingSink {
+        fn emit(&mut self, _events: &[InputEvent]) -> Result<()> {
+            Err(Error::InputError("device gone".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_failed_touch_start_frees_slot() {
+        let mut touchscreen = VirtualTouchscreen::with_sink(FailingSink, 1000, 500, 0, 0);
+        for id in 0..=MAX_SLOTS {
+            assert!(touchscreen.touch_start(id, 0.5, 0.5).is_err());
+        }
+        assert_eq!(touchscreen.active_touch_count(), 0);
+    }
+
+    #[test]
+    fn test_touch_slots_exhausted() {
+        let (mut touchscreen, sink) = touchscreen();
+
+        for id in 0..MAX_SLOTS {
+            touchscreen.touch_start(id, 0.5, 0.5).unwrap();
+        }
+        sink.take();
+
+        assert!(touchscreen.touch_start(MAX_SLOTS, 0.5, 0.5).is_err());
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_touch_id() {
+        let (mut touchscreen, sink) = touchscreen();
+
+        assert!(touchscreen.touch_move(42, 0.5, 0.5).is_err());
+        assert!(touchscreen.touch_end(42).is_err());
+        assert!(sink.take().is_empty());
+    }
+}
@@ -237,6 +237,30 @@ impl VirtualDevice {
         Self::new_stylus_with_offset(name, width, height, 0, 0)
     }
 
+    /// Create a new virtual keyboard device
+    /// Advertises the standard keyboard range (KEY_ESC..=KEY_MICMUTE)
+    pub fn new_keyboard(name: &str) -> Result<Self> {
+        let mut keys = AttributeSet::<KeyCode>::new();
+        for code in KeyCode::KEY_ESC.0..=KeyCode::KEY_MICMUTE.0 {
+            keys.insert(KeyCode::new(code));
+        }
+
+        let device = EvdevVirtualDevice::builder()
+            .map_err(|e| Error::UinputCreation(e.to_string()))?
+            .name(name)
+            .with_keys(&keys)
+            .map_err(|e| Error::UinputCreation(e.to_string()))?
+            .build()
+            .map_err(|e| Error::UinputCreation(e.to_string()))?;
+
+        info!("Created virtual keyboard: {}", name);
+
+        Ok(Self {
+            device,
+            name: name.to_string(),
+        })
+    }
+
     /// Emit input events
     pub fn emit(&mut self, events: &[InputEvent]) -> Result<()> {
         self.device
@@ -0,0 +1,452 @@
+//! Adaptive bitrate control
+//!
+//! The clients of a rendition share its encoder, so each rendition's stream
+//! has to fit the slowest of its clients. Each connection reports congestion
+//! signals (broadcast lag, send queue depth, ping round-trip time and the
+//! dropped frames and decoder backlog the client reports) to the rendition it
+//! is watching; once per tick the controller steps the bitrate
+//! down while any client is struggling, drops the frame rate once the bitrate
+//! has hit its floor, and recovers in reverse order after every client has
+//! been clear for a few ticks.
+
+use crate::broadcast::AppState;
+use linglide_core::protocol::{ClientId, ClientStats};
+use linglide_encoder::pipeline::{self, QualitySettings};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How often congestion signals are evaluated
+pub const ADAPT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Segments waiting in a client's broadcast queue before it counts as congested
+const MAX_QUEUE_DEPTH: usize = 4;
+
+/// Round-trip time above a client's best before it counts as congested
+const RTT_SLACK_MS: u64 = 150;
+
+/// Share of a client's frames dropped, in percent, before it counts as congested
+const MAX_DROP_PERCENT: u64 = 10;
+
+/// Frames waiting in a client's decoder before it counts as unable to keep up
+const MAX_JITTER_DEPTH: u32 = 4;
+
+/// Bitrate kept on congestion, in percent
+const DECREASE_PERCENT: u64 = 70;
+
+/// Bitrate growth while recovering, in percent
+const INCREASE_PERCENT: u64 = 115;
+
+/// Consecutive clear ticks before stepping back up
+const CLEAR_TICKS: u32 = 3;
+
+/// Lowest bitrate (kbps) the controller steps down to before reducing frame rate
+const MIN_ADAPTIVE_BITRATE: u32 = 500;
+
+/// Lowest frame rate the controller steps down to
+const MIN_ADAPTIVE_FPS: u32 = 15;
+
+/// Congestion signals collected from one client since the last tick
+#[derive(Debug, Default)]
+struct ClientSignals {
+    /// Segments dropped because the client fell behind the broadcast channel
+    lagged: u64,
+    /// Deepest send queue seen
+    queue_depth: usize,
+    /// Slowest ping round trip seen, in milliseconds
+    rtt_ms: Option<u64>,
+    /// Best round trip seen over the whole connection
+    min_rtt_ms: Option<u64>,
+    /// Frames the client reported showing
+    decoded: u64,
+    /// Frames the client reported dropping
+    dropped: u64,
+    /// Deepest decoder backlog reported
+    jitter_depth: u32,
+}
+
+impl ClientSignals {
+    /// Whether the client showed signs of congestion, resetting the window
+    fn take_congested(&mut self) -> bool {
+        let rtt_high = match (self.rtt_ms, self.min_rtt_ms) {
+            (Some(rtt), Some(min)) => rtt > min + RTT_SLACK_MS,
+            _ => false,
+        };
+        // Counts come from clients, so huge values must not overflow
+        let dropping = self.dropped > 0
+            && self.dropped.saturating_mul(100)
+                >= self
+                    .decoded
+                    .saturating_add(self.dropped)
+                    .saturating_mul(MAX_DROP_PERCENT);
+        let congested = self.lagged > 0
+            || self.queue_depth >= MAX_QUEUE_DEPTH
+            || rtt_high
+            || dropping
+            || self.jitter_depth >= MAX_JITTER_DEPTH;
+
+        self.lagged = 0;
+        self.queue_depth = 0;
+        self.rtt_ms = None;
+        self.decoded = 0;
+        self.dropped = 0;
+        self.jitter_depth = 0;
+        congested
+    }
+}
+
+/// Steps encoder quality between a floor and the requested ceiling
+#[derive(Debug)]
+pub struct AdaptiveBitrate {
+    /// Quality requested by configuration or the last `SetQuality`
+    ceiling: QualitySettings,
+    /// Quality the encoder is currently running at
+    current: QualitySettings,
+    clients: HashMap<ClientId, ClientSignals>,
+    clear_ticks: u32,
+}
+
+impl AdaptiveBitrate {
+    /// Create a controller starting at (and never exceeding) the given quality
+    pub fn new(bitrate: u32, fps: u32) -> Self {
+        let ceiling = QualitySettings { bitrate, fps };
+        Self {
+            ceiling,
+            current: ceiling,
+            clients: HashMap::new(),
+            clear_ticks: 0,
+        }
+    }
+
+    /// Quality the encoder should currently be running at
+    pub fn current(&self) -> QualitySettings {
+        self.current
+    }
+
+    /// Replace the ceiling after an explicit quality request
+    pub fn set_ceiling(&mut self, quality: QualitySettings) {
+        self.ceiling = quality;
+        self.current = quality;
+        self.clear_ticks = 0;
+    }
+
+    /// Return to the quality the encoder is still running at after a change
+    /// failed to apply
+    pub fn revert(&mut self, quality: QualitySettings) {
+        self.current = quality;
+        self.clear_ticks = 0;
+    }
+
+    /// Start tracking a client
+    pub fn add_client(&mut self, client_id: ClientId) {
+        self.clients.insert(client_id, ClientSignals::default());
+    }
+
+    /// Stop tracking a client
+    pub fn remove_client(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+    }
+
+    /// Record segments a client missed because it fell behind
+    pub fn record_lag(&mut self, client_id: ClientId, frames: u64) {
+        if let Some(signals) = self.clients.get_mut(&client_id) {
+            signals.lagged += frames;
+        }
+    }
+
+    /// Record how many segments are waiting to be sent to a client
+    pub fn record_queue_depth(&mut self, client_id: ClientId, depth: usize) {
+        if let Some(signals) = self.clients.get_mut(&client_id) {
+            signals.queue_depth = signals.queue_depth.max(depth);
+        }
+    }
+
+    /// Record a ping round trip to a client
+    pub fn record_rtt(&mut self, client_id: ClientId, rtt_ms: u64) {
+        if let Some(signals) = self.clients.get_mut(&client_id) {
+            signals.rtt_ms = Some(signals.rtt_ms.map_or(rtt_ms, |rtt| rtt.max(rtt_ms)));
+            signals.min_rtt_ms = Some(signals.min_rtt_ms.map_or(rtt_ms, |min| min.min(rtt_ms)));
+        }
+    }
+
+    /// Record playback statistics reported by a client
+    pub fn record_playback(&mut self, client_id: ClientId, stats: &ClientStats) {
+        if let Some(signals) = self.clients.get_mut(&client_id) {
+            signals.decoded = signals.decoded.saturating_add(stats.decoded_frames);
+            signals.dropped = signals.dropped.saturating_add(stats.dropped_frames);
+            if let Some(depth) = stats.jitter_buffer_depth {
+                signals.jitter_depth = signals.jitter_depth.max(depth);
+            }
+        }
+    }
+
+    /// Evaluate the signals since the last tick, returning new settings to apply
+    pub fn tick(&mut self) -> Option<QualitySettings> {
+        if self.clients.is_empty() {
+            self.clear_ticks = 0;
+            return None;
+        }
+
+        let mut congested = false;
+        for signals in self.clients.values_mut() {
+            congested |= signals.take_congested();
+        }
+
+        let previous = self.current;
+        if congested {
+            self.clear_ticks = 0;
+            self.step_down();
+        } else {
+            self.clear_ticks += 1;
+            if self.clear_ticks >= CLEAR_TICKS {
+                self.clear_ticks = 0;
+                self.step_up();
+            }
+        }
+
+        (self.current != previous).then_some(self.current)
+    }
+
+    fn step_down(&mut self) {
+        let min_bitrate = MIN_ADAPTIVE_BITRATE.min(self.ceiling.bitrate);
+        let min_fps = MIN_ADAPTIVE_FPS.min(self.ceiling.fps);
+
+        if self.current.bitrate > min_bitrate {
+            let bitrate = (self.current.bitrate as u64 * DECREASE_PERCENT / 100) as u32;
+            self.current.bitrate = bitrate.max(min_bitrate);
+        } else if self.current.fps > min_fps {
+            self.current.fps = (self.current.fps * 2 / 3).max(min_fps);
+        }
+    }
+
+    fn step_up(&mut self) {
+        if self.current.fps < self.ceiling.fps {
+            self.current.fps = (self.current.fps * 3 / 2).min(self.ceiling.fps);
+        } else if self.current.bitrate < self.ceiling.bitrate {
+            let bitrate = (self.current.bitrate as u64 * INCREASE_PERCENT / 100) as u32;
+            self.current.bitrate = bitrate
+                .max(self.current.bitrate + 1)
+                .min(self.ceiling.bitrate);
+        }
+    }
+}
+
+/// Periodically apply each rendition controller's decisions to its encoder
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(ADAPT_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    'run: loop {
+        interval.tick().await;
+
+        for rendition in &state.renditions {
+            let (previous, quality) = {
+                let mut adaptive = rendition.adaptive();
+                (adaptive.current(), adaptive.tick())
+            };
+            let Some(quality) = quality else {
+                continue;
+            };
+            match pipeline::request_quality(
+                &rendition.encoder_control,
+                quality.bitrate,
+                Some(quality.fps),
+            )
+            .await
+            {
+                Ok(applied) => info!(
+                    "Adaptive bitrate: {}x{} at {} kbps @ {} fps",
+                    rendition.offer.width, rendition.offer.height, applied.bitrate, applied.fps
+                ),
+                // The pipeline is gone, so there is nothing left to adapt
+                Err(_) if rendition.encoder_control.is_closed() => break 'run,
+                // A rejected reconfigure leaves the encoder where it was; try
+                // again on a later tick
+                Err(e) => {
+                    warn!("Adaptive bitrate: failed to apply quality: {}", e);
+                    rendition.adaptive().revert(previous);
+                }
+            }
+        }
+    }
+
+    debug!("Adaptive bitrate stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn congest(abr: &mut AdaptiveBitrate) -> Option<QualitySettings> {
+        abr.record_lag(1, 3);
+        abr.tick()
+    }
+
+    #[test]
+    fn test_no_change_without_clients() {
+        let mut abr = AdaptiveBitrate::new(8000, 60);
+        abr.record_lag(1, 10);
+        assert_eq!(abr.tick(), None);
+        assert_eq!(
+            abr.current(),
+            QualitySettings {
+                bitrate: 8000,
+                fps: 60
+            }
+        );
+    }
+
+    #[test]
+    fn test_steps_bitrate_then_frame_rate_down() {
+        let mut abr = AdaptiveBitrate::new(2000, 60);
+        abr.add_client(1);
+
+        assert_eq!(congest(&mut abr).unwrap().bitrate, 1400);
+        while abr.current().bitrate > MIN_ADAPTIVE_BITRATE {
+            assert_eq!(congest(&mut abr).unwrap().fps, 60);
+        }
+        assert_eq!(abr.current().bitrate, MIN_ADAPTIVE_BITRATE);
+
+        assert_eq!(congest(&mut abr).unwrap().fps, 40);
+        assert_eq!(congest(&mut abr).unwrap().fps, 26);
+        assert_eq!(congest(&mut abr).unwrap().fps, 17);
+        assert_eq!(congest(&mut abr).unwrap().fps, MIN_ADAPTIVE_FPS);
+        assert_eq!(congest(&mut abr), None);
+    }
+
+    #[test]
+    fn test_recovers_after_clear_ticks() {
+        let mut abr = AdaptiveBitrate::new(600, 30);
+        abr.add_client(1);
+        congest(&mut abr);
+        congest(&mut abr);
+        assert_eq!(
+            abr.current(),
+            QualitySettings {
+                bitrate: 500,
+                fps: 20
+            }
+        );
+
+        // Frame rate comes back before bitrate
+        assert_eq!(abr.tick(), None);
+        assert_eq!(abr.tick(), None);
+        assert_eq!(
+            abr.tick(),
+            Some(QualitySettings {
+                bitrate: 500,
+                fps: 30
+            })
+        );
+        for _ in 0..CLEAR_TICKS * 3 {
+            abr.tick();
+        }
+        assert_eq!(
+            abr.current(),
+            QualitySettings {
+                bitrate: 600,
+                fps: 30
+            }
+        );
+    }
+
+    #[test]
+    fn test_revert_after_failed_change() {
+        let mut abr = AdaptiveBitrate::new(2000, 60);
+        abr.add_client(1);
+        let previous = abr.current();
+
+        assert_eq!(congest(&mut abr).unwrap().bitrate, 1400);
+        abr.revert(previous);
+        assert_eq!(abr.current(), previous);
+        // The next congested tick steps down from the encoder's real quality
+        assert_eq!(congest(&mut abr).unwrap().bitrate, 1400);
+    }
+
+    #[test]
+    fn test_congestion_signals() {
+        let mut abr = AdaptiveBitrate::new(8000, 60);
+        abr.add_client(1);
+        abr.add_client(2);
+
+        // A deep send queue on any client counts
+        abr.record_queue_depth(2, MAX_QUEUE_DEPTH);
+        assert_eq!(abr.tick().unwrap().bitrate, 5600);
+
+        // Round trips are judged against the client's best
+        abr.record_rtt(1, 40);
+        assert_eq!(abr.tick(), None);
+        abr.record_rtt(1, 40 + RTT_SLACK_MS + 1);
+        assert_eq!(abr.tick().unwrap().bitrate, 3920);
+
+        // A departed client no longer holds the stream back
+        abr.remove_client(1);
+        abr.remove_client(2);
+        abr.record_lag(1, 5);
+        assert_eq!(abr.tick(), None);
+    }
+
+    #[test]
+    fn test_client_playback_signals() {
+        let mut abr = AdaptiveBitrate::new(8000, 60);
+        abr.add_client(1);
+
+        // A few drops among many shown frames are tolerated
+        let mut stats = ClientStats {
+            decoded_frames: 95,
+            dropped_frames: 5,
+            ..Default::default()
+        };
+        abr.record_playback(1, &stats);
+        assert_eq!(abr.tick(), None);
+
+        stats.decoded_frames = 45;
+        stats.dropped_frames = 15;
+        abr.record_playback(1, &stats);
+        assert_eq!(abr.tick().unwrap().bitrate, 5600);
+
+        // A decoder falling behind counts even without drops
+        abr.record_playback(
+            1,
+            &ClientStats {
+                decoded_frames: 60,
+                jitter_buffer_depth: Some(MAX_JITTER_DEPTH),
+                ..Default::default()
+            },
+        );
+        assert_eq!(abr.tick().unwrap().bitrate, 3920);
+
+        // Absurd counts saturate instead of overflowing
+        let flood = ClientStats {
+            decoded_frames: u64::MAX,
+            dropped_frames: u64::MAX,
+            ..Default::default()
+        };
+        abr.record_playback(1, &flood);
+        abr.record_playback(1, &flood);
+        assert_eq!(abr.tick().unwrap().bitrate, 2744);
+    }
+
+    #[test]
+    fn test_set_ceiling() {
+        let mut abr = AdaptiveBitrate::new(8000, 60);
+        abr.add_client(1);
+        congest(&mut abr);
+
+        abr.set_ceiling(QualitySettings {
+            bitrate: 3000,
+            fps: 30,
+        });
+        assert_eq!(
+            abr.current(),
+            QualitySettings {
+                bitrate: 3000,
+                fps: 30
+            }
+        );
+        for _ in 0..CLEAR_TICKS * 2 {
+            assert_eq!(abr.tick(), None);
+        }
+    }
+}
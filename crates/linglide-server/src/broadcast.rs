@@ -1,59 +1,64 @@
 //! Broadcast channel management for video frames and state
 
+use crate::adaptive::AdaptiveBitrate;
+use crate::metrics::Metrics;
+use crate::negotiation::StreamOffer;
 use linglide_auth::PairingManager;
-use linglide_core::{protocol::InputEvent, Config};
-use linglide_encoder::pipeline::StreamSegment;
-use std::sync::{Arc, RwLock};
+use linglide_core::{
+    protocol::{ClientId, InputCommand},
+    Config, Result,
+};
+use linglide_encoder::pipeline::{
+    self, KeyframeRequest, PipelineCommand, QualitySettings, StreamSegment,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
 use tokio::sync::{broadcast, mpsc};
 
 /// Codec configuration for WebCodecs
 pub struct CodecConfig {
     pub codec_string: String,
-    pub avcc_data: Vec<u8>,
+    /// Decoder configuration record (avcC, hvcC or av1C)
+    pub description: Vec<u8>,
 }
 
-/// Shared application state
-pub struct AppState {
-    /// Configuration
-    pub config: Config,
+/// One encoded version of the display that video clients subscribe to
+pub struct RenditionStream {
+    /// How the stream is described to clients
+    pub offer: StreamOffer,
     /// Video segment broadcast sender
     pub video_tx: broadcast::Sender<StreamSegment>,
-    /// Input event sender
-    pub input_tx: mpsc::Sender<InputEvent>,
+    /// Runtime commands for the encoding pipeline
+    pub encoder_control: mpsc::Sender<PipelineCommand>,
+    /// Keyframe requests for the encoding pipeline
+    keyframe_request: KeyframeRequest,
+    /// Congestion-driven quality control shared by the rendition's clients
+    adaptive: Mutex<AdaptiveBitrate>,
     /// fMP4 init segment (moov box with codec config)
-    pub init_segment: RwLock<Option<Vec<u8>>>,
+    init_segment: RwLock<Option<Vec<u8>>>,
     /// Codec configuration for WebCodecs
-    pub codec_config: RwLock<Option<CodecConfig>>,
+    codec_config: RwLock<Option<CodecConfig>>,
     /// Most recent keyframe segment (for new clients)
-    pub keyframe_segment: RwLock<Option<Vec<u8>>>,
-    /// Pairing manager for device authentication
-    pub pairing_manager: Arc<PairingManager>,
-    /// Whether authentication is required for connections
-    pub auth_required: bool,
-    /// Certificate fingerprint for verification
-    pub cert_fingerprint: Option<String>,
+    keyframe_segment: RwLock<Option<Vec<u8>>>,
 }
 
-impl AppState {
-    /// Create a new application state
+impl RenditionStream {
+    /// Create a rendition fed by an encoding pipeline
     pub fn new(
-        config: Config,
+        offer: StreamOffer,
         video_tx: broadcast::Sender<StreamSegment>,
-        input_tx: mpsc::Sender<InputEvent>,
-        pairing_manager: Arc<PairingManager>,
-        auth_required: bool,
-        cert_fingerprint: Option<String>,
+        encoder_control: mpsc::Sender<PipelineCommand>,
+        keyframe_request: KeyframeRequest,
     ) -> Self {
         Self {
-            config,
+            offer,
             video_tx,
-            input_tx,
+            encoder_control,
+            keyframe_request,
+            adaptive: Mutex::new(AdaptiveBitrate::new(offer.bitrate, offer.fps)),
             init_segment: RwLock::new(None),
             codec_config: RwLock::new(None),
             keyframe_segment: RwLock::new(None),
-            pairing_manager,
-            auth_required,
-            cert_fingerprint,
         }
     }
 
@@ -70,11 +75,11 @@ impl AppState {
     }
 
     /// Set the codec configuration
-    pub fn set_codec_config(&self, codec_string: String, avcc_data: Vec<u8>) {
+    pub fn set_codec_config(&self, codec_string: String, description: Vec<u8>) {
         if let Ok(mut guard) = self.codec_config.write() {
             *guard = Some(CodecConfig {
                 codec_string,
-                avcc_data,
+                description,
             });
         }
     }
@@ -84,7 +89,7 @@ impl AppState {
         self.codec_config.read().ok().and_then(|g| {
             g.as_ref().map(|c| CodecConfig {
                 codec_string: c.codec_string.clone(),
-                avcc_data: c.avcc_data.clone(),
+                description: c.description.clone(),
             })
         })
     }
@@ -101,6 +106,82 @@ impl AppState {
         self.keyframe_segment.read().ok().and_then(|g| g.clone())
     }
 
+    /// Get the adaptive bitrate controller
+    pub fn adaptive(&self) -> MutexGuard<'_, AdaptiveBitrate> {
+        self.adaptive.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Change the encoder bitrate (kbps) and optionally the frame rate
+    ///
+    /// The result becomes the ceiling adaptive bitrate works below.
+    pub async fn set_quality(&self, bitrate: u32, fps: Option<u32>) -> Result<QualitySettings> {
+        let quality = pipeline::request_quality(&self.encoder_control, bitrate, fps).await?;
+        self.adaptive().set_ceiling(quality);
+        Ok(quality)
+    }
+
+    /// Ask the encoder for a keyframe
+    ///
+    /// The pipeline coalesces requests from all clients, so this is cheap to
+    /// call whenever a client may be missing reference frames.
+    pub fn request_keyframe(&self) {
+        self.keyframe_request.request();
+    }
+}
+
+/// Shared application state
+pub struct AppState {
+    /// Configuration
+    pub config: Config,
+    /// Encoded streams, the full-size one first
+    pub renditions: Vec<Arc<RenditionStream>>,
+    /// Input command sender
+    pub input_tx: mpsc::Sender<InputCommand>,
+    /// Next client identifier
+    next_client_id: AtomicU64,
+    /// Pairing manager for device authentication
+    pub pairing_manager: Arc<PairingManager>,
+    /// Whether authentication is required for connections
+    pub auth_required: bool,
+    /// Certificate fingerprint for verification
+    pub cert_fingerprint: Option<String>,
+    /// Pipeline and client metrics
+    pub metrics: Metrics,
+}
+
+impl AppState {
+    /// Create a new application state
+    pub fn new(
+        config: Config,
+        renditions: Vec<Arc<RenditionStream>>,
+        input_tx: mpsc::Sender<InputCommand>,
+        pairing_manager: Arc<PairingManager>,
+        auth_required: bool,
+        cert_fingerprint: Option<String>,
+    ) -> Self {
+        let offers: Vec<_> = renditions.iter().map(|r| r.offer).collect();
+        Self {
+            config,
+            metrics: Metrics::new(&offers),
+            renditions,
+            input_tx,
+            next_client_id: AtomicU64::new(1),
+            pairing_manager,
+            auth_required,
+            cert_fingerprint,
+        }
+    }
+
+    /// Allocate an identifier for a new connection
+    pub fn next_client_id(&self) -> ClientId {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Streams video clients can choose from, indexed like `renditions`
+    pub fn stream_offers(&self) -> Vec<StreamOffer> {
+        self.renditions.iter().map(|r| r.offer).collect()
+    }
+
     /// Validate an authentication token
     pub async fn validate_token(&self, token: &str) -> bool {
         if !self.auth_required {
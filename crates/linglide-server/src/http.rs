@@ -49,6 +49,7 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         // Server info
         .route("/api/info", get(server_info_handler))
         .route("/api/discovery", get(discovery_handler))
+        .route("/api/metrics", get(metrics_handler))
         .with_state(state)
 }
 
@@ -340,6 +341,38 @@ async fn server_info_handler(State(state): State<Arc<AppState>>) -> Json<ServerI
     })
 }
 
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Query parameters for the metrics endpoint
+#[derive(Debug, Deserialize)]
+struct MetricsQuery {
+    /// `json` for JSON, Prometheus text otherwise
+    #[serde(default)]
+    format: Option<String>,
+}
+
+/// Pipeline and client metrics
+///
+/// Prometheus text format by default, JSON with `?format=json`.
+async fn metrics_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MetricsQuery>,
+) -> Response {
+    if query.format.as_deref() == Some("json") {
+        return Json(state.metrics.snapshot()).into_response();
+    }
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        state.metrics.render_prometheus(),
+    )
+        .into_response()
+}
+
 // ============================================================================
 // Discovery
 // ============================================================================
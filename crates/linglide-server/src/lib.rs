@@ -2,12 +2,21 @@
 //!
 //! This crate provides the web server for serving the viewer and handling input.
 
+pub mod adaptive;
 pub mod broadcast;
 pub mod http;
+pub mod metrics;
+pub mod negotiation;
+pub mod session;
 pub mod tls;
 pub mod websocket;
 
 pub use http::create_router;
+pub use metrics::{Metrics, MetricsSnapshot};
+pub use session::{
+    CaptureBackend, EncoderBackend, InputBackend, Session, SessionBuilder, SessionEvent,
+    StopHandle, TlsMode,
+};
 pub use tls::{
     calculate_cert_fingerprint, create_rustls_config, create_rustls_config_from_files,
     generate_self_signed_cert, CertificateManager,
@@ -0,0 +1,824 @@
+//! Pipeline and client metrics
+//!
+//! Stage timings and segment sizes are recorded per rendition as segments
+//! leave the encoder. Send latency, queue depth, lag and ping round-trip time
+//! are recorded per video client as segments go out on its socket, alongside
+//! the playback statistics the client reports. Histograms cover everything
+//! since the server started; client gauges disappear when the client
+//! disconnects.
+//!
+//! The HTTP API serves them at `/api/metrics` in the Prometheus text format,
+//! or as JSON with `?format=json`.
+
+use crate::negotiation::StreamOffer;
+use linglide_core::protocol::{ClientId, ClientStats};
+use linglide_core::StageTimes;
+use linglide_encoder::pipeline::StreamSegment;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Histogram bucket bounds for stage timings, in seconds
+const TIME_BUCKETS: &[f64] = &[
+    0.001, 0.002, 0.004, 0.008, 0.016, 0.033, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// Histogram bucket bounds for segment sizes, in bytes
+const SIZE_BUCKETS: &[f64] = &[
+    1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0,
+];
+
+/// Distribution of observed values over fixed buckets
+#[derive(Debug, Clone)]
+struct Histogram {
+    /// Upper bounds, ascending (an implicit `+Inf` bucket follows)
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative
+    counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            count: 0,
+            sum: 0.0,
+            max: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    fn observe_duration(&mut self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, capped at the max
+    fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            seen += count;
+            if seen >= rank {
+                return bound.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Summary with values multiplied by `scale`
+    fn summary(&self, scale: f64) -> Summary {
+        let mean = if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        };
+        Summary {
+            count: self.count,
+            mean: mean * scale,
+            p50: self.quantile(0.5) * scale,
+            p95: self.quantile(0.95) * scale,
+            max: self.max * scale,
+        }
+    }
+}
+
+/// Selects one of a rendition's histograms
+type RenditionHistogram = fn(&RenditionMetrics) -> &Histogram;
+
+/// Reads one value from a client, `None` if not known yet
+type ClientValue = fn(&ClientMetrics) -> Option<f64>;
+
+/// Per-rendition encoder output
+#[derive(Debug)]
+struct RenditionMetrics {
+    offer: StreamOffer,
+    capture: Histogram,
+    convert: Histogram,
+    encode: Histogram,
+    /// Capture to segment written to a client's socket
+    latency: Histogram,
+    segment_bytes: Histogram,
+    keyframes: u64,
+}
+
+impl RenditionMetrics {
+    fn new(offer: StreamOffer) -> Self {
+        Self {
+            offer,
+            capture: Histogram::new(TIME_BUCKETS),
+            convert: Histogram::new(TIME_BUCKETS),
+            encode: Histogram::new(TIME_BUCKETS),
+            latency: Histogram::new(TIME_BUCKETS),
+            segment_bytes: Histogram::new(SIZE_BUCKETS),
+            keyframes: 0,
+        }
+    }
+}
+
+/// One connected video client
+#[derive(Debug)]
+struct ClientMetrics {
+    rendition: usize,
+    connected: Instant,
+    segments_sent: u64,
+    bytes_sent: u64,
+    queue_depth: usize,
+    max_queue_depth: usize,
+    lag_events: u64,
+    lagged_segments: u64,
+    rtt_ms: Option<u64>,
+    /// Reported by the client
+    decoded_frames: u64,
+    dropped_frames: u64,
+    decode_latency_ms: Option<f64>,
+    jitter_buffer_depth: Option<u32>,
+    refresh_rate: Option<f64>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    renditions: Vec<RenditionMetrics>,
+    clients: BTreeMap<ClientId, ClientMetrics>,
+    /// Video clients connected since start
+    clients_total: u64,
+    /// Lag across all clients since start, including disconnected ones
+    lag_events: u64,
+    lagged_segments: u64,
+    /// Frames clients reported dropping, including disconnected ones
+    dropped_frames: u64,
+}
+
+/// Metrics shared by the encoding tasks and video connections
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    /// Create metrics for renditions described by `offers`
+    pub fn new(offers: &[StreamOffer]) -> Self {
+        Self {
+            started: Instant::now(),
+            inner: Mutex::new(Inner {
+                renditions: offers.iter().copied().map(RenditionMetrics::new).collect(),
+                clients: BTreeMap::new(),
+                clients_total: 0,
+                lag_events: 0,
+                lagged_segments: 0,
+                dropped_frames: 0,
+            }),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record a segment produced by a rendition's encoder
+    pub fn record_segment(&self, rendition: usize, segment: &StreamSegment) {
+        let mut inner = self.inner();
+        let Some(metrics) = inner.renditions.get_mut(rendition) else {
+            return;
+        };
+        let times = &segment.times;
+        if times.capture_started_us != 0 {
+            metrics.capture.observe_duration(times.capture_time());
+        }
+        if times.converted_us != 0 {
+            metrics.convert.observe_duration(times.convert_time());
+            metrics.encode.observe_duration(times.encode_time());
+        }
+        metrics.segment_bytes.observe(segment.data.len() as f64);
+        if segment.is_keyframe {
+            metrics.keyframes += 1;
+        }
+    }
+
+    /// Start tracking a video client watching `rendition`
+    pub fn add_client(&self, client_id: ClientId, rendition: usize) {
+        let mut inner = self.inner();
+        inner.clients_total += 1;
+        inner.clients.insert(
+            client_id,
+            ClientMetrics {
+                rendition,
+                connected: Instant::now(),
+                segments_sent: 0,
+                bytes_sent: 0,
+                queue_depth: 0,
+                max_queue_depth: 0,
+                lag_events: 0,
+                lagged_segments: 0,
+                rtt_ms: None,
+                decoded_frames: 0,
+                dropped_frames: 0,
+                decode_latency_ms: None,
+                jitter_buffer_depth: None,
+                refresh_rate: None,
+            },
+        );
+    }
+
+    /// Stop tracking a video client
+    pub fn remove_client(&self, client_id: ClientId) {
+        self.inner().clients.remove(&client_id);
+    }
+
+    /// Note that a client moved to another rendition
+    pub fn set_client_rendition(&self, client_id: ClientId, rendition: usize) {
+        if let Some(client) = self.inner().clients.get_mut(&client_id) {
+            client.rendition = rendition;
+        }
+    }
+
+    /// Record a segment written to a client's socket
+    pub fn record_sent(&self, client_id: ClientId, times: &StageTimes, bytes: usize) {
+        let mut inner = self.inner();
+        let Some(client) = inner.clients.get_mut(&client_id) else {
+            return;
+        };
+        client.segments_sent += 1;
+        client.bytes_sent += bytes as u64;
+        let rendition = client.rendition;
+        if times.captured_us != 0 && times.sent_us != 0 {
+            if let Some(metrics) = inner.renditions.get_mut(rendition) {
+                metrics.latency.observe_duration(times.total_time());
+            }
+        }
+    }
+
+    /// Record how many segments are waiting in a client's queue
+    pub fn record_queue_depth(&self, client_id: ClientId, depth: usize) {
+        if let Some(client) = self.inner().clients.get_mut(&client_id) {
+            client.queue_depth = depth;
+            client.max_queue_depth = client.max_queue_depth.max(depth);
+        }
+    }
+
+    /// Record a client falling behind the broadcast by `segments`
+    pub fn record_lag(&self, client_id: ClientId, segments: u64) {
+        let mut inner = self.inner();
+        inner.lag_events += 1;
+        inner.lagged_segments += segments;
+        if let Some(client) = inner.clients.get_mut(&client_id) {
+            client.lag_events += 1;
+            client.lagged_segments += segments;
+        }
+    }
+
+    /// Record a ping round trip
+    pub fn record_rtt(&self, client_id: ClientId, rtt_ms: u64) {
+        if let Some(client) = self.inner().clients.get_mut(&client_id) {
+            client.rtt_ms = Some(rtt_ms);
+        }
+    }
+
+    /// Record playback statistics reported by a client
+    ///
+    /// Values that aren't finite and non-negative are ignored.
+    pub fn record_client_stats(&self, client_id: ClientId, stats: &ClientStats) {
+        let valid = |value: Option<f64>| value.filter(|v| v.is_finite() && *v >= 0.0);
+        let mut inner = self.inner();
+        inner.dropped_frames = inner.dropped_frames.saturating_add(stats.dropped_frames);
+        if let Some(client) = inner.clients.get_mut(&client_id) {
+            client.decoded_frames = client.decoded_frames.saturating_add(stats.decoded_frames);
+            client.dropped_frames = client.dropped_frames.saturating_add(stats.dropped_frames);
+            client.decode_latency_ms = valid(stats.decode_latency_ms);
+            client.jitter_buffer_depth = stats.jitter_buffer_depth;
+            client.refresh_rate = valid(stats.refresh_rate);
+        }
+    }
+
+    /// Current values for JSON and the desktop app
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = self.inner();
+        MetricsSnapshot {
+            uptime_secs: self.started.elapsed().as_secs(),
+            clients_total: inner.clients_total,
+            lag_events: inner.lag_events,
+            lagged_segments: inner.lagged_segments,
+            dropped_frames: inner.dropped_frames,
+            renditions: inner
+                .renditions
+                .iter()
+                .enumerate()
+                .map(|(index, r)| RenditionSnapshot {
+                    index,
+                    stream: r.offer.to_string(),
+                    segments: r.segment_bytes.count,
+                    keyframes: r.keyframes,
+                    bytes: r.segment_bytes.sum as u64,
+                    capture_ms: r.capture.summary(1000.0),
+                    convert_ms: r.convert.summary(1000.0),
+                    encode_ms: r.encode.summary(1000.0),
+                    latency_ms: r.latency.summary(1000.0),
+                    segment_bytes: r.segment_bytes.summary(1.0),
+                })
+                .collect(),
+            clients: inner
+                .clients
+                .iter()
+                .map(|(&id, c)| ClientSnapshot {
+                    id,
+                    rendition: c.rendition,
+                    connected_secs: c.connected.elapsed().as_secs(),
+                    segments_sent: c.segments_sent,
+                    bytes_sent: c.bytes_sent,
+                    queue_depth: c.queue_depth,
+                    max_queue_depth: c.max_queue_depth,
+                    lag_events: c.lag_events,
+                    lagged_segments: c.lagged_segments,
+                    rtt_ms: c.rtt_ms,
+                    decoded_frames: c.decoded_frames,
+                    dropped_frames: c.dropped_frames,
+                    decode_latency_ms: c.decode_latency_ms,
+                    jitter_buffer_depth: c.jitter_buffer_depth,
+                    refresh_rate: c.refresh_rate,
+                })
+                .collect(),
+        }
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let inner = self.inner();
+        let mut out = String::new();
+
+        gauge_header(
+            &mut out,
+            "linglide_uptime_seconds",
+            "Time since the server started",
+        );
+        let _ = writeln!(
+            out,
+            "linglide_uptime_seconds {}",
+            self.started.elapsed().as_secs_f64()
+        );
+
+        gauge_header(
+            &mut out,
+            "linglide_rendition_info",
+            "Encoded streams offered to clients",
+        );
+        for (index, r) in inner.renditions.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "linglide_rendition_info{{rendition=\"{}\",codec=\"{}\",width=\"{}\",height=\"{}\",fps=\"{}\",bitrate_kbps=\"{}\"}} 1",
+                index, r.offer.codec, r.offer.width, r.offer.height, r.offer.fps, r.offer.bitrate
+            );
+        }
+
+        let histograms: [(&str, &str, RenditionHistogram); 5] = [
+            (
+                "linglide_capture_seconds",
+                "Time the capture backend took per frame",
+                |r| &r.capture,
+            ),
+            (
+                "linglide_convert_seconds",
+                "Time spent scaling a frame and converting it to YUV",
+                |r| &r.convert,
+            ),
+            (
+                "linglide_encode_seconds",
+                "Time spent encoding and muxing a frame",
+                |r| &r.encode,
+            ),
+            (
+                "linglide_latency_seconds",
+                "Time from capture to a segment being written to a client",
+                |r| &r.latency,
+            ),
+            (
+                "linglide_segment_bytes",
+                "Size of encoded media segments",
+                |r| &r.segment_bytes,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for (index, r) in inner.renditions.iter().enumerate() {
+                write_histogram(&mut out, name, index, histogram(r));
+            }
+        }
+
+        counter_header(&mut out, "linglide_keyframes_total", "Keyframes encoded");
+        for (index, r) in inner.renditions.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "linglide_keyframes_total{{rendition=\"{}\"}} {}",
+                index, r.keyframes
+            );
+        }
+
+        gauge_header(
+            &mut out,
+            "linglide_video_clients",
+            "Connected video clients",
+        );
+        let _ = writeln!(out, "linglide_video_clients {}", inner.clients.len());
+        counter_header(
+            &mut out,
+            "linglide_video_clients_total",
+            "Video clients connected since start",
+        );
+        let _ = writeln!(out, "linglide_video_clients_total {}", inner.clients_total);
+        counter_header(
+            &mut out,
+            "linglide_lag_events_total",
+            "Times a client fell behind the broadcast",
+        );
+        let _ = writeln!(out, "linglide_lag_events_total {}", inner.lag_events);
+        counter_header(
+            &mut out,
+            "linglide_lagged_segments_total",
+            "Segments skipped by clients that fell behind",
+        );
+        let _ = writeln!(
+            out,
+            "linglide_lagged_segments_total {}",
+            inner.lagged_segments
+        );
+        counter_header(
+            &mut out,
+            "linglide_dropped_frames_total",
+            "Frames clients reported dropping",
+        );
+        let _ = writeln!(
+            out,
+            "linglide_dropped_frames_total {}",
+            inner.dropped_frames
+        );
+
+        let client_metrics: [(&str, &str, &str, ClientValue); 11] = [
+            (
+                "linglide_client_queue_depth",
+                "gauge",
+                "Segments waiting to be sent to the client",
+                |c| Some(c.queue_depth as f64),
+            ),
+            (
+                "linglide_client_rtt_seconds",
+                "gauge",
+                "Latest ping round-trip time",
+                |c| c.rtt_ms.map(|ms| ms as f64 / 1000.0),
+            ),
+            (
+                "linglide_client_segments_sent_total",
+                "counter",
+                "Segments sent to the client",
+                |c| Some(c.segments_sent as f64),
+            ),
+            (
+                "linglide_client_bytes_sent_total",
+                "counter",
+                "Segment bytes sent to the client",
+                |c| Some(c.bytes_sent as f64),
+            ),
+            (
+                "linglide_client_lag_events_total",
+                "counter",
+                "Times the client fell behind the broadcast",
+                |c| Some(c.lag_events as f64),
+            ),
+            (
+                "linglide_client_lagged_segments_total",
+                "counter",
+                "Segments the client skipped after falling behind",
+                |c| Some(c.lagged_segments as f64),
+            ),
+            (
+                "linglide_client_decoded_frames_total",
+                "counter",
+                "Frames the client reported decoding and showing",
+                |c| Some(c.decoded_frames as f64),
+            ),
+            (
+                "linglide_client_dropped_frames_total",
+                "counter",
+                "Frames the client reported dropping",
+                |c| Some(c.dropped_frames as f64),
+            ),
+            (
+                "linglide_client_decode_latency_seconds",
+                "gauge",
+                "Average decode time the client reported",
+                |c| c.decode_latency_ms.map(|ms| ms / 1000.0),
+            ),
+            (
+                "linglide_client_jitter_buffer_depth",
+                "gauge",
+                "Frames waiting in the client's decoder or for display",
+                |c| c.jitter_buffer_depth.map(f64::from),
+            ),
+            (
+                "linglide_client_refresh_rate_hertz",
+                "gauge",
+                "Display refresh rate the client reported",
+                |c| c.refresh_rate,
+            ),
+        ];
+        for (name, kind, help, value) in client_metrics {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (id, client) in &inner.clients {
+                if let Some(value) = value(client) {
+                    let _ = writeln!(
+                        out,
+                        "{}{{client=\"{}\",rendition=\"{}\"}} {}",
+                        name, id, client.rendition, value
+                    );
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn gauge_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+}
+
+fn counter_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+}
+
+/// Cumulative bucket, sum and count lines of one rendition's histogram
+fn write_histogram(out: &mut String, name: &str, rendition: usize, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+        cumulative += count;
+        let _ = writeln!(
+            out,
+            "{}_bucket{{rendition=\"{}\",le=\"{}\"}} {}",
+            name, rendition, bound, cumulative
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{{rendition=\"{}\",le=\"+Inf\"}} {}",
+        name, rendition, histogram.count
+    );
+    let _ = writeln!(
+        out,
+        "{}_sum{{rendition=\"{}\"}} {}",
+        name, rendition, histogram.sum
+    );
+    let _ = writeln!(
+        out,
+        "{}_count{{rendition=\"{}\"}} {}",
+        name, rendition, histogram.count
+    );
+}
+
+/// Distribution of a measurement
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub mean: f64,
+    /// Median, estimated from histogram buckets
+    pub p50: f64,
+    /// 95th percentile, estimated from histogram buckets
+    pub p95: f64,
+    pub max: f64,
+}
+
+/// Point-in-time copy of all metrics
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MetricsSnapshot {
+    pub uptime_secs: u64,
+    /// Video clients connected since start
+    pub clients_total: u64,
+    /// Times any client fell behind the broadcast
+    pub lag_events: u64,
+    /// Segments skipped by clients that fell behind
+    pub lagged_segments: u64,
+    /// Frames clients reported dropping
+    pub dropped_frames: u64,
+    pub renditions: Vec<RenditionSnapshot>,
+    pub clients: Vec<ClientSnapshot>,
+}
+
+/// Encoder output of one rendition
+#[derive(Debug, Clone, Serialize)]
+pub struct RenditionSnapshot {
+    pub index: usize,
+    /// Codec, size, frame rate and bitrate
+    pub stream: String,
+    pub segments: u64,
+    pub keyframes: u64,
+    pub bytes: u64,
+    pub capture_ms: Summary,
+    pub convert_ms: Summary,
+    pub encode_ms: Summary,
+    /// Capture to a segment being written to a client
+    pub latency_ms: Summary,
+    pub segment_bytes: Summary,
+}
+
+/// One connected video client
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientSnapshot {
+    pub id: ClientId,
+    pub rendition: usize,
+    pub connected_secs: u64,
+    pub segments_sent: u64,
+    pub bytes_sent: u64,
+    /// Segments waiting to be sent
+    pub queue_depth: usize,
+    pub max_queue_depth: usize,
+    pub lag_events: u64,
+    pub lagged_segments: u64,
+    /// Latest ping round trip
+    pub rtt_ms: Option<u64>,
+    /// Frames the client reported showing
+    pub decoded_frames: u64,
+    /// Frames the client reported dropping
+    pub dropped_frames: u64,
+    /// Latest reported average decode time
+    pub decode_latency_ms: Option<f64>,
+    /// Latest reported frames waiting in the decoder or for display
+    pub jitter_buffer_depth: Option<u32>,
+    /// Display refresh rate in Hz
+    pub refresh_rate: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linglide_core::VideoCodec;
+
+    fn metrics() -> Metrics {
+        Metrics::new(&[StreamOffer {
+            codec: VideoCodec::H264,
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            bitrate: 8000,
+        }])
+    }
+
+    fn segment(bytes: usize, is_keyframe: bool) -> StreamSegment {
+        StreamSegment {
+            data: vec![0; bytes],
+            is_init: false,
+            is_keyframe,
+            sequence: 0,
+            times: StageTimes {
+                capture_started_us: 1_000,
+                captured_us: 4_000,
+                dequeued_us: 4_500,
+                converted_us: 5_000,
+                encoded_us: 12_000,
+                sent_us: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_histogram_quantiles() {
+        let mut histogram = Histogram::new(TIME_BUCKETS);
+        assert_eq!(histogram.quantile(0.5), 0.0);
+        for _ in 0..19 {
+            histogram.observe(0.003);
+        }
+        histogram.observe(0.2);
+
+        assert_eq!(histogram.count, 20);
+        assert_eq!(histogram.quantile(0.5), 0.004);
+        assert_eq!(histogram.quantile(0.95), 0.004);
+        assert_eq!(histogram.quantile(1.0), 0.2);
+        // Values past the last bound land in +Inf and report the max
+        histogram.observe(3.0);
+        assert_eq!(histogram.quantile(1.0), 3.0);
+    }
+
+    #[test]
+    fn test_segment_and_client_metrics() {
+        let metrics = metrics();
+        metrics.record_segment(0, &segment(5000, true));
+        metrics.record_segment(0, &segment(1000, false));
+
+        metrics.add_client(7, 0);
+        let mut times = segment(0, false).times;
+        times.sent_us = 20_000;
+        metrics.record_sent(7, &times, 5000);
+        metrics.record_queue_depth(7, 3);
+        metrics.record_queue_depth(7, 1);
+        metrics.record_lag(7, 12);
+        metrics.record_rtt(7, 45);
+
+        let snapshot = metrics.snapshot();
+        let rendition = &snapshot.renditions[0];
+        assert_eq!(rendition.segments, 2);
+        assert_eq!(rendition.keyframes, 1);
+        assert_eq!(rendition.bytes, 6000);
+        assert_eq!(rendition.capture_ms.mean, 3.0);
+        assert_eq!(rendition.convert_ms.mean, 0.5);
+        assert_eq!(rendition.encode_ms.max, 7.0);
+        assert_eq!(rendition.latency_ms.count, 1);
+        assert_eq!(rendition.latency_ms.max, 16.0);
+
+        let client = &snapshot.clients[0];
+        assert_eq!(
+            (client.id, client.segments_sent, client.bytes_sent),
+            (7, 1, 5000)
+        );
+        assert_eq!((client.queue_depth, client.max_queue_depth), (1, 3));
+        assert_eq!((client.lag_events, client.lagged_segments), (1, 12));
+        assert_eq!(client.rtt_ms, Some(45));
+
+        // Totals outlive the client
+        metrics.remove_client(7);
+        let snapshot = metrics.snapshot();
+        assert!(snapshot.clients.is_empty());
+        assert_eq!((snapshot.clients_total, snapshot.lag_events), (1, 1));
+    }
+
+    #[test]
+    fn test_client_stats() {
+        let metrics = metrics();
+        metrics.add_client(2, 0);
+        let mut stats = ClientStats {
+            decoded_frames: 58,
+            dropped_frames: 2,
+            decode_latency_ms: Some(4.5),
+            jitter_buffer_depth: Some(1),
+            refresh_rate: Some(120.0),
+        };
+        metrics.record_client_stats(2, &stats);
+
+        // Counts add up, the rest reflects the latest report
+        stats.decode_latency_ms = Some(f64::NAN);
+        stats.refresh_rate = Some(-1.0);
+        metrics.record_client_stats(2, &stats);
+
+        let snapshot = metrics.snapshot();
+        let client = &snapshot.clients[0];
+        assert_eq!((client.decoded_frames, client.dropped_frames), (116, 4));
+        assert_eq!(client.decode_latency_ms, None);
+        assert_eq!(client.jitter_buffer_depth, Some(1));
+        assert_eq!(client.refresh_rate, None);
+        assert_eq!(snapshot.dropped_frames, 4);
+
+        // Absurd counts saturate instead of overflowing
+        stats.dropped_frames = u64::MAX;
+        metrics.record_client_stats(2, &stats);
+        assert_eq!(metrics.snapshot().clients[0].dropped_frames, u64::MAX);
+        assert_eq!(metrics.snapshot().dropped_frames, u64::MAX);
+    }
+
+    #[test]
+    fn test_prometheus_format() {
+        let metrics = metrics();
+        metrics.record_segment(0, &segment(2000, true));
+        metrics.add_client(3, 0);
+        metrics.record_rtt(3, 120);
+        metrics.record_client_stats(
+            3,
+            &ClientStats {
+                refresh_rate: Some(60.0),
+                ..Default::default()
+            },
+        );
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("# TYPE linglide_encode_seconds histogram\n"));
+        assert!(text.contains("linglide_encode_seconds_bucket{rendition=\"0\",le=\"0.004\"} 0\n"));
+        assert!(text.contains("linglide_encode_seconds_bucket{rendition=\"0\",le=\"0.008\"} 1\n"));
+        assert!(text.contains("linglide_encode_seconds_bucket{rendition=\"0\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("linglide_segment_bytes_sum{rendition=\"0\"} 2000\n"));
+        assert!(text.contains("linglide_keyframes_total{rendition=\"0\"} 1\n"));
+        assert!(text.contains("linglide_video_clients 1\n"));
+        assert!(text.contains("linglide_client_rtt_seconds{client=\"3\",rendition=\"0\"} 0.12\n"));
+        assert!(
+            text.contains("linglide_client_refresh_rate_hertz{client=\"3\",rendition=\"0\"} 60\n")
+        );
+        // Values the client didn't report are left out
+        assert!(!text.contains("linglide_client_decode_latency_seconds{"));
+        assert!(text
+            .lines()
+            .all(|line| line.starts_with('#') || line.split(' ').count() == 2));
+    }
+}
@@ -0,0 +1,232 @@
+//! Stream negotiation for video clients
+//!
+//! A client opens the video socket with a `Hello` listing the codecs it can
+//! decode, its screen size and its preferred frame rate. The server picks the
+//! rendition that suits it best and describes it in `Init`, or turns the
+//! client away with an error when it can't decode anything on offer. A client
+//! that falls behind moves to a lower-bitrate rendition it can decode, and
+//! back up once its link has recovered.
+
+use linglide_core::protocol::ClientHello;
+use linglide_core::VideoCodec;
+
+/// A stream the server can send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOffer {
+    pub codec: VideoCodec,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /// Target bitrate in kbps
+    pub bitrate: u32,
+}
+
+impl std::fmt::Display for StreamOffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}x{} @ {} fps, {} kbps",
+            self.codec, self.width, self.height, self.fps, self.bitrate
+        )
+    }
+}
+
+/// Codecs a client reported, ignoring ones the server doesn't know
+pub fn client_codecs(hello: &ClientHello) -> Vec<VideoCodec> {
+    let mut codecs = Vec::new();
+    for codec in hello
+        .codecs
+        .iter()
+        .filter_map(|c| VideoCodec::from_codec_string(c.trim()))
+    {
+        if !codecs.contains(&codec) {
+            codecs.push(codec);
+        }
+    }
+    codecs
+}
+
+/// Largest useful picture for a client, in device pixels
+pub fn client_max_size(hello: &ClientHello) -> Option<(u32, u32)> {
+    let (width, height) = hello.max_width.zip(hello.max_height)?;
+    let scale = hello
+        .device_pixel_ratio
+        .filter(|dpr| dpr.is_finite() && *dpr > 0.0)
+        .unwrap_or(1.0);
+    Some((
+        (width as f64 * scale).round() as u32,
+        (height as f64 * scale).round() as u32,
+    ))
+}
+
+/// Pick the offer that suits a client best, returning its index
+///
+/// Only offers in a codec the client decodes are considered. Among those, the
+/// largest that fits its screen wins (or the smallest if none fits), then the
+/// frame rate closest to its preference, then the most efficient codec, then
+/// the highest bitrate. Returns `None` when the client can't decode any offer.
+pub fn choose_stream(offers: &[StreamOffer], hello: &ClientHello) -> Option<usize> {
+    let codecs = client_codecs(hello);
+    let max_size = client_max_size(hello);
+
+    compatible(offers, &codecs)
+        .max_by_key(|&(_, offer)| {
+            let area = i64::from(offer.width) * i64::from(offer.height);
+            let fits = max_size.is_none_or(|(w, h)| offer.width <= w && offer.height <= h);
+            let size = if fits { area } else { -area };
+            let fps = match hello.fps {
+                Some(fps) => -(i64::from(offer.fps) - i64::from(fps)).abs(),
+                None => i64::from(offer.fps),
+            };
+            let efficiency = VideoCodec::ALL.iter().rev().position(|c| *c == offer.codec);
+            (fits, size, fps, efficiency, offer.bitrate)
+        })
+        .map(|(index, _)| index)
+}
+
+/// The next offer below `current` in bitrate that the client can decode
+pub fn lower_stream(offers: &[StreamOffer], hello: &ClientHello, current: usize) -> Option<usize> {
+    let bitrate = offers.get(current)?.bitrate;
+    compatible(offers, &client_codecs(hello))
+        .filter(|(_, offer)| offer.bitrate < bitrate)
+        .max_by_key(|(_, offer)| offer.bitrate)
+        .map(|(index, _)| index)
+}
+
+/// The next offer above `current` in bitrate that the client can decode,
+/// going no higher than `preferred`
+pub fn higher_stream(
+    offers: &[StreamOffer],
+    hello: &ClientHello,
+    current: usize,
+    preferred: usize,
+) -> Option<usize> {
+    let bitrate = offers.get(current)?.bitrate;
+    let limit = offers.get(preferred)?.bitrate;
+    if current == preferred {
+        return None;
+    }
+    compatible(offers, &client_codecs(hello))
+        .filter(|(_, offer)| offer.bitrate > bitrate && offer.bitrate <= limit)
+        .min_by_key(|(index, offer)| (offer.bitrate, *index != preferred))
+        .map(|(index, _)| index)
+}
+
+/// Offers in one of `codecs`, with their indices
+fn compatible<'a>(
+    offers: &'a [StreamOffer],
+    codecs: &'a [VideoCodec],
+) -> impl Iterator<Item = (usize, &'a StreamOffer)> {
+    offers
+        .iter()
+        .enumerate()
+        .filter(|(_, offer)| codecs.contains(&offer.codec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(codecs: &[&str]) -> ClientHello {
+        ClientHello {
+            codecs: codecs.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn offer(codec: VideoCodec, width: u32, height: u32, fps: u32) -> StreamOffer {
+        StreamOffer {
+            codec,
+            width,
+            height,
+            fps,
+            bitrate: width * height / 250,
+        }
+    }
+
+    #[test]
+    fn test_client_codecs() {
+        let client = hello(&["hvc1.1.6.L123.B0", "vp09", " avc1", "avc3.42e01f"]);
+        assert_eq!(
+            client_codecs(&client),
+            vec![VideoCodec::Hevc, VideoCodec::H264]
+        );
+    }
+
+    #[test]
+    fn test_no_compatible_codec() {
+        let offers = [offer(VideoCodec::Hevc, 1920, 1080, 60)];
+        assert_eq!(choose_stream(&offers, &hello(&["avc1"])), None);
+        assert_eq!(choose_stream(&offers, &hello(&[])), None);
+        assert_eq!(choose_stream(&offers, &hello(&["hev1"])), Some(0));
+    }
+
+    #[test]
+    fn test_prefers_stream_fitting_screen() {
+        let offers = [
+            offer(VideoCodec::H264, 2560, 1600, 60),
+            offer(VideoCodec::H264, 1280, 800, 60),
+            offer(VideoCodec::H264, 640, 400, 60),
+        ];
+
+        // 1280x800 CSS pixels at 2x: the full size stream fits
+        let mut client = hello(&["avc1"]);
+        client.max_width = Some(1280);
+        client.max_height = Some(800);
+        client.device_pixel_ratio = Some(2.0);
+        assert_eq!(choose_stream(&offers, &client), Some(0));
+
+        client.device_pixel_ratio = None;
+        assert_eq!(choose_stream(&offers, &client), Some(1));
+
+        // Nothing fits: the smallest is the least wasteful
+        client.max_width = Some(320);
+        client.max_height = Some(200);
+        assert_eq!(choose_stream(&offers, &client), Some(2));
+
+        // Without a screen size the largest wins
+        assert_eq!(choose_stream(&offers, &hello(&["avc1"])), Some(0));
+    }
+
+    #[test]
+    fn test_prefers_fps_then_efficient_codec() {
+        let offers = [
+            offer(VideoCodec::H264, 1920, 1080, 60),
+            offer(VideoCodec::H264, 1920, 1080, 30),
+            offer(VideoCodec::Av1, 1920, 1080, 30),
+        ];
+
+        let mut client = hello(&["avc1", "av01"]);
+        assert_eq!(choose_stream(&offers, &client), Some(0));
+
+        client.fps = Some(30);
+        assert_eq!(choose_stream(&offers, &client), Some(2));
+
+        client.codecs = vec!["avc1".to_string()];
+        assert_eq!(choose_stream(&offers, &client), Some(1));
+    }
+
+    #[test]
+    fn test_switching_between_bitrates() {
+        let offers = [
+            offer(VideoCodec::Hevc, 1920, 1080, 60),
+            offer(VideoCodec::H264, 1920, 1080, 60),
+            offer(VideoCodec::H264, 960, 540, 60),
+            offer(VideoCodec::H264, 480, 270, 30),
+        ];
+        let client = hello(&["avc1"]);
+        let preferred = choose_stream(&offers, &client).unwrap();
+        assert_eq!(preferred, 1);
+
+        // Down one step at a time, skipping codecs the client can't decode
+        assert_eq!(lower_stream(&offers, &client, 1), Some(2));
+        assert_eq!(lower_stream(&offers, &client, 2), Some(3));
+        assert_eq!(lower_stream(&offers, &client, 3), None);
+
+        // Back up, but never past the negotiated rendition
+        assert_eq!(higher_stream(&offers, &client, 3, preferred), Some(2));
+        assert_eq!(higher_stream(&offers, &client, 2, preferred), Some(1));
+        assert_eq!(higher_stream(&offers, &client, 1, preferred), None);
+        assert_eq!(higher_stream(&offers, &client, 3, 2), Some(2));
+    }
+}
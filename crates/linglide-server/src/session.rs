@@ -0,0 +1,955 @@
+//! Server session lifecycle
+//!
+//! A `Session` wires a capture backend, the encoding pipeline, an input
+//! backend and the HTTP/WebSocket server together, together with TLS,
+//! pairing, mDNS and USB forwarding. The CLI, the desktop app and embedders
+//! all start the server through it.
+
+use crate::broadcast::{AppState, RenditionStream};
+use crate::negotiation::StreamOffer;
+use crate::{create_router, create_rustls_config, CertificateManager};
+use axum_server::tls_rustls::RustlsConfig;
+use futures::future::{BoxFuture, LocalBoxFuture};
+use futures::FutureExt;
+use linglide_auth::{DeviceStorage, PairingManager};
+use linglide_core::protocol::InputCommand;
+use linglide_core::{frame_handoff, Config, Error, FrameReceiver, FrameSender, Result};
+use linglide_discovery::{ServiceAdvertiser, UsbConnectionManager};
+use linglide_encoder::pipeline::StreamSegment;
+use linglide_encoder::{EncodingPipeline, KeyframeRequest};
+use linglide_input::{InputDispatcher, InputSink};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// How long to wait for the encoder to produce its init segment
+const INIT_SEGMENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// ============================================================================
+// Backends
+// ============================================================================
+
+/// Source of captured frames
+///
+/// The backend runs on a dedicated thread with its own current-thread
+/// runtime, so the returned future does not need to be `Send` (EVDI handles
+/// are not). Frames are handed over latest-wins, so the backend never waits
+/// on the encoder. It should return once `frame_tx` closes or `shutdown`
+/// changes.
+pub trait CaptureBackend: Send + 'static {
+    /// Capture frames until the channel closes or shutdown is requested
+    fn run_capture(
+        self: Box<Self>,
+        config: Config,
+        frame_tx: FrameSender,
+        shutdown: watch::Receiver<bool>,
+    ) -> LocalBoxFuture<'static, ()>;
+}
+
+impl<F, Fut> CaptureBackend for F
+where
+    F: FnOnce(Config, FrameSender, watch::Receiver<bool>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    fn run_capture(
+        self: Box<Self>,
+        config: Config,
+        frame_tx: FrameSender,
+        shutdown: watch::Receiver<bool>,
+    ) -> LocalBoxFuture<'static, ()> {
+        (*self)(config, frame_tx, shutdown).boxed_local()
+    }
+}
+
+/// Factory for encoding pipelines
+///
+/// Called once per rendition on that rendition's encoder thread, since
+/// encoders are generally not `Send`.
+pub trait EncoderBackend: Send + Sync + 'static {
+    /// Create the pipeline for the given configuration
+    fn create_pipeline(&self, config: &Config) -> Result<EncodingPipeline>;
+}
+
+impl<F> EncoderBackend for F
+where
+    F: Fn(&Config) -> Result<EncodingPipeline> + Send + Sync + 'static,
+{
+    fn create_pipeline(&self, config: &Config) -> Result<EncodingPipeline> {
+        self(config)
+    }
+}
+
+/// Default encoder: the built-in pipeline configured from `Config`
+fn default_encoder(config: &Config) -> Result<EncodingPipeline> {
+    EncodingPipeline::from_config(config)
+}
+
+/// Consumer of input commands from connected clients
+pub trait InputBackend: Send + 'static {
+    /// Process commands until the channel closes
+    fn run_input(self: Box<Self>, input_rx: mpsc::Receiver<InputCommand>)
+        -> BoxFuture<'static, ()>;
+}
+
+impl<S: InputSink + Send + 'static> InputBackend for InputDispatcher<S> {
+    fn run_input(
+        self: Box<Self>,
+        input_rx: mpsc::Receiver<InputCommand>,
+    ) -> BoxFuture<'static, ()> {
+        self.run(input_rx).boxed()
+    }
+}
+
+// ============================================================================
+// Configuration
+// ============================================================================
+
+/// How the server secures its connections
+#[derive(Debug, Clone, Default)]
+pub enum TlsMode {
+    /// Self-signed certificate stored in the config directory
+    #[default]
+    Persistent,
+    /// Certificate and key supplied as PEM strings
+    Pem { cert: String, key: String },
+    /// Plain HTTP (WebCodecs will only work on localhost)
+    Disabled,
+}
+
+/// Status events emitted over the session lifetime
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// Server is listening
+    Started {
+        url: String,
+        fingerprint: Option<String>,
+    },
+    /// Encoder produced its init segment
+    EncoderReady { codec: String },
+    /// mDNS advertisement started or stopped
+    MdnsStatus { active: bool },
+    /// ADB port forwarding started or stopped
+    UsbStatus { active: bool },
+    /// Non-fatal error
+    Error { message: String },
+    /// Server stopped and resources were released
+    Stopped,
+}
+
+/// Builder for a [`Session`]
+pub struct SessionBuilder {
+    config: Config,
+    capture: Option<Box<dyn CaptureBackend>>,
+    encoder: Arc<dyn EncoderBackend>,
+    input: Option<Box<dyn InputBackend>>,
+    tls: TlsMode,
+    auth_required: bool,
+    pairing_manager: Option<Arc<PairingManager>>,
+    mdns: bool,
+    service_name: Option<String>,
+    usb: bool,
+    shutdown_timeout: Duration,
+}
+
+impl SessionBuilder {
+    /// Builder pattern: set the capture backend
+    pub fn capture(mut self, backend: impl CaptureBackend) -> Self {
+        self.capture = Some(Box::new(backend));
+        self
+    }
+
+    /// Builder pattern: set the encoder backend
+    pub fn encoder(mut self, backend: impl EncoderBackend) -> Self {
+        self.encoder = Arc::new(backend);
+        self
+    }
+
+    /// Builder pattern: set the input backend (input is discarded without one)
+    pub fn input(mut self, backend: impl InputBackend) -> Self {
+        self.input = Some(Box::new(backend));
+        self
+    }
+
+    /// Builder pattern: set the TLS mode
+    pub fn tls(mut self, tls: TlsMode) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Builder pattern: set whether clients must pair before connecting
+    pub fn auth_required(mut self, required: bool) -> Self {
+        self.auth_required = required;
+        self
+    }
+
+    /// Builder pattern: use an existing pairing manager
+    pub fn pairing_manager(mut self, pairing_manager: Arc<PairingManager>) -> Self {
+        self.pairing_manager = Some(pairing_manager);
+        self
+    }
+
+    /// Builder pattern: enable or disable mDNS advertisement
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.mdns = enabled;
+        self
+    }
+
+    /// Builder pattern: set the mDNS service name
+    pub fn service_name(mut self, name: impl Into<String>) -> Self {
+        self.service_name = Some(name.into());
+        self
+    }
+
+    /// Builder pattern: enable or disable ADB port forwarding
+    pub fn usb(mut self, enabled: bool) -> Self {
+        self.usb = enabled;
+        self
+    }
+
+    /// Builder pattern: set how long open connections get to close on shutdown
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Build the session (nothing is started yet)
+    pub fn build(self) -> Session {
+        let (events, _) = broadcast::channel(32);
+        let (shutdown_tx, _) = watch::channel(false);
+
+        Session {
+            builder: Some(self),
+            events,
+            shutdown_tx: Arc::new(shutdown_tx),
+            running: None,
+        }
+    }
+}
+
+// ============================================================================
+// Session
+// ============================================================================
+
+/// Resources of a started session
+struct Running {
+    state: Arc<AppState>,
+    url: String,
+    fingerprint: Option<String>,
+    /// Server task, taken by the first `wait`
+    task: Option<JoinHandle<()>>,
+}
+
+/// Handle that can stop a session from another task
+#[derive(Clone)]
+pub struct StopHandle {
+    shutdown_tx: Arc<watch::Sender<bool>>,
+}
+
+impl StopHandle {
+    /// Request a graceful shutdown
+    pub fn stop(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+}
+
+/// A LinGlide server session
+pub struct Session {
+    builder: Option<SessionBuilder>,
+    events: broadcast::Sender<SessionEvent>,
+    shutdown_tx: Arc<watch::Sender<bool>>,
+    running: Option<Running>,
+}
+
+impl Session {
+    /// Create a session builder for a configuration
+    pub fn builder(config: Config) -> SessionBuilder {
+        SessionBuilder {
+            config,
+            capture: None,
+            encoder: Arc::new(default_encoder),
+            input: None,
+            tls: TlsMode::default(),
+            auth_required: true,
+            pairing_manager: None,
+            mdns: true,
+            service_name: None,
+            usb: false,
+            shutdown_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Subscribe to status events
+    ///
+    /// Subscribe before calling [`start`](Self::start) to see `Started`.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Get a handle that can stop the session from elsewhere
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            shutdown_tx: self.shutdown_tx.clone(),
+        }
+    }
+
+    /// Shared server state (available once started)
+    pub fn state(&self) -> Option<&Arc<AppState>> {
+        self.running.as_ref().map(|r| &r.state)
+    }
+
+    /// Pairing manager (available once started)
+    pub fn pairing_manager(&self) -> Option<&Arc<PairingManager>> {
+        self.state().map(|s| &s.pairing_manager)
+    }
+
+    /// URL clients connect to (available once started)
+    pub fn url(&self) -> Option<&str> {
+        self.running.as_ref().map(|r| r.url.as_str())
+    }
+
+    /// TLS certificate fingerprint, if TLS is enabled
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.running.as_ref().and_then(|r| r.fingerprint.as_deref())
+    }
+
+    /// Whether the server is currently running
+    pub fn is_running(&self) -> bool {
+        self.running
+            .as_ref()
+            .and_then(|r| r.task.as_ref())
+            .map(|task| !task.is_finished())
+            .unwrap_or(false)
+    }
+
+    /// Start the session
+    ///
+    /// Returns once the server is listening and the encoder is initialized.
+    /// Fails if the session was already started, the port is in use or the
+    /// full-size encoder can't be created.
+    pub async fn start(&mut self) -> Result<()> {
+        let builder = self
+            .builder
+            .take()
+            .ok_or_else(|| Error::Server("Session already started".to_string()))?;
+        let capture = builder
+            .capture
+            .ok_or_else(|| Error::Config("No capture backend configured".to_string()))?;
+        let config = builder.config;
+
+        let local_ip = local_ip().unwrap_or_else(|| "localhost".to_string());
+
+        // TLS
+        let (tls_config, fingerprint) = setup_tls(&builder.tls, &local_ip)
+            .await?
+            .map_or((None, None), |(tls, fp)| (Some(tls), Some(fp)));
+        if let Some(ref fp) = fingerprint {
+            info!("Certificate fingerprint: {}", fp);
+        }
+
+        let protocol = if tls_config.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let url = format!("{}://{}:{}", protocol, local_ip, config.port);
+
+        // Pairing
+        let pairing_manager = match builder.pairing_manager {
+            Some(pm) => pm,
+            None => {
+                info!("Initializing device storage...");
+                let storage = DeviceStorage::new().await.map_err(|e| {
+                    Error::Server(format!("Failed to initialize device storage: {}", e))
+                })?;
+                Arc::new(PairingManager::new(Arc::new(storage), url.clone()))
+            }
+        };
+
+        if builder.auth_required {
+            let paired = pairing_manager.list_devices().await.len();
+            info!("Authentication: ENABLED ({} paired devices)", paired);
+        } else {
+            warn!("Authentication: DISABLED");
+        }
+
+        // Bind early so a busy port is reported to the caller
+        let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+        let listener = std::net::TcpListener::bind(addr)
+            .map_err(|e| Error::Server(format!("Port {} is already in use: {}", config.port, e)))?;
+        listener.set_nonblocking(true)?;
+
+        let (input_tx, input_rx) = mpsc::channel(64);
+
+        // Discovery
+        let mdns_advertiser = if builder.mdns {
+            start_mdns(
+                config.port,
+                builder.service_name,
+                fingerprint.as_deref(),
+                &self.events,
+            )
+        } else {
+            debug!("mDNS: Disabled");
+            None
+        };
+        let usb_manager = if builder.usb {
+            start_usb(config.port, &self.events).await
+        } else {
+            None
+        };
+
+        // Each rendition has its own encoder thread (encoders are not Send)
+        let mut encoders = Vec::new();
+        for (index, rendition) in config.all_renditions().iter().enumerate() {
+            let encoder_config = config.for_rendition(rendition);
+            let (frame_tx, frame_rx) = frame_handoff();
+            let (segment_tx, _segment_rx) = broadcast::channel::<StreamSegment>(16);
+            let (control_tx, control_rx) = mpsc::channel(16);
+            let (init_tx, init_rx) = oneshot::channel::<Result<(Vec<u8>, String, Vec<u8>)>>();
+            let (width, height) = encoder_config.stream_size();
+            let offer = StreamOffer {
+                codec: config.encoder.codec(),
+                width,
+                height,
+                fps: encoder_config.fps,
+                bitrate: encoder_config.bitrate,
+            };
+
+            let keyframe_request = KeyframeRequest::default();
+            let encoder = builder.encoder.clone();
+            let encoder_segment_tx = segment_tx.clone();
+            let encoder_keyframe_request = keyframe_request.clone();
+            std::thread::Builder::new()
+                .name(format!("linglide-encoder-{}", index))
+                .spawn(move || {
+                    let pipeline = match encoder.create_pipeline(&encoder_config) {
+                        Ok(p) => p.with_keyframe_request(encoder_keyframe_request),
+                        Err(e) => {
+                            let _ = init_tx.send(Err(e));
+                            return;
+                        }
+                    };
+
+                    if let Some(init_segment) = pipeline.get_init_segment() {
+                        let codec_string = pipeline.get_codec_string();
+                        let description = pipeline.get_codec_description();
+                        let _ = init_tx.send(Ok((init_segment, codec_string, description)));
+                    }
+
+                    let rt = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("Failed to create encoder runtime");
+                    rt.block_on(pipeline.run(frame_rx, control_rx, encoder_segment_tx));
+                    debug!("Encoder thread {} finished", index);
+                })?;
+
+            let stream = RenditionStream::new(offer, segment_tx, control_tx, keyframe_request);
+            encoders.push((Arc::new(stream), frame_tx, init_rx));
+        }
+
+        // Extra renditions that fail to start are dropped; without the
+        // full-size one there is nothing to serve
+        let deadline = tokio::time::Instant::now() + INIT_SEGMENT_TIMEOUT;
+        let mut renditions = Vec::new();
+        let mut frame_txs = Vec::new();
+        for (index, (rendition, frame_tx, init_rx)) in encoders.into_iter().enumerate() {
+            let ready = match tokio::time::timeout_at(deadline, init_rx).await {
+                Ok(Ok(Ok((init_segment, codec_string, description)))) => {
+                    info!(
+                        "Received init segment for {}: {} bytes, codec: {}",
+                        rendition.offer,
+                        init_segment.len(),
+                        codec_string
+                    );
+                    rendition.set_init_segment(init_segment);
+                    rendition.set_codec_config(codec_string.clone(), description);
+                    if index == 0 {
+                        let _ = self.events.send(SessionEvent::EncoderReady {
+                            codec: codec_string,
+                        });
+                    }
+                    Ok(())
+                }
+                Ok(Ok(Err(e))) => {
+                    warn!("Failed to create encoder for {}: {}", rendition.offer, e);
+                    Err(format!("Failed to create encoder: {}", e))
+                }
+                _ => {
+                    warn!(
+                        "Failed to receive init segment from encoder for {}",
+                        rendition.offer
+                    );
+                    Err("Encoder did not produce an init segment".to_string())
+                }
+            };
+
+            match ready {
+                Ok(()) => {
+                    renditions.push(rendition);
+                    frame_txs.push(frame_tx);
+                }
+                Err(message) if index == 0 => {
+                    stop_discovery(mdns_advertiser, usb_manager, &self.events).await;
+                    return Err(Error::EncoderError(message));
+                }
+                Err(message) => {
+                    let _ = self.events.send(SessionEvent::Error { message });
+                }
+            }
+        }
+
+        let state = Arc::new(AppState::new(
+            config.clone(),
+            renditions,
+            input_tx,
+            pairing_manager,
+            builder.auth_required,
+            fingerprint.clone(),
+        ));
+        let router = create_router(state.clone());
+
+        // Capture feeds every encoder through a fan-out task when there are several
+        let (frame_tx, fan_out_task) = if frame_txs.len() == 1 {
+            (frame_txs.remove(0), None)
+        } else {
+            let (frame_tx, frame_rx) = frame_handoff();
+            (frame_tx, Some(tokio::spawn(fan_out(frame_rx, frame_txs))))
+        };
+
+        // Capture runs on its own thread (EVDI is not Send)
+        let capture_config = config.clone();
+        let capture_shutdown = self.shutdown_tx.subscribe();
+        std::thread::Builder::new()
+            .name("linglide-capture".to_string())
+            .spawn(move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to create capture runtime");
+                rt.block_on(capture.run_capture(capture_config, frame_tx, capture_shutdown));
+                debug!("Capture thread finished");
+            })?;
+
+        // Cache keyframes for newly connecting clients and record encoder metrics
+        let segment_tasks: Vec<_> = state
+            .renditions
+            .iter()
+            .enumerate()
+            .map(|(index, rendition)| {
+                let state = state.clone();
+                let rendition = rendition.clone();
+                let mut segment_rx = rendition.video_tx.subscribe();
+                tokio::spawn(async move {
+                    loop {
+                        match segment_rx.recv().await {
+                            Ok(segment) => {
+                                state.metrics.record_segment(index, &segment);
+                                if segment.is_keyframe {
+                                    rendition.set_keyframe_segment(segment.data);
+                                }
+                            }
+                            Err(broadcast::error::RecvError::Lagged(_)) => {}
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // Adaptive bitrate
+        let adaptive_task = config
+            .adaptive_bitrate
+            .then(|| tokio::spawn(crate::adaptive::run(state.clone())));
+
+        // Input
+        let input_task = match builder.input {
+            Some(input) => tokio::spawn(input.run_input(input_rx)),
+            None => tokio::spawn(drain_input(input_rx)),
+        };
+
+        // Server
+        let server = serve(
+            listener,
+            tls_config,
+            router,
+            self.shutdown_tx.subscribe(),
+            builder.shutdown_timeout,
+        );
+        let events = self.events.clone();
+        let shutdown_tx = self.shutdown_tx.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!("Server error: {}", e);
+                let _ = events.send(SessionEvent::Error {
+                    message: e.to_string(),
+                });
+            }
+
+            // Stop capture (and with it the encoder) if the server exited on its own
+            shutdown_tx.send_replace(true);
+            for task in segment_tasks {
+                task.abort();
+            }
+            if let Some(task) = fan_out_task {
+                task.abort();
+            }
+            input_task.abort();
+            if let Some(task) = adaptive_task {
+                task.abort();
+            }
+
+            stop_discovery(mdns_advertiser, usb_manager, &events).await;
+
+            info!("Server stopped");
+            let _ = events.send(SessionEvent::Stopped);
+        });
+
+        info!("Server listening on {}", url);
+        let _ = self.events.send(SessionEvent::Started {
+            url: url.clone(),
+            fingerprint: fingerprint.clone(),
+        });
+
+        self.running = Some(Running {
+            state,
+            url,
+            fingerprint,
+            task: Some(task),
+        });
+
+        Ok(())
+    }
+
+    /// Request a graceful shutdown without waiting for it
+    pub fn stop(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    /// Wait until the server has stopped and resources are released
+    ///
+    /// Returns immediately once an earlier call has seen the server stop.
+    pub async fn wait(&mut self) -> Result<()> {
+        if let Some(task) = self.running.as_mut().and_then(|r| r.task.take()) {
+            task.await
+                .map_err(|e| Error::Server(format!("Session task failed: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Stop the session and wait for it to finish
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stop();
+        self.wait().await
+    }
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Hand each captured frame to every rendition's encoder
+///
+/// Frames share their pixel buffer, so cloning one is cheap, and each
+/// encoder only ever sees the newest frame, so a slow rendition doesn't hold
+/// up the others. Returns once capture stops or every encoder has exited.
+async fn fan_out(mut frame_rx: FrameReceiver, frame_txs: Vec<FrameSender>) {
+    while let Some(frame) = frame_rx.recv().await {
+        for frame_tx in &frame_txs {
+            let _ = frame_tx.send(frame.clone());
+        }
+        if frame_txs.iter().all(|tx| tx.is_closed()) {
+            break;
+        }
+    }
+}
+
+/// Withdraw the mDNS advertisement and ADB forwarding
+async fn stop_discovery(
+    mdns_advertiser: Option<ServiceAdvertiser>,
+    usb_manager: Option<UsbConnectionManager>,
+    events: &broadcast::Sender<SessionEvent>,
+) {
+    if let Some(mut advertiser) = mdns_advertiser {
+        if let Err(e) = advertiser.stop() {
+            warn!("mDNS: Failed to stop advertisement: {}", e);
+        }
+        let _ = events.send(SessionEvent::MdnsStatus { active: false });
+    }
+
+    if let Some(mut manager) = usb_manager {
+        if let Err(e) = manager.remove_forwarding().await {
+            warn!("USB: Failed to remove ADB forwarding: {}", e);
+        }
+        let _ = events.send(SessionEvent::UsbStatus { active: false });
+    }
+}
+
+/// Get the local IP address used for outbound traffic
+pub fn local_ip() -> Option<String> {
+    use std::net::UdpSocket;
+
+    // Connecting a UDP socket sends nothing but selects the outbound interface
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let addr = socket.local_addr().ok()?;
+    Some(addr.ip().to_string())
+}
+
+/// Prepare the TLS configuration, returning it with the certificate fingerprint
+async fn setup_tls(tls: &TlsMode, local_ip: &str) -> Result<Option<(RustlsConfig, String)>> {
+    let (cert_pem, key_pem, fingerprint) = match tls {
+        TlsMode::Disabled => return Ok(None),
+        TlsMode::Pem { cert, key } => {
+            info!("Using provided TLS certificate...");
+            let fingerprint = crate::calculate_cert_fingerprint(cert);
+            (cert.clone(), key.clone(), fingerprint)
+        }
+        TlsMode::Persistent => {
+            info!("Using persistent certificate storage...");
+            let cert_manager = CertificateManager::new().map_err(|e| {
+                Error::Server(format!("Failed to create certificate manager: {}", e))
+            })?;
+            let hostnames = vec![local_ip.to_string(), "localhost".to_string()];
+            cert_manager
+                .load_or_generate(&hostnames)
+                .map_err(|e| Error::Server(format!("Failed to load/generate certificate: {}", e)))?
+        }
+    };
+
+    let config = create_rustls_config(&cert_pem, &key_pem)
+        .await
+        .map_err(|e| Error::Server(format!("Failed to create TLS config: {}", e)))?;
+
+    Ok(Some((config, fingerprint)))
+}
+
+/// Start mDNS advertisement
+fn start_mdns(
+    port: u16,
+    service_name: Option<String>,
+    fingerprint: Option<&str>,
+    events: &broadcast::Sender<SessionEvent>,
+) -> Option<ServiceAdvertiser> {
+    let mut advertiser = match ServiceAdvertiser::new(port, service_name) {
+        Ok(advertiser) => advertiser,
+        Err(e) => {
+            warn!("mDNS: Failed to create advertiser: {}", e);
+            return None;
+        }
+    };
+
+    let addresses: Vec<IpAddr> = local_ip()
+        .and_then(|ip| ip.parse().ok())
+        .into_iter()
+        .collect();
+
+    match advertiser.start(env!("CARGO_PKG_VERSION"), fingerprint, Some(addresses)) {
+        Ok(()) => {
+            info!("mDNS: Advertising as '{}'", advertiser.instance_name());
+            let _ = events.send(SessionEvent::MdnsStatus { active: true });
+            Some(advertiser)
+        }
+        Err(e) => {
+            warn!("mDNS: Failed to start advertisement: {}", e);
+            None
+        }
+    }
+}
+
+/// Start ADB port forwarding
+async fn start_usb(
+    port: u16,
+    events: &broadcast::Sender<SessionEvent>,
+) -> Option<UsbConnectionManager> {
+    let mut manager = UsbConnectionManager::new(port);
+
+    if !manager.is_adb_available().await {
+        warn!("USB: ADB not found in PATH, USB forwarding disabled");
+        return None;
+    }
+
+    match manager.setup_forwarding().await {
+        Ok(()) => {
+            info!("USB: ADB port forwarding enabled");
+            let _ = events.send(SessionEvent::UsbStatus { active: true });
+            Some(manager)
+        }
+        Err(e) => {
+            warn!("USB: Failed to setup ADB forwarding: {}", e);
+            None
+        }
+    }
+}
+
+/// Discard input when no input backend is configured
+async fn drain_input(mut input_rx: mpsc::Receiver<InputCommand>) {
+    while input_rx.recv().await.is_some() {}
+}
+
+/// Serve HTTP(S) until shutdown is requested
+async fn serve(
+    listener: std::net::TcpListener,
+    tls_config: Option<RustlsConfig>,
+    router: axum::Router,
+    mut shutdown: watch::Receiver<bool>,
+    shutdown_timeout: Duration,
+) -> Result<()> {
+    let wait_for_shutdown = async move {
+        // An error means the session was dropped, which also means stop
+        while !*shutdown.borrow_and_update() {
+            if shutdown.changed().await.is_err() {
+                break;
+            }
+        }
+        info!("Shutting down...");
+    };
+
+    match tls_config {
+        Some(tls_config) => {
+            let handle = axum_server::Handle::new();
+            let shutdown_handle = handle.clone();
+            tokio::spawn(async move {
+                wait_for_shutdown.await;
+                shutdown_handle.graceful_shutdown(Some(shutdown_timeout));
+            });
+
+            axum_server::from_tcp_rustls(listener, tls_config)
+                .handle(handle)
+                .serve(router.into_make_service())
+                .await?;
+        }
+        None => {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            axum::serve(listener, router)
+                .with_graceful_shutdown(wait_for_shutdown)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn idle_capture(
+        _config: Config,
+        _frame_tx: FrameSender,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let _ = shutdown.wait_for(|stop| *stop).await;
+    }
+
+    async fn test_pairing_manager(dir: &std::path::Path) -> Arc<PairingManager> {
+        let storage = DeviceStorage::with_path(dir.join("devices.json"))
+            .await
+            .unwrap();
+        Arc::new(PairingManager::new(
+            Arc::new(storage),
+            "http://localhost".to_string(),
+        ))
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn test_start_requires_capture_backend() {
+        let mut session = Session::builder(Config::new()).build();
+        assert!(matches!(session.start().await, Err(Error::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let dir = tempdir().unwrap();
+        let config = Config::new()
+            .with_width(64)
+            .with_height(64)
+            .with_port(free_port());
+
+        let mut session = Session::builder(config)
+            .capture(idle_capture)
+            .tls(TlsMode::Disabled)
+            .pairing_manager(test_pairing_manager(dir.path()).await)
+            .mdns(false)
+            .build();
+        let mut events = session.subscribe();
+
+        session.start().await.unwrap();
+        assert!(session.is_running());
+        assert!(session.url().unwrap().starts_with("http://"));
+        assert!(session.fingerprint().is_none());
+        assert!(session.start().await.is_err());
+
+        session.shutdown().await.unwrap();
+        assert!(!session.is_running());
+        // Waiting again after the server stopped is a no-op
+        session.wait().await.unwrap();
+        session.shutdown().await.unwrap();
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            seen.push(event);
+        }
+        assert!(seen
+            .iter()
+            .any(|e| matches!(e, SessionEvent::Started { .. })));
+        assert!(matches!(seen.last(), Some(SessionEvent::Stopped)));
+    }
+
+    #[tokio::test]
+    async fn test_encoder_failure_fails_start() {
+        let dir = tempdir().unwrap();
+        let config = Config::new()
+            .with_width(64)
+            .with_height(64)
+            .with_port(free_port());
+
+        let mut session = Session::builder(config)
+            .capture(idle_capture)
+            .encoder(|_: &Config| -> Result<EncodingPipeline> {
+                Err(Error::EncoderError("no encoder".to_string()))
+            })
+            .tls(TlsMode::Disabled)
+            .pairing_manager(test_pairing_manager(dir.path()).await)
+            .mdns(false)
+            .build();
+
+        match session.start().await {
+            Err(Error::EncoderError(message)) => assert!(message.contains("no encoder")),
+            other => panic!("expected encoder error, got {:?}", other.map(|_| ())),
+        }
+        assert!(!session.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_port_in_use() {
+        let dir = tempdir().unwrap();
+        let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut session = Session::builder(Config::new().with_port(port))
+            .capture(idle_capture)
+            .tls(TlsMode::Disabled)
+            .pairing_manager(test_pairing_manager(dir.path()).await)
+            .mdns(false)
+            .build();
+
+        match session.start().await {
+            Err(Error::Server(message)) => assert!(message.contains("already in use")),
+            other => panic!("expected port error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -11,13 +11,33 @@ use axum::{
     response::IntoResponse,
 };
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures::stream::{SplitSink, SplitStream};
 use futures::{SinkExt, StreamExt};
-use linglide_core::protocol::{InputEvent, ServerMessage};
+use linglide_core::protocol::{
+    ClientHello, ClientId, ClientMessage, InputCommand, InputEvent, ServerMessage,
+};
+use linglide_core::unix_time_us;
+use linglide_encoder::fmp4;
+use linglide_encoder::pipeline::StreamSegment;
 use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 use tracing::{debug, info, warn};
 
-use crate::broadcast::AppState;
+use crate::broadcast::{AppState, RenditionStream};
+use crate::negotiation;
+
+/// Interval between pings on the video socket (keepalive and RTT probe)
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a video client has to send its `Hello`
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time without lag before a client moved to a lighter rendition tries a
+/// heavier one again
+const UPGRADE_AFTER: Duration = Duration::from_secs(20);
 
 /// Query parameters for WebSocket connections
 #[derive(Debug, Deserialize)]
@@ -110,51 +130,69 @@ pub async fn input_ws_handler(
 }
 
 /// Handle video WebSocket connection
+///
+/// The client opens with a `Hello`; the stream chosen for it is described in
+/// the `Init` reply. Clients that can't decode any stream get an error and
+/// are disconnected.
 pub async fn handle_video_socket(socket: WebSocket, state: Arc<AppState>) {
     let (mut sender, mut receiver) = socket.split();
+    let client_id = state.next_client_id();
 
-    info!("Video client connected");
-
-    // Subscribe to video segments
-    let mut segment_rx = state.video_tx.subscribe();
+    info!("Video client {} connected", client_id);
 
-    // Send init message with display configuration and codec info
-    let (codec, codec_data) = if let Some(config) = state.get_codec_config() {
-        (
-            Some(config.codec_string),
-            Some(BASE64.encode(&config.avcc_data)),
-        )
-    } else {
-        (None, None)
+    let hello = match tokio::time::timeout(HELLO_TIMEOUT, receive_hello(&mut receiver)).await {
+        Ok(Some(hello)) => hello,
+        Ok(None) => {
+            info!("Video client {} left before Hello", client_id);
+            return;
+        }
+        Err(_) => {
+            warn!("Video client {} sent no Hello", client_id);
+            reject(&mut sender, "Expected Hello".to_string()).await;
+            return;
+        }
     };
+    debug!("Video client {} hello: {:?}", client_id, hello);
 
-    let init_msg = ServerMessage::Init {
-        width: state.config.width,
-        height: state.config.height,
-        fps: state.config.fps,
-        codec,
-        codec_data,
+    let offers = state.stream_offers();
+    let Some(preferred) = negotiation::choose_stream(&offers, &hello) else {
+        let codecs = negotiation::client_codecs(&hello);
+        let message = format!(
+            "No compatible stream: this device decodes {}, the server sends {}",
+            if codecs.is_empty() {
+                "no supported codec".to_string()
+            } else {
+                join(&codecs)
+            },
+            join(&offers)
+        );
+        warn!("Video client {}: {}", client_id, message);
+        reject(&mut sender, message).await;
+        return;
     };
+    info!("Video client {} streaming {}", client_id, offers[preferred]);
 
-    if let Ok(json) = serde_json::to_string(&init_msg) {
-        debug!("Sending init: {}", json);
-        if sender.send(Message::Text(json)).await.is_err() {
-            warn!("Failed to send init message");
-            return;
-        }
+    // Rendition being watched, shared with the receiver task
+    let current = Arc::new(AtomicUsize::new(preferred));
+    let mut rendition = state.renditions[preferred].clone();
+
+    // Subscribe to video segments
+    let mut segment_rx = rendition.video_tx.subscribe();
+
+    // Send init message with display configuration and codec info
+    if !send_json(&mut sender, &init_message(&state, &rendition)).await {
+        warn!("Failed to send init message");
+        return;
     }
 
     // Send ready message
-    let ready_msg = ServerMessage::Ready;
-    if let Ok(json) = serde_json::to_string(&ready_msg) {
-        if sender.send(Message::Text(json)).await.is_err() {
-            warn!("Failed to send ready message");
-            return;
-        }
+    if !send_json(&mut sender, &ServerMessage::Ready).await {
+        warn!("Failed to send ready message");
+        return;
     }
 
     // Send init segment (fMP4 moov box) if available
-    if let Some(init_segment) = state.get_init_segment() {
+    if let Some(init_segment) = rendition.get_init_segment() {
         debug!("Sending init segment: {} bytes", init_segment.len());
         if sender.send(Message::Binary(init_segment)).await.is_err() {
             warn!("Failed to send init segment");
@@ -164,8 +202,14 @@ pub async fn handle_video_socket(socket: WebSocket, state: Arc<AppState>) {
         debug!("No init segment available yet");
     }
 
+    // The cached keyframe shows a picture right away, but the segments after
+    // it may reference frames this client never received. Hold back deltas
+    // until a fresh keyframe arrives.
+    rendition.request_keyframe();
+    let mut awaiting_keyframe = true;
+
     // Send most recent keyframe segment so client can start decoding immediately
-    if let Some(keyframe_segment) = state.get_keyframe_segment() {
+    if let Some(keyframe_segment) = rendition.get_keyframe_segment() {
         debug!("Sending keyframe segment: {} bytes", keyframe_segment.len());
         if sender
             .send(Message::Binary(keyframe_segment))
@@ -179,17 +223,59 @@ pub async fn handle_video_socket(socket: WebSocket, state: Arc<AppState>) {
         debug!("No keyframe segment available yet");
     }
 
-    // Spawn receiver task to handle client messages
+    // Congestion on this client feeds its rendition's adaptive bitrate controller
+    rendition.adaptive().add_client(client_id);
+    state.metrics.add_client(client_id, preferred);
+
+    // Spawn receiver task to handle client messages; replies go through the sending loop
+    let (reply_tx, mut reply_rx) = tokio::sync::mpsc::channel::<ServerMessage>(8);
+    let recv_state = state.clone();
+    let recv_current = current.clone();
     let recv_task = tokio::spawn(async move {
         while let Some(msg) = receiver.next().await {
+            let rendition = &recv_state.renditions[recv_current.load(Ordering::Relaxed)];
             match msg {
                 Ok(Message::Close(_)) => break,
                 Ok(Message::Ping(_)) => {
                     debug!("Received ping");
                 }
-                Ok(Message::Text(text)) => {
-                    debug!("Received text message: {}", text);
-                }
+                Ok(Message::Text(text)) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::SetQuality { bitrate, fps }) => {
+                        info!("Client requested {} kbps, fps {:?}", bitrate, fps);
+                        let reply = match rendition.set_quality(bitrate, fps).await {
+                            Ok(quality) => ServerMessage::QualityChanged {
+                                bitrate: quality.bitrate,
+                                fps: quality.fps,
+                            },
+                            Err(e) => ServerMessage::Error {
+                                message: e.to_string(),
+                            },
+                        };
+                        if reply_tx.send(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(ClientMessage::RequestKeyframe) => {
+                        debug!("Video client {} requested a keyframe", client_id);
+                        rendition.request_keyframe();
+                    }
+                    Ok(ClientMessage::Stats(stats)) => {
+                        debug!("Video client {} playback: {:?}", client_id, stats);
+                        rendition.adaptive().record_playback(client_id, &stats);
+                        recv_state.metrics.record_client_stats(client_id, &stats);
+                    }
+                    Ok(ClientMessage::Pong { timestamp }) => {
+                        let rtt_ms = unix_millis().saturating_sub(timestamp);
+                        rendition.adaptive().record_rtt(client_id, rtt_ms);
+                        recv_state.metrics.record_rtt(client_id, rtt_ms);
+                    }
+                    Ok(msg) => {
+                        debug!("Received control message: {:?}", msg);
+                    }
+                    Err(_) => {
+                        debug!("Received text message: {}", text);
+                    }
+                },
                 Err(e) => {
                     warn!("WebSocket receive error: {}", e);
                     break;
@@ -201,38 +287,121 @@ pub async fn handle_video_socket(socket: WebSocket, state: Arc<AppState>) {
 
     // Send video segments
     let mut frames_sent = 0u64;
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // Rendition being switched to, with its segments until a keyframe arrives
+    let mut switch: Option<(usize, broadcast::Receiver<StreamSegment>)> = None;
+    let mut last_lag = Instant::now();
     loop {
         tokio::select! {
             result = segment_rx.recv() => {
                 match result {
                     Ok(segment) => {
-                        frames_sent += 1;
-                        if frames_sent <= 5 || frames_sent.is_multiple_of(100) {
-                            debug!("Sending segment {} to client: {} bytes", frames_sent, segment.data.len());
+                        rendition.adaptive().record_queue_depth(client_id, segment_rx.len());
+                        state.metrics.record_queue_depth(client_id, segment_rx.len());
+                        if awaiting_keyframe && !segment.is_keyframe {
+                            continue;
                         }
+                        awaiting_keyframe = false;
+                        frames_sent += 1;
+                        let (size, mut times) = (segment.data.len(), segment.times);
                         if sender.send(Message::Binary(segment.data)).await.is_err() {
                             break;
                         }
+                        times.sent_us = unix_time_us();
+                        state.metrics.record_sent(client_id, &times, size);
+                        if frames_sent <= 5 || frames_sent.is_multiple_of(100) {
+                            debug!(
+                                "Sent segment {} to client: {} bytes, {:?} after capture (queue {:?}, convert {:?}, encode {:?}, send {:?})",
+                                frames_sent,
+                                size,
+                                times.total_time(),
+                                times.queue_time(),
+                                times.convert_time(),
+                                times.encode_time(),
+                                times.send_time()
+                            );
+                        }
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        warn!("Video client lagged {} frames", n);
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Video client {} lagged {} frames", client_id, n);
+                        rendition.adaptive().record_lag(client_id, n);
+                        state.metrics.record_lag(client_id, n);
+                        // The skipped segments broke the reference chain
+                        rendition.request_keyframe();
+                        awaiting_keyframe = true;
+                        last_lag = Instant::now();
+
+                        // Move to a lighter rendition if there is one
+                        let index = current.load(Ordering::Relaxed);
+                        if switch.is_none() {
+                            if let Some(lower) = negotiation::lower_stream(&offers, &hello, index) {
+                                switch = Some(begin_switch(&state, client_id, index, lower));
+                            }
+                        }
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    Err(broadcast::error::RecvError::Closed) => {
                         break;
                     }
                 }
             }
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs(30)) => {
-                // Send ping for keepalive
+            result = recv_switch(&mut switch) => {
+                match result {
+                    // Deltas of the new rendition are useless before its keyframe
+                    Ok(segment) if !segment.is_keyframe => {}
+                    Ok(segment) => {
+                        let Some((index, rx)) = switch.take() else {
+                            continue;
+                        };
+                        let next = state.renditions[index].clone();
+                        if !send_json(&mut sender, &init_message(&state, &next)).await {
+                            break;
+                        }
+                        if let Some(init_segment) = next.get_init_segment() {
+                            if sender.send(Message::Binary(init_segment)).await.is_err() {
+                                break;
+                            }
+                        }
+                        let (size, mut times) = (segment.data.len(), segment.times);
+                        if sender.send(Message::Binary(segment.data)).await.is_err() {
+                            break;
+                        }
+                        times.sent_us = unix_time_us();
+
+                        rendition.adaptive().remove_client(client_id);
+                        next.adaptive().add_client(client_id);
+                        state.metrics.set_client_rendition(client_id, index);
+                        state.metrics.record_sent(client_id, &times, size);
+                        info!("Video client {} switched to {}", client_id, next.offer);
+                        rendition = next;
+                        segment_rx = rx;
+                        current.store(index, Ordering::Relaxed);
+                        awaiting_keyframe = false;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => switch = None,
+                }
+            }
+            Some(reply) = reply_rx.recv() => {
+                if !send_json(&mut sender, &reply).await {
+                    break;
+                }
+            }
+            _ = ping_interval.tick() => {
+                // Send ping for keepalive; the echoed timestamp measures RTT
                 let ping_msg = ServerMessage::Ping {
-                    timestamp: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
+                    timestamp: unix_millis(),
                 };
-                if let Ok(json) = serde_json::to_string(&ping_msg) {
-                    if sender.send(Message::Text(json)).await.is_err() {
-                        break;
+                if !send_json(&mut sender, &ping_msg).await {
+                    break;
+                }
+
+                // Step back up towards the negotiated rendition once the link is clear
+                let index = current.load(Ordering::Relaxed);
+                if switch.is_none() && last_lag.elapsed() >= UPGRADE_AFTER {
+                    if let Some(higher) = negotiation::higher_stream(&offers, &hello, index, preferred) {
+                        switch = Some(begin_switch(&state, client_id, index, higher));
+                        last_lag = Instant::now();
                     }
                 }
             }
@@ -240,21 +409,129 @@ pub async fn handle_video_socket(socket: WebSocket, state: Arc<AppState>) {
     }
 
     recv_task.abort();
-    info!("Video client disconnected");
+    rendition.adaptive().remove_client(client_id);
+    state.metrics.remove_client(client_id);
+    info!("Video client {} disconnected", client_id);
+}
+
+/// `Init` describing a rendition
+fn init_message(state: &AppState, rendition: &RenditionStream) -> ServerMessage {
+    let (codec, codec_data) = match rendition.get_codec_config() {
+        Some(config) => (
+            Some(config.codec_string),
+            Some(BASE64.encode(&config.description)),
+        ),
+        None => (None, None),
+    };
+
+    ServerMessage::Init {
+        width: rendition.offer.width,
+        height: rendition.offer.height,
+        display_width: state.config.width,
+        display_height: state.config.height,
+        fps: rendition.offer.fps,
+        codec,
+        codec_data,
+        color_space: Some(state.config.color_space.into()),
+        timescale: fmp4::TIMESCALE,
+    }
+}
+
+/// Start moving a client to another rendition
+///
+/// The client keeps receiving its current rendition until the new one
+/// produces a keyframe.
+fn begin_switch(
+    state: &AppState,
+    client_id: ClientId,
+    from: usize,
+    to: usize,
+) -> (usize, broadcast::Receiver<StreamSegment>) {
+    let next = &state.renditions[to];
+    info!(
+        "Video client {} switching from {} to {}",
+        client_id, state.renditions[from].offer, next.offer
+    );
+    let rx = next.video_tx.subscribe();
+    next.request_keyframe();
+    (to, rx)
+}
+
+/// Next segment of the rendition being switched to; pending if there is none
+async fn recv_switch(
+    switch: &mut Option<(usize, broadcast::Receiver<StreamSegment>)>,
+) -> Result<StreamSegment, broadcast::error::RecvError> {
+    match switch {
+        Some((_, rx)) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Send a control message, returning false if the socket is gone
+async fn send_json(sender: &mut SplitSink<WebSocket, Message>, message: &ServerMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => sender.send(Message::Text(json)).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+/// Wait for the client's `Hello`, skipping anything sent before it
+///
+/// Returns `None` if the socket closes first.
+async fn receive_hello(receiver: &mut SplitStream<WebSocket>) -> Option<ClientHello> {
+    while let Some(msg) = receiver.next().await {
+        match msg {
+            Ok(Message::Text(text)) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(ClientMessage::Hello(hello)) => return Some(hello),
+                Ok(msg) => debug!("Ignoring {:?} before Hello", msg),
+                Err(e) => debug!("Invalid message before Hello: {} - raw: {}", e, text),
+            },
+            Ok(Message::Close(_)) | Err(_) => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Send an error and close the socket
+async fn reject(sender: &mut SplitSink<WebSocket, Message>, message: String) {
+    if let Ok(json) = serde_json::to_string(&ServerMessage::Error { message }) {
+        let _ = sender.send(Message::Text(json)).await;
+    }
+    let _ = sender.send(Message::Close(None)).await;
+}
+
+/// Comma-separated list for messages
+fn join<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Current wall-clock time in milliseconds since the Unix epoch
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
 
 /// Handle input WebSocket connection
 pub async fn handle_input_socket(socket: WebSocket, state: Arc<AppState>) {
     let (mut sender, mut receiver) = socket.split();
+    let client_id = state.next_client_id();
 
-    info!("Input client connected successfully");
+    info!("Input client {} connected successfully", client_id);
 
     while let Some(msg) = receiver.next().await {
         match msg {
             Ok(Message::Text(text)) => match serde_json::from_str::<InputEvent>(&text) {
                 Ok(event) => {
                     info!("Input event received: {:?}", event);
-                    if state.input_tx.send(event).await.is_err() {
+                    let command = InputCommand::Event { client_id, event };
+                    if state.input_tx.send(command).await.is_err() {
                         warn!("Input channel closed");
                         break;
                     }
@@ -277,5 +554,11 @@ pub async fn handle_input_socket(socket: WebSocket, state: Arc<AppState>) {
         }
     }
 
-    info!("Input client disconnected");
+    // Release anything the client was still holding
+    let _ = state
+        .input_tx
+        .send(InputCommand::Disconnect { client_id })
+        .await;
+
+    info!("Input client {} disconnected", client_id);
 }
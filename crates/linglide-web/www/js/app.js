@@ -382,6 +382,15 @@ class LinGlideApp {
         this.container.innerHTML = `
             <div class="viewer" id="viewer-container">
                 <canvas id="display" class="viewer__canvas"></canvas>
+                <textarea id="text-input" class="viewer__text-input"
+                    autocapitalize="off" autocomplete="off" autocorrect="off" spellcheck="false"
+                    aria-label="Keyboard input"></textarea>
+                <button id="btn-keyboard" class="viewer__keyboard-btn" aria-label="Toggle keyboard">
+                    <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2">
+                        <rect x="2" y="6" width="20" height="12" rx="2"/>
+                        <path d="M6 10h.01M10 10h.01M14 10h.01M18 10h.01M7 14h10"/>
+                    </svg>
+                </button>
                 <div id="status" class="viewer__status">
                     <div class="loader"></div>
                     <p id="status-text" class="viewer__status-text">Connecting...</p>
@@ -421,9 +430,14 @@ class LinGlideApp {
         const { InputHandler } = await import('./viewer/input.js');
         this.inputHandler = new InputHandler({
             canvas: document.getElementById('display'),
+            textInput: document.getElementById('text-input'),
             serverUrl: url,
             authToken: token
         });
+
+        document.getElementById('btn-keyboard')?.addEventListener('click', () => {
+            this.inputHandler.toggleSoftKeyboard();
+        });
     }
 }
 
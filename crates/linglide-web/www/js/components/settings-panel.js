@@ -16,6 +16,7 @@ export class SettingsPanel {
      * @param {HTMLElement} container
      * @param {Object} options
      * @param {() => void} [options.onDisconnect]
+     * @param {(bitrateKbps: number) => void} [options.onQualityChange]
      */
     constructor(container, options = {}) {
         this.container = container;
@@ -158,7 +159,7 @@ export class SettingsPanel {
             const value = parseInt(e.target.value);
             this.preferences.bitrateKbps = value;
             storage.updatePreferences({ bitrateKbps: value });
-            // TODO: Notify server of bitrate change
+            this.options.onQualityChange?.(value);
         });
 
         // Toggle switches
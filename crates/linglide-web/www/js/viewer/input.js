@@ -1,13 +1,14 @@
 /**
  * LinGlide Input Handler
  *
- * Handles touch, mouse, and stylus input for the video viewer.
+ * Handles touch, mouse, stylus, and keyboard input for the video viewer.
  */
 
 /**
  * Input handler options
  * @typedef {Object} InputOptions
  * @property {HTMLCanvasElement} canvas
+ * @property {HTMLTextAreaElement} [textInput] - Hidden field that summons the on-screen keyboard
  * @property {string} serverUrl
  * @property {string} [authToken]
  */
@@ -21,6 +22,7 @@ export class InputHandler {
      */
     constructor(options) {
         this.canvas = options.canvas;
+        this.textInput = options.textInput || null;
         this.serverUrl = options.serverUrl;
         this.authToken = options.authToken;
 
@@ -86,6 +88,16 @@ export class InputHandler {
             this.canvas.addEventListener('pointermove', (e) => this.handlePointerMove(e));
             this.canvas.addEventListener('pointerup', (e) => this.handlePointerUp(e));
         }
+
+        // Keyboard events (hardware keyboards attached to the device)
+        window.addEventListener('keydown', (e) => this.handleKeyDown(e));
+        window.addEventListener('keyup', (e) => this.handleKeyUp(e));
+
+        // Text events (on-screen keyboards and IMEs)
+        if (this.textInput) {
+            this.textInput.addEventListener('input', (e) => this.handleTextInput(e));
+            this.textInput.addEventListener('compositionend', (e) => this.handleCompositionEnd(e));
+        }
     }
 
     /**
@@ -290,6 +302,119 @@ export class InputHandler {
         });
     }
 
+    // ========================================================================
+    // Keyboard Events
+    // ========================================================================
+
+    /**
+     * Get modifier state from a keyboard event
+     * @param {KeyboardEvent} e
+     * @returns {{ ctrl: boolean, alt: boolean, shift: boolean, meta: boolean }}
+     */
+    getModifiers(e) {
+        return {
+            ctrl: e.ctrlKey,
+            alt: e.altKey,
+            shift: e.shiftKey,
+            meta: e.metaKey
+        };
+    }
+
+    /**
+     * Check whether a key event should be left to the text input field
+     * @param {KeyboardEvent} e
+     * @returns {boolean}
+     */
+    isTextKey(e) {
+        return e.target === this.textInput
+            && e.key.length === 1
+            && !e.ctrlKey
+            && !e.metaKey;
+    }
+
+    handleKeyDown(e) {
+        // Composition events carry no physical key code
+        if (e.isComposing || !e.code) return;
+        // Printable keys typed into the text field arrive as TextInput
+        if (this.isTextKey(e)) return;
+        e.preventDefault();
+
+        this.send({
+            type: 'KeyDown',
+            key: e.code,
+            modifiers: this.getModifiers(e)
+        });
+    }
+
+    handleKeyUp(e) {
+        if (e.isComposing || !e.code) return;
+        if (this.isTextKey(e)) return;
+        e.preventDefault();
+
+        this.send({
+            type: 'KeyUp',
+            key: e.code,
+            modifiers: this.getModifiers(e)
+        });
+    }
+
+    /**
+     * Tap a key by its KeyboardEvent.code
+     * @param {string} code
+     */
+    tapKey(code) {
+        const modifiers = { ctrl: false, alt: false, shift: false, meta: false };
+        this.send({ type: 'KeyDown', key: code, modifiers });
+        this.send({ type: 'KeyUp', key: code, modifiers });
+    }
+
+    handleTextInput(e) {
+        // Composed text is sent once on compositionend
+        if (e.isComposing) return;
+
+        switch (e.inputType) {
+            case 'insertText':
+            case 'insertReplacementText':
+                if (e.data) {
+                    this.send({ type: 'TextInput', text: e.data });
+                }
+                break;
+            case 'insertLineBreak':
+            case 'insertParagraph':
+                this.tapKey('Enter');
+                break;
+            case 'deleteContentBackward':
+                this.tapKey('Backspace');
+                break;
+            case 'deleteContentForward':
+                this.tapKey('Delete');
+                break;
+        }
+
+        // Keep the field empty so every edit arrives as a delta
+        this.textInput.value = '';
+    }
+
+    handleCompositionEnd(e) {
+        if (e.data) {
+            this.send({ type: 'TextInput', text: e.data });
+        }
+        this.textInput.value = '';
+    }
+
+    /**
+     * Show or hide the on-screen keyboard
+     */
+    toggleSoftKeyboard() {
+        if (!this.textInput) return;
+
+        if (document.activeElement === this.textInput) {
+            this.textInput.blur();
+        } else {
+            this.textInput.focus();
+        }
+    }
+
     /**
      * Disconnect and clean up
      */
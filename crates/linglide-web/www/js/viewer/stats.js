@@ -1,7 +1,8 @@
 /**
  * LinGlide Statistics Tracker
  *
- * Tracks latency, FPS, and bitrate statistics.
+ * Tracks latency, FPS, and bitrate statistics, plus the playback counters
+ * reported to the server.
  */
 
 /**
@@ -65,6 +66,9 @@ export class StatsTracker {
         // Latency tracking (from Ping/Pong)
         this.latency = new RollingAverage(10);
 
+        // Capture to display latency of decoded frames
+        this.frameLatency = new RollingAverage(30);
+
         // FPS tracking
         this.frameTimes = [];
         this.lastFpsCalculation = performance.now();
@@ -75,10 +79,34 @@ export class StatsTracker {
         this.lastBitrateCalculation = performance.now();
         this.currentBitrate = 0;
 
+        // Playback counters since the last report to the server
+        this.decodedFrames = 0;
+        this.droppedFrames = 0;
+        this.maxBufferDepth = 0;
+        this.decodeLatency = new RollingAverage(30);
+
+        // Display refresh rate, from the interval between animation frames
+        this.refreshIntervals = new RollingAverage(60);
+        this.lastAnimationFrame = null;
+        this.animationFrame = requestAnimationFrame((time) => this.measureRefresh(time));
+
         // Start periodic calculations
         this.calculationInterval = setInterval(() => this.calculate(), 1000);
     }
 
+    /**
+     * Sample the animation frame interval
+     * @param {number} time - Animation frame timestamp
+     */
+    measureRefresh(time) {
+        // Long gaps mean the page was hidden, not a slow display
+        if (this.lastAnimationFrame !== null && time - this.lastAnimationFrame < 100) {
+            this.refreshIntervals.add(time - this.lastAnimationFrame);
+        }
+        this.lastAnimationFrame = time;
+        this.animationFrame = requestAnimationFrame((next) => this.measureRefresh(next));
+    }
+
     /**
      * Record a ping/pong latency measurement
      * @param {number} ms - Round-trip time in milliseconds
@@ -87,6 +115,14 @@ export class StatsTracker {
         this.latency.add(Math.max(0, ms));
     }
 
+    /**
+     * Record the time from screen capture to display of a frame
+     * @param {number} ms - Latency in milliseconds
+     */
+    recordFrameLatency(ms) {
+        this.frameLatency.add(Math.max(0, ms));
+    }
+
     /**
      * Record a decoded frame
      */
@@ -101,6 +137,50 @@ export class StatsTracker {
         }
     }
 
+    /**
+     * Record a frame coming out of the decoder
+     * @param {number} ms - Time since it was handed to the decoder
+     */
+    recordDecoded(ms) {
+        this.decodedFrames++;
+        this.decodeLatency.add(Math.max(0, ms));
+    }
+
+    /**
+     * Record frames that were received but will never be shown
+     * @param {number} [count]
+     */
+    recordDropped(count = 1) {
+        this.droppedFrames += count;
+    }
+
+    /**
+     * Record how many frames are waiting in the decoder
+     * @param {number} depth
+     */
+    recordBufferDepth(depth) {
+        this.maxBufferDepth = Math.max(this.maxBufferDepth, depth);
+    }
+
+    /**
+     * Playback statistics for a `Stats` message, restarting the counts
+     * @returns {Object}
+     */
+    takePlaybackReport() {
+        const refreshInterval = this.refreshIntervals.average;
+        const report = {
+            decoded_frames: this.decodedFrames,
+            dropped_frames: this.droppedFrames,
+            decode_latency_ms: this.decodeLatency.samples.length > 0 ? this.decodeLatency.average : null,
+            jitter_buffer_depth: this.maxBufferDepth,
+            refresh_rate: refreshInterval > 0 ? 1000 / refreshInterval : null
+        };
+        this.decodedFrames = 0;
+        this.droppedFrames = 0;
+        this.maxBufferDepth = 0;
+        return report;
+    }
+
     /**
      * Record bytes received
      * @param {number} bytes
@@ -137,6 +217,7 @@ export class StatsTracker {
             // Latency in milliseconds
             latency: Math.round(this.latency.average),
             latencyLatest: Math.round(this.latency.latest),
+            frameLatency: Math.round(this.frameLatency.average),
 
             // Frames per second
             fps: Math.round(this.currentFps),
@@ -161,6 +242,11 @@ export class StatsTracker {
      */
     reset() {
         this.latency.reset();
+        this.frameLatency.reset();
+        this.decodeLatency.reset();
+        this.decodedFrames = 0;
+        this.droppedFrames = 0;
+        this.maxBufferDepth = 0;
         this.frameTimes = [];
         this.bytesReceived = 0;
         this.currentFps = 0;
@@ -175,6 +261,10 @@ export class StatsTracker {
             clearInterval(this.calculationInterval);
             this.calculationInterval = null;
         }
+        if (this.animationFrame) {
+            cancelAnimationFrame(this.animationFrame);
+            this.animationFrame = null;
+        }
     }
 }
 
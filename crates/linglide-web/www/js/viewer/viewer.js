@@ -1,11 +1,29 @@
 /**
  * LinGlide Enhanced Video Viewer
  *
- * WebCodecs H.264 decoder with statistics tracking.
+ * WebCodecs H.264, HEVC and AV1 decoder with statistics tracking.
  */
 
 import { StatsTracker } from './stats.js';
 
+/** Seconds from the NTP epoch (1900) to the Unix epoch (1970) */
+const NTP_UNIX_OFFSET = 2208988800;
+
+/** Media timescale assumed when the server doesn't send one */
+const DEFAULT_TIMESCALE = 90000;
+
+/**
+ * Codec strings probed for the Hello, at the highest level the server
+ * produces (1080p60 or more)
+ */
+const PROBE_CODECS = ['av01.0.09M.08', 'hvc1.1.6.L123.B0', 'avc1.42E032'];
+
+/** trun flags: first-sample-flags, sample-duration, sample-size and sample-flags present */
+const TRUN_FIRST_SAMPLE_FLAGS = 0x000004;
+const TRUN_SAMPLE_DURATION = 0x000100;
+const TRUN_SAMPLE_SIZE = 0x000200;
+const TRUN_SAMPLE_FLAGS = 0x000400;
+
 /**
  * Video viewer options
  * @typedef {Object} ViewerOptions
@@ -14,10 +32,12 @@ import { StatsTracker } from './stats.js';
  * @property {HTMLElement} statusTextElement
  * @property {string} serverUrl
  * @property {string} [authToken]
+ * @property {number} [fps] - Preferred frame rate
  * @property {() => void} [onConnect]
  * @property {() => void} [onDisconnect]
  * @property {(error: string) => void} [onError]
  * @property {(stats: Object) => void} [onStats]
+ * @property {(quality: {bitrate: number, fps: number}) => void} [onQualityChange]
  */
 
 /**
@@ -34,12 +54,14 @@ export class VideoViewer {
         this.statusTextElement = options.statusTextElement;
         this.serverUrl = options.serverUrl;
         this.authToken = options.authToken;
+        this.fps = options.fps;
 
         // Callbacks
         this.onConnect = options.onConnect;
         this.onDisconnect = options.onDisconnect;
         this.onError = options.onError;
         this.onStats = options.onStats;
+        this.onQualityChange = options.onQualityChange;
 
         // WebSocket
         this.ws = null;
@@ -53,6 +75,14 @@ export class VideoViewer {
         this.gotKeyframe = false;
         this.frameCount = 0;
         this.skippedFrames = 0;
+        // Sample entry types this device decodes, probed once
+        this.codecs = null;
+        // Set when the server turns this client away, so it stops reconnecting
+        this.rejected = false;
+        // Capture wall-clock time (ms) of chunks in flight, by chunk timestamp
+        this.captureTimes = new Map();
+        // When chunks in flight were handed to the decoder, by chunk timestamp
+        this.decodeStarts = new Map();
 
         // Statistics
         this.stats = new StatsTracker();
@@ -72,6 +102,7 @@ export class VideoViewer {
         if (this.authToken) {
             url += `?token=${encodeURIComponent(this.authToken)}`;
         }
+        this.codecs ??= await this.probeCodecs();
 
         try {
             this.ws = new WebSocket(url);
@@ -87,6 +118,26 @@ export class VideoViewer {
         }
     }
 
+    /**
+     * Find the codecs this device can decode
+     * @returns {Promise<string[]>} Sample entry types, such as `hvc1`
+     */
+    async probeCodecs() {
+        if (!('VideoDecoder' in window)) return [];
+
+        const supported = [];
+        for (const codec of PROBE_CODECS) {
+            try {
+                const { supported: ok } = await VideoDecoder.isConfigSupported({ codec });
+                if (ok) supported.push(codec.slice(0, 4));
+            } catch (error) {
+                console.warn(`Codec probe failed for ${codec}:`, error);
+            }
+        }
+        console.log('Decodable codecs:', supported);
+        return supported;
+    }
+
     /**
      * Handle WebSocket open
      */
@@ -94,6 +145,18 @@ export class VideoViewer {
         console.log('Video WebSocket connected');
         this.setStatus('Waiting for video...');
         this.reconnectAttempts = 0;
+        // Filled in by this connection's Init
+        this.config = null;
+
+        // Tell the server what this device can play; it answers with Init
+        this.ws.send(JSON.stringify({
+            type: 'Hello',
+            codecs: this.codecs,
+            max_width: window.screen.width,
+            max_height: window.screen.height,
+            device_pixel_ratio: window.devicePixelRatio,
+            fps: this.fps
+        }));
 
         // Start stats reporting
         this.startStatsReporting();
@@ -118,15 +181,26 @@ export class VideoViewer {
     handleControlMessage(msg) {
         switch (msg.type) {
             case 'Init':
+                // A later Init moves this client to another rendition; its
+                // keyframe follows, so start a fresh decoder for it
+                this.closeDecoder();
                 this.config = {
                     width: msg.width,
                     height: msg.height,
+                    displayWidth: msg.display_width || msg.width,
+                    displayHeight: msg.display_height || msg.height,
                     fps: msg.fps,
                     codec: msg.codec || 'avc1.64002a',
-                    codecData: msg.codec_data ? this.base64ToArrayBuffer(msg.codec_data) : null
+                    codecData: msg.codec_data ? this.base64ToArrayBuffer(msg.codec_data) : null,
+                    colorSpace: msg.color_space || null,
+                    timescale: msg.timescale || DEFAULT_TIMESCALE
                 };
+                // Keep the desktop's shape even if the stream's differs, so
+                // normalized input coordinates land where they were aimed
                 this.canvas.width = msg.width;
-                this.canvas.height = msg.height;
+                this.canvas.height = Math.round(
+                    msg.width * this.config.displayHeight / this.config.displayWidth
+                );
                 console.log('Video config:', this.config);
                 this.initDecoder();
                 break;
@@ -148,8 +222,15 @@ export class VideoViewer {
                 }
                 break;
 
+            case 'QualityChanged':
+                console.log(`Encoder quality: ${msg.bitrate} kbps @ ${msg.fps} fps`);
+                this.onQualityChange?.({ bitrate: msg.bitrate, fps: msg.fps });
+                break;
+
             case 'Error':
                 console.error('Server error:', msg.message);
+                // Errors before Init answer the Hello: nothing here is playable
+                if (!this.config) this.rejected = true;
                 this.setStatus(`Error: ${msg.message}`, true);
                 this.onError?.(msg.message);
                 break;
@@ -185,6 +266,7 @@ export class VideoViewer {
                 output: (frame) => this.handleFrame(frame),
                 error: (error) => {
                     console.error('Decoder error:', error);
+                    this.recoverDecoder();
                 }
             });
 
@@ -195,6 +277,11 @@ export class VideoViewer {
                 optimizeForLatency: true
             };
 
+            // Match the server's RGB to YUV conversion
+            if (this.config.colorSpace) {
+                decoderConfig.colorSpace = this.config.colorSpace;
+            }
+
             await this.decoder.configure(decoderConfig);
             console.log('Decoder initialized');
         } catch (error) {
@@ -204,6 +291,27 @@ export class VideoViewer {
         }
     }
 
+    /**
+     * Close the current decoder, dropping queued frames
+     */
+    closeDecoder() {
+        if (this.decoder && this.decoder.state !== 'closed') {
+            this.decoder.close();
+        }
+        this.decoder = null;
+        this.gotKeyframe = false;
+        this.captureTimes.clear();
+        this.dropPending();
+    }
+
+    /**
+     * Count frames still in the decoder as dropped and forget them
+     */
+    dropPending() {
+        this.stats.recordDropped(this.decodeStarts.size);
+        this.decodeStarts.clear();
+    }
+
     /**
      * Handle video data
      * @param {Uint8Array} data
@@ -230,6 +338,7 @@ export class VideoViewer {
                         console.log('Got first keyframe');
                     } else {
                         this.skippedFrames++;
+                        this.stats.recordDropped();
                         continue;
                     }
                 }
@@ -237,41 +346,59 @@ export class VideoViewer {
                 const chunk = new EncodedVideoChunk({
                     type: chunkType,
                     timestamp: nal.timestamp,
+                    duration: nal.duration,
                     data: nal.data
                 });
 
+                if (nal.captureTime !== null) {
+                    this.captureTimes.set(nal.timestamp, nal.captureTime);
+                }
                 this.decoder.decode(chunk);
+                this.decodeStarts.set(nal.timestamp, performance.now());
+                this.stats.recordBufferDepth(this.decodeStarts.size);
                 this.frameCount++;
             } catch (error) {
                 console.error('Decode error:', error);
+                this.stats.recordDropped();
+                this.requestKeyframe();
             }
         }
     }
 
     /**
      * Parse MP4 container
+     *
+     * Each media segment is a `prft` box with the capture time, a `moof`
+     * with the decode time and duration, and an `mdat` with the frame.
      * @param {Uint8Array} data
      * @returns {Array}
      */
     parseMP4(data) {
         const nalUnits = [];
         let offset = 0;
+        let captureTime = null;
+        let timing = null;
 
         while (offset + 8 <= data.length) {
-            const size = (data[offset] << 24) | (data[offset + 1] << 16) |
-                         (data[offset + 2] << 8) | data[offset + 3];
-            const type = String.fromCharCode(data[offset + 4], data[offset + 5],
-                                            data[offset + 6], data[offset + 7]);
+            const size = this.readU32(data, offset);
+            const type = this.boxType(data, offset);
 
             if (size < 8 || offset + size > data.length) break;
+            const content = data.subarray(offset + 8, offset + size);
 
-            if (type === 'mdat') {
+            if (type === 'prft') {
+                captureTime = this.parseCaptureTime(content);
+            } else if (type === 'moof') {
+                timing = this.parseTiming(content);
+            } else if (type === 'mdat') {
                 const mdatData = data.slice(offset + 8, offset + size);
-                const isKeyframe = this.isKeyframe(mdatData);
+                const isKeyframe = timing?.isKeyframe ?? this.isKeyframe(mdatData);
 
                 nalUnits.push({
                     data: mdatData,
-                    timestamp: performance.now() * 1000,
+                    timestamp: timing ? timing.timestamp : performance.now() * 1000,
+                    duration: timing?.duration,
+                    captureTime,
                     isKeyframe
                 });
             }
@@ -282,19 +409,119 @@ export class VideoViewer {
         return nalUnits;
     }
 
+    /**
+     * Read the decode time and first sample duration of a moof, in
+     * microseconds, and whether the first sample is a sync sample
+     * @param {Uint8Array} moof - moof box content
+     * @returns {{timestamp: number, duration: number|undefined, isKeyframe: boolean|undefined}|null}
+     */
+    parseTiming(moof) {
+        const traf = this.findBox(moof, 'traf');
+        const tfdt = traf && this.findBox(traf, 'tfdt');
+        if (!tfdt) return null;
+
+        const ticksToUs = 1000000 / this.config.timescale;
+        const decodeTime = tfdt[0] === 1 ? this.readU64(tfdt, 4) : this.readU32(tfdt, 4);
+
+        // trun: version/flags, sample count, optional data offset and first
+        // sample flags, then the first sample's duration, size and flags
+        let duration;
+        let isKeyframe;
+        const trun = this.findBox(traf, 'trun');
+        if (trun) {
+            const flags = this.readU32(trun, 0) & 0xFFFFFF;
+            let offset = (flags & 0x01) ? 12 : 8;
+            let sampleFlags;
+            if (flags & TRUN_FIRST_SAMPLE_FLAGS) {
+                sampleFlags = this.readU32(trun, offset);
+                offset += 4;
+            }
+            if (flags & TRUN_SAMPLE_DURATION) {
+                duration = this.readU32(trun, offset) * ticksToUs;
+                offset += 4;
+            }
+            if (flags & TRUN_SAMPLE_SIZE) offset += 4;
+            if (flags & TRUN_SAMPLE_FLAGS) sampleFlags = this.readU32(trun, offset);
+
+            // sample_depends_on == 2: the sample references no other frame
+            if (sampleFlags !== undefined) isKeyframe = ((sampleFlags >>> 24) & 0x03) === 2;
+        }
+
+        return { timestamp: Math.round(decodeTime * ticksToUs), duration, isKeyframe };
+    }
+
+    /**
+     * Read the capture wall-clock time from a prft box
+     * @param {Uint8Array} prft - prft box content
+     * @returns {number} Milliseconds since the Unix epoch
+     */
+    parseCaptureTime(prft) {
+        const seconds = this.readU32(prft, 8);
+        const fraction = this.readU32(prft, 12);
+        return (seconds - NTP_UNIX_OFFSET) * 1000 + (fraction / 2 ** 32) * 1000;
+    }
+
+    /**
+     * Find a child box
+     * @param {Uint8Array} data - Parent box content
+     * @param {string} type
+     * @returns {Uint8Array|null} Child box content
+     */
+    findBox(data, type) {
+        let offset = 0;
+        while (offset + 8 <= data.length) {
+            const size = this.readU32(data, offset);
+            if (size < 8 || offset + size > data.length) break;
+            if (this.boxType(data, offset) === type) {
+                return data.subarray(offset + 8, offset + size);
+            }
+            offset += size;
+        }
+        return null;
+    }
+
+    boxType(data, offset) {
+        return String.fromCharCode(data[offset + 4], data[offset + 5],
+                                   data[offset + 6], data[offset + 7]);
+    }
+
+    readU32(data, offset) {
+        return ((data[offset] << 24) | (data[offset + 1] << 16) |
+                (data[offset + 2] << 8) | data[offset + 3]) >>> 0;
+    }
+
+    readU64(data, offset) {
+        return this.readU32(data, offset) * 2 ** 32 + this.readU32(data, offset + 4);
+    }
+
     /**
      * Check if NAL contains keyframe
+     *
+     * Fallback for segments without trun sample flags; only understands
+     * H.264 and HEVC Annex B data.
      * @param {Uint8Array} data
      * @returns {boolean}
      */
     isKeyframe(data) {
+        const hevc = /^(hvc1|hev1)/.test(this.config?.codec ?? '');
         for (let i = 0; i < data.length - 4; i++) {
-            if (data[i] === 0 && data[i + 1] === 0 && data[i + 2] === 0 && data[i + 3] === 1) {
-                const nalType = data[i + 4] & 0x1F;
-                if (nalType === 5 || nalType === 7) return true;
+            if (data[i] !== 0 || data[i + 1] !== 0) continue;
+
+            let header;
+            if (data[i + 2] === 0 && data[i + 3] === 1) {
+                header = data[i + 4];
+            } else if (data[i + 2] === 1) {
+                header = data[i + 3];
+            } else {
+                continue;
             }
-            if (data[i] === 0 && data[i + 1] === 0 && data[i + 2] === 1) {
-                const nalType = data[i + 3] & 0x1F;
+
+            if (hevc) {
+                // IRAP pictures (16-21) and VPS/SPS/PPS (32-34)
+                const nalType = (header >> 1) & 0x3F;
+                if ((nalType >= 16 && nalType <= 21) || (nalType >= 32 && nalType <= 34)) return true;
+            } else {
+                const nalType = header & 0x1F;
                 if (nalType === 5 || nalType === 7) return true;
             }
         }
@@ -315,8 +542,21 @@ export class VideoViewer {
         // Record frame for FPS tracking
         this.stats.recordFrame();
 
+        const decodeStart = this.decodeStarts.get(frame.timestamp);
+        if (decodeStart !== undefined) {
+            this.decodeStarts.delete(frame.timestamp);
+            this.stats.recordDecoded(performance.now() - decodeStart);
+        }
+
+        // Capture to display latency, assuming server and client clocks agree
+        const captureTime = this.captureTimes.get(frame.timestamp);
+        if (captureTime !== undefined) {
+            this.captureTimes.delete(frame.timestamp);
+            this.stats.recordFrameLatency(Date.now() - captureTime);
+        }
+
         // Draw frame
-        this.ctx.drawImage(frame, 0, 0);
+        this.ctx.drawImage(frame, 0, 0, this.canvas.width, this.canvas.height);
         frame.close();
     }
 
@@ -329,7 +569,7 @@ export class VideoViewer {
         this.onDisconnect?.();
 
         // Attempt reconnection
-        if (this.reconnectAttempts < this.maxReconnectAttempts) {
+        if (!this.rejected && this.reconnectAttempts < this.maxReconnectAttempts) {
             this.reconnectAttempts++;
             this.setStatus(`Reconnecting... (${this.reconnectAttempts}/${this.maxReconnectAttempts})`);
 
@@ -373,9 +613,18 @@ export class VideoViewer {
         this.statsInterval = setInterval(() => {
             const stats = this.stats.getStats();
             this.onStats?.(stats);
+            this.sendPlaybackStats();
         }, 1000);
     }
 
+    /**
+     * Report decoding and display statistics to the server
+     */
+    sendPlaybackStats() {
+        if (!this.config || this.ws?.readyState !== WebSocket.OPEN) return;
+        this.ws.send(JSON.stringify({ type: 'Stats', ...this.stats.takePlaybackReport() }));
+    }
+
     /**
      * Stop stats reporting
      */
@@ -386,6 +635,39 @@ export class VideoViewer {
         }
     }
 
+    /**
+     * Drop deltas until the next keyframe and ask the server for one
+     */
+    requestKeyframe() {
+        this.gotKeyframe = false;
+        if (this.ws?.readyState === WebSocket.OPEN) {
+            this.ws.send(JSON.stringify({ type: 'RequestKeyframe' }));
+        }
+    }
+
+    /**
+     * Replace a decoder closed by an error and resume from a fresh keyframe
+     */
+    recoverDecoder() {
+        this.captureTimes.clear();
+        this.dropPending();
+        this.requestKeyframe();
+        if (this.decoder?.state === 'closed' && this.config) {
+            this.initDecoder();
+        }
+    }
+
+    /**
+     * Ask the server to change the encoder bitrate and, optionally, frame rate
+     * @param {number} bitrateKbps
+     * @param {number} [fps]
+     */
+    setQuality(bitrateKbps, fps) {
+        if (this.ws?.readyState === WebSocket.OPEN) {
+            this.ws.send(JSON.stringify({ type: 'SetQuality', bitrate: bitrateKbps, fps }));
+        }
+    }
+
     /**
      * Disconnect and clean up
      */
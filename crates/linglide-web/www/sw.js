@@ -4,7 +4,7 @@
  * Provides offline caching for static assets and the pairing UI.
  */
 
-const CACHE_NAME = 'linglide-v2';
+const CACHE_NAME = 'linglide-v3';
 
 // Assets to cache for offline use
 const STATIC_ASSETS = [
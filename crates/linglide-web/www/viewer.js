@@ -14,14 +14,57 @@ class VideoViewer {
         this.frameQueue = [];
         this.isProcessing = false;
 
+        // Playback statistics reported to the server every second
+        this.decodeStarts = new Map();
+        this.playback = { decoded: 0, dropped: 0, decodeMs: 0, maxDepth: 0 };
+        this.refreshInterval = 0;
+
         this.init();
     }
 
     init() {
         this.fullscreenBtn.addEventListener('click', () => this.toggleFullscreen());
+        this.measureRefresh();
+        setInterval(() => this.sendPlaybackStats(), 1000);
         this.connect();
     }
 
+    measureRefresh() {
+        let last = null;
+        const tick = (time) => {
+            // Long gaps mean the page was hidden, not a slow display
+            if (last !== null && time - last < 100) {
+                const interval = time - last;
+                this.refreshInterval = this.refreshInterval
+                    ? this.refreshInterval * 0.95 + interval * 0.05
+                    : interval;
+            }
+            last = time;
+            requestAnimationFrame(tick);
+        };
+        requestAnimationFrame(tick);
+    }
+
+    sendPlaybackStats() {
+        if (!this.config || !this.ws || this.ws.readyState !== WebSocket.OPEN) return;
+        const playback = this.playback;
+        this.ws.send(JSON.stringify({
+            type: 'Stats',
+            decoded_frames: playback.decoded,
+            dropped_frames: playback.dropped,
+            decode_latency_ms: playback.decoded > 0 ? playback.decodeMs / playback.decoded : null,
+            jitter_buffer_depth: playback.maxDepth,
+            refresh_rate: this.refreshInterval > 0 ? 1000 / this.refreshInterval : null
+        }));
+        this.playback = { decoded: 0, dropped: 0, decodeMs: 0, maxDepth: 0 };
+    }
+
+    dropPending() {
+        // Frames still in a closed or failed decoder will never be shown
+        this.playback.dropped += this.decodeStarts.size;
+        this.decodeStarts.clear();
+    }
+
     connect() {
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
         const url = `${protocol}//${window.location.host}/ws/video`;
@@ -33,6 +76,14 @@ class VideoViewer {
         this.ws.onopen = () => {
             console.log('Video WebSocket connected');
             this.statusText.textContent = 'Waiting for video...';
+            // Keyframe detection here only understands H.264
+            this.ws.send(JSON.stringify({
+                type: 'Hello',
+                codecs: ['avc1'],
+                max_width: window.screen.width,
+                max_height: window.screen.height,
+                device_pixel_ratio: window.devicePixelRatio
+            }));
         };
 
         this.ws.onmessage = (event) => {
@@ -63,15 +114,28 @@ class VideoViewer {
 
         switch (msg.type) {
             case 'Init':
+                // A later Init switches rendition: decode it with a fresh decoder
+                if (this.decoder && this.decoder.state !== 'closed') {
+                    this.decoder.close();
+                }
+                this.dropPending();
+                this.gotKeyframe = false;
                 this.config = {
                     width: msg.width,
                     height: msg.height,
+                    displayWidth: msg.display_width || msg.width,
+                    displayHeight: msg.display_height || msg.height,
                     fps: msg.fps,
                     codec: msg.codec || 'avc1.64002a',
-                    codecData: msg.codec_data ? this.base64ToArrayBuffer(msg.codec_data) : null
+                    codecData: msg.codec_data ? this.base64ToArrayBuffer(msg.codec_data) : null,
+                    colorSpace: msg.color_space || null,
+                    timescale: msg.timescale || 90000
                 };
+                // Stretch the stream back to the desktop's shape for input mapping
                 this.canvas.width = msg.width;
-                this.canvas.height = msg.height;
+                this.canvas.height = Math.round(
+                    msg.width * this.config.displayHeight / this.config.displayWidth
+                );
                 console.log('Codec:', this.config.codec, 'Has codecData:', !!this.config.codecData);
                 this.initDecoder();
                 break;
@@ -87,6 +151,10 @@ class VideoViewer {
                 }
                 break;
 
+            case 'QualityChanged':
+                console.log(`Encoder quality: ${msg.bitrate} kbps @ ${msg.fps} fps`);
+                break;
+
             case 'Error':
                 console.error('Server error:', msg.message);
                 this.statusText.textContent = `Error: ${msg.message}`;
@@ -95,6 +163,28 @@ class VideoViewer {
         }
     }
 
+    requestKeyframe() {
+        // Drop deltas until the next keyframe
+        this.gotKeyframe = false;
+        if (this.ws && this.ws.readyState === WebSocket.OPEN) {
+            this.ws.send(JSON.stringify({ type: 'RequestKeyframe' }));
+        }
+    }
+
+    recoverDecoder() {
+        this.dropPending();
+        this.requestKeyframe();
+        if (this.decoder && this.decoder.state === 'closed' && this.config) {
+            this.initDecoder();
+        }
+    }
+
+    setQuality(bitrateKbps, fps) {
+        if (this.ws && this.ws.readyState === WebSocket.OPEN) {
+            this.ws.send(JSON.stringify({ type: 'SetQuality', bitrate: bitrateKbps, fps }));
+        }
+    }
+
     base64ToArrayBuffer(base64) {
         const binaryString = atob(base64);
         const bytes = new Uint8Array(binaryString.length);
@@ -116,6 +206,7 @@ class VideoViewer {
                 output: (frame) => this.handleFrame(frame),
                 error: (error) => {
                     console.error('Decoder error:', error);
+                    this.recoverDecoder();
                 }
             });
 
@@ -126,6 +217,11 @@ class VideoViewer {
                 optimizeForLatency: true
             };
 
+            // Match the server's RGB to YUV conversion
+            if (this.config.colorSpace) {
+                decoderConfig.colorSpace = this.config.colorSpace;
+            }
+
             // Note: Not using description because our NAL units are in Annex B format
             // The decoder will get SPS/PPS from the keyframes directly
 
@@ -164,6 +260,7 @@ class VideoViewer {
                     } else {
                         // Skip P-frames until we get a keyframe
                         this.skippedFrames = (this.skippedFrames || 0) + 1;
+                        this.playback.dropped++;
                         if (this.skippedFrames <= 5 || this.skippedFrames % 100 === 0) {
                             console.log('Waiting for keyframe, skipped:', this.skippedFrames);
                         }
@@ -178,13 +275,18 @@ class VideoViewer {
                 const chunk = new EncodedVideoChunk({
                     type: chunkType,
                     timestamp: nal.timestamp,
+                    duration: nal.duration,
                     data: nal.data
                 });
 
                 this.decoder.decode(chunk);
+                this.decodeStarts.set(nal.timestamp, performance.now());
+                this.playback.maxDepth = Math.max(this.playback.maxDepth, this.decodeStarts.size);
                 this.frameCount = (this.frameCount || 0) + 1;
             } catch (error) {
                 console.error('Decode error:', error);
+                this.playback.dropped++;
+                this.requestKeyframe();
             }
         }
     }
@@ -192,23 +294,26 @@ class VideoViewer {
     parseMP4(data) {
         const nalUnits = [];
         let offset = 0;
+        let timing = null;
 
         while (offset + 8 <= data.length) {
-            const size = (data[offset] << 24) | (data[offset + 1] << 16) |
-                         (data[offset + 2] << 8) | data[offset + 3];
-            const type = String.fromCharCode(data[offset + 4], data[offset + 5],
-                                            data[offset + 6], data[offset + 7]);
+            const size = this.readU32(data, offset);
+            const type = this.boxType(data, offset);
 
             if (size < 8 || offset + size > data.length) break;
 
-            if (type === 'mdat') {
+            if (type === 'moof') {
+                // Decode time and duration from the capture timestamps
+                timing = this.parseTiming(data.subarray(offset + 8, offset + size));
+            } else if (type === 'mdat') {
                 // Extract video data from mdat
                 const mdatData = data.slice(offset + 8, offset + size);
                 const isKeyframe = this.isKeyframe(mdatData);
 
                 nalUnits.push({
                     data: mdatData,
-                    timestamp: performance.now() * 1000,
+                    timestamp: timing ? timing.timestamp : performance.now() * 1000,
+                    duration: timing ? timing.duration : undefined,
                     isKeyframe
                 });
             }
@@ -219,6 +324,51 @@ class VideoViewer {
         return nalUnits;
     }
 
+    parseTiming(moof) {
+        const traf = this.findBox(moof, 'traf');
+        const tfdt = traf && this.findBox(traf, 'tfdt');
+        if (!tfdt) return null;
+
+        const ticksToUs = 1000000 / this.config.timescale;
+        const decodeTime = tfdt[0] === 1 ? this.readU64(tfdt, 4) : this.readU32(tfdt, 4);
+
+        // trun sample duration follows the optional data offset
+        let duration;
+        const trun = this.findBox(traf, 'trun');
+        if (trun && (trun[2] & 0x01)) {
+            duration = this.readU32(trun, (trun[3] & 0x01) ? 12 : 8) * ticksToUs;
+        }
+
+        return { timestamp: Math.round(decodeTime * ticksToUs), duration };
+    }
+
+    findBox(data, type) {
+        let offset = 0;
+        while (offset + 8 <= data.length) {
+            const size = this.readU32(data, offset);
+            if (size < 8 || offset + size > data.length) break;
+            if (this.boxType(data, offset) === type) {
+                return data.subarray(offset + 8, offset + size);
+            }
+            offset += size;
+        }
+        return null;
+    }
+
+    boxType(data, offset) {
+        return String.fromCharCode(data[offset + 4], data[offset + 5],
+                                   data[offset + 6], data[offset + 7]);
+    }
+
+    readU32(data, offset) {
+        return ((data[offset] << 24) | (data[offset + 1] << 16) |
+                (data[offset + 2] << 8) | data[offset + 3]) >>> 0;
+    }
+
+    readU64(data, offset) {
+        return this.readU32(data, offset) * 2 ** 32 + this.readU32(data, offset + 4);
+    }
+
     isKeyframe(data) {
         // Check for SPS (type 7) or IDR NAL unit (type 5)
         for (let i = 0; i < data.length - 4; i++) {
@@ -249,8 +399,15 @@ class VideoViewer {
             console.log('Frame', this.renderedFrames, ':', frame.codedWidth, 'x', frame.codedHeight, 'format:', frame.format);
         }
 
+        const decodeStart = this.decodeStarts.get(frame.timestamp);
+        if (decodeStart !== undefined) {
+            this.decodeStarts.delete(frame.timestamp);
+            this.playback.decoded++;
+            this.playback.decodeMs += performance.now() - decodeStart;
+        }
+
         // Draw frame to canvas
-        this.ctx.drawImage(frame, 0, 0);
+        this.ctx.drawImage(frame, 0, 0, this.canvas.width, this.canvas.height);
         frame.close();
     }
 
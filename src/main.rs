@@ -5,21 +5,15 @@
 
 use anyhow::Result;
 use clap::Parser;
-use linglide_auth::{DeviceStorage, PairingManager};
-use linglide_capture::{Frame, ScreenCapture, VirtualDisplay};
-use linglide_core::{Config, DisplayPosition};
-use linglide_discovery::{ServiceAdvertiser, UsbConnectionManager};
-use linglide_encoder::pipeline::StreamSegment;
-use linglide_encoder::EncodingPipeline;
-use linglide_input::{mouse::RelativeMouse, VirtualMouse, VirtualStylus, VirtualTouchscreen};
-use linglide_server::{
-    broadcast::AppState, create_router, create_rustls_config, CertificateManager,
+use linglide_capture::{run_capture, FileSource, FrameSource};
+use linglide_core::{
+    CaptureSource, ColorSpace, Config, DisplayPosition, EncoderKind, EncoderSettings, H264Profile,
+    Rendition, ScaleFilter, MAX_QP,
 };
-use std::net::IpAddr;
-use std::sync::Arc;
+use linglide_input::InputDispatcher;
+use linglide_server::{Session, TlsMode};
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, info, warn, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::EnvFilter;
 
 /// LinGlide - Use your mobile device as an extended display
@@ -35,6 +29,18 @@ struct Args {
     #[arg(short = 'H', long, default_value = "1080")]
     height: u32,
 
+    /// Encoded stream width (default: display width; alone, keeps the aspect ratio)
+    #[arg(long)]
+    stream_width: Option<u32>,
+
+    /// Encoded stream height (default: display height; alone, keeps the aspect ratio)
+    #[arg(long)]
+    stream_height: Option<u32>,
+
+    /// Filter for scaling frames to the stream size: area or bilinear
+    #[arg(long, default_value = "area")]
+    scale_filter: String,
+
     /// Target frame rate
     #[arg(short, long, default_value = "60")]
     fps: u32,
@@ -60,6 +66,59 @@ struct Args {
     #[arg(short, long)]
     mirror: bool,
 
+    /// Frame source: display (virtual display, or primary in mirror mode), test-pattern,
+    /// or file:<path> to replay a raw BGRA dump, a .y4m file or a directory of PNGs
+    /// The test pattern and file sources need no X server or EVDI module
+    #[arg(long, default_value = "display")]
+    source: String,
+
+    /// Record every captured frame to this file as raw BGRA (replay with --source file:<path>)
+    #[arg(long)]
+    dump_capture: Option<String>,
+
+    /// Video encoder: openh264 or x264 (H.264), x265 (HEVC), svt-av1 (AV1); all
+    /// but openh264 require building with the matching --features flag
+    #[arg(long, default_value = "openh264")]
+    encoder: String,
+
+    /// H.264 profile: baseline (constrained baseline) or high
+    #[arg(long, default_value = "baseline")]
+    profile: String,
+
+    /// Frames between keyframes (0 = only at start and when a client needs one)
+    #[arg(long, default_value = "0")]
+    gop: u32,
+
+    /// Refresh the picture gradually instead of sending full keyframes (x264 and x265)
+    #[arg(long)]
+    intra_refresh: bool,
+
+    /// Slices per frame (0 = encoder decides)
+    #[arg(long, default_value = "1")]
+    slices: u32,
+
+    /// Lowest quantizer rate control may use (higher caps quality and bitrate)
+    #[arg(long, default_value = "0")]
+    min_qp: u8,
+
+    /// Highest quantizer rate control may use (lower keeps a quality floor)
+    #[arg(long, default_value_t = MAX_QP)]
+    max_qp: u8,
+
+    /// Video color space: bt709, bt601, bt709-full or bt601-full
+    #[arg(long, default_value = "bt709")]
+    color_space: String,
+
+    /// Extra lower-resolution stream as SCALE:KBPS (e.g. 2:1500 for half size
+    /// at 1500 kbps); repeat for more. Clients pick the one fitting their screen
+    /// and drop to a lighter one when they fall behind
+    #[arg(long = "rendition", value_name = "SCALE:KBPS")]
+    renditions: Vec<String>,
+
+    /// Keep the configured bitrate and frame rate even when clients fall behind
+    #[arg(long)]
+    no_adaptive_bitrate: bool,
+
     /// Disable HTTPS (not recommended - WebCodecs requires secure context)
     #[arg(long)]
     no_tls: bool,
@@ -122,116 +181,147 @@ async fn main() -> Result<()> {
         .parse()
         .map_err(|e: String| anyhow::anyhow!(e))?;
 
+    // Parse frame source
+    let source: CaptureSource = args
+        .source
+        .parse()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+
+    // Parse encoder
+    let encoder: EncoderKind = args
+        .encoder
+        .parse()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+
+    // Parse color space
+    let color_space: ColorSpace = args
+        .color_space
+        .parse()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+
+    // Parse encoder tuning
+    let encoder_settings = EncoderSettings {
+        gop: args.gop,
+        intra_refresh: args.intra_refresh,
+        profile: args
+            .profile
+            .parse::<H264Profile>()
+            .map_err(|e: String| anyhow::anyhow!(e))?,
+        slices: args.slices,
+        min_qp: args.min_qp,
+        max_qp: args.max_qp,
+    };
+    encoder_settings.validate()?;
+
+    // Parse scale filter
+    let scale_filter: ScaleFilter = args
+        .scale_filter
+        .parse()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+
+    // Parse extra renditions
+    let renditions = args
+        .renditions
+        .iter()
+        .map(|r| r.parse::<Rendition>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+
+    // Recordings with their own dimensions override --width/--height
+    let (width, height) = match source {
+        CaptureSource::File(ref path) => {
+            FileSource::open(path, args.width, args.height)?.dimensions()
+        }
+        _ => (args.width, args.height),
+    };
+    let synthetic = source != CaptureSource::Display;
+
     // Create configuration
-    let config = Config::new()
-        .with_width(args.width)
-        .with_height(args.height)
+    let mut config = Config::new()
+        .with_width(width)
+        .with_height(height)
         .with_fps(args.fps)
         .with_port(args.port)
         .with_position(position)
         .with_bitrate(args.bitrate)
-        .with_mirror_mode(args.mirror);
+        .with_mirror_mode(args.mirror)
+        .with_source(source)
+        .with_color_space(color_space)
+        .with_encoder(encoder)
+        .with_encoder_settings(encoder_settings)
+        .with_scale_filter(scale_filter)
+        .with_renditions(renditions)
+        .with_adaptive_bitrate(!args.no_adaptive_bitrate);
+    if let Some(width) = args.stream_width {
+        config = config.with_stream_width(width);
+    }
+    if let Some(height) = args.stream_height {
+        config = config.with_stream_height(height);
+    }
+    if let Some(ref path) = args.dump_capture {
+        config = config.with_capture_dump(path);
+    }
 
-    // Capture setup: EVDI for virtual display, ScreenCapture for mirror mode
-    let use_evdi = !config.mirror_mode;
     // TODO: For now, use offset 0 to test if touch works at all
     // On Wayland, input devices may need special handling for virtual displays
     let (offset_x, offset_y) = (0_i32, 0_i32);
 
-    // Create channels
-    let (frame_tx, frame_rx) = mpsc::channel::<Frame>(2);
-    let (segment_tx, _segment_rx) = broadcast::channel::<StreamSegment>(16);
-    let (input_tx, mut input_rx) = mpsc::channel(64);
-
-    // Create input devices
-    info!("Creating virtual input devices...");
-    let mut touchscreen = VirtualTouchscreen::new(config.width, config.height, offset_x, offset_y)?;
-    let mut mouse = VirtualMouse::new(config.width, config.height, offset_x, offset_y)?;
-    let mut scroll_mouse = RelativeMouse::new()?;
-    let mut stylus = VirtualStylus::new(config.width, config.height, offset_x, offset_y)?;
-
-    // Get local IP address for display
-    let local_ip = get_local_ip().unwrap_or_else(|| "localhost".to_string());
-
-    // Setup TLS with persistent certificates
-    let use_tls = !args.no_tls;
-    let (tls_config, cert_fingerprint) = if use_tls {
-        let (cert_pem, key_pem, fingerprint) = match (&args.cert, &args.key) {
+    // Create input devices (optional for synthetic sources, which may run without uinput)
+    let dispatcher = match InputDispatcher::new(config.width, config.height, offset_x, offset_y) {
+        Ok(dispatcher) => Some(dispatcher),
+        Err(e) if synthetic => {
+            warn!("Input injection disabled: {}", e);
+            None
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // TLS: explicit certificate files, or persistent self-signed certificate
+    let tls = if args.no_tls {
+        TlsMode::Disabled
+    } else {
+        match (&args.cert, &args.key) {
             (Some(cert_path), Some(key_path)) => {
                 info!("Loading TLS certificate from files...");
-                let cert = std::fs::read_to_string(cert_path)?;
-                let key = std::fs::read_to_string(key_path)?;
-                let fp = linglide_server::calculate_cert_fingerprint(&cert);
-                (cert, key, fp)
-            }
-            _ => {
-                info!("Using persistent certificate storage...");
-                let cert_manager = CertificateManager::new()
-                    .map_err(|e| anyhow::anyhow!("Failed to create certificate manager: {}", e))?;
-
-                let hostnames = vec![local_ip.clone(), "localhost".to_string()];
-                cert_manager
-                    .load_or_generate(&hostnames)
-                    .map_err(|e| anyhow::anyhow!("Failed to load/generate certificate: {}", e))?
+                TlsMode::Pem {
+                    cert: std::fs::read_to_string(cert_path)?,
+                    key: std::fs::read_to_string(key_path)?,
+                }
             }
-        };
-
-        info!("Certificate fingerprint: {}", fingerprint);
-
-        let config = create_rustls_config(&cert_pem, &key_pem)
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to create TLS config: {}", e))?;
-
-        (Some(config), Some(fingerprint))
-    } else {
-        (None, None)
+            _ => TlsMode::Persistent,
+        }
     };
 
-    // Initialize device storage and pairing manager
-    info!("Initializing device storage...");
-    let device_storage = Arc::new(
-        DeviceStorage::new()
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to initialize device storage: {}", e))?,
-    );
-
-    let protocol = if use_tls { "https" } else { "http" };
-    let server_url = format!("{}://{}:{}", protocol, local_ip, config.port);
-    let pairing_manager = Arc::new(PairingManager::new(
-        device_storage.clone(),
-        server_url.clone(),
-    ));
-
-    // Check authentication status
     let auth_required = !args.no_auth;
-    let paired_count = pairing_manager.list_devices().await.len();
-
-    if auth_required {
-        info!("Authentication: ENABLED ({} paired devices)", paired_count);
-    } else {
-        warn!("Authentication: DISABLED (--no-auth flag set)");
+    let mut builder = Session::builder(config.clone())
+        .capture(run_capture)
+        .tls(tls)
+        .auth_required(auth_required)
+        .mdns(!args.no_mdns)
+        .usb(args.enable_usb);
+    if let Some(dispatcher) = dispatcher {
+        builder = builder.input(dispatcher);
+    }
+    if let Some(name) = args.service_name.clone() {
+        builder = builder.service_name(name);
     }
 
-    // Create app state
-    let state = Arc::new(AppState::new(
-        config.clone(),
-        segment_tx.clone(),
-        input_tx,
-        pairing_manager.clone(),
-        auth_required,
-        cert_fingerprint.clone(),
-    ));
-
-    // Create router
-    let router = create_router(state.clone());
-
+    let mut session = builder.build();
     info!("Starting server on port {}...", config.port);
+    session.start().await?;
+
+    let server_url = session.url().unwrap_or_default().to_string();
+    let cert_fingerprint = session.fingerprint().map(str::to_string);
+    let pairing_manager = session
+        .pairing_manager()
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("Session did not start"))?;
+    let paired_count = pairing_manager.list_devices().await.len();
+
     info!("");
     info!("  Access URL: {}", server_url);
-    if use_tls {
-        if let Some(ref fp) = cert_fingerprint {
-            info!("  Cert fingerprint: {}...", &fp[..23]);
-        }
+    if let Some(ref fp) = cert_fingerprint {
+        info!("  Cert fingerprint: {}...", &fp[..23]);
         info!("");
         info!("  NOTE: You may need to accept the self-signed certificate in your browser.");
     }
@@ -323,345 +413,19 @@ async fn main() -> Result<()> {
     info!("Press Ctrl+C to stop.");
     info!("");
 
-    // Initialize mDNS service advertisement
-    let mut mdns_advertiser: Option<ServiceAdvertiser> = None;
-    if !args.no_mdns {
-        match ServiceAdvertiser::new(config.port, args.service_name.clone()) {
-            Ok(mut advertiser) => {
-                // Get IP addresses for advertisement
-                let addresses: Vec<IpAddr> = get_local_ip()
-                    .and_then(|ip| ip.parse().ok())
-                    .into_iter()
-                    .collect();
-
-                let fp = cert_fingerprint.as_deref();
-                match advertiser.start(env!("CARGO_PKG_VERSION"), fp, Some(addresses)) {
-                    Ok(()) => {
-                        info!("mDNS: Advertising as '{}'", advertiser.instance_name());
-                        mdns_advertiser = Some(advertiser);
-                    }
-                    Err(e) => {
-                        warn!("mDNS: Failed to start advertisement: {}", e);
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("mDNS: Failed to create advertiser: {}", e);
-            }
-        }
-    } else {
-        debug!("mDNS: Disabled via --no-mdns flag");
-    }
-
-    // Initialize USB/ADB port forwarding
-    let mut usb_manager: Option<UsbConnectionManager> = None;
-    if args.enable_usb {
-        let mut manager = UsbConnectionManager::new(config.port);
-
-        if manager.is_adb_available().await {
-            match manager.setup_forwarding().await {
-                Ok(()) => {
-                    info!("USB: ADB port forwarding enabled");
-                    usb_manager = Some(manager);
-                }
-                Err(e) => {
-                    warn!("USB: Failed to setup ADB forwarding: {}", e);
-                }
-            }
-        } else {
-            warn!("USB: ADB not found in PATH, USB forwarding disabled");
-        }
-    }
-
-    // Spawn capture task
-    // EVDI uses a dedicated thread (contains raw pointers, not Send)
-    // Mirror mode uses async task
-    let frame_duration = Duration::from_micros(1_000_000 / config.fps as u64);
-    let capture_config = config.clone();
-
-    let capture_handle = if use_evdi {
-        // EVDI capture on dedicated thread
-        let _capture_thread = std::thread::spawn(move || {
-            // Create runtime for this thread
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create capture runtime");
-
-            rt.block_on(async move {
-                // Create and enable virtual display
-                info!("Creating EVDI virtual display...");
-                let mut vd = match VirtualDisplay::new(capture_config) {
-                    Ok(vd) => vd,
-                    Err(e) => {
-                        warn!("Failed to create virtual display: {}", e);
-                        return;
-                    }
-                };
-
-                if let Err(e) = vd.enable() {
-                    warn!("Failed to enable virtual display: {}", e);
-                    return;
-                }
-
-                // Initialize buffer (wait for mode from compositor)
-                info!("Waiting for display mode from compositor...");
-                if let Err(e) = vd.init_buffer().await {
-                    warn!("Failed to initialize buffer: {}", e);
-                    return;
-                }
-
-                info!("EVDI virtual display ready, starting capture...");
-
-                // Capture loop
-                loop {
-                    let start = std::time::Instant::now();
-
-                    match vd.capture_async().await {
-                        Ok(frame) => {
-                            if frame_tx.send(frame).await.is_err() {
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            warn!("EVDI capture error: {}", e);
-                        }
-                    }
-
-                    // Maintain frame rate
-                    let elapsed = start.elapsed();
-                    if elapsed < frame_duration {
-                        tokio::time::sleep(frame_duration - elapsed).await;
-                    }
-                }
-
-                // Cleanup
-                if let Err(e) = vd.disable() {
-                    warn!("Failed to disable virtual display: {}", e);
-                }
-            });
-        });
-
-        // Return a dummy handle that we can abort
-        tokio::spawn(async move {
-            // Just keep running - actual capture is on the thread
-            loop {
-                tokio::time::sleep(Duration::from_secs(3600)).await;
-            }
-        })
-    } else {
-        // Mirror mode: use async ScreenCapture
-        info!("Mirror mode: capturing primary display");
-        let mut capture = ScreenCapture::new(capture_config.width, capture_config.height, 0, 0)
-            .expect("Failed to create screen capture");
-
-        tokio::spawn(async move {
-            loop {
-                let start = std::time::Instant::now();
-
-                match capture.capture() {
-                    Ok(frame) => {
-                        if frame_tx.send(frame).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Capture error: {}", e);
-                    }
-                }
-
-                // Maintain frame rate
-                let elapsed = start.elapsed();
-                if elapsed < frame_duration {
-                    tokio::time::sleep(frame_duration - elapsed).await;
-                }
-            }
-        })
-    };
-
-    // Spawn encoding task on a dedicated thread (x264 is not Send)
-    // We need to create the encoder inside the thread
-    let segment_tx_clone = segment_tx.clone();
-    let enc_width = config.width;
-    let enc_height = config.height;
-    let enc_fps = config.fps;
-    let enc_bitrate = config.bitrate;
-
-    // Channel to receive init segment and codec info from encoder thread
-    let (init_tx, init_rx) = std::sync::mpsc::channel::<(Vec<u8>, String, Vec<u8>)>();
-    let state_clone = state.clone();
-
-    let _encoding_handle = std::thread::spawn(move || {
-        // Create encoder inside the thread
-        let pipeline = match EncodingPipeline::new(enc_width, enc_height, enc_fps, enc_bitrate) {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("Failed to create encoder: {}", e);
-                return;
-            }
-        };
-
-        // Send init segment and codec info to main thread
-        if let Some(init_segment) = pipeline.get_init_segment() {
-            let codec_string = pipeline.get_codec_string();
-            let avcc_data = pipeline.get_avcc_data();
-            let _ = init_tx.send((init_segment, codec_string, avcc_data));
-        }
-
-        // Create a single-threaded runtime for this thread
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .unwrap();
-
-        rt.block_on(pipeline.run(frame_rx, segment_tx_clone));
-    });
-
-    // Receive and store init segment and codec info in app state
-    if let Ok((init_segment, codec_string, avcc_data)) =
-        init_rx.recv_timeout(std::time::Duration::from_secs(5))
-    {
-        info!(
-            "Received init segment: {} bytes, codec: {}",
-            init_segment.len(),
-            codec_string
-        );
-        state_clone.set_init_segment(init_segment);
-        state_clone.set_codec_config(codec_string, avcc_data);
-    } else {
-        warn!("Failed to receive init segment from encoder");
-    }
-
-    // Spawn task to capture keyframe segments for new clients
-    let keyframe_state = state.clone();
-    let mut keyframe_rx = segment_tx.subscribe();
+    // Stop gracefully on Ctrl+C
+    let stop_handle = session.stop_handle();
     tokio::spawn(async move {
-        while let Ok(segment) = keyframe_rx.recv().await {
-            if segment.is_keyframe {
-                keyframe_state.set_keyframe_segment(segment.data);
-            }
-        }
+        tokio::signal::ctrl_c().await.ok();
+        stop_handle.stop();
     });
 
-    // Spawn input handling task
-    let input_handle = tokio::spawn(async move {
-        use linglide_core::protocol::InputEvent;
-
-        while let Some(event) = input_rx.recv().await {
-            let result = match event {
-                InputEvent::TouchStart { id, x, y } => touchscreen.touch_start(id, x, y),
-                InputEvent::TouchMove { id, x, y } => touchscreen.touch_move(id, x, y),
-                InputEvent::TouchEnd { id } => touchscreen.touch_end(id),
-                InputEvent::TouchCancel { id } => touchscreen.touch_cancel(id),
-                InputEvent::MouseDown { button, x, y } => mouse.mouse_down(button, x, y),
-                InputEvent::MouseUp { button, x, y } => mouse.mouse_up(button, x, y),
-                InputEvent::MouseMove { x, y } => mouse.mouse_move(x, y),
-                InputEvent::Scroll { dx, dy } => scroll_mouse.scroll(dx, dy),
-                InputEvent::KeyDown { .. } | InputEvent::KeyUp { .. } => {
-                    // Keyboard input not implemented yet
-                    Ok(())
-                }
-                // Stylus/pen events
-                InputEvent::PenHover {
-                    x,
-                    y,
-                    pressure,
-                    tilt_x,
-                    tilt_y,
-                } => stylus.pen_hover(x, y, pressure, tilt_x, tilt_y),
-                InputEvent::PenDown {
-                    x,
-                    y,
-                    pressure,
-                    tilt_x,
-                    tilt_y,
-                    button,
-                } => stylus.pen_down(x, y, pressure, tilt_x, tilt_y, button),
-                InputEvent::PenMove {
-                    x,
-                    y,
-                    pressure,
-                    tilt_x,
-                    tilt_y,
-                } => stylus.pen_move(x, y, pressure, tilt_x, tilt_y),
-                InputEvent::PenUp { x, y } => stylus.pen_up(x, y),
-                InputEvent::PenButtonEvent { button, pressed } => {
-                    stylus.pen_button(button, pressed)
-                }
-            };
-
-            if let Err(e) = result {
-                warn!("Input error: {}", e);
-            }
-        }
-    });
-
-    // Start HTTP/HTTPS server
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], config.port));
-
-    // Run server with graceful shutdown
-    if let Some(tls_config) = tls_config {
-        let handle = axum_server::Handle::new();
-        let shutdown_handle = handle.clone();
-
-        tokio::spawn(async move {
-            tokio::signal::ctrl_c().await.ok();
-            info!("Shutting down...");
-            shutdown_handle.graceful_shutdown(Some(std::time::Duration::from_secs(5)));
-        });
-
-        axum_server::bind_rustls(addr, tls_config)
-            .handle(handle)
-            .serve(router.into_make_service())
-            .await?;
-    } else {
-        let shutdown = async {
-            tokio::signal::ctrl_c().await.ok();
-            info!("Shutting down...");
-        };
-
-        let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, router)
-            .with_graceful_shutdown(shutdown)
-            .await?;
-    }
-
-    // Cleanup
-    capture_handle.abort();
-    input_handle.abort();
-
-    // Stop mDNS advertisement
-    if let Some(mut advertiser) = mdns_advertiser {
-        if let Err(e) = advertiser.stop() {
-            warn!("mDNS: Failed to stop advertisement: {}", e);
-        }
-    }
-
-    // Remove USB/ADB forwarding
-    if let Some(mut manager) = usb_manager {
-        if let Err(e) = manager.remove_forwarding().await {
-            warn!("USB: Failed to remove ADB forwarding: {}", e);
-        }
-    }
-
-    // Note: VirtualDisplay cleanup happens via Drop when capture_handle is aborted
+    session.wait().await?;
 
     info!("Goodbye!");
     Ok(())
 }
 
-/// Get the local IP address
-fn get_local_ip() -> Option<String> {
-    use std::net::UdpSocket;
-
-    // Create a UDP socket and connect to an external address
-    // This doesn't actually send any data but helps determine the local IP
-    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
-    socket.connect("8.8.8.8:80").ok()?;
-    let addr = socket.local_addr().ok()?;
-    Some(addr.ip().to_string())
-}
-
 /// Display a QR code in the terminal
 fn display_qr_code(data: &str) {
     use qrcode::QrCode;